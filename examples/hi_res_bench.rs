@@ -0,0 +1,83 @@
+//! Compares the compression ratio of `CompressionLevel::hi_res` against `L8`. Run with
+//! `cargo run --release --example hi_res_bench -- [file.flac ...]`, ideally on real 88.2-192 kHz
+//! recordings; without arguments it uses a synthetic 96 kHz signal, which says little about
+//! real material.
+
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+
+use flac_encoder::{AudioBlock, CompressionLevel, FlacBuilder, FlacReader};
+
+const SAMPLE_RATE: u32 = 96000;
+const SECONDS: usize = 30;
+
+fn main() {
+    let paths: Vec<String> = env::args().skip(1).collect();
+
+    let inputs: Vec<(String, AudioBlock)> = if paths.is_empty() {
+        vec![("synthetic 96 kHz".to_string(), synthetic())]
+    } else {
+        paths
+            .into_iter()
+            .map(|path| {
+                let reader = FlacReader::open(&path).unwrap();
+                (path, reader.audio)
+            })
+            .collect()
+    };
+
+    for (name, block) in &inputs {
+        let (l8_ratio, l8_time) = encode(block, CompressionLevel::L8);
+        let (hi_res_ratio, hi_res_time) =
+            encode(block, CompressionLevel::hi_res(block.sample_rate));
+
+        println!("{name} ({} Hz, {} bps):", block.sample_rate, block.bps);
+        println!("  L8:     {:.2}% in {l8_time:?}", l8_ratio * 100.0);
+        println!("  hi_res: {:.2}% in {hi_res_time:?}", hi_res_ratio * 100.0);
+    }
+}
+
+/// Compressed size as a fraction of the PCM size, and how long the encode took.
+fn encode(block: &AudioBlock, level: CompressionLevel) -> (f64, Duration) {
+    let start = Instant::now();
+    let (_, report) = FlacBuilder::from_block(block)
+        .compression_level(level)
+        .build_with_report()
+        .unwrap();
+
+    (report.compression_ratio(), start.elapsed())
+}
+
+/// 24-bit stereo tones with quiet noise, most of it in the audible band like a real
+/// recording, leaving the top octaves nearly empty.
+fn synthetic() -> AudioBlock {
+    let mut noise_state = 1u32;
+    let mut noise = move || {
+        noise_state = noise_state.wrapping_mul(1664525).wrapping_add(1013904223);
+        (noise_state >> 8) as f64 / (1 << 24) as f64 - 0.5
+    };
+
+    let mut low_passed = [0.0; 2];
+    let mut samples = Vec::with_capacity(SAMPLE_RATE as usize * SECONDS * 2);
+
+    for i in 0..SAMPLE_RATE as usize * SECONDS {
+        let t = i as f64 / SAMPLE_RATE as f64;
+
+        for (channel, low_passed) in low_passed.iter_mut().enumerate() {
+            *low_passed += 0.2 * (noise() - *low_passed);
+            let tone = (t * (220.0 + channel as f64 * 110.0) * std::f64::consts::TAU).sin();
+            let sample = 0.4 * tone + 0.05 * *low_passed;
+
+            samples.push((sample * ((1 << 23) - 1) as f64) as i32);
+        }
+    }
+
+    AudioBlock {
+        channels: 2,
+        bps: 24,
+        sample_rate: SAMPLE_RATE,
+        samples,
+    }
+}
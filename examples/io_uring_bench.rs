@@ -0,0 +1,62 @@
+//! Compares `write_file` against `write_file_with_io_uring` with several encodes writing at
+//! once. Run with `cargo run --release --example io_uring_bench --features io-uring`.
+
+use std::{
+    env, thread,
+    time::{Duration, Instant},
+};
+
+use flac_encoder::FlacBuilder;
+
+const SAMPLE_RATE: u32 = 44100;
+const SECONDS: usize = 120;
+const ENCODES: usize = 8;
+
+fn main() {
+    let channels: Vec<Vec<f32>> = (0..2)
+        .map(|c| {
+            (0..SAMPLE_RATE as usize * SECONDS)
+                .map(|i| {
+                    let t = i as f32 / SAMPLE_RATE as f32;
+                    0.5 * (t * (440.0 + c as f32 * 110.0) * std::f32::consts::TAU).sin()
+                })
+                .collect()
+        })
+        .collect();
+
+    let dir = env::temp_dir();
+
+    let default = run(|i| {
+        FlacBuilder::from_planar(&channels, SAMPLE_RATE)
+            .write_file(dir.join(format!("bench-default-{i}.flac")))
+            .unwrap();
+    });
+
+    let uring = run(|i| {
+        FlacBuilder::from_planar(&channels, SAMPLE_RATE)
+            .write_file_with_io_uring(dir.join(format!("bench-uring-{i}.flac")))
+            .unwrap();
+    });
+
+    println!("{ENCODES} concurrent encodes of {SECONDS}s stereo:");
+    println!("  write_file:               {default:?}");
+    println!("  write_file_with_io_uring: {uring:?}");
+
+    for i in 0..ENCODES {
+        let _ = std::fs::remove_file(dir.join(format!("bench-default-{i}.flac")));
+        let _ = std::fs::remove_file(dir.join(format!("bench-uring-{i}.flac")));
+    }
+}
+
+fn run(encode: impl Fn(usize) + Sync) -> Duration {
+    let start = Instant::now();
+
+    thread::scope(|scope| {
+        for i in 0..ENCODES {
+            let encode = &encode;
+            scope.spawn(move || encode(i));
+        }
+    });
+
+    start.elapsed()
+}
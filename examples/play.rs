@@ -0,0 +1,21 @@
+//! Plays a FLAC file on the default output device. Run with
+//! `cargo run --example play --features cpal -- file.flac`.
+
+use std::{env, process::ExitCode};
+
+use flac_encoder::Player;
+
+fn main() -> ExitCode {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: play file.flac");
+        return ExitCode::FAILURE;
+    };
+
+    match Player::open(&path).and_then(Player::wait) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{path}: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Encodes raw PCM or WAV from stdin to FLAC on stdout, for shell pipelines, e.g.
+//! `sox in.aiff -t raw -e signed -b 16 - | cargo run --example stdin_to_flac -- --bits 16
+//! --rate 44100 --channels 2 > out.flac`. WAV input is recognized by its header and needs no
+//! flags. Raw PCM is signed; `--endian big` reads big-endian samples.
+//!
+//! When stdout is a pipe it can't seek, so STREAMINFO is left without the MD5 and frame sizes.
+
+use std::{
+    env,
+    io::{self, BufRead, BufReader, BufWriter},
+    process::ExitCode,
+};
+
+use flac_encoder::{FlacBuilder, PcmReader, Streamed, WavReader};
+
+const USAGE: &str =
+    "usage: stdin_to_flac [--bits N --rate HZ --channels N [--endian little|big]] < in > out.flac";
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{message}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut bits = None;
+    let mut rate = None;
+    let mut channels = None;
+    let mut big_endian = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(USAGE)?;
+        let number = || {
+            value
+                .parse::<u32>()
+                .map_err(|_| format!("invalid {flag} {value}"))
+        };
+
+        match flag.as_str() {
+            "--bits" => bits = Some(number()?),
+            "--rate" => rate = Some(number()?),
+            "--channels" => channels = Some(number()? as usize),
+            "--endian" => match value.as_str() {
+                "little" => big_endian = false,
+                "big" => big_endian = true,
+                _ => return Err(format!("invalid --endian {value}")),
+            },
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let mut input = BufReader::new(io::stdin().lock());
+    let is_wav = input
+        .fill_buf()
+        .map_err(|e| e.to_string())?
+        .starts_with(b"RIFF");
+
+    let builder = if is_wav {
+        FlacBuilder::from_source(WavReader::new(input).map_err(|e| e.to_string())?)
+    } else {
+        let (Some(bits), Some(rate), Some(channels)) = (bits, rate, channels) else {
+            return Err(format!(
+                "raw PCM needs --bits, --rate and --channels\n{USAGE}"
+            ));
+        };
+
+        let mut reader = PcmReader::new(input, channels, rate, bits);
+        if big_endian {
+            reader = reader.big_endian();
+        }
+        FlacBuilder::from_source(reader)
+    };
+
+    builder
+        .write_to_sink(Streamed(BufWriter::new(io::stdout().lock())))
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,349 @@
+//! Analysis done on the converted samples as they flow to the encoder.
+
+use std::time::Duration;
+
+use crate::{BpsLevel, ChannelStats, ConversionLoss, Peaks, SilentRegion};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SilenceSettings {
+    pub threshold_db: f64,
+    pub min_duration: Duration,
+}
+
+pub(crate) struct SilenceDetector {
+    threshold: i32,
+    min_frames: usize,
+    position: usize,
+    run_start: Option<usize>,
+    regions: Vec<SilentRegion>,
+}
+
+impl SilenceDetector {
+    pub fn new(settings: SilenceSettings, bps: BpsLevel, sample_rate: u32) -> Self {
+        let full_scale = ((1i64 << (bps.to_u32() - 1)) - 1) as f64;
+
+        SilenceDetector {
+            threshold: (10f64.powf(settings.threshold_db / 20.0) * full_scale) as i32,
+            min_frames: (settings.min_duration.as_secs_f64() * sample_rate as f64) as usize,
+            position: 0,
+            run_start: None,
+            regions: vec![],
+        }
+    }
+
+    /// `chunk` is interleaved.
+    pub fn feed(&mut self, chunk: &[i32], channels: usize) {
+        for frame in chunk.chunks_exact(channels) {
+            let silent = frame
+                .iter()
+                .all(|s| s.unsigned_abs() <= self.threshold as u32);
+
+            match (silent, self.run_start) {
+                (true, None) => self.run_start = Some(self.position),
+                (false, Some(start)) => {
+                    self.close_run(start);
+                    self.run_start = None;
+                }
+                _ => {}
+            }
+
+            self.position += 1;
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<SilentRegion> {
+        if let Some(start) = self.run_start {
+            self.close_run(start);
+        }
+        self.regions
+    }
+
+    fn close_run(&mut self, start: usize) {
+        if self.position - start >= self.min_frames {
+            self.regions.push(SilentRegion {
+                start,
+                end: self.position,
+            });
+        }
+    }
+}
+
+pub(crate) struct PeakCollector {
+    samples_per_peak: usize,
+    bits: u32,
+    in_current: usize,
+    current: Vec<(i32, i32)>,
+    peaks: Vec<Vec<(i32, i32)>>,
+}
+
+impl PeakCollector {
+    pub fn new(samples_per_peak: usize, channels: usize, bps: BpsLevel) -> Self {
+        PeakCollector {
+            samples_per_peak: samples_per_peak.max(1),
+            bits: bps.to_u32(),
+            in_current: 0,
+            current: vec![(i32::MAX, i32::MIN); channels],
+            peaks: vec![vec![]; channels],
+        }
+    }
+
+    /// `chunk` is interleaved.
+    pub fn feed(&mut self, chunk: &[i32], channels: usize) {
+        for frame in chunk.chunks_exact(channels) {
+            for (current, sample) in self.current.iter_mut().zip(frame) {
+                current.0 = current.0.min(*sample);
+                current.1 = current.1.max(*sample);
+            }
+
+            self.in_current += 1;
+
+            if self.in_current == self.samples_per_peak {
+                self.flush();
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        for (peaks, current) in self.peaks.iter_mut().zip(&mut self.current) {
+            peaks.push(*current);
+            *current = (i32::MAX, i32::MIN);
+        }
+        self.in_current = 0;
+    }
+
+    pub fn finish(mut self) -> Peaks {
+        if self.in_current > 0 {
+            self.flush();
+        }
+
+        Peaks {
+            samples_per_peak: self.samples_per_peak,
+            bits: self.bits,
+            channels: self.peaks,
+        }
+    }
+}
+
+pub(crate) struct StatsCollector {
+    bits: u32,
+    /// Every sample ORed together, so low bits that are always zero stay zero.
+    set_bits: Vec<i32>,
+    peak: Vec<u32>,
+    sum_squares: Vec<f64>,
+    frames: usize,
+}
+
+impl StatsCollector {
+    pub fn new(channels: usize, bps: BpsLevel) -> Self {
+        StatsCollector {
+            bits: bps.to_u32(),
+            set_bits: vec![0; channels],
+            peak: vec![0; channels],
+            sum_squares: vec![0.0; channels],
+            frames: 0,
+        }
+    }
+
+    /// `chunk` is interleaved.
+    pub fn feed(&mut self, chunk: &[i32], channels: usize) {
+        for frame in chunk.chunks_exact(channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                self.set_bits[channel] |= sample;
+                self.peak[channel] = self.peak[channel].max(sample.unsigned_abs());
+                self.sum_squares[channel] += sample as f64 * sample as f64;
+            }
+        }
+        self.frames += chunk.len() / channels.max(1);
+    }
+
+    pub fn finish(self) -> Vec<ChannelStats> {
+        let full_scale = ((1i64 << (self.bits - 1)) - 1) as f64;
+        let dbfs = |value: f64| 20.0 * (value / full_scale).log10();
+
+        (0..self.set_bits.len())
+            .map(|channel| {
+                let set_bits = self.set_bits[channel];
+                let peak = self.peak[channel];
+                let rms = (self.sum_squares[channel] / self.frames.max(1) as f64).sqrt();
+
+                ChannelStats {
+                    used_bits: if set_bits == 0 {
+                        0
+                    } else {
+                        self.bits - set_bits.trailing_zeros()
+                    },
+                    peak,
+                    peak_dbfs: dbfs(peak as f64),
+                    rms_dbfs: dbfs(rms),
+                }
+            })
+            .collect()
+    }
+}
+
+pub(crate) struct LossMeter {
+    bits: u32,
+    max_error: f64,
+    sum_squared_error: f64,
+    clipped: usize,
+    samples: usize,
+}
+
+impl LossMeter {
+    pub fn new() -> Self {
+        LossMeter {
+            bits: 0,
+            max_error: 0.0,
+            sum_squared_error: 0.0,
+            clipped: 0,
+            samples: 0,
+        }
+    }
+
+    /// `float` quantized to `quantized` at `bits`.
+    pub fn record(&mut self, float: f64, quantized: i32, bits: u32) {
+        self.bits = bits;
+        self.samples += 1;
+
+        if float.abs() > 1.0 {
+            self.clipped += 1;
+            return;
+        }
+
+        let full_scale = ((1i64 << (bits - 1)) - 1) as f64;
+        let error = (float * full_scale - quantized as f64).abs();
+
+        self.max_error = self.max_error.max(error);
+        self.sum_squared_error += error * error;
+    }
+
+    /// `None` if no float samples were recorded.
+    pub fn finish(self) -> Option<ConversionLoss> {
+        if self.samples == 0 {
+            return None;
+        }
+
+        let unclipped = (self.samples - self.clipped).max(1);
+
+        Some(ConversionLoss {
+            bits: self.bits,
+            max_error_lsb: self.max_error,
+            rms_error_lsb: (self.sum_squared_error / unclipped as f64).sqrt(),
+            clipped_samples: self.clipped,
+            samples: self.samples,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FlacBuilder;
+
+    #[test]
+    fn reports_runs_long_enough_to_count() {
+        let mut samples = vec![0.5f32; 1000];
+        samples.extend([0.0; 2000]);
+        samples.extend([0.5; 1000]);
+        // Too short to count, and cut off by the end of the input.
+        samples.extend([0.0; 500]);
+
+        let (_, report) = FlacBuilder::from_interleaved(&samples, 1, 1000)
+            .detect_silence(-60.0, Duration::from_secs(1))
+            .build_with_report()
+            .unwrap();
+
+        assert_eq!(
+            report.silent_regions,
+            [SilentRegion {
+                start: 1000,
+                end: 3000
+            }]
+        );
+    }
+
+    #[test]
+    fn every_channel_has_to_be_quiet() {
+        let settings = SilenceSettings {
+            threshold_db: -60.0,
+            min_duration: Duration::from_millis(1),
+        };
+        let mut detector = SilenceDetector::new(settings, BpsLevel::Bps16, 1000);
+
+        detector.feed(&[0, 0, 0, 0, 0, 20_000, 0, 0], 2);
+
+        assert_eq!(
+            detector.finish(),
+            [
+                SilentRegion { start: 0, end: 2 },
+                SilentRegion { start: 3, end: 4 }
+            ]
+        );
+    }
+
+    #[test]
+    fn peaks_cover_each_run_of_samples() {
+        let mut collector = PeakCollector::new(2, 2, BpsLevel::Bps24);
+
+        collector.feed(&[1, -5, 3, 7, -2, 0], 2);
+        let peaks = collector.finish();
+
+        assert_eq!(peaks.bits, 24);
+        assert_eq!(
+            peaks.channels,
+            [vec![(1, 3), (-2, -2)], vec![(-5, 7), (0, 0)]]
+        );
+        assert_eq!(
+            peaks.to_json(),
+            r#"{"samples_per_peak":2,"bits":24,"channels":[[1,3,-2,-2],[-5,7,0,0]]}"#
+        );
+
+        let bytes = peaks.to_bytes();
+        assert_eq!(&bytes[..4], b"PEAK");
+        assert_eq!(bytes[4..14], [2, 0, 2, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(bytes.len(), 14 + 2 * 2 * 4);
+    }
+
+    #[test]
+    fn channel_stats_see_through_padding() {
+        // 16-bit audio on the left, shifted up to 24 bits; silence on the right.
+        let samples: Vec<i16> = (0..1000).flat_map(|i| [(i % 200 - 100) * 301, 0]).collect();
+
+        let (_, report) = FlacBuilder::from_interleaved(&samples, 2, 1000)
+            .bps(BpsLevel::Bps24)
+            .channel_stats()
+            .build_with_report()
+            .unwrap();
+
+        let [left, right] = report.channel_stats[..] else {
+            panic!("one entry per channel");
+        };
+        assert_eq!(left.used_bits, 16);
+        assert_eq!(left.peak, 30_100 << 8);
+        assert!(left.peak_dbfs < 0.0 && left.rms_dbfs < left.peak_dbfs);
+        assert_eq!(right.used_bits, 0);
+        assert_eq!(right.peak, 0);
+    }
+
+    #[test]
+    fn conversion_loss_measures_float_quantization() {
+        let mut samples: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.01).sin() * 0.7).collect();
+        samples[10] = 1.5;
+
+        let (_, report) = FlacBuilder::from_interleaved(&samples, 1, 1000)
+            .build_with_report()
+            .unwrap();
+        let loss = report.conversion_loss.unwrap();
+
+        assert_eq!(loss.bits, 16);
+        assert_eq!(loss.samples, 1000);
+        assert_eq!(loss.clipped_samples, 1);
+        assert!(loss.max_error_lsb > 0.0 && loss.max_error_lsb < 1.0);
+        assert!(loss.rms_error_lsb <= loss.max_error_lsb);
+
+        let (_, report) = FlacBuilder::from_interleaved(&[0i16; 1000], 1, 1000)
+            .build_with_report()
+            .unwrap();
+        assert_eq!(report.conversion_loss, None);
+    }
+}
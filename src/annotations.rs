@@ -0,0 +1,143 @@
+//! Time-stamped markers stored in an `APPLICATION` block.
+
+use std::time::Duration;
+
+use crate::{
+    picture::BeReader,
+    raw::{RawMetadata, BLOCK_TYPE_APPLICATION},
+    EncoderError,
+};
+
+/// Application ID of the block. It isn't in the registry at xiph.org; other software skips
+/// application blocks it doesn't know.
+pub const ANNOTATION_APPLICATION_ID: [u8; 4] = *b"ANOT";
+
+/// A note attached to a point in the audio, e.g. "take 3 starts here", see
+/// [`FlacBuilder::annotate`](crate::FlacBuilder::annotate).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    /// Position in samples per channel from the start of the stream.
+    pub position: u64,
+    pub text: String,
+}
+
+impl Annotation {
+    pub fn time(&self, sample_rate: u32) -> Duration {
+        Duration::from_secs_f64(self.position as f64 / sample_rate as f64)
+    }
+}
+
+/// Serializes to the body of the `APPLICATION` block after its ID: a `u32` count, then each
+/// annotation as a `u64` position, `u32` text length and UTF-8 text, all big-endian.
+pub(crate) fn to_block_data(annotations: &[Annotation]) -> Vec<u8> {
+    let mut out = vec![];
+
+    out.extend((annotations.len() as u32).to_be_bytes());
+    for annotation in annotations {
+        out.extend(annotation.position.to_be_bytes());
+        out.extend((annotation.text.len() as u32).to_be_bytes());
+        out.extend(annotation.text.as_bytes());
+    }
+
+    out
+}
+
+fn from_block_data(data: &[u8]) -> Result<Vec<Annotation>, EncoderError> {
+    let mut reader = BeReader { data, cursor: 0 };
+
+    let count = reader.u32()?;
+    let mut annotations = Vec::with_capacity(count.min(1024) as usize);
+
+    for _ in 0..count {
+        let position = reader.u64()?;
+        let length = reader.u32()? as usize;
+        let text = String::from_utf8_lossy(reader.bytes(length)?).to_string();
+
+        annotations.push(Annotation { position, text });
+    }
+
+    Ok(annotations)
+}
+
+/// The annotations of an in-memory FLAC stream, in the order they were added. Returns an empty
+/// list if the stream has none.
+pub fn read_annotations(bytes: &[u8]) -> Result<Vec<Annotation>, EncoderError> {
+    let metadata = RawMetadata::parse(bytes)?;
+
+    let Some(block) = metadata.blocks.iter().find(|b| {
+        b.block_type == BLOCK_TYPE_APPLICATION && b.data.starts_with(&ANNOTATION_APPLICATION_ID)
+    }) else {
+        return Ok(vec![]);
+    };
+
+    from_block_data(&block.data[4..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw::{write_block, BLOCK_TYPE_PADDING, BLOCK_TYPE_STREAMINFO};
+
+    fn annotations() -> Vec<Annotation> {
+        vec![
+            Annotation {
+                position: 0,
+                text: "start".to_string(),
+            },
+            Annotation {
+                position: 44_100 * 90,
+                text: "take 3 — best".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn block_data_round_trips() {
+        let data = to_block_data(&annotations());
+
+        assert_eq!(data[..4], 2u32.to_be_bytes());
+        assert_eq!(from_block_data(&data).unwrap(), annotations());
+        assert!(matches!(
+            from_block_data(&data[..data.len() - 1]),
+            Err(EncoderError::MalformedFlacData)
+        ));
+    }
+
+    #[test]
+    fn reads_the_annotation_block() {
+        let mut application = b"XXXX".to_vec();
+        application.extend(to_block_data(&[]));
+        let mut annotation = ANNOTATION_APPLICATION_ID.to_vec();
+        annotation.extend(to_block_data(&annotations()));
+
+        let mut bytes = b"fLaC".to_vec();
+        write_block(&mut bytes, BLOCK_TYPE_STREAMINFO, &[0; 34], false);
+        // Another application's block comes first and is skipped.
+        write_block(&mut bytes, BLOCK_TYPE_APPLICATION, &application, false);
+        write_block(&mut bytes, BLOCK_TYPE_APPLICATION, &annotation, false);
+        write_block(&mut bytes, BLOCK_TYPE_PADDING, &[0; 4], true);
+
+        assert_eq!(read_annotations(&bytes).unwrap(), annotations());
+    }
+
+    #[test]
+    fn time_is_position_over_sample_rate() {
+        assert_eq!(annotations()[1].time(44_100), Duration::from_secs(90));
+    }
+
+    #[test]
+    fn annotate_round_trips_through_an_encode() {
+        let bytes = crate::FlacBuilder::from_interleaved(&[0.0f32; 1000], 1, 1000)
+            .annotate(Duration::from_millis(250), "take 3 starts here")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            read_annotations(&bytes).unwrap(),
+            [Annotation {
+                position: 250,
+                text: "take 3 starts here".to_string(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! Writing a file under a temporary name and renaming it into place once complete.
+
+use std::path::{Path, PathBuf};
+
+use crate::EncoderError;
+
+/// Where to write the output for `destination` before renaming it there: in `temp_dir` if set,
+/// otherwise next to the destination. Fails if `temp_dir` is on another file system, which
+/// the rename can't cross.
+pub(crate) fn temp_path(
+    destination: &Path,
+    temp_dir: Option<&Path>,
+) -> Result<PathBuf, EncoderError> {
+    let destination_dir = match destination.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let dir = temp_dir.unwrap_or(destination_dir);
+
+    if !same_file_system(dir, destination_dir)? {
+        return Err(EncoderError::TempDirOnOtherFileSystem(dir.to_path_buf()));
+    }
+
+    let name = destination
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    // Hidden and unique per process so concurrent encodes of the same name don't collide.
+    Ok(dir.join(format!(".{name}.{}.tmp", std::process::id())))
+}
+
+#[cfg(unix)]
+fn same_file_system(a: &Path, b: &Path) -> Result<bool, EncoderError> {
+    use std::os::unix::fs::MetadataExt;
+
+    let device = |path: &Path| {
+        std::fs::metadata(path)
+            .map(|m| m.dev())
+            .map_err(EncoderError::Io)
+    };
+
+    Ok(device(a)? == device(b)?)
+}
+
+/// Without a portable device ID, a cross-device rename surfaces as an I/O error at the end.
+#[cfg(not(unix))]
+fn same_file_system(_a: &Path, _b: &Path) -> Result<bool, EncoderError> {
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FlacBuilder;
+
+    #[test]
+    fn the_destination_only_ever_holds_a_complete_file() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("atomic-{}.flac", std::process::id()));
+        let temp = temp_path(&path, Some(&dir)).unwrap();
+        assert_eq!(temp.parent(), Some(dir.as_path()));
+
+        let result = FlacBuilder::<f32>::from_interleaved(&[], 1, 44100)
+            .atomic_write()
+            .temp_dir(&dir)
+            .write_file(&path);
+        assert!(matches!(result, Err(EncoderError::NoData)));
+        assert!(!temp.exists());
+        assert!(!path.exists());
+
+        FlacBuilder::from_interleaved(&[0.0f32; 1024], 1, 44100)
+            .atomic_write()
+            .write_file(&path)
+            .unwrap();
+        let written = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&written[..4], b"fLaC");
+        assert!(!temp_path(&path, None).unwrap().exists());
+
+        // A non-empty directory in the way makes the rename fail.
+        std::fs::create_dir_all(path.join("occupied")).unwrap();
+        let result = FlacBuilder::from_interleaved(&[0.0f32; 1024], 1, 44100)
+            .atomic_write()
+            .write_file(&path);
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert!(matches!(result, Err(EncoderError::Io(_))));
+        assert!(!temp_path(&path, None).unwrap().exists());
+    }
+}
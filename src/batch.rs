@@ -0,0 +1,199 @@
+//! Encoding many files at once.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::{pipe, EncodeReport, EncoderError, FlacBuilder, FlacDecoder, WavReader};
+
+/// One file for [`encode_batch`] to encode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodeJob {
+    /// A WAV or FLAC file.
+    pub input: PathBuf,
+    pub output: PathBuf,
+}
+
+/// Result of [`encode_batch`], with one entry per job in the same order.
+#[derive(Debug, Default)]
+pub struct EncodeBatchReport {
+    pub jobs: Vec<JobResult>,
+}
+
+impl EncodeBatchReport {
+    /// Whether every job was encoded.
+    pub fn is_ok(&self) -> bool {
+        self.jobs.iter().all(|job| job.result.is_ok())
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &JobResult> {
+        self.jobs.iter().filter(|job| job.result.is_err())
+    }
+}
+
+#[derive(Debug)]
+pub struct JobResult {
+    pub job: EncodeJob,
+    pub result: Result<EncodeReport, EncoderError>,
+}
+
+/// Encodes every job using up to `parallelism` threads (`0` for one per CPU), each read a
+/// chunk at a time with [`FlacBuilder::from_source`]. WAV input is read with [`WavReader`];
+/// FLAC input is re-encoded with [`pipe`], keeping its tags and pictures.
+///
+/// `configure` sets what every job shares, e.g. the compression level. `per_job_tags` is then
+/// called for each job on the worker encoding it, for tags that come from the job itself,
+/// like a database ID looked up from the file name, and its tags are added after the shared
+/// ones.
+pub fn encode_batch(
+    jobs: &[EncodeJob],
+    parallelism: usize,
+    configure: impl Fn(FlacBuilder<'_, i32>) -> FlacBuilder<'_, i32> + Sync,
+    per_job_tags: impl Fn(&EncodeJob) -> Vec<(String, String)> + Sync,
+) -> EncodeBatchReport {
+    let parallelism = match parallelism {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+    .min(jobs.len());
+
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<JobResult>>> = Mutex::new(jobs.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..parallelism {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(job) = jobs.get(i) else {
+                    break;
+                };
+
+                let result = encode_job(job, &configure, &per_job_tags);
+
+                results.lock().unwrap()[i] = Some(JobResult {
+                    job: job.clone(),
+                    result,
+                });
+            });
+        }
+    });
+
+    EncodeBatchReport {
+        jobs: results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect(),
+    }
+}
+
+fn encode_job(
+    job: &EncodeJob,
+    configure: impl Fn(FlacBuilder<'_, i32>) -> FlacBuilder<'_, i32>,
+    per_job_tags: impl Fn(&EncodeJob) -> Vec<(String, String)>,
+) -> Result<EncodeReport, EncoderError> {
+    let mut input = BufReader::new(File::open(&job.input).map_err(EncoderError::Io)?);
+    let is_wav = input
+        .fill_buf()
+        .map_err(EncoderError::Io)?
+        .starts_with(b"RIFF");
+
+    let finish = |builder| {
+        per_job_tags(job)
+            .iter()
+            .fold(configure(builder), |builder, (key, value)| {
+                builder.vorbis_comment(key, value)
+            })
+    };
+
+    if !is_wav {
+        return pipe(FlacDecoder::new(input)?, &job.output, finish);
+    }
+
+    finish(FlacBuilder::from_source(WavReader::new(input)?)).write_file_with_report(&job.output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{read_comments, AudioSource};
+
+    #[test]
+    fn encodes_wav_and_flac_with_per_job_tags() {
+        let dir = std::env::temp_dir().join(format!("batch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let samples: Vec<f32> = (0..20_000).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+        let flac = FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .title("Sine")
+            .build()
+            .unwrap();
+        let mut wav = vec![];
+        FlacDecoder::new(&flac[..])
+            .unwrap()
+            .write_wav(&mut wav)
+            .unwrap();
+        fs::write(dir.join("a.wav"), &wav).unwrap();
+        fs::write(dir.join("b.flac"), &flac).unwrap();
+
+        let jobs: Vec<EncodeJob> = ["a.wav", "b.flac", "missing.wav"]
+            .iter()
+            .map(|name| EncodeJob {
+                input: dir.join(name),
+                output: dir.join(name).with_extension("out.flac"),
+            })
+            .collect();
+
+        let report = encode_batch(
+            &jobs,
+            2,
+            |builder| builder.artist("Band"),
+            |job| {
+                let stem = job.input.file_stem().unwrap().to_string_lossy();
+                vec![("SOURCE".to_string(), stem.into_owned())]
+            },
+        );
+
+        assert!(!report.is_ok());
+        let failed: Vec<_> = report.failed().map(|job| &job.job).collect();
+        assert_eq!(failed, [&jobs[2]]);
+        assert!(matches!(report.jobs[2].result, Err(EncoderError::Io(_))));
+
+        let a = fs::read(&jobs[0].output).unwrap();
+        let b = fs::read(&jobs[1].output).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let tag = |key: &str, value: &str| (key.to_string(), value.to_string());
+        assert_eq!(
+            read_comments(&a).unwrap(),
+            [tag("ARTIST", "Band"), tag("SOURCE", "a")]
+        );
+        assert_eq!(
+            read_comments(&b).unwrap(),
+            [
+                tag("TITLE", "Sine"),
+                tag("ARTIST", "Band"),
+                tag("SOURCE", "b")
+            ]
+        );
+
+        let decode = |bytes: &[u8]| {
+            let mut samples = vec![0; 1 << 16];
+            let n = FlacDecoder::new(bytes).unwrap().fill(&mut samples).unwrap();
+            samples.truncate(n);
+            samples
+        };
+        assert_eq!(decode(&a), decode(&flac));
+        assert_eq!(decode(&b), decode(&flac));
+    }
+}
@@ -0,0 +1,245 @@
+//! Audio passed between the parts of the crate.
+
+use std::{io::Read, time::Duration};
+
+use crate::{EncoderError, FlacDecoder, FlacReader};
+
+/// The order of the bytes of each sample in raw PCM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    #[default]
+    LittleEndian,
+    BigEndian,
+}
+
+/// A run of interleaved integer samples with their format. This is what the encoder takes with
+/// [`FlacBuilder::from_block`](crate::FlacBuilder::from_block), so audio can be handed on
+/// without converting to floats and back.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AudioBlock {
+    pub channels: usize,
+    /// Bits per sample; every sample fits in this many bits, signed.
+    pub bps: u32,
+    pub sample_rate: u32,
+    /// Interleaved, e.g. LRLRLR.
+    pub samples: Vec<i32>,
+}
+
+impl AudioBlock {
+    /// Samples per channel.
+    pub fn frames(&self) -> usize {
+        self.samples.len().checked_div(self.channels).unwrap_or(0)
+    }
+
+    /// The samples of one channel.
+    pub fn channel(&self, channel: usize) -> impl Iterator<Item = i32> + '_ {
+        self.samples
+            .iter()
+            .skip(channel)
+            .step_by(self.channels.max(1))
+            .copied()
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames() as f64 / self.sample_rate.max(1) as f64)
+    }
+
+    /// The smallest bps FLAC can be asked to encode (16, 20 or 24) that holds every sample
+    /// exactly, e.g. 16 for 16-bit audio padded to 24 bits. `None` if even 24 would lose bits.
+    pub fn lossless_bps(&self) -> Option<u32> {
+        lossless_bps(self.samples.iter().copied(), self.bps)
+    }
+
+    /// The samples as headerless signed PCM of 8, 16, 24 or 32 bits in `order`, e.g. for a C
+    /// library or a `.raw` file. Samples are shifted from the block's bps to `bits`, so
+    /// narrowing drops the low bits. Fails with [`EncoderError::InvalidSampleType`] for other
+    /// widths.
+    pub fn export_pcm(&self, order: ByteOrder, bits: u32) -> Result<Vec<u8>, EncoderError> {
+        if !matches!(bits, 8 | 16 | 24 | 32) {
+            return Err(EncoderError::InvalidSampleType);
+        }
+        let width = bits as usize / 8;
+
+        let mut bytes = Vec::with_capacity(self.samples.len() * width);
+        for &sample in &self.samples {
+            let sample = if bits >= self.bps {
+                sample << (bits - self.bps)
+            } else {
+                sample >> (self.bps - bits)
+            };
+
+            match order {
+                ByteOrder::LittleEndian => bytes.extend(&sample.to_le_bytes()[..width]),
+                ByteOrder::BigEndian => bytes.extend(&sample.to_be_bytes()[4 - width..]),
+            }
+        }
+
+        Ok(bytes)
+    }
+}
+
+impl<R: Read> FlacDecoder<R> {
+    /// Decodes the rest of the stream as headerless PCM, see [`AudioBlock::export_pcm`].
+    pub fn export_pcm(self, order: ByteOrder, bits: u32) -> Result<Vec<u8>, EncoderError> {
+        AudioBlock::from_source(self)?.export_pcm(order, bits)
+    }
+}
+
+impl FlacReader {
+    /// The decoded audio as headerless PCM, see [`AudioBlock::export_pcm`].
+    pub fn export_pcm(&self, order: ByteOrder, bits: u32) -> Result<Vec<u8>, EncoderError> {
+        self.audio.export_pcm(order, bits)
+    }
+}
+
+/// Like [`AudioBlock::lossless_bps`] for any samples that fit in `bps` bits.
+pub(crate) fn lossless_bps(samples: impl IntoIterator<Item = i32>, bps: u32) -> Option<u32> {
+    let mut trailing_zeros = u32::MAX;
+    let mut min = 0;
+    let mut max = 0;
+
+    for sample in samples {
+        if sample != 0 {
+            trailing_zeros = trailing_zeros.min(sample.trailing_zeros());
+        }
+        min = min.min(sample);
+        max = max.max(sample);
+    }
+
+    [16, 20, 24].into_iter().find(|&target| {
+        // Going down to `target` shifts out the low bits, which must all be zero.
+        let shift = bps.saturating_sub(target);
+        let limit = 1 << (target - 1);
+
+        (shift == 0 || trailing_zeros >= shift)
+            && (min >> shift) >= -limit
+            && (max >> shift) < limit
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_range, FlacBuilder};
+
+    fn block(bps: u32) -> AudioBlock {
+        AudioBlock {
+            channels: 2,
+            bps,
+            sample_rate: 8000,
+            samples: (0..8000).map(|i| (i % 200) - 100).collect(),
+        }
+    }
+
+    #[test]
+    fn frames_channels_and_duration() {
+        let block = block(16);
+
+        assert_eq!(block.frames(), 4000);
+        assert_eq!(block.duration(), Duration::from_millis(500));
+        assert_eq!(
+            block.channel(1).take(3).collect::<Vec<_>>(),
+            [-99, -97, -95]
+        );
+        assert_eq!(AudioBlock::default().frames(), 0);
+    }
+
+    #[test]
+    fn encodes_at_the_block_bps_or_the_next_one_up() {
+        for (bps, encoded_bps, shift) in [(24, 24, 0), (12, 16, 4)] {
+            let block = block(bps);
+            let bytes = FlacBuilder::from_block(&block).build().unwrap();
+
+            let decoded = decode_range(std::io::Cursor::new(bytes), 0..u64::MAX).unwrap();
+            assert_eq!(decoded.bps, encoded_bps);
+            assert_eq!(decoded.sample_rate, 8000);
+            let expected: Vec<i32> = block.samples.iter().map(|s| s << shift).collect();
+            assert_eq!(decoded.samples, expected);
+        }
+    }
+
+    #[test]
+    fn lossless_bps_ignores_padding_bits() {
+        let padded = AudioBlock {
+            samples: block(16).samples.iter().map(|s| s << 8).collect(),
+            ..block(24)
+        };
+        assert_eq!(padded.lossless_bps(), Some(16));
+
+        let mut twenty = padded.clone();
+        twenty.samples[7] |= 1 << 4;
+        assert_eq!(twenty.lossless_bps(), Some(20));
+
+        let mut full = padded.clone();
+        full.samples[7] |= 1;
+        assert_eq!(full.lossless_bps(), Some(24));
+
+        let clipped = AudioBlock {
+            samples: vec![40_000, -40_000],
+            ..block(16)
+        };
+        assert_eq!(clipped.lossless_bps(), Some(20));
+        assert_eq!(block(12).lossless_bps(), Some(16));
+    }
+
+    #[test]
+    fn bps_auto_encodes_padded_audio_at_its_real_bps() {
+        let padded = AudioBlock {
+            samples: block(16).samples.iter().map(|s| s << 8).collect(),
+            ..block(24)
+        };
+        let bytes = FlacBuilder::from_block(&padded).bps_auto().build().unwrap();
+
+        let decoded = decode_range(std::io::Cursor::new(bytes), 0..u64::MAX).unwrap();
+        assert_eq!(decoded.bps, 16);
+        assert_eq!(decoded.samples, block(16).samples);
+    }
+
+    #[test]
+    fn export_pcm_shifts_to_the_width_in_either_order() {
+        let block = AudioBlock {
+            channels: 1,
+            bps: 16,
+            sample_rate: 8000,
+            samples: vec![0x1234, -2],
+        };
+
+        assert_eq!(
+            block.export_pcm(ByteOrder::LittleEndian, 16).unwrap(),
+            [0x34, 0x12, 0xfe, 0xff]
+        );
+        assert_eq!(
+            block.export_pcm(ByteOrder::BigEndian, 24).unwrap(),
+            [0x12, 0x34, 0x00, 0xff, 0xfe, 0x00]
+        );
+        assert_eq!(
+            block.export_pcm(ByteOrder::LittleEndian, 8).unwrap(),
+            [0x12, 0xff]
+        );
+        assert!(matches!(
+            block.export_pcm(ByteOrder::LittleEndian, 20),
+            Err(EncoderError::InvalidSampleType)
+        ));
+    }
+
+    #[test]
+    fn the_decoder_exports_what_it_decodes() {
+        let samples: Vec<i16> = (0..2000).map(|i| i * 7 - 7000).collect();
+        let bytes = FlacBuilder::from_interleaved(&samples, 2, 8000)
+            .build()
+            .unwrap();
+
+        let pcm = FlacDecoder::new(&bytes[..])
+            .unwrap()
+            .export_pcm(ByteOrder::LittleEndian, 16)
+            .unwrap();
+        let expected: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        assert_eq!(pcm, expected);
+
+        let reader = FlacReader::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            reader.export_pcm(ByteOrder::LittleEndian, 16).unwrap(),
+            expected
+        );
+    }
+}
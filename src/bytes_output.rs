@@ -0,0 +1,68 @@
+//! Encoded output as [`bytes::Bytes`].
+
+use bytes::Bytes;
+
+use crate::{raw::RawMetadata, EncoderError, StreamInfo};
+
+/// An encoded stream from [`FlacBuilder::build_bytes`](crate::FlacBuilder::build_bytes). The
+/// slices share the same buffer, so e.g. a server can send patched metadata followed by the
+/// untouched audio without copying either.
+#[derive(Debug, Clone)]
+pub struct FlacBytes {
+    bytes: Bytes,
+    audio_offset: usize,
+    stream_info: StreamInfo,
+}
+
+impl FlacBytes {
+    pub(crate) fn new(bytes: Bytes) -> Result<Self, EncoderError> {
+        let audio_offset = RawMetadata::parse(&bytes)?.audio_offset;
+        let stream_info = StreamInfo::from_bytes(&bytes)?;
+
+        Ok(FlacBytes {
+            bytes,
+            audio_offset,
+            stream_info,
+        })
+    }
+
+    /// The `fLaC` marker and every metadata block.
+    pub fn header(&self) -> Bytes {
+        self.bytes.slice(..self.audio_offset)
+    }
+
+    pub fn stream_info(&self) -> StreamInfo {
+        self.stream_info
+    }
+
+    /// The audio frames.
+    pub fn audio(&self) -> Bytes {
+        self.bytes.slice(self.audio_offset..)
+    }
+
+    /// The whole stream.
+    pub fn into_bytes(self) -> Bytes {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::FlacBuilder;
+
+    #[test]
+    fn splits_at_the_first_frame() {
+        let samples: Vec<f32> = (0..10_000).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+        let builder = || FlacBuilder::from_interleaved(&samples, 1, 44100).padding(100);
+
+        let bytes = builder().build_bytes().unwrap();
+        let built = builder().build().unwrap();
+
+        assert_eq!(&bytes.header()[..4], b"fLaC");
+        // Frames start with the 14-bit sync code.
+        assert_eq!(bytes.audio()[0], 0xff);
+        assert_eq!(bytes.audio()[1] & 0xfe, 0xf8);
+        assert_eq!([bytes.header(), bytes.audio()].concat(), built);
+        assert_eq!(bytes.into_bytes(), built);
+    }
+}
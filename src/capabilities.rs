@@ -0,0 +1,112 @@
+//! What the linked libFLAC can do, judged from the version it reports. `libflac-sys` may link a
+//! system libFLAC older than the one it bundles, and older versions silently ignore or reject
+//! settings they don't know. Nothing here is resolved dynamically: a function the crate calls
+//! that the library lacks, like `FLAC__stream_encoder_set_limit_min_bitrate`, is a link error
+//! when building, or a load error if a shared libFLAC is swapped for an older one afterwards.
+
+use crate::{libflac_version, EncoderError};
+
+/// libFLAC features that not every version has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LibFlacFeature {
+    /// Encoding on several threads, from 1.5.0. Only reported: the builder can't set it, as not
+    /// every `libflac-sys` version this crate accepts binds it.
+    Threads,
+    /// 32 bits per sample, from 1.4.0, for [`BpsLevel::Bps32`](crate::BpsLevel::Bps32).
+    Bps32,
+    /// [`FlacBuilder::limit_min_bitrate`](crate::FlacBuilder::limit_min_bitrate), from 1.4.0.
+    /// The crate calls the setter directly, so a library without it fails to link rather than
+    /// failing this check.
+    LimitMinBitrate,
+    /// The `subdivide_tukey` apodization, from 1.4.0. Older versions skip it, falling back to
+    /// the other apodizations given or none.
+    SubdivideTukey,
+}
+
+impl LibFlacFeature {
+    /// The first libFLAC version with the feature.
+    pub fn since(self) -> (u32, u32, u32) {
+        match self {
+            LibFlacFeature::Threads => (1, 5, 0),
+            LibFlacFeature::Bps32
+            | LibFlacFeature::LimitMinBitrate
+            | LibFlacFeature::SubdivideTukey => (1, 4, 0),
+        }
+    }
+}
+
+/// See [`libflac_capabilities`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LibFlacCapabilities {
+    /// `(major, minor, patch)`, `(0, 0, 0)` if the version string couldn't be read.
+    pub version: (u32, u32, u32),
+}
+
+impl LibFlacCapabilities {
+    pub fn supports(&self, feature: LibFlacFeature) -> bool {
+        self.version >= feature.since()
+    }
+
+    /// Fails with [`EncoderError::UnsupportedByLibFlac`] unless `feature` is supported.
+    pub fn require(&self, feature: LibFlacFeature) -> Result<(), EncoderError> {
+        if self.supports(feature) {
+            Ok(())
+        } else {
+            Err(EncoderError::UnsupportedByLibFlac {
+                feature,
+                version: libflac_version(),
+            })
+        }
+    }
+}
+
+/// Features of the libFLAC linked at runtime, read from its version string. This only reports
+/// the version; see the module docs for functions the library lacks.
+pub fn libflac_capabilities() -> LibFlacCapabilities {
+    let version = libflac_version();
+    let mut parts = version.split('.').map(|part| {
+        part.chars()
+            .take_while(char::is_ascii_digit)
+            .collect::<String>()
+            .parse()
+            .unwrap_or(0)
+    });
+
+    LibFlacCapabilities {
+        version: (
+            parts.next().unwrap_or(0),
+            parts.next().unwrap_or(0),
+            parts.next().unwrap_or(0),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn features_need_their_first_version() {
+        let old = LibFlacCapabilities { version: (1, 3, 4) };
+        assert!(!old.supports(LibFlacFeature::Bps32));
+        assert!(matches!(
+            old.require(LibFlacFeature::SubdivideTukey),
+            Err(EncoderError::UnsupportedByLibFlac {
+                feature: LibFlacFeature::SubdivideTukey,
+                ..
+            })
+        ));
+
+        let new = LibFlacCapabilities { version: (1, 4, 3) };
+        assert!(new.supports(LibFlacFeature::LimitMinBitrate));
+        assert!(!new.supports(LibFlacFeature::Threads));
+        new.require(LibFlacFeature::Bps32).unwrap();
+    }
+
+    #[test]
+    fn reads_the_linked_version() {
+        let capabilities = libflac_capabilities();
+        assert!(libflac_version().starts_with(&format!("{}.", capabilities.version.0)));
+        assert!(capabilities.version >= (1, 3, 0));
+    }
+}
@@ -0,0 +1,234 @@
+//! C interface to the builder, built with `cargo cinstall --features capi`. Functions return 0
+//! on success and -1 on failure, after which `flac_encoder_last_error` describes the failure
+//! and `flac_encoder_last_error_code` identifies it.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, CStr, CString},
+    ptr::null,
+    slice::from_raw_parts,
+};
+
+use crate::{self_test, BpsLevel, CompressionLevel, EncoderError, FlacBuilder};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<(CString, u32)>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: EncoderError) -> c_int {
+    let message = CString::new(error.to_string()).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some((message, error.code())));
+    -1
+}
+
+fn bps_level(bps: u32) -> Option<BpsLevel> {
+    match bps {
+        16 => Some(BpsLevel::Bps16),
+        20 => Some(BpsLevel::Bps20),
+        24 => Some(BpsLevel::Bps24),
+        32 => Some(BpsLevel::Bps32),
+        _ => None,
+    }
+}
+
+unsafe fn builder<'a>(
+    samples: *const f32,
+    len: usize,
+    channels: u32,
+    sample_rate: u32,
+    bps: u32,
+    compression_level: u32,
+) -> Result<FlacBuilder<'a, f32>, EncoderError> {
+    if samples.is_null() || channels == 0 {
+        return Err(EncoderError::NoData);
+    }
+
+    let bps = bps_level(bps).ok_or(EncoderError::InvalidSampleType)?;
+
+    Ok(
+        FlacBuilder::from_interleaved(from_raw_parts(samples, len), channels as usize, sample_rate)
+            .bps(bps)
+            .compression_level(CompressionLevel::try_from(compression_level)?),
+    )
+}
+
+/// Encodes `len` interleaved `float` samples in [-1.0, 1.0] into memory. `bps` is 16, 20, 24
+/// or 32. On success `*out` and `*out_len` hold the stream, which must be released with
+/// `flac_encoder_free`.
+///
+/// # Safety
+///
+/// `samples` must point to `len` floats and `out` and `out_len` must be valid for writes. Fails
+/// without encoding if either of them is NULL.
+#[no_mangle]
+pub unsafe extern "C" fn flac_encoder_encode_f32(
+    samples: *const f32,
+    len: usize,
+    channels: u32,
+    sample_rate: u32,
+    bps: u32,
+    compression_level: u32,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    if out.is_null() {
+        return set_last_error(EncoderError::NullArgument("out"));
+    }
+    if out_len.is_null() {
+        return set_last_error(EncoderError::NullArgument("out_len"));
+    }
+
+    let encoded = builder(samples, len, channels, sample_rate, bps, compression_level)
+        .and_then(FlacBuilder::build);
+
+    match encoded {
+        Ok(data) => {
+            let data = data.into_boxed_slice();
+            *out_len = data.len();
+            *out = Box::into_raw(data) as *mut u8;
+            0
+        }
+        Err(e) => set_last_error(e),
+    }
+}
+
+/// Like `flac_encoder_encode_f32` but writes the stream to the file at `path`, a UTF-8 string.
+///
+/// # Safety
+///
+/// `samples` must point to `len` floats and `path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn flac_encoder_encode_f32_file(
+    samples: *const f32,
+    len: usize,
+    channels: u32,
+    sample_rate: u32,
+    bps: u32,
+    compression_level: u32,
+    path: *const c_char,
+) -> c_int {
+    if path.is_null() {
+        return set_last_error(EncoderError::NullCharInPath);
+    }
+
+    let path = CStr::from_ptr(path).to_string_lossy().into_owned();
+
+    let result = builder(samples, len, channels, sample_rate, bps, compression_level)
+        .and_then(|builder| builder.write_file(path));
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => set_last_error(e),
+    }
+}
+
+/// Runs `self_test`, for checking at startup that the library works where it was deployed.
+#[no_mangle]
+pub extern "C" fn flac_encoder_self_test() -> c_int {
+    match self_test() {
+        Ok(()) => 0,
+        Err(e) => set_last_error(e),
+    }
+}
+
+/// Releases a stream returned by `flac_encoder_encode_f32`.
+///
+/// # Safety
+///
+/// `data` and `len` must come from one successful `flac_encoder_encode_f32` call and not have
+/// been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn flac_encoder_free(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
+
+/// Describes the last failure on this thread, or NULL if there hasn't been one. The string is
+/// valid until the next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn flac_encoder_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(null(), |(message, _)| message.as_ptr())
+    })
+}
+
+/// `EncoderError::code` of the last failure on this thread, or 0 if there hasn't been one.
+#[no_mangle]
+pub extern "C" fn flac_encoder_last_error_code() -> u32 {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(0, |(_, code)| *code))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr::null_mut;
+
+    use super::*;
+
+    #[test]
+    fn encodes_into_memory_and_frees_it() {
+        let samples = [0.25f32; 2048];
+        let mut out = null_mut();
+        let mut out_len = 0;
+
+        let status = unsafe {
+            flac_encoder_encode_f32(
+                samples.as_ptr(),
+                2048,
+                2,
+                44100,
+                16,
+                5,
+                &mut out,
+                &mut out_len,
+            )
+        };
+        assert_eq!(status, 0);
+
+        let stream = unsafe { from_raw_parts(out, out_len) };
+        assert_eq!(&stream[..4], b"fLaC");
+        unsafe { flac_encoder_free(out, out_len) };
+    }
+
+    #[test]
+    fn failures_set_the_last_error() {
+        let samples = [0.25f32; 16];
+        let mut out = null_mut();
+        let mut out_len = 0;
+
+        let status = unsafe {
+            flac_encoder_encode_f32(
+                samples.as_ptr(),
+                16,
+                2,
+                44100,
+                12,
+                5,
+                &mut out,
+                &mut out_len,
+            )
+        };
+        assert_eq!(status, -1);
+
+        let error = unsafe { CStr::from_ptr(flac_encoder_last_error()) };
+        assert_eq!(error.to_str().unwrap(), "unsupported sample type or bps");
+        assert_eq!(
+            flac_encoder_last_error_code(),
+            EncoderError::InvalidSampleType.code()
+        );
+
+        let status = unsafe {
+            flac_encoder_encode_f32(samples.as_ptr(), 16, 2, 44100, 16, 5, &mut out, null_mut())
+        };
+        assert_eq!(status, -1);
+        let error = unsafe { CStr::from_ptr(flac_encoder_last_error()) };
+        assert_eq!(error.to_str().unwrap(), "`out_len` is NULL");
+    }
+
+    #[test]
+    fn self_test_passes() {
+        assert_eq!(flac_encoder_self_test(), 0);
+    }
+}
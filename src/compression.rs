@@ -0,0 +1,293 @@
+//! Compression presets and the encoder settings behind them.
+
+use std::ffi::CString;
+
+use libflac_sys::*;
+
+use crate::{libflac_capabilities, EncoderError, LibFlacFeature};
+
+/// libFLAC's compression presets, from fastest (`L0`) to smallest (`L8`), or a custom set of
+/// encoder settings. [`settings`](Self::settings) shows what each preset stands for, which is
+/// also a good starting point for `Custom`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum CompressionLevel {
+    L0,
+    L1,
+    L2,
+    L3,
+    L4,
+    #[default]
+    L5,
+    L6,
+    L7,
+    L8,
+    Custom(AdvancedSettings),
+}
+
+/// The encoder settings a [`CompressionLevel`] preset sets, see
+/// [libFLAC's documentation](https://xiph.org/flac/api/group__flac__stream__encoder.html#gaacc01aab02849119f929b8516420fcd3)
+/// for what each does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdvancedSettings {
+    /// Samples per channel in each frame.
+    pub block_size: u32,
+    /// Try coding stereo as mid and side channels. Ignored for other channel counts.
+    pub mid_side_stereo: bool,
+    /// Only re-check whether mid/side pays off every few frames.
+    pub loose_mid_side_stereo: bool,
+    /// Window functions for LPC analysis, separated by `;`, e.g. `"tukey(5e-1)"`.
+    pub apodization: String,
+    /// `0` for fixed predictors only.
+    pub max_lpc_order: u32,
+    /// `0` to pick it from the block size.
+    pub qlp_coeff_precision: u32,
+    pub qlp_coeff_precision_search: bool,
+    pub exhaustive_model_search: bool,
+    pub min_residual_partition_order: u32,
+    pub max_residual_partition_order: u32,
+}
+
+impl CompressionLevel {
+    /// The presets in order, e.g. to try each one.
+    pub const PRESETS: [CompressionLevel; 9] = [
+        CompressionLevel::L0,
+        CompressionLevel::L1,
+        CompressionLevel::L2,
+        CompressionLevel::L3,
+        CompressionLevel::L4,
+        CompressionLevel::L5,
+        CompressionLevel::L6,
+        CompressionLevel::L7,
+        CompressionLevel::L8,
+    ];
+
+    /// The preset number, `None` for `Custom`.
+    pub fn preset(&self) -> Option<u32> {
+        Self::PRESETS
+            .iter()
+            .position(|preset| preset == self)
+            .map(|i| i as u32)
+    }
+
+    /// What this level sets, mirroring libFLAC's preset table.
+    pub fn settings(&self) -> AdvancedSettings {
+        let preset = match self {
+            CompressionLevel::Custom(settings) => return settings.clone(),
+            preset => preset.preset().unwrap_or_default(),
+        };
+
+        // (mid/side, loose mid/side, max LPC order, max partition order, apodization)
+        let (mid_side, loose, max_lpc_order, max_partition_order, apodization) = match preset {
+            0 => (false, false, 0, 3, "tukey(5e-1)"),
+            1 => (true, true, 0, 3, "tukey(5e-1)"),
+            2 => (true, false, 0, 3, "tukey(5e-1)"),
+            3 => (false, false, 6, 4, "tukey(5e-1)"),
+            4 => (true, true, 8, 4, "tukey(5e-1)"),
+            5 => (true, false, 8, 5, "tukey(5e-1)"),
+            6 => (true, false, 8, 6, "subdivide_tukey(2)"),
+            7 => (true, false, 12, 6, "subdivide_tukey(2)"),
+            _ => (true, false, 12, 6, "subdivide_tukey(3)"),
+        };
+
+        AdvancedSettings {
+            block_size: if preset < 3 { 1152 } else { 4096 },
+            mid_side_stereo: mid_side,
+            loose_mid_side_stereo: loose,
+            apodization: apodization.to_string(),
+            max_lpc_order,
+            qlp_coeff_precision: 0,
+            qlp_coeff_precision_search: false,
+            exhaustive_model_search: false,
+            min_residual_partition_order: 0,
+            max_residual_partition_order: max_partition_order,
+        }
+    }
+
+    /// Settings for material above 48 kHz, where libFLAC's presets (tuned for 44.1 and 48 kHz)
+    /// leave ratio on the table: frames of the same duration rather than the same sample
+    /// count, higher LPC orders to model the mostly empty top octaves, and finer residual
+    /// partitions. Still within the streamable subset, which allows larger blocks and orders
+    /// above 48 kHz. At 48 kHz and below this is [`L8`](Self::L8). The `hi_res_bench` example
+    /// compares the two on your own files.
+    pub fn hi_res(sample_rate: u32) -> Self {
+        let (block_size, max_lpc_order) = match sample_rate {
+            0..=48000 => return CompressionLevel::L8,
+            48001..=96000 => (8192, 16),
+            _ => (16384, 24),
+        };
+
+        CompressionLevel::Custom(AdvancedSettings {
+            block_size,
+            max_lpc_order,
+            max_residual_partition_order: 8,
+            ..CompressionLevel::L8.settings()
+        })
+    }
+
+    /// Presets go through libFLAC's own preset so they match its version exactly.
+    pub(crate) unsafe fn apply(
+        &self,
+        encoder: *mut FLAC__StreamEncoder,
+    ) -> Result<(), EncoderError> {
+        let Some(preset) = self.preset() else {
+            return self.settings().apply(encoder);
+        };
+
+        if 0 == FLAC__stream_encoder_set_compression_level(encoder, preset) {
+            return Err(EncoderError::InvalidCompressionLevel);
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<u32> for CompressionLevel {
+    type Error = EncoderError;
+
+    fn try_from(level: u32) -> Result<Self, Self::Error> {
+        Self::PRESETS
+            .get(level as usize)
+            .cloned()
+            .ok_or(EncoderError::InvalidCompressionLevel)
+    }
+}
+
+impl AdvancedSettings {
+    unsafe fn apply(&self, encoder: *mut FLAC__StreamEncoder) -> Result<(), EncoderError> {
+        if self.apodization.contains("subdivide_tukey") {
+            libflac_capabilities().require(LibFlacFeature::SubdivideTukey)?;
+        }
+
+        let Ok(apodization) = CString::new(self.apodization.as_str()) else {
+            return Err(EncoderError::InvalidCompressionLevel);
+        };
+
+        let all_set = [
+            FLAC__stream_encoder_set_blocksize(encoder, self.block_size),
+            FLAC__stream_encoder_set_do_mid_side_stereo(
+                encoder,
+                self.mid_side_stereo as FLAC__bool,
+            ),
+            FLAC__stream_encoder_set_loose_mid_side_stereo(
+                encoder,
+                self.loose_mid_side_stereo as FLAC__bool,
+            ),
+            FLAC__stream_encoder_set_apodization(encoder, apodization.as_ptr()),
+            FLAC__stream_encoder_set_max_lpc_order(encoder, self.max_lpc_order),
+            FLAC__stream_encoder_set_qlp_coeff_precision(encoder, self.qlp_coeff_precision),
+            FLAC__stream_encoder_set_do_qlp_coeff_prec_search(
+                encoder,
+                self.qlp_coeff_precision_search as FLAC__bool,
+            ),
+            FLAC__stream_encoder_set_do_exhaustive_model_search(
+                encoder,
+                self.exhaustive_model_search as FLAC__bool,
+            ),
+            FLAC__stream_encoder_set_min_residual_partition_order(
+                encoder,
+                self.min_residual_partition_order,
+            ),
+            FLAC__stream_encoder_set_max_residual_partition_order(
+                encoder,
+                self.max_residual_partition_order,
+            ),
+        ];
+
+        if all_set.contains(&0) {
+            return Err(EncoderError::InvalidCompressionLevel);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        decoder::FlacDecoder, read_comments, AudioSource, FlacBuilder, IntoSample, StreamInfo,
+    };
+
+    fn sine() -> Vec<f32> {
+        (0..20_000).map(|i| (i as f32 / 30.0).sin() * 0.5).collect()
+    }
+
+    #[test]
+    fn presets_round_trip_through_u32() {
+        for (i, preset) in CompressionLevel::PRESETS.iter().enumerate() {
+            assert_eq!(preset.preset(), Some(i as u32));
+            assert_eq!(CompressionLevel::try_from(i as u32).unwrap(), *preset);
+        }
+
+        assert!(matches!(
+            CompressionLevel::try_from(9),
+            Err(EncoderError::InvalidCompressionLevel)
+        ));
+        assert_eq!(CompressionLevel::default(), CompressionLevel::L5);
+    }
+
+    #[test]
+    fn custom_settings_encode_losslessly() {
+        let samples = sine();
+        let settings = AdvancedSettings {
+            block_size: 576,
+            apodization: "hann".to_string(),
+            ..CompressionLevel::L8.settings()
+        };
+        assert_eq!(CompressionLevel::Custom(settings.clone()).preset(), None);
+
+        let bytes = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .compression_level(CompressionLevel::Custom(settings))
+            .encoder_settings_tag()
+            .build()
+            .unwrap();
+
+        let mut decoded = vec![0; samples.len()];
+        FlacDecoder::new(&bytes[..])
+            .unwrap()
+            .fill(&mut decoded)
+            .unwrap();
+        let expected: Vec<i32> = samples.iter().map(|s| s.to_i16() as i32).collect();
+        assert_eq!(decoded, expected);
+
+        let settings = &read_comments(&bytes).unwrap()[0].1;
+        assert!(settings.starts_with("compression=custom blocksize=576 apodization=hann "));
+    }
+
+    #[test]
+    fn hi_res_scales_the_block_size_with_the_rate() {
+        assert_eq!(CompressionLevel::hi_res(48000), CompressionLevel::L8);
+        assert_eq!(CompressionLevel::hi_res(88200).settings().block_size, 8192);
+
+        let samples: Vec<f32> = (0..40_000).map(|i| (i as f32 / 90.0).sin() * 0.5).collect();
+        let bytes = FlacBuilder::from_interleaved(&samples, 1, 192000)
+            .compression_level(CompressionLevel::hi_res(192000))
+            .build()
+            .unwrap();
+        assert_eq!(
+            StreamInfo::from_bytes(&bytes).unwrap().max_block_size,
+            16384
+        );
+
+        let mut decoded = vec![0; samples.len()];
+        FlacDecoder::new(&bytes[..])
+            .unwrap()
+            .fill(&mut decoded)
+            .unwrap();
+        let expected: Vec<i32> = samples.iter().map(|s| s.to_i16() as i32).collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn invalid_custom_settings_are_rejected() {
+        let settings = AdvancedSettings {
+            apodization: "hann\0".to_string(),
+            ..CompressionLevel::L0.settings()
+        };
+        let result = FlacBuilder::from_interleaved(&sine(), 1, 44100)
+            .compression_level(CompressionLevel::Custom(settings))
+            .build();
+
+        assert!(matches!(result, Err(EncoderError::InvalidCompressionLevel)));
+    }
+}
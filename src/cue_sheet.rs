@@ -0,0 +1,370 @@
+//! `CUESHEET` metadata blocks, for the track layout of a CD rip.
+
+use std::{
+    ffi::{c_char, CStr},
+    ptr::null,
+};
+
+use libflac_sys::*;
+
+use crate::{DiscToc, EncoderError};
+
+/// Samples per channel in one CD frame (1/75 of a second at 44.1 kHz).
+const SAMPLES_PER_CD_FRAME: u64 = 588;
+
+/// Lead-in of a standard CD, in samples per channel.
+const CD_LEAD_IN: u64 = 150 * SAMPLES_PER_CD_FRAME;
+
+/// Number of the lead-out track on a CD; other cue sheets use 255.
+const CD_LEAD_OUT_TRACK: u8 = 170;
+
+/// The track layout of a disc for [`FlacBuilder::cuesheet`](crate::FlacBuilder::cuesheet).
+/// Offsets are in samples per channel from the start of the audio. For a CD every offset must
+/// fall on a CD frame, i.e. be a multiple of 588.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CueSheet {
+    /// For CDs the 13 digit UPC/EAN, or empty. Up to 128 printable ASCII characters.
+    pub media_catalog_number: String,
+    /// Samples before the first track on the disc.
+    pub lead_in: u64,
+    /// Whether this is a CD-DA disc, which libFLAC holds to the stricter Red Book rules.
+    pub is_cd: bool,
+    /// Tracks in order, ending with the lead-out track.
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CueTrack {
+    pub number: u8,
+    pub offset: u64,
+    /// 12 character ISRC, or empty.
+    pub isrc: String,
+    /// A data track rather than audio.
+    pub is_data: bool,
+    pub pre_emphasis: bool,
+    /// Index points relative to the track's offset. Every track except the lead-out needs at
+    /// least one; index 1 is where the track starts, index 0 the pregap before it.
+    pub indices: Vec<CueIndex>,
+}
+
+impl CueTrack {
+    /// Where index `number` is, in samples per channel from the start of the audio. Index 1
+    /// is where the track starts playing, after any pregap.
+    pub fn index_offset(&self, number: u8) -> Option<u64> {
+        self.indices
+            .iter()
+            .find(|index| index.number == number)
+            .map(|index| self.offset + index.offset)
+    }
+
+    /// Samples per channel between index 0 and index 1, zero without a pregap.
+    pub fn pregap_len(&self) -> u64 {
+        match (self.index_offset(0), self.index_offset(1)) {
+            (Some(pregap), Some(start)) => start.saturating_sub(pregap),
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CueIndex {
+    pub number: u8,
+    pub offset: u64,
+}
+
+impl CueSheet {
+    /// An empty CD cue sheet with the standard 2 second lead-in; add tracks with
+    /// [`track`](Self::track) and finish with [`lead_out`](Self::lead_out).
+    pub fn cd() -> Self {
+        CueSheet {
+            media_catalog_number: String::new(),
+            lead_in: CD_LEAD_IN,
+            is_cd: true,
+            tracks: vec![],
+        }
+    }
+
+    /// The cue sheet of a CD from its table of contents, for an image of the whole disc
+    /// starting at the first track. Fails with [`EncoderError::InvalidDiscToc`] if the table of
+    /// contents doesn't pass [`DiscToc::check`].
+    pub fn from_toc(toc: &DiscToc) -> Result<Self, EncoderError> {
+        toc.check()?;
+
+        let first_offset = toc.track_offsets[0];
+        let to_samples = |offset: u32| (offset - first_offset) as u64 * SAMPLES_PER_CD_FRAME;
+
+        let sheet = (toc.first_track..)
+            .zip(&toc.track_offsets)
+            .fold(CueSheet::cd(), |sheet, (number, offset)| {
+                sheet.track(number, to_samples(*offset))
+            })
+            .lead_out(to_samples(toc.lead_out));
+
+        Ok(sheet)
+    }
+
+    pub fn media_catalog_number(mut self, number: &str) -> Self {
+        self.media_catalog_number = number.to_string();
+        self
+    }
+
+    /// Adds an audio track starting at `offset` with index 1 at its start.
+    pub fn track(mut self, number: u8, offset: u64) -> Self {
+        self.tracks.push(CueTrack {
+            number,
+            offset,
+            isrc: String::new(),
+            is_data: false,
+            pre_emphasis: false,
+            indices: vec![CueIndex {
+                number: 1,
+                offset: 0,
+            }],
+        });
+        self
+    }
+
+    /// Gives the track added last a pregap of `length` samples before its start, as index 0.
+    /// The track's offset moves back to the pregap and index 1 stays where the track starts.
+    /// On track 1 this holds hidden track one audio, e.g. `.track(1, hidden).pregap(hidden)`
+    /// for a rip whose audio starts with `hidden` samples before the first track. A pregap
+    /// longer than the audio before the track is cut to start at the beginning of the audio.
+    pub fn pregap(mut self, length: u64) -> Self {
+        if let Some(track) = self.tracks.last_mut() {
+            let start = track.index_offset(1).unwrap_or(track.offset);
+            let offset = start - length.min(start);
+
+            track.indices.retain(|index| index.number != 0);
+            for index in &mut track.indices {
+                index.offset = track.offset + index.offset - offset;
+            }
+            if offset < start {
+                track.indices.insert(
+                    0,
+                    CueIndex {
+                        number: 0,
+                        offset: 0,
+                    },
+                );
+            }
+            track.offset = offset;
+        }
+        self
+    }
+
+    /// Adds the lead-out track at `offset`, the end of the audio.
+    pub fn lead_out(mut self, offset: u64) -> Self {
+        self.tracks.push(CueTrack {
+            number: if self.is_cd { CD_LEAD_OUT_TRACK } else { 255 },
+            offset,
+            isrc: String::new(),
+            is_data: false,
+            pre_emphasis: false,
+            indices: vec![],
+        });
+        self
+    }
+
+    /// Fills in an empty `CUESHEET` block and checks it with libFLAC.
+    pub(crate) unsafe fn fill(&self, block: *mut FLAC__StreamMetadata) -> Result<(), EncoderError> {
+        let cue_sheet = &mut (*block).data.cue_sheet;
+
+        let catalog = self.media_catalog_number.as_bytes();
+        if catalog.len() >= cue_sheet.media_catalog_number.len()
+            || !catalog.iter().all(|b| (0x20..=0x7e).contains(b))
+        {
+            return Err(EncoderError::InvalidCueSheet(format!(
+                "media catalog number {:?} isn't up to 128 printable ASCII characters",
+                self.media_catalog_number
+            )));
+        }
+        for (target, byte) in cue_sheet.media_catalog_number.iter_mut().zip(catalog) {
+            *target = *byte as c_char;
+        }
+
+        cue_sheet.lead_in = self.lead_in;
+        cue_sheet.is_cd = self.is_cd as FLAC__bool;
+
+        for (track_i, track) in self.tracks.iter().enumerate() {
+            if 0 == FLAC__metadata_object_cuesheet_insert_blank_track(block, track_i as u32) {
+                return Err(EncoderError::FailedToSetMetadata);
+            }
+
+            let target = &mut *(*block).data.cue_sheet.tracks.add(track_i);
+            target.number = track.number;
+            target.offset = track.offset;
+            target.set_type(track.is_data as u32);
+            target.set_pre_emphasis(track.pre_emphasis as u32);
+
+            let isrc = track.isrc.as_bytes();
+            if !(isrc.is_empty() || isrc.len() == 12 && isrc.iter().all(u8::is_ascii_alphanumeric))
+            {
+                return Err(EncoderError::InvalidCueSheet(format!(
+                    "ISRC {:?} of track {} isn't 12 letters and digits",
+                    track.isrc, track.number
+                )));
+            }
+            for (target, byte) in target.isrc.iter_mut().zip(isrc) {
+                *target = *byte as c_char;
+            }
+
+            for (index_i, index) in track.indices.iter().enumerate() {
+                if 0 == FLAC__metadata_object_cuesheet_track_insert_blank_index(
+                    block,
+                    track_i as u32,
+                    index_i as u32,
+                ) {
+                    return Err(EncoderError::FailedToSetMetadata);
+                }
+
+                let target = &mut *(*(*block).data.cue_sheet.tracks.add(track_i))
+                    .indices
+                    .add(index_i);
+                target.number = index.number;
+                target.offset = index.offset;
+            }
+        }
+
+        let mut violation = null();
+        if 0 == FLAC__metadata_object_cuesheet_is_legal(
+            block,
+            self.is_cd as FLAC__bool,
+            &mut violation,
+        ) {
+            return Err(EncoderError::InvalidCueSheet(
+                CStr::from_ptr(violation).to_string_lossy().into_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{raw::RawMetadata, FlacBuilder};
+
+    fn index(number: u8, offset: u64) -> CueIndex {
+        CueIndex { number, offset }
+    }
+
+    #[test]
+    fn toc_tracks_start_at_the_first_track() {
+        let toc = DiscToc::from_track_lengths(&[1000, 2000]).unwrap();
+        let sheet = CueSheet::from_toc(&toc).unwrap();
+
+        let offsets: Vec<(u8, u64)> = sheet.tracks.iter().map(|t| (t.number, t.offset)).collect();
+        assert_eq!(offsets, [(1, 0), (2, 1000 * 588), (170, 3000 * 588)]);
+        assert!(sheet.tracks[2].indices.is_empty());
+    }
+
+    #[test]
+    fn pregap_moves_the_track_back_and_keeps_its_start() {
+        let sheet = CueSheet::cd()
+            .track(1, 0)
+            .track(2, 10_000 * 588)
+            .pregap(150 * 588)
+            .lead_out(20_000 * 588);
+        let track = &sheet.tracks[1];
+
+        assert_eq!(track.offset, 9_850 * 588);
+        assert_eq!(track.indices, [index(0, 0), index(1, 150 * 588)]);
+        assert_eq!(track.index_offset(1), Some(10_000 * 588));
+        assert_eq!(track.pregap_len(), 150 * 588);
+        assert_eq!(sheet.tracks[0].pregap_len(), 0);
+    }
+
+    #[test]
+    fn hidden_track_one_audio_is_a_pregap_from_the_start() {
+        let hidden = 30 * 75 * 588;
+        let sheet = CueSheet::cd().track(1, hidden).pregap(hidden);
+        let track = &sheet.tracks[0];
+
+        assert_eq!(track.offset, 0);
+        assert_eq!(track.index_offset(0), Some(0));
+        assert_eq!(track.index_offset(1), Some(hidden));
+    }
+
+    #[test]
+    fn pregap_is_cut_at_the_start_and_can_be_replaced() {
+        let sheet = CueSheet::cd().track(1, 588).pregap(10 * 588);
+        assert_eq!(sheet.tracks[0].pregap_len(), 588);
+
+        let sheet = sheet.pregap(0);
+        assert_eq!(sheet.tracks[0].offset, 588);
+        assert_eq!(sheet.tracks[0].indices, [index(1, 0)]);
+    }
+
+    /// Two seconds of CD audio carrying `sheet`.
+    fn encode(sheet: CueSheet) -> Result<Vec<u8>, EncoderError> {
+        let samples = vec![0.1f32; 2 * 2 * 44100];
+        FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .cuesheet(sheet)
+            .build()
+    }
+
+    /// Two one-second tracks, the second with a pregap.
+    fn disc() -> CueSheet {
+        CueSheet::cd()
+            .media_catalog_number("0123456789012")
+            .track(1, 0)
+            .track(2, 44100)
+            .pregap(588 * 10)
+            .lead_out(2 * 44100)
+    }
+
+    #[test]
+    fn is_stored_as_a_cuesheet_block() {
+        let bytes = encode(disc()).unwrap();
+        let metadata = RawMetadata::parse(&bytes).unwrap();
+        let block = metadata
+            .blocks
+            .iter()
+            .find(|b| b.block_type == FLAC__METADATA_TYPE_CUESHEET as u8)
+            .unwrap();
+
+        assert_eq!(&block.data[..13], b"0123456789012");
+        // Lead-in, then the CD flag in the top bit and the track count after the reserved bytes.
+        assert_eq!(block.data[136] & 0x80, 0x80);
+        assert_eq!(block.data[395], 3);
+
+        // Track 2's offset and number, after track 1 with its one index.
+        let track_2 = &block.data[396 + 36 + 12..];
+        assert_eq!(
+            u64::from_be_bytes(track_2[..8].try_into().unwrap()),
+            44100 - 588 * 10
+        );
+        assert_eq!(track_2[8], 2);
+        assert_eq!(track_2[35], 2);
+    }
+
+    #[test]
+    fn libflac_checks_it_before_encoding() {
+        let bad_isrc = CueSheet {
+            tracks: disc()
+                .tracks
+                .into_iter()
+                .map(|track| CueTrack {
+                    isrc: "GB-AAA".to_string(),
+                    ..track
+                })
+                .collect(),
+            ..disc()
+        };
+        assert!(matches!(
+            encode(bad_isrc),
+            Err(EncoderError::InvalidCueSheet(_))
+        ));
+
+        // CD tracks have to start on a sector boundary.
+        let off_sector = CueSheet::cd()
+            .track(1, 0)
+            .track(2, 1000)
+            .lead_out(2 * 44100);
+        assert!(matches!(
+            encode(off_sector),
+            Err(EncoderError::InvalidCueSheet(_))
+        ));
+    }
+}
@@ -0,0 +1,977 @@
+//! Decoding FLAC back to samples with libFLAC.
+
+use std::{
+    collections::VecDeque,
+    ffi::{c_void, CStr},
+    fs::File,
+    io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+    slice::{from_raw_parts, from_raw_parts_mut},
+};
+
+use libflac_sys::*;
+
+use crate::{
+    AudioBlock, AudioSource, EncodeReport, EncoderError, FlacBuilder, Picture, StreamInfo,
+    CHUNK_SIZE,
+};
+
+/// A whole FLAC stream decoded into memory along with its metadata, e.g. for round-trip tests
+/// or a simple player. The MD5 in STREAMINFO is checked when it is set. Use [`FlacDecoder`] to
+/// decode a frame at a time instead.
+///
+/// It is also an [`AudioSource`] over the decoded audio, so a file can be re-encoded with
+/// [`FlacBuilder::from_source`](crate::FlacBuilder::from_source).
+#[derive(Debug, Clone)]
+pub struct FlacReader {
+    pub stream_info: StreamInfo,
+    /// Vorbis comments in the order they are stored.
+    pub comments: Vec<(String, String)>,
+    pub pictures: Vec<Picture>,
+    /// The audio, interleaved, at the stream's bps.
+    pub audio: AudioBlock,
+    /// Samples already handed out as an `AudioSource`.
+    read_cursor: usize,
+}
+
+impl FlacReader {
+    /// Decodes an in-memory FLAC stream.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, EncoderError> {
+        let stream_info = StreamInfo::from_bytes(bytes)?;
+
+        // Capped as a damaged header could claim any length.
+        let expected_samples = stream_info.total_samples * stream_info.channels as u64;
+        let mut samples = Vec::with_capacity(expected_samples.min(bytes.len() as u64 * 4) as usize);
+
+        let mut decoder = FlacDecoder::new(bytes)?;
+        while !decoder.finished {
+            decoder.decode_frame()?;
+            samples.extend(decoder.state.pending.drain(..));
+        }
+
+        Ok(FlacReader {
+            stream_info,
+            comments: std::mem::take(&mut decoder.state.comments),
+            pictures: std::mem::take(&mut decoder.state.pictures),
+            audio: AudioBlock {
+                channels: stream_info.channels as usize,
+                bps: stream_info.bps,
+                sample_rate: stream_info.sample_rate,
+                samples,
+            },
+            read_cursor: 0,
+        })
+    }
+
+    /// Reads and decodes a FLAC file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, EncoderError> {
+        Self::from_bytes(&std::fs::read(path).map_err(EncoderError::Io)?)
+    }
+
+    /// The audio with each frame's samples next to each other, e.g. LRLRLR.
+    pub fn interleaved(&self) -> &[i32] {
+        &self.audio.samples
+    }
+
+    /// The audio as one list of samples per channel.
+    pub fn planar(&self) -> Vec<Vec<i32>> {
+        (0..self.audio.channels)
+            .map(|channel| self.audio.channel(channel).collect())
+            .collect()
+    }
+}
+
+impl AudioSource for FlacReader {
+    fn channels(&self) -> usize {
+        self.audio.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.audio.sample_rate
+    }
+
+    fn bps(&self) -> u32 {
+        self.audio.bps
+    }
+
+    fn len_hint(&self) -> Option<usize> {
+        Some((self.audio.samples.len() - self.read_cursor) / self.audio.channels.max(1))
+    }
+
+    fn fill(&mut self, buffer: &mut [i32]) -> Result<usize, EncoderError> {
+        let remaining = &self.audio.samples[self.read_cursor..];
+        let whole_frames = buffer.len() - buffer.len() % self.audio.channels.max(1);
+        let n = whole_frames.min(remaining.len());
+
+        buffer[..n].copy_from_slice(&remaining[..n]);
+        self.read_cursor += n;
+
+        Ok(n)
+    }
+}
+
+/// Decodes a FLAC stream a frame at a time as it is read, so only about one frame of audio is
+/// in memory however long the stream is. The metadata is read up front.
+///
+/// It can be encoded again as it is decoded, see [`pipe`]. The MD5 in STREAMINFO, when it is
+/// set, is checked once the last frame has been read, so a mismatch fails the final
+/// [`fill`](Self::fill). Decoders made with [`seekable`](Self::seekable) can also
+/// [`seek`](Self::seek).
+///
+/// By default a damaged frame fails the decode; a [`lenient`](Self::lenient) decoder carries
+/// on past it instead, to salvage what it can.
+pub struct FlacDecoder<R> {
+    // Declared first so it is dropped first; libFLAC holds a pointer to the state.
+    handle: DecoderHandle,
+    state: Box<DecodeState<R>>,
+    stream_info: StreamParameters,
+    is_seekable: bool,
+    /// Whether the end of the stream has been reached.
+    finished: bool,
+    /// Samples per channel handed out by `fill`, or the sample last sought to.
+    frames_read: u64,
+}
+
+impl<R: Read> FlacDecoder<R> {
+    /// Reads the metadata from `reader`, leaving it at the first frame.
+    pub fn new(reader: R) -> Result<Self, EncoderError> {
+        Self::init(reader, 0, 0, (None, None, None, None))
+    }
+
+    /// `position` is where `reader` is now and `length` where it ends, only needed for
+    /// `seek_callbacks`, which are all set or none.
+    fn init(
+        reader: R,
+        position: u64,
+        length: u64,
+        seek_callbacks: SeekCallbacks,
+    ) -> Result<Self, EncoderError> {
+        let (seek, tell, length_callback, eof) = seek_callbacks;
+
+        let mut state = Box::new(DecodeState {
+            reader,
+            position,
+            length,
+            pending: VecDeque::new(),
+            stream_info: None,
+            comments: vec![],
+            pictures: vec![],
+            error: None,
+            io_error: None,
+            lenient: false,
+            damage: vec![],
+            next_sample: 0,
+        });
+
+        let handle = DecoderHandle::new()?;
+
+        unsafe {
+            FLAC__stream_decoder_set_md5_checking(handle.0, 1);
+            FLAC__stream_decoder_set_metadata_respond(handle.0, FLAC__METADATA_TYPE_VORBIS_COMMENT);
+            FLAC__stream_decoder_set_metadata_respond(handle.0, FLAC__METADATA_TYPE_PICTURE);
+
+            if FLAC__STREAM_DECODER_INIT_STATUS_OK
+                != FLAC__stream_decoder_init_stream(
+                    handle.0,
+                    Some(read_callback::<R>),
+                    seek,
+                    tell,
+                    length_callback,
+                    eof,
+                    Some(write_callback::<R>),
+                    Some(metadata_callback::<R>),
+                    Some(error_callback::<R>),
+                    state.as_mut() as *mut _ as *mut c_void,
+                )
+            {
+                return Err(EncoderError::InitializationError);
+            }
+
+            let result = FLAC__stream_decoder_process_until_end_of_metadata(handle.0);
+            state.check(&handle, result)?;
+        }
+
+        let Some(stream_info) = state.stream_info else {
+            return Err(EncoderError::MalformedFlacData);
+        };
+
+        Ok(FlacDecoder {
+            handle,
+            state,
+            stream_info,
+            is_seekable: seek.is_some(),
+            finished: false,
+            frames_read: 0,
+        })
+    }
+
+    /// Samples per channel in the whole stream, or 0 if STREAMINFO doesn't say.
+    pub fn total_samples(&self) -> u64 {
+        self.stream_info.total_samples
+    }
+
+    /// Vorbis comments in the order they are stored, without the vendor string.
+    pub fn comments(&self) -> &[(String, String)] {
+        &self.state.comments
+    }
+
+    pub fn pictures(&self) -> &[Picture] {
+        &self.state.pictures
+    }
+
+    /// Skips damaged frames rather than failing, recording each in [`damage`](Self::damage),
+    /// e.g. to salvage a partly corrupted archive. libFLAC replaces a frame that fails its CRC
+    /// with silence, and audio lost while it finds the next frame, or cut off the end of a
+    /// stream whose length is known, is filled with silence here, so everything after the
+    /// damage stays where it was. The MD5 isn't checked once there is damage, as it can't
+    /// match.
+    pub fn lenient(mut self) -> Self {
+        self.state.lenient = true;
+        self
+    }
+
+    /// The damage a [`lenient`](Self::lenient) decoder has skipped so far, in stream order.
+    pub fn damage(&self) -> &[DecodeDamage] {
+        &self.state.damage
+    }
+
+    /// Decodes the next frame into `state.pending`, checking the MD5 at the end of the stream.
+    fn decode_frame(&mut self) -> Result<(), EncoderError> {
+        unsafe {
+            let result = FLAC__stream_decoder_process_single(self.handle.0);
+            self.state.check(&self.handle, result)?;
+
+            if FLAC__stream_decoder_get_state(self.handle.0) == FLAC__STREAM_DECODER_END_OF_STREAM {
+                self.finished = true;
+
+                let total_samples = self.stream_info.total_samples;
+                if self.state.lenient && self.state.next_sample < total_samples {
+                    self.state.record("stream ends early");
+                    self.state.pad_to(total_samples, self.stream_info.channels);
+                }
+
+                let result = self.handle.finish();
+                if self.state.damage.is_empty() {
+                    result?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> FlacDecoder<R> {
+    /// Like [`new`](Self::new) but able to [`seek`](Self::seek), e.g. over a `File` or an
+    /// `io::Cursor`. The stream starts where `reader` is now.
+    pub fn seekable(mut reader: R) -> Result<Self, EncoderError> {
+        let start = reader.stream_position().map_err(EncoderError::Io)?;
+        let end = reader.seek(SeekFrom::End(0)).map_err(EncoderError::Io)?;
+        reader
+            .seek(SeekFrom::Start(start))
+            .map_err(EncoderError::Io)?;
+
+        let callbacks = (
+            Some(seek_callback::<R> as _),
+            Some(tell_callback::<R> as _),
+            Some(length_callback::<R> as _),
+            Some(eof_callback::<R> as _),
+        );
+
+        Self::init(reader, start, end, callbacks)
+    }
+
+    /// Moves to `sample` (per channel) so the next [`fill`](Self::fill) starts there.
+    /// libFLAC stops checking the MD5 after a seek. Fails for decoders not made with
+    /// [`seekable`](Self::seekable), and once the end of the stream has been read.
+    pub fn seek(&mut self, sample: u64) -> Result<(), EncoderError> {
+        if !self.is_seekable || self.finished {
+            return Err(EncoderError::DecodeFailed(
+                "the decoder can't seek".to_string(),
+            ));
+        }
+
+        // libFLAC decodes the frame it lands in straight away.
+        self.state.pending.clear();
+        self.state.next_sample = sample;
+        self.frames_read = sample;
+
+        unsafe {
+            let result = FLAC__stream_decoder_seek_absolute(self.handle.0, sample);
+            self.state.check(&self.handle, result)
+        }
+    }
+}
+
+impl FlacDecoder<BufReader<File>> {
+    /// Opens a FLAC file to decode.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, EncoderError> {
+        let file = File::open(path).map_err(EncoderError::Io)?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> AudioSource for FlacDecoder<R> {
+    fn channels(&self) -> usize {
+        self.stream_info.channels as usize
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.stream_info.sample_rate
+    }
+
+    fn bps(&self) -> u32 {
+        self.stream_info.bps
+    }
+
+    fn len_hint(&self) -> Option<usize> {
+        if self.stream_info.total_samples == 0 {
+            return None;
+        }
+
+        Some(
+            self.stream_info
+                .total_samples
+                .saturating_sub(self.frames_read) as usize,
+        )
+    }
+
+    fn fill(&mut self, buffer: &mut [i32]) -> Result<usize, EncoderError> {
+        let channels = self.channels().max(1);
+
+        while self.state.pending.len() < buffer.len() && !self.finished {
+            self.decode_frame()?;
+        }
+
+        let whole_frames = buffer.len() - buffer.len() % channels;
+        let n = whole_frames.min(self.state.pending.len());
+
+        for (to, from) in buffer.iter_mut().zip(self.state.pending.drain(..n)) {
+            *to = from;
+        }
+        self.frames_read += (n / channels) as u64;
+
+        Ok(n)
+    }
+}
+
+/// Re-encodes everything `decoder` has left into a file at `path` as it is decoded, so memory
+/// use stays bounded however long the stream is, e.g. to change the compression level of a
+/// large file. The tags and pictures are carried over; `configure` sets everything else and can add more.
+/// Returns the report as [`write_file_with_report`](FlacBuilder::write_file_with_report) does.
+pub fn pipe<'data, R: Read + 'data>(
+    mut decoder: FlacDecoder<R>,
+    path: impl AsRef<Path>,
+    configure: impl FnOnce(FlacBuilder<'data, i32>) -> FlacBuilder<'data, i32>,
+) -> Result<EncodeReport, EncoderError> {
+    let comments = std::mem::take(&mut decoder.state.comments);
+    let pictures = std::mem::take(&mut decoder.state.pictures);
+
+    let mut builder = FlacBuilder::from_source(decoder);
+    for (key, value) in &comments {
+        builder = builder.vorbis_comment(key, value);
+    }
+    for picture in pictures {
+        builder = builder.picture_block(picture);
+    }
+
+    configure(builder).write_file_with_report(path)
+}
+
+/// Decodes only the frames in `samples` (per channel, from the start of the stream) of a
+/// seekable FLAC stream, e.g. a `File` or an `io::Cursor` over bytes, for a waveform preview or
+/// scrubbing that never needs the whole file. A range past the end stops at the end.
+pub fn decode_range(
+    input: impl Read + Seek,
+    samples: Range<u64>,
+) -> Result<AudioBlock, EncoderError> {
+    let mut decoder = FlacDecoder::seekable(input)?;
+    let channels = decoder.channels();
+    let total_samples = decoder.total_samples();
+
+    let mut block = AudioBlock {
+        channels,
+        bps: decoder.bps(),
+        sample_rate: decoder.sample_rate(),
+        samples: vec![],
+    };
+
+    let past_end = total_samples != 0 && samples.start >= total_samples;
+    if samples.is_empty() || past_end {
+        return Ok(block);
+    }
+
+    decoder.seek(samples.start)?;
+
+    let mut buffer = vec![0; CHUNK_SIZE * channels];
+    let mut frames_left = samples.end - samples.start;
+
+    while frames_left > 0 {
+        let frames = frames_left.min(CHUNK_SIZE as u64) as usize;
+        let n = decoder.fill(&mut buffer[..frames * channels])?;
+        if n == 0 {
+            break;
+        }
+
+        frames_left -= (n / channels) as u64;
+        block.samples.extend_from_slice(&buffer[..n]);
+    }
+
+    Ok(block)
+}
+
+/// A problem a [`lenient`](FlacDecoder::lenient) decoder skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeDamage {
+    /// The first sample (per channel) that may be affected; everything before it decoded.
+    pub sample: u64,
+    /// How far into the stream libFLAC had read when it found the problem. It reads ahead, so
+    /// this is at or a little past the damage.
+    pub byte_offset: u64,
+    /// libFLAC's description of the problem, e.g. "frame CRC mismatch".
+    pub problem: &'static str,
+    /// Samples per channel of silence put in for audio that was lost, not counting frames
+    /// libFLAC silenced itself.
+    pub silence: u64,
+}
+
+/// libFLAC's seek, tell, length and eof callbacks.
+type SeekCallbacks = (
+    FLAC__StreamDecoderSeekCallback,
+    FLAC__StreamDecoderTellCallback,
+    FLAC__StreamDecoderLengthCallback,
+    FLAC__StreamDecoderEofCallback,
+);
+
+/// The STREAMINFO fields the decoder needs.
+#[derive(Clone, Copy)]
+struct StreamParameters {
+    sample_rate: u32,
+    channels: u32,
+    bps: u32,
+    total_samples: u64,
+}
+
+/// Client data for the callbacks below.
+struct DecodeState<R> {
+    reader: R,
+    /// Where `reader` is, tracked here so `tell` and `eof` don't have to ask it.
+    position: u64,
+    /// Where `reader` ends, for decoders that can seek.
+    length: u64,
+    /// Decoded samples not handed out yet, interleaved.
+    pending: VecDeque<i32>,
+    stream_info: Option<StreamParameters>,
+    comments: Vec<(String, String)>,
+    pictures: Vec<Picture>,
+    /// The first problem libFLAC reported, which it otherwise recovers from.
+    error: Option<&'static str>,
+    /// libFLAC can't carry an `io::Error`, so a failed read is kept here.
+    io_error: Option<io::Error>,
+    /// Whether libFLAC's errors go to `damage` rather than `error`.
+    lenient: bool,
+    damage: Vec<DecodeDamage>,
+    /// The sample (per channel) the next frame should start at.
+    next_sample: u64,
+}
+
+impl<R> DecodeState<R> {
+    fn record(&mut self, problem: &'static str) {
+        self.damage.push(DecodeDamage {
+            sample: self.next_sample,
+            byte_offset: self.position,
+            problem,
+            silence: 0,
+        });
+    }
+
+    /// Fills the gap up to `sample` with silence, put down to the last damage recorded.
+    fn pad_to(&mut self, sample: u64, channels: u32) {
+        let gap = sample.saturating_sub(self.next_sample);
+        if gap == 0 {
+            return;
+        }
+
+        if self.damage.is_empty() {
+            self.record("missing frames");
+        }
+        if let Some(damage) = self.damage.last_mut() {
+            damage.silence += gap;
+        }
+
+        self.pending
+            .extend(std::iter::repeat_n(0, (gap * channels as u64) as usize));
+        self.next_sample = sample;
+    }
+
+    /// Turns what happened during a call into `handle` returning `result` into an error.
+    unsafe fn check(
+        &mut self,
+        handle: &DecoderHandle,
+        result: FLAC__bool,
+    ) -> Result<(), EncoderError> {
+        if let Some(e) = self.io_error.take() {
+            return Err(EncoderError::Io(e));
+        }
+
+        if let Some(error) = self.error.take() {
+            return Err(EncoderError::DecodeFailed(error.to_string()));
+        }
+
+        if result == 0 {
+            let state = CStr::from_ptr(FLAC__stream_decoder_get_resolved_state_string(handle.0));
+            return Err(EncoderError::DecodeFailed(
+                state.to_string_lossy().into_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Owns a `FLAC__StreamDecoder`, deleting it when dropped.
+struct DecoderHandle(*mut FLAC__StreamDecoder);
+
+impl DecoderHandle {
+    fn new() -> Result<Self, EncoderError> {
+        let decoder = unsafe { FLAC__stream_decoder_new() };
+
+        if decoder.is_null() {
+            return Err(EncoderError::InitializationError);
+        }
+
+        Ok(DecoderHandle(decoder))
+    }
+
+    /// Ends decoding, checking the MD5 if it was set.
+    unsafe fn finish(&self) -> Result<(), EncoderError> {
+        // Only fails when the MD5 was checked and didn't match.
+        if 0 == FLAC__stream_decoder_finish(self.0) {
+            return Err(EncoderError::DecodeFailed(
+                "MD5 of the decoded audio doesn't match STREAMINFO".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for DecoderHandle {
+    fn drop(&mut self) {
+        unsafe {
+            FLAC__stream_decoder_delete(self.0);
+        }
+    }
+}
+
+unsafe extern "C" fn read_callback<R: Read>(
+    _decoder: *const FLAC__StreamDecoder,
+    buffer: *mut FLAC__byte,
+    bytes: *mut usize,
+    client_data: *mut c_void,
+) -> FLAC__StreamDecoderReadStatus {
+    let state = &mut *(client_data as *mut DecodeState<R>);
+    let buffer = from_raw_parts_mut(buffer, *bytes);
+
+    loop {
+        match state.reader.read(buffer) {
+            Ok(0) => {
+                *bytes = 0;
+                return FLAC__STREAM_DECODER_READ_STATUS_END_OF_STREAM;
+            }
+            Ok(read) => {
+                *bytes = read;
+                state.position += read as u64;
+                return FLAC__STREAM_DECODER_READ_STATUS_CONTINUE;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => {
+                state.io_error.get_or_insert(e);
+                *bytes = 0;
+                return FLAC__STREAM_DECODER_READ_STATUS_ABORT;
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn seek_callback<R: Seek>(
+    _decoder: *const FLAC__StreamDecoder,
+    absolute_byte_offset: u64,
+    client_data: *mut c_void,
+) -> FLAC__StreamDecoderSeekStatus {
+    let state = &mut *(client_data as *mut DecodeState<R>);
+
+    match state.reader.seek(SeekFrom::Start(absolute_byte_offset)) {
+        Ok(position) => {
+            state.position = position;
+            FLAC__STREAM_DECODER_SEEK_STATUS_OK
+        }
+        Err(e) => {
+            state.io_error.get_or_insert(e);
+            FLAC__STREAM_DECODER_SEEK_STATUS_ERROR
+        }
+    }
+}
+
+unsafe extern "C" fn tell_callback<R>(
+    _decoder: *const FLAC__StreamDecoder,
+    absolute_byte_offset: *mut u64,
+    client_data: *mut c_void,
+) -> FLAC__StreamDecoderTellStatus {
+    let state = &*(client_data as *const DecodeState<R>);
+
+    *absolute_byte_offset = state.position;
+    FLAC__STREAM_DECODER_TELL_STATUS_OK
+}
+
+unsafe extern "C" fn length_callback<R>(
+    _decoder: *const FLAC__StreamDecoder,
+    stream_length: *mut u64,
+    client_data: *mut c_void,
+) -> FLAC__StreamDecoderLengthStatus {
+    let state = &*(client_data as *const DecodeState<R>);
+
+    *stream_length = state.length;
+    FLAC__STREAM_DECODER_LENGTH_STATUS_OK
+}
+
+unsafe extern "C" fn eof_callback<R>(
+    _decoder: *const FLAC__StreamDecoder,
+    client_data: *mut c_void,
+) -> FLAC__bool {
+    let state = &*(client_data as *const DecodeState<R>);
+
+    (state.position >= state.length) as FLAC__bool
+}
+
+unsafe extern "C" fn write_callback<R>(
+    _decoder: *const FLAC__StreamDecoder,
+    frame: *const FLAC__Frame,
+    buffer: *const *const FLAC__int32,
+    client_data: *mut c_void,
+) -> FLAC__StreamDecoderWriteStatus {
+    let state = &mut *(client_data as *mut DecodeState<R>);
+    let header = &(*frame).header;
+
+    // libFLAC numbers every frame it hands out by its first sample.
+    let first_sample = header.number.sample_number;
+    if state.lenient {
+        state.pad_to(first_sample, header.channels);
+    }
+    state.next_sample = first_sample + header.blocksize as u64;
+
+    let channels: Vec<&[FLAC__int32]> = from_raw_parts(buffer, header.channels as usize)
+        .iter()
+        .map(|channel| from_raw_parts(*channel, header.blocksize as usize))
+        .collect();
+
+    for i in 0..header.blocksize as usize {
+        state
+            .pending
+            .extend(channels.iter().map(|channel| channel[i]));
+    }
+
+    FLAC__STREAM_DECODER_WRITE_STATUS_CONTINUE
+}
+
+unsafe extern "C" fn metadata_callback<R>(
+    _decoder: *const FLAC__StreamDecoder,
+    metadata: *const FLAC__StreamMetadata,
+    client_data: *mut c_void,
+) {
+    let state = &mut *(client_data as *mut DecodeState<R>);
+    let metadata = &*metadata;
+
+    match metadata.type_ {
+        FLAC__METADATA_TYPE_STREAMINFO => {
+            let info = &metadata.data.stream_info;
+
+            state.stream_info = Some(StreamParameters {
+                sample_rate: info.sample_rate,
+                channels: info.channels,
+                bps: info.bits_per_sample,
+                total_samples: info.total_samples,
+            });
+        }
+        FLAC__METADATA_TYPE_VORBIS_COMMENT => {
+            let block = &metadata.data.vorbis_comment;
+
+            for i in 0..block.num_comments as usize {
+                let entry = &*block.comments.add(i);
+                if entry.entry.is_null() {
+                    continue;
+                }
+
+                let entry =
+                    String::from_utf8_lossy(from_raw_parts(entry.entry, entry.length as usize));
+                if let Some((key, value)) = entry.split_once('=') {
+                    state.comments.push((key.to_string(), value.to_string()));
+                }
+            }
+        }
+        FLAC__METADATA_TYPE_PICTURE => {
+            state
+                .pictures
+                .push(Picture::from_block(&metadata.data.picture));
+        }
+        _ => {}
+    }
+}
+
+unsafe extern "C" fn error_callback<R>(
+    _decoder: *const FLAC__StreamDecoder,
+    status: FLAC__StreamDecoderErrorStatus,
+    client_data: *mut c_void,
+) {
+    let state = &mut *(client_data as *mut DecodeState<R>);
+
+    let problem = match status {
+        FLAC__STREAM_DECODER_ERROR_STATUS_LOST_SYNC => "lost sync",
+        FLAC__STREAM_DECODER_ERROR_STATUS_BAD_HEADER => "bad frame header",
+        FLAC__STREAM_DECODER_ERROR_STATUS_FRAME_CRC_MISMATCH => "frame CRC mismatch",
+        FLAC__STREAM_DECODER_ERROR_STATUS_UNPARSEABLE_STREAM => "unparseable stream",
+        _ => "bad metadata",
+    };
+
+    if state.lenient {
+        state.record(problem);
+    } else {
+        state.error.get_or_insert(problem);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompressionLevel, IntoSample, PictureType};
+
+    const FRAMES: usize = 20_480;
+
+    /// A sine on the left and a quieter cosine on the right, interleaved.
+    fn sine() -> Vec<f32> {
+        (0..FRAMES)
+            .flat_map(|i| {
+                let phase = i as f32 * 440.0 * std::f32::consts::TAU / 44100.0;
+                [phase.sin() * 0.5, phase.cos() * 0.25]
+            })
+            .collect()
+    }
+
+    fn encode(samples: &[f32]) -> Vec<u8> {
+        FlacBuilder::from_interleaved(samples, 2, 44100)
+            .title("Sine")
+            .build()
+            .unwrap()
+    }
+
+    fn quantized(samples: &[f32]) -> Vec<i32> {
+        samples.iter().map(|s| s.to_i16() as i32).collect()
+    }
+
+    fn read_all<R: Read>(decoder: &mut FlacDecoder<R>) -> Vec<i32> {
+        let mut out = vec![];
+        let mut buffer = vec![0; 4096];
+
+        loop {
+            match decoder.fill(&mut buffer).unwrap() {
+                0 => return out,
+                n => out.extend_from_slice(&buffer[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn decoder_streams_the_encoded_samples() {
+        let samples = sine();
+        let bytes = encode(&samples);
+        let mut decoder = FlacDecoder::new(&bytes[..]).unwrap();
+
+        assert_eq!(decoder.channels(), 2);
+        assert_eq!(decoder.sample_rate(), 44100);
+        assert_eq!(decoder.bps(), 16);
+        assert_eq!(decoder.total_samples(), FRAMES as u64);
+        assert!(decoder
+            .comments()
+            .contains(&("TITLE".to_string(), "Sine".to_string())));
+        assert_eq!(read_all(&mut decoder), quantized(&samples));
+    }
+
+    #[test]
+    fn reader_round_trips_samples_and_metadata() {
+        let samples = sine();
+        let bytes = FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .title("Sine")
+            .picture(vec![1, 2, 3], "image/png", PictureType::FrontCover, "cover")
+            .build()
+            .unwrap();
+        let reader = FlacReader::from_bytes(&bytes).unwrap();
+
+        assert_eq!(reader.interleaved(), quantized(&samples));
+        assert_eq!(reader.stream_info.channels, 2);
+        assert_eq!(reader.stream_info.sample_rate, 44100);
+        assert_eq!(reader.stream_info.bps, 16);
+        assert_eq!(reader.stream_info.total_samples, FRAMES as u64);
+
+        assert!(reader
+            .comments
+            .contains(&("TITLE".to_string(), "Sine".to_string())));
+        assert_eq!(reader.pictures.len(), 1);
+        assert_eq!(reader.pictures[0].picture_type, PictureType::FrontCover);
+        assert_eq!(reader.pictures[0].mime_type, "image/png");
+        assert_eq!(reader.pictures[0].data, [1, 2, 3]);
+        assert_eq!(
+            FlacDecoder::new(&bytes[..]).unwrap().pictures(),
+            reader.pictures
+        );
+
+        let planar = reader.planar();
+        assert_eq!(planar[0][1], reader.interleaved()[2]);
+        assert_eq!(planar[1][1], reader.interleaved()[3]);
+    }
+
+    #[test]
+    fn reader_is_a_source_to_encode_again() {
+        let bytes = encode(&sine());
+        let reader = FlacReader::from_bytes(&bytes).unwrap();
+        let decoded = reader.interleaved().to_vec();
+
+        let again = FlacBuilder::from_source(reader)
+            .compression_level(CompressionLevel::L8)
+            .build()
+            .unwrap();
+        assert_eq!(
+            FlacReader::from_bytes(&again).unwrap().interleaved(),
+            decoded
+        );
+    }
+
+    #[test]
+    fn reader_fails_on_a_damaged_frame() {
+        let mut bytes = encode(&sine());
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0x10;
+
+        assert!(matches!(
+            FlacReader::from_bytes(&bytes),
+            Err(EncoderError::DecodeFailed(_))
+        ));
+    }
+
+    #[test]
+    fn decoder_fails_on_a_damaged_frame() {
+        let mut bytes = encode(&sine());
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0x10;
+
+        let mut decoder = FlacDecoder::new(&bytes[..]).unwrap();
+        let mut buffer = vec![0; 4096];
+        let result = (0..FRAMES).try_for_each(|_| decoder.fill(&mut buffer).map(drop));
+
+        assert!(matches!(result, Err(EncoderError::DecodeFailed(_))));
+    }
+
+    #[test]
+    fn pipe_reencodes_samples_tags_and_pictures() {
+        let samples = sine();
+        let bytes = FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .title("Sine")
+            .picture(vec![1, 2, 3], "image/png", PictureType::FrontCover, "cover")
+            .build()
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("pipe-{}.flac", std::process::id()));
+
+        pipe(FlacDecoder::new(&bytes[..]).unwrap(), &path, |builder| {
+            builder
+                .compression_level(CompressionLevel::L8)
+                .artist("Band")
+        })
+        .unwrap();
+
+        let mut decoder = FlacDecoder::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            decoder.comments(),
+            [
+                ("TITLE".to_string(), "Sine".to_string()),
+                ("ARTIST".to_string(), "Band".to_string()),
+            ]
+        );
+        assert_eq!(decoder.pictures().len(), 1);
+        assert_eq!(decoder.pictures()[0].data, [1, 2, 3]);
+        assert_eq!(read_all(&mut decoder), quantized(&samples));
+    }
+
+    #[test]
+    fn decode_range_returns_only_the_window() {
+        let samples = sine();
+        let bytes = encode(&samples);
+        let expected = quantized(&samples);
+
+        let window = decode_range(io::Cursor::new(&bytes), 5000..9000).unwrap();
+        assert_eq!(
+            (window.channels, window.bps, window.sample_rate),
+            (2, 16, 44100)
+        );
+        assert_eq!(window.samples, expected[5000 * 2..9000 * 2]);
+
+        let tail = decode_range(io::Cursor::new(&bytes), 20_000..30_000).unwrap();
+        assert_eq!(tail.samples, expected[20_000 * 2..]);
+
+        let past_end = decode_range(io::Cursor::new(&bytes), 30_000..40_000).unwrap();
+        assert!(past_end.samples.is_empty());
+    }
+
+    #[test]
+    fn only_seekable_decoders_seek() {
+        let bytes = encode(&sine());
+
+        let mut decoder = FlacDecoder::new(io::Cursor::new(&bytes)).unwrap();
+        assert!(matches!(
+            decoder.seek(100),
+            Err(EncoderError::DecodeFailed(_))
+        ));
+
+        let mut decoder = FlacDecoder::seekable(io::Cursor::new(&bytes)).unwrap();
+        decoder.seek(FRAMES as u64 - 10).unwrap();
+        assert_eq!(read_all(&mut decoder).len(), 20);
+    }
+
+    #[test]
+    fn lenient_decoder_skips_damage() {
+        let samples = sine();
+        let mut bytes = encode(&samples);
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0x10;
+
+        let mut decoder = FlacDecoder::new(&bytes[..]).unwrap().lenient();
+        let decoded = read_all(&mut decoder);
+        let expected = quantized(&samples);
+
+        assert_eq!(decoded.len(), expected.len());
+        let damage = decoder.damage()[0].clone();
+        assert!(damage.byte_offset >= middle as u64);
+        assert!(damage.sample > 0 && damage.sample < FRAMES as u64);
+        let intact = damage.sample as usize * 2;
+        assert_eq!(decoded[..intact], expected[..intact]);
+        assert_ne!(decoded, expected);
+    }
+
+    #[test]
+    fn lenient_decoder_pads_a_truncated_stream() {
+        let samples = sine();
+        let bytes = encode(&samples);
+        let cut = &bytes[..bytes.len() * 3 / 4];
+
+        let mut decoder = FlacDecoder::new(cut).unwrap().lenient();
+        let decoded = read_all(&mut decoder);
+
+        assert_eq!(decoded.len(), FRAMES * 2);
+        let damage = decoder.damage().last().unwrap();
+        assert_eq!(damage.sample + damage.silence, FRAMES as u64);
+        assert!(decoded[damage.sample as usize * 2..]
+            .iter()
+            .all(|&s| s == 0));
+    }
+}
@@ -0,0 +1,226 @@
+//! Disc IDs for tagging CD rips.
+
+use crate::EncoderError;
+
+/// A CD table of contents. Offsets are in CD frames (1/75 of a second) from the start of the
+/// disc and include the 150 frame (2 second) lead-in, as reported by drives. Build it with
+/// [`new`](Self::new) or [`from_track_lengths`](Self::from_track_lengths) to have it checked;
+/// one put together by hand is checked when an ID is computed from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscToc {
+    pub first_track: u8,
+    pub track_offsets: Vec<u32>,
+    pub lead_out: u32,
+}
+
+/// Frames before the first track on a standard disc.
+const LEAD_IN_FRAMES: u32 = 150;
+
+/// The highest track number a CD can have.
+const MAX_TRACK: usize = 99;
+
+impl DiscToc {
+    /// Fails with [`EncoderError::InvalidDiscToc`] unless the tracks are numbered within 1 to
+    /// 99 and the offsets, then the lead-out, strictly increase.
+    pub fn new(
+        first_track: u8,
+        track_offsets: Vec<u32>,
+        lead_out: u32,
+    ) -> Result<Self, EncoderError> {
+        let toc = DiscToc {
+            first_track,
+            track_offsets,
+            lead_out,
+        };
+        toc.check()?;
+
+        Ok(toc)
+    }
+
+    /// Build a table of contents from track lengths in CD frames, assuming the tracks are
+    /// numbered from 1 and the first one starts right after the standard lead-in.
+    pub fn from_track_lengths(lengths: &[u32]) -> Result<Self, EncoderError> {
+        let mut track_offsets = Vec::with_capacity(lengths.len());
+        let mut offset = LEAD_IN_FRAMES;
+
+        for length in lengths {
+            track_offsets.push(offset);
+            offset = offset
+                .checked_add(*length)
+                .ok_or_else(|| EncoderError::InvalidDiscToc("the disc is too long".to_string()))?;
+        }
+
+        DiscToc::new(1, track_offsets, offset)
+    }
+
+    /// See [`new`](Self::new).
+    pub fn check(&self) -> Result<(), EncoderError> {
+        let invalid = |problem: String| Err(EncoderError::InvalidDiscToc(problem));
+
+        if self.track_offsets.is_empty() {
+            return invalid("there are no tracks".to_string());
+        }
+        if self.first_track == 0 || self.last_track().is_none() {
+            return invalid(format!(
+                "tracks {} to {} aren't within 1 to {MAX_TRACK}",
+                self.first_track,
+                self.first_track as usize + self.track_offsets.len() - 1
+            ));
+        }
+
+        let offsets = self.track_offsets.iter().chain([&self.lead_out]);
+        if !offsets.clone().zip(offsets.skip(1)).all(|(a, b)| a < b) {
+            return invalid("track offsets and the lead-out don't increase".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// `None` past track 99.
+    fn last_track(&self) -> Option<u8> {
+        let last = (self.first_track as usize + self.track_offsets.len()).checked_sub(1)?;
+        (last <= MAX_TRACK).then_some(last as u8)
+    }
+
+    /// The FreeDB/CDDB disc ID as 8 lowercase hex digits.
+    pub fn freedb_id(&self) -> Result<String, EncoderError> {
+        self.check()?;
+
+        let digit_sum = |mut n: u32| {
+            let mut sum = 0;
+            while n > 0 {
+                sum += n % 10;
+                n /= 10;
+            }
+            sum
+        };
+
+        let checksum: u32 = self
+            .track_offsets
+            .iter()
+            .map(|offset| digit_sum(offset / 75))
+            .sum();
+
+        // Checked to be lower than the lead-out.
+        let first_offset = self.track_offsets[0];
+        let length_seconds = self.lead_out / 75 - first_offset / 75;
+
+        let id =
+            ((checksum % 0xff) << 24) | (length_seconds << 8) | self.track_offsets.len() as u32;
+        Ok(format!("{id:08x}"))
+    }
+
+    /// The [MusicBrainz disc ID](https://musicbrainz.org/doc/Disc_ID_Calculation), as written
+    /// to the `MUSICBRAINZ_DISCID` tag.
+    pub fn musicbrainz_id(&self) -> Result<String, EncoderError> {
+        self.check()?;
+        let last_track = self.last_track().unwrap_or_default();
+
+        let mut sha = crate::hash::Sha1::new();
+
+        sha.update(format!("{:02X}", self.first_track).as_bytes());
+        sha.update(format!("{last_track:02X}").as_bytes());
+        sha.update(format!("{:08X}", self.lead_out).as_bytes());
+
+        // One slot per track number from 1 to 99, zero for tracks not on the disc.
+        for track in 1..=MAX_TRACK {
+            let offset = track
+                .checked_sub(self.first_track as usize)
+                .and_then(|i| self.track_offsets.get(i))
+                .copied()
+                .unwrap_or(0);
+            sha.update(format!("{offset:08X}").as_bytes());
+        }
+
+        Ok(musicbrainz_base64(&sha.finish()))
+    }
+}
+
+/// Base64 with the URL-safe substitutions MusicBrainz uses (`.`, `_` and `-` for padding).
+fn musicbrainz_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789._";
+
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('-');
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The six track example disc from MusicBrainz's disc ID calculation page.
+    fn example_disc() -> DiscToc {
+        DiscToc {
+            first_track: 1,
+            track_offsets: vec![150, 15363, 32314, 46592, 63414, 80489],
+            lead_out: 95462,
+        }
+    }
+
+    #[test]
+    fn musicbrainz_id_of_the_published_example() {
+        assert_eq!(
+            example_disc().musicbrainz_id().unwrap(),
+            "49HHV7Eb8UKF3aQiNmu1GR8vKTY-"
+        );
+    }
+
+    /// Digit sums of the start seconds 2, 204, 430, 621, 845 and 1073 add to 0x34, the disc
+    /// is 1272 - 2 = 0x4f6 seconds long and has 6 tracks.
+    #[test]
+    fn freedb_id_of_the_example() {
+        assert_eq!(example_disc().freedb_id().unwrap(), "3404f606");
+    }
+
+    #[test]
+    fn track_lengths_start_after_the_lead_in() {
+        let toc = DiscToc::from_track_lengths(&[1000, 2000]).unwrap();
+
+        assert_eq!(toc.track_offsets, [150, 1150]);
+        assert_eq!(toc.lead_out, 3150);
+    }
+
+    #[test]
+    fn rejects_tables_of_contents_a_cd_cant_have() {
+        let invalid = |toc: Result<DiscToc, EncoderError>| {
+            matches!(toc, Err(EncoderError::InvalidDiscToc(_)))
+        };
+
+        assert!(invalid(DiscToc::new(1, vec![], 150)));
+        assert!(invalid(DiscToc::new(0, vec![150], 1000)));
+        assert!(invalid(DiscToc::new(99, vec![150, 1000], 2000)));
+        assert!(invalid(DiscToc::new(1, vec![150, 150], 2000)));
+        assert!(invalid(DiscToc::new(1, vec![150, 1000], 1000)));
+        assert!(invalid(DiscToc::from_track_lengths(&[u32::MAX])));
+
+        let by_hand = DiscToc {
+            lead_out: 100,
+            ..example_disc()
+        };
+        assert!(matches!(
+            by_hand.musicbrainz_id(),
+            Err(EncoderError::InvalidDiscToc(_))
+        ));
+    }
+
+    #[test]
+    fn base64_uses_musicbrainz_substitutions() {
+        assert_eq!(musicbrainz_base64(&[0xfb, 0xff]), "._8-");
+        assert_eq!(musicbrainz_base64(b"Man"), "TWFu");
+    }
+}
@@ -0,0 +1,57 @@
+//! Processing applied to float samples before they are quantized.
+
+/// Bends samples above half of `ceiling` (6 dB below it) smoothly towards it, so they approach
+/// but never reach it. Quieter samples pass through unchanged and the curve's slope is
+/// continuous at the knee.
+pub(crate) fn soft_clip(sample: f64, ceiling: f64) -> f64 {
+    let knee = ceiling / 2.0;
+    let magnitude = sample.abs();
+
+    if magnitude <= knee {
+        return sample;
+    }
+
+    let range = ceiling - knee;
+    (knee + range * ((magnitude - knee) / range).tanh()).copysign(sample)
+}
+
+/// Linear gain for `frame` out of `total`, ramping up from silence over the first `fade_in`
+/// frames and down to silence over the last `fade_out`.
+pub(crate) fn fade_gain(frame: usize, total: usize, fade_in: usize, fade_out: usize) -> f64 {
+    let mut gain: f64 = 1.0;
+
+    if frame < fade_in {
+        gain = frame as f64 / fade_in as f64;
+    }
+
+    let frames_after = total.saturating_sub(frame + 1);
+    if frames_after < fade_out {
+        gain = gain.min(frames_after as f64 / fade_out as f64);
+    }
+
+    gain
+}
+
+/// Crossfades the start of `chunk` (interleaved) from `held`, the last frame before a splice,
+/// into the new audio, so the join doesn't click. `ramp` is the length of the whole crossfade
+/// in frames and `done` how much of it earlier chunks covered; returns how much this one
+/// covered.
+pub(crate) fn splice_ramp(chunk: &mut [i32], held: &[i32], ramp: usize, done: usize) -> usize {
+    let channels = held.len().max(1);
+    let mut frames = 0;
+
+    for (frame, samples) in chunk.chunks_exact_mut(channels).enumerate() {
+        let position = done + frame;
+        if position >= ramp {
+            break;
+        }
+
+        let gain = (position + 1) as f64 / (ramp + 1) as f64;
+        for (sample, &held) in samples.iter_mut().zip(held) {
+            *sample = (held as f64 + (*sample - held) as f64 * gain).round() as i32;
+        }
+        frames += 1;
+    }
+
+    frames
+}
@@ -0,0 +1,19 @@
+//! Lifecycle events of an encode, see [`FlacBuilder::on_event`](crate::FlacBuilder::on_event).
+
+/// Delivered in order: `Started`, `MetadataWritten`, a `ChunkDone` per chunk of input, then
+/// `Finished`. A `Warning` can come at any point. If the verify failure policy encodes again,
+/// the sequence restarts from `Started`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncoderEvent {
+    Started {
+        samples_per_channel: usize,
+    },
+    /// The metadata blocks have been written to the output.
+    MetadataWritten,
+    ChunkDone {
+        samples_done: usize,
+        samples_per_channel: usize,
+    },
+    Warning(String),
+    Finished,
+}
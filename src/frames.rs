@@ -0,0 +1,485 @@
+//! Walking the audio frames of an encoded stream without decoding them.
+
+use std::ops::Range;
+
+use crate::{raw::RawMetadata, EncoderError, StreamInfo};
+
+const CRC8_TABLE: [u8; 256] = crc8_table();
+const CRC16_TABLE: [u16; 256] = crc16_table();
+
+/// CRC-8 with polynomial x^8 + x^2 + x + 1, as used for frame headers.
+const fn crc8_table() -> [u8; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-16 with polynomial x^16 + x^15 + x^2 + 1, as used for whole frames.
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0, |crc, b| CRC8_TABLE[(crc ^ b) as usize])
+}
+
+fn crc16_update(crc: u16, byte: u8) -> u16 {
+    (crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ byte) as usize]
+}
+
+/// The fields of a frame header that matter for walking the stream.
+pub(crate) struct FrameHeader {
+    pub block_size: u32,
+    pub channels: u32,
+    pub length: usize,
+    /// The sample number of the first sample if `variable_blocksize`, otherwise the frame
+    /// number.
+    pub number: u64,
+    pub variable_blocksize: bool,
+}
+
+impl FrameHeader {
+    /// Parses the header at the start of `bytes`, returning `None` unless it is well-formed and
+    /// its CRC-8 matches.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 6 || bytes[0] != 0xff || bytes[1] & 0xfe != 0xf8 {
+            return None;
+        }
+
+        let variable_blocksize = bytes[1] & 1 != 0;
+        let block_size_code = bytes[2] >> 4;
+        let sample_rate_code = bytes[2] & 0x0f;
+        let channel_code = bytes[3] >> 4;
+        let sample_size_code = (bytes[3] >> 1) & 0x07;
+
+        if block_size_code == 0
+            || sample_rate_code == 0x0f
+            || channel_code > 10
+            || sample_size_code == 3
+            || bytes[3] & 1 != 0
+        {
+            return None;
+        }
+
+        let mut cursor = 4;
+
+        // The frame/sample number is UTF-8 style coded.
+        let lead = bytes[cursor];
+        let extra = lead.leading_ones() as usize;
+        if extra == 1 || extra > 7 || (!variable_blocksize && extra > 6) {
+            return None;
+        }
+        let mut number = if extra == 0 {
+            lead as u64
+        } else {
+            (lead & (0x7f >> extra)) as u64
+        };
+        for i in 1..extra.max(1) {
+            let byte = *bytes.get(cursor + i)?;
+            if byte & 0xc0 != 0x80 {
+                return None;
+            }
+            number = (number << 6) | (byte & 0x3f) as u64;
+        }
+        cursor += extra.max(1);
+
+        let block_size = match block_size_code {
+            1 => 192,
+            2..=5 => 576 << (block_size_code - 2),
+            6 => {
+                cursor += 1;
+                *bytes.get(cursor - 1)? as u32 + 1
+            }
+            7 => {
+                cursor += 2;
+                u16::from_be_bytes([*bytes.get(cursor - 2)?, *bytes.get(cursor - 1)?]) as u32 + 1
+            }
+            _ => 256 << (block_size_code - 8),
+        };
+
+        cursor += match sample_rate_code {
+            0x0c => 1,
+            0x0d | 0x0e => 2,
+            _ => 0,
+        };
+
+        let crc = *bytes.get(cursor)?;
+        if crc8(&bytes[..cursor]) != crc {
+            return None;
+        }
+
+        Some(FrameHeader {
+            block_size,
+            channels: if channel_code < 8 {
+                channel_code as u32 + 1
+            } else {
+                2
+            },
+            length: cursor + 1,
+            number,
+            variable_blocksize,
+        })
+    }
+}
+
+fn has_sync_code(bytes: &[u8]) -> bool {
+    bytes.len() >= 2 && bytes[0] == 0xff && bytes[1] & 0xfe == 0xf8
+}
+
+/// Result of [`scan_frames`].
+#[derive(Debug, Clone, Default)]
+pub struct FrameScanReport {
+    pub stream_info: StreamInfo,
+    /// Frames found, including damaged ones.
+    pub frames: usize,
+    pub errors: Vec<FrameError>,
+}
+
+impl FrameScanReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameError {
+    /// Byte offset in the input where the problem starts.
+    pub offset: usize,
+    pub kind: FrameErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameErrorKind {
+    /// Expected a frame header but found something else; the scan skipped ahead to the next
+    /// valid header.
+    LostSync,
+    /// The frame header is fine but the CRC-16 over the frame doesn't match.
+    CrcMismatch,
+}
+
+/// A frame's position in the input.
+pub(crate) struct FrameSpan {
+    pub start: usize,
+    pub end: usize,
+    pub crc_ok: bool,
+    pub header: FrameHeader,
+}
+
+/// Walks the frames of a stream by their headers, finding each frame's end by the position
+/// where the CRC-16 closes and the next valid header starts.
+pub(crate) struct FrameWalker<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+    stream_info: StreamInfo,
+}
+
+impl<'a> FrameWalker<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, EncoderError> {
+        let metadata = RawMetadata::parse(bytes)?;
+
+        Ok(FrameWalker {
+            bytes,
+            cursor: metadata.audio_offset,
+            stream_info: StreamInfo::from_bytes(bytes)?,
+        })
+    }
+
+    pub fn stream_info(&self) -> StreamInfo {
+        self.stream_info
+    }
+
+    /// The next frame, or where sync was lost. `None` at the end of the input.
+    pub fn next_frame(&mut self) -> Option<Result<FrameSpan, usize>> {
+        if self.cursor >= self.bytes.len() {
+            return None;
+        }
+
+        let start = self.cursor;
+
+        let Some(header) = FrameHeader::parse(&self.bytes[start..]) else {
+            self.cursor = self
+                .next_header_from(start + 1, self.bytes.len())
+                .unwrap_or(self.bytes.len());
+            return Some(Err(start));
+        };
+
+        // Generous bound for a verbatim frame when STREAMINFO doesn't say.
+        let max_size = if self.stream_info.max_frame_size > 0 {
+            self.stream_info.max_frame_size as usize
+        } else {
+            header.block_size as usize * header.channels as usize * 5 + 1024
+        };
+        // One past the largest frame so a header straight after it is still checked.
+        let limit = self.bytes.len().min(start + max_size + 1);
+
+        // The CRC-16 of a frame including its own big-endian CRC is 0, so a frame ends where
+        // the running CRC is 0 and another sync code (or the end of the input) follows. The
+        // following header isn't validated here so damage to it is reported against it.
+        let mut crc = 0u16;
+        let mut end = None;
+
+        for i in start..limit {
+            if i > start + header.length && crc == 0 && has_sync_code(&self.bytes[i..]) {
+                end = Some(i);
+                break;
+            }
+            crc = crc16_update(crc, self.bytes[i]);
+        }
+
+        if end.is_none() && limit == self.bytes.len() && crc == 0 {
+            end = Some(limit);
+        }
+
+        let (end, crc_ok) = match end {
+            Some(end) => (end, true),
+            None => (
+                self.next_header_from(start + header.length, self.bytes.len())
+                    .unwrap_or(self.bytes.len()),
+                false,
+            ),
+        };
+
+        self.cursor = end;
+
+        Some(Ok(FrameSpan {
+            start,
+            end,
+            crc_ok,
+            header,
+        }))
+    }
+
+    fn next_header_from(&self, from: usize, to: usize) -> Option<usize> {
+        (from..to)
+            .find(|&i| self.bytes[i] == 0xff && FrameHeader::parse(&self.bytes[i..]).is_some())
+    }
+}
+
+/// Checks the CRC-8 of every frame header and the CRC-16 of every frame in an in-memory FLAC
+/// stream without decoding any audio. Much faster than a full decode, for triaging bit-rot
+/// across large collections; a clean result doesn't check the MD5 of the decoded audio,
+/// which [`verify_batch`](crate::verify_batch) also does.
+pub fn scan_frames(bytes: &[u8]) -> Result<FrameScanReport, EncoderError> {
+    let mut walker = FrameWalker::new(bytes)?;
+    let mut report = FrameScanReport {
+        stream_info: walker.stream_info(),
+        ..Default::default()
+    };
+
+    while let Some(frame) = walker.next_frame() {
+        match frame {
+            Ok(span) => {
+                report.frames += 1;
+
+                if !span.crc_ok {
+                    report.errors.push(FrameError {
+                        offset: span.start,
+                        kind: FrameErrorKind::CrcMismatch,
+                    });
+                }
+            }
+            Err(offset) => report.errors.push(FrameError {
+                offset,
+                kind: FrameErrorKind::LostSync,
+            }),
+        }
+    }
+
+    Ok(report)
+}
+
+/// One encoded frame, from [`frames`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    /// Byte offset of the frame in the input.
+    pub offset: usize,
+    /// The whole frame, header to CRC-16.
+    pub bytes: &'a [u8],
+    /// Index of the frame's first sample per channel in the stream.
+    pub first_sample: u64,
+    /// Samples per channel in the frame.
+    pub samples: u32,
+}
+
+impl Frame<'_> {
+    /// The samples per channel the frame covers, e.g. for a container's timestamps.
+    pub fn sample_range(&self) -> Range<u64> {
+        self.first_sample..self.first_sample + self.samples as u64
+    }
+}
+
+/// Iterator over the frames of a stream, see [`frames`].
+pub struct Frames<'a> {
+    walker: FrameWalker<'a>,
+    bytes: &'a [u8],
+}
+
+impl<'a> Frames<'a> {
+    pub fn stream_info(&self) -> StreamInfo {
+        self.walker.stream_info()
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<Frame<'a>, FrameError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let span = match self.walker.next_frame()? {
+            Ok(span) => span,
+            Err(offset) => {
+                return Some(Err(FrameError {
+                    offset,
+                    kind: FrameErrorKind::LostSync,
+                }))
+            }
+        };
+
+        if !span.crc_ok {
+            return Some(Err(FrameError {
+                offset: span.start,
+                kind: FrameErrorKind::CrcMismatch,
+            }));
+        }
+
+        // Fixed-blocksize streams number frames rather than samples; every frame but the last
+        // has the stream's block size.
+        let first_sample = if span.header.variable_blocksize {
+            span.header.number
+        } else {
+            let block_size = match self.walker.stream_info().max_block_size {
+                0 => span.header.block_size,
+                size => size as u32,
+            };
+            span.header.number * block_size as u64
+        };
+
+        Some(Ok(Frame {
+            offset: span.start,
+            bytes: &self.bytes[span.start..span.end],
+            first_sample,
+            samples: span.header.block_size,
+        }))
+    }
+}
+
+/// Splits an in-memory FLAC stream into its frames without decoding them, e.g. to mux it into
+/// Matroska or MP4, which store each frame as a packet with a timestamp. Damaged frames and
+/// lost sync come out as errors and the iterator carries on after them.
+pub fn frames(bytes: &[u8]) -> Result<Frames<'_>, EncoderError> {
+    Ok(Frames {
+        walker: FrameWalker::new(bytes)?,
+        bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FlacBuilder;
+
+    const FRAMES: usize = 10_000;
+
+    /// Two channels of a 440 Hz sine at 44.1 kHz, interleaved.
+    fn encode_sine() -> Vec<u8> {
+        let samples: Vec<f32> = (0..FRAMES * 2)
+            .map(|i| ((i / 2) as f32 * 440.0 * std::f32::consts::TAU / 44100.0).sin() * 0.25)
+            .collect();
+
+        FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .build()
+            .unwrap()
+    }
+
+    /// The CRC catalogue's check values for CRC-8/SMBUS and CRC-16/UMTS, which are the
+    /// parameters FLAC uses.
+    #[test]
+    fn crc_check_values() {
+        assert_eq!(crc8(b"123456789"), 0xf4);
+        assert_eq!(
+            b"123456789".iter().fold(0, |crc, b| crc16_update(crc, *b)),
+            0xfee8
+        );
+    }
+
+    #[test]
+    fn frame_header_needs_its_crc() {
+        let bytes = encode_sine();
+        let start = RawMetadata::parse(&bytes).unwrap().audio_offset;
+        let mut frame = bytes[start..].to_vec();
+
+        let header = FrameHeader::parse(&frame).unwrap();
+        assert_eq!(header.channels, 2);
+        assert_eq!(header.block_size, 4096);
+
+        frame[header.length - 1] ^= 1;
+        assert!(FrameHeader::parse(&frame).is_none());
+    }
+
+    #[test]
+    fn scan_finds_every_frame_of_an_encode() {
+        let report = scan_frames(&encode_sine()).unwrap();
+
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert_eq!(report.frames, FRAMES.div_ceil(4096));
+    }
+
+    #[test]
+    fn scan_reports_a_flipped_bit() {
+        let mut bytes = encode_sine();
+        let middle = (RawMetadata::parse(&bytes).unwrap().audio_offset + bytes.len()) / 2;
+        bytes[middle] ^= 0x10;
+
+        let report = scan_frames(&bytes).unwrap();
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn frames_cover_the_audio_back_to_back() {
+        let bytes = encode_sine();
+        let audio_offset = RawMetadata::parse(&bytes).unwrap().audio_offset;
+
+        let frames: Vec<Frame> = frames(&bytes).unwrap().collect::<Result<_, _>>().unwrap();
+
+        assert_eq!(frames.len(), FRAMES.div_ceil(4096));
+        assert_eq!(frames[0].offset, audio_offset);
+        assert_eq!(frames.last().unwrap().sample_range().end, FRAMES as u64);
+        for pair in frames.windows(2) {
+            assert_eq!(pair[0].sample_range().end, pair[1].first_sample);
+            assert_eq!(pair[0].offset + pair[0].bytes.len(), pair[1].offset);
+        }
+        let last = frames.last().unwrap();
+        assert_eq!(last.offset + last.bytes.len(), bytes.len());
+    }
+}
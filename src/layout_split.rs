@@ -0,0 +1,212 @@
+//! Starting a new stream when a live recording's channel count changes.
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    EncodeReport, EncoderError, FlacBuilder, FlacStreamEncoder, IntoSample, Seekable, SegmentStart,
+};
+
+/// Where [`LayoutSplitEncoder`] started a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayoutSplit {
+    /// Index of the stream, counting from 0.
+    pub stream: usize,
+    pub channels: usize,
+    /// Position in frames per channel from the start of the session, across every stream.
+    pub start_frame: u64,
+}
+
+type StreamFile = Seekable<BufWriter<File>>;
+type Configure<'data, Sample> =
+    Box<dyn Fn(FlacBuilder<'data, Sample>) -> FlacBuilder<'data, Sample> + 'data>;
+type NewStreamHandler<'data> = Box<dyn FnMut(&LayoutSplit) -> SegmentStart + 'data>;
+type CloseHandler<'data> = Box<dyn FnMut(&Path, EncodeReport) + 'data>;
+
+/// A [`FlacStreamEncoder`] whose input may change channel count mid-session, e.g. an
+/// interview that switches from mono to stereo. A FLAC stream has one channel count
+/// throughout, so a push with a different one finalizes the current stream and starts a new
+/// file, with no audio lost in between.
+///
+/// `on_new_stream` is called with each [`LayoutSplit`] as its first audio arrives and says
+/// where the stream goes and what extra tags it gets. Once a stream is complete, or the session
+/// is [`finish`](Self::finish)ed, `on_close` gets its path and report.
+pub struct LayoutSplitEncoder<'data, Sample: IntoSample> {
+    sample_rate: u32,
+    configure: Configure<'data, Sample>,
+    on_new_stream: NewStreamHandler<'data>,
+    on_close: CloseHandler<'data>,
+    current: Option<(PathBuf, FlacStreamEncoder<'data, Sample, StreamFile>)>,
+    splits: Vec<LayoutSplit>,
+    /// Frames per channel in the streams already finalized.
+    finished_frames: u64,
+}
+
+impl<'data, Sample: IntoSample> LayoutSplitEncoder<'data, Sample> {
+    /// `configure` sets what every stream shares, as for [`FlacStreamEncoder::new`].
+    pub fn new(
+        sample_rate: u32,
+        configure: impl Fn(FlacBuilder<'data, Sample>) -> FlacBuilder<'data, Sample> + 'data,
+        on_new_stream: impl FnMut(&LayoutSplit) -> SegmentStart + 'data,
+        on_close: impl FnMut(&Path, EncodeReport) + 'data,
+    ) -> Self {
+        LayoutSplitEncoder {
+            sample_rate,
+            configure: Box::new(configure),
+            on_new_stream: Box::new(on_new_stream),
+            on_close: Box::new(on_close),
+            current: None,
+            splits: vec![],
+            finished_frames: 0,
+        }
+    }
+
+    /// Encodes interleaved samples with `channels` channels, a whole number of frames,
+    /// starting a new stream first if the channel count differs from the last push.
+    pub fn push_interleaved(
+        &mut self,
+        channels: usize,
+        samples: &[Sample],
+    ) -> Result<(), EncoderError> {
+        if channels == 0 {
+            return Err(EncoderError::InvalidChannelCount);
+        }
+        if !samples.len().is_multiple_of(channels) {
+            return Err(EncoderError::MismatchedSampleCountPerChannels);
+        }
+        if samples.is_empty() {
+            return Ok(());
+        }
+
+        if self.channels() != Some(channels) {
+            self.close_stream()?;
+            self.current = Some(self.open_stream(channels)?);
+        }
+        let Some((_, encoder)) = &mut self.current else {
+            unreachable!();
+        };
+
+        encoder.push_interleaved(samples)
+    }
+
+    /// Channel count of the stream being written, if any.
+    pub fn channels(&self) -> Option<usize> {
+        self.current
+            .as_ref()
+            .and(self.splits.last())
+            .map(|split| split.channels)
+    }
+
+    /// Every stream started so far, in order.
+    pub fn splits(&self) -> &[LayoutSplit] {
+        &self.splits
+    }
+
+    /// Finalizes the stream being written, if any, and returns where each stream started.
+    pub fn finish(mut self) -> Result<Vec<LayoutSplit>, EncoderError> {
+        self.close_stream()?;
+        Ok(self.splits)
+    }
+
+    fn open_stream(
+        &mut self,
+        channels: usize,
+    ) -> Result<(PathBuf, FlacStreamEncoder<'data, Sample, StreamFile>), EncoderError> {
+        let split = LayoutSplit {
+            stream: self.splits.len(),
+            channels,
+            start_frame: self.finished_frames,
+        };
+        let SegmentStart { path, tags } = (self.on_new_stream)(&split);
+
+        let file = File::create(&path).map_err(EncoderError::Io)?;
+        let configure = &self.configure;
+
+        let encoder = FlacStreamEncoder::new(
+            channels,
+            self.sample_rate,
+            Seekable::new(BufWriter::new(file)).map_err(EncoderError::Io)?,
+            |builder| {
+                tags.iter()
+                    .fold(configure(builder), |builder, (key, value)| {
+                        builder.vorbis_comment(key, value)
+                    })
+            },
+        )?;
+        self.splits.push(split);
+
+        Ok((path, encoder))
+    }
+
+    fn close_stream(&mut self) -> Result<(), EncoderError> {
+        if let Some((path, encoder)) = self.current.take() {
+            self.finished_frames += encoder.frames() as u64;
+            let report = encoder.finalize()?;
+            (self.on_close)(&path, report);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, fs};
+
+    use super::*;
+    use crate::{AudioSource, FlacDecoder};
+
+    #[test]
+    fn a_new_channel_count_starts_a_new_stream() {
+        let dir = std::env::temp_dir();
+        let closed = RefCell::new(vec![]);
+
+        let mut encoder = LayoutSplitEncoder::new(
+            8000,
+            |builder| builder.artist("Interview"),
+            |split| SegmentStart {
+                path: dir.join(format!(
+                    "layout-{}-{}.flac",
+                    split.stream,
+                    std::process::id()
+                )),
+                tags: vec![],
+            },
+            |path, report| closed.borrow_mut().push((path.to_owned(), report)),
+        );
+
+        encoder.push_interleaved(1, &[0.25f32; 1000]).unwrap();
+        encoder.push_interleaved(1, &[0.25; 500]).unwrap();
+        assert_eq!(encoder.channels(), Some(1));
+        encoder.push_interleaved(2, &[0.5; 1600]).unwrap();
+        encoder.push_interleaved(1, &[0.25; 200]).unwrap();
+        let splits = encoder.finish().unwrap();
+
+        let split = |stream, channels, start_frame| LayoutSplit {
+            stream,
+            channels,
+            start_frame,
+        };
+        assert_eq!(
+            splits,
+            [split(0, 1, 0), split(1, 2, 1500), split(2, 1, 2300)]
+        );
+
+        let closed = closed.into_inner();
+        assert_eq!(closed.len(), 3);
+        for ((path, _), expected) in closed.iter().zip([1500, 1600, 200]) {
+            let bytes = fs::read(path).unwrap();
+            fs::remove_file(path).unwrap();
+
+            let mut buffer = vec![0; 4000];
+            let n = FlacDecoder::new(&bytes[..])
+                .unwrap()
+                .fill(&mut buffer)
+                .unwrap();
+            assert_eq!(n, expected);
+        }
+    }
+}
@@ -1,40 +1,199 @@
 #![doc = include_str!("../README.md")]
 
 use std::{
-    ffi::{c_char, CString},
+    ffi::{c_char, CStr, CString},
+    fmt,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Seek, Write},
     mem::zeroed,
-    os::raw::c_void,
-    path::Path,
-    ptr::null_mut,
-    slice::from_raw_parts,
+    ops::Range,
+    path::{Path, PathBuf},
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 use libflac_sys::*;
 
+mod analysis;
+mod annotations;
+mod atomic;
+mod batch;
+mod block;
+#[cfg(feature = "bytes")]
+mod bytes_output;
+mod capabilities;
+#[cfg(feature = "capi")]
+mod capi;
+mod compression;
+mod cue_sheet;
+mod decoder;
+mod discid;
+mod dsp;
+mod events;
+mod frames;
+mod hash;
+mod layout_split;
+mod limits;
+mod loudness;
+mod metadata_editor;
+#[cfg(feature = "num-traits")]
+mod num;
+mod picture;
+#[cfg(feature = "cpal")]
+mod playback;
+mod pool;
+mod preflight;
+mod processor;
+#[cfg(feature = "python")]
+mod python;
+mod raw;
+mod recompress;
+mod report;
+mod rolling;
+mod seek_table;
+mod self_test;
+mod session;
+mod shared;
+mod simple_iterator;
+mod sink;
+mod source;
+mod stream;
+mod stream_info;
+mod tags;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+mod verify;
+#[cfg(feature = "cpal")]
+mod voice_memo;
+mod wav;
+
+use analysis::{LossMeter, PeakCollector, SilenceDetector, SilenceSettings, StatsCollector};
+use hash::Hasher;
+use session::{EncoderHandle, MetadataSession};
+use sink::{init_sink, SinkState};
+
+pub use annotations::{read_annotations, Annotation, ANNOTATION_APPLICATION_ID};
+pub use batch::{encode_batch, EncodeBatchReport, EncodeJob, JobResult};
+pub use block::{AudioBlock, ByteOrder};
+#[cfg(feature = "bytes")]
+pub use bytes_output::FlacBytes;
+pub use capabilities::{libflac_capabilities, LibFlacCapabilities, LibFlacFeature};
+pub use compression::{AdvancedSettings, CompressionLevel};
+pub use cue_sheet::{CueIndex, CueSheet, CueTrack};
+pub use decoder::{decode_range, pipe, DecodeDamage, FlacDecoder, FlacReader};
+pub use discid::DiscToc;
+pub use events::EncoderEvent;
+pub use frames::{frames, scan_frames, Frame, FrameError, FrameErrorKind, FrameScanReport, Frames};
+pub use hash::{HashAlgorithm, OutputHash};
+pub use layout_split::{LayoutSplit, LayoutSplitEncoder};
+pub use limits::{LimitKind, Limits};
+pub use loudness::{analyze, tag_album_gain, AlbumLoudness, LoudnessReport};
+pub use metadata_editor::MetadataEditor;
+#[cfg(feature = "num-traits")]
+pub use num::NumSample;
+pub use picture::{Picture, PictureType};
+#[cfg(feature = "cpal")]
+pub use playback::Player;
+pub use pool::{EncoderPool, Shutdown};
+pub use preflight::PreflightProblem;
+pub use processor::{Processor, ProcessorContext};
+pub use raw::{extract_pictures, read_comments, replace_picture};
+pub use recompress::recompress_in_place;
+pub use report::{
+    ChannelStats, ConversionLoss, EncodeReport, EncodeTimings, Peaks, Regression,
+    RegressionTolerance, SessionStats, SilentRegion,
+};
+pub use rolling::{RollingEncoder, SegmentStart};
+pub use seek_table::SeekTableSpec;
+pub use self_test::self_test;
+pub use shared::SharedEncoder;
+pub use simple_iterator::{reclaim_padding, BlockInfo, MetadataBlockType, SimpleMetadataIterator};
+pub use sink::{
+    async_sink, AlignedChunks, AsyncReceiver, AsyncSink, ByteSink, Seekable, SplitHeader, Streamed,
+};
+pub use source::{AudioSource, IterSource, PcmReader, SliceSource};
+pub use stream::FlacStreamEncoder;
+pub use stream_info::StreamInfo;
+pub use tags::{
+    comments_to_map, map_to_comments, normalize_numeric_tag, TagIssue, TagMap, TagProblem,
+    TagProfile, STANDARD_VORBIS_FIELDS,
+};
+pub use verify::{verify_batch, FileVerification, VerifyBatchReport};
+#[cfg(feature = "cpal")]
+pub use voice_memo::VoiceMemoRecorder;
+pub use wav::{default_channel_mask, WavReader, CHANNEL_MASK_TAG};
+
+/// Called with `(encoded_samples, total_samples, bytes_written)`.
+type ProgressHandler<'data> = Box<dyn FnMut(u64, u64, u64) + 'data>;
+
 pub struct FlacBuilder<'data, Sample>
 where
     Sample: IntoSample,
 {
     data: InputData<'data, Sample>,
+    /// Set by `from_source`, whose input is read from it as the encode goes.
+    audio_source: Option<Box<dyn AudioSource + 'data>>,
     bps: BpsLevel,
+    source_bps: Option<BpsLevel>,
+    /// Linear ceiling.
+    soft_clip: Option<f64>,
+    fade_in: Duration,
+    fade_out: Duration,
     sample_rate: u32,
-    compression_level: u32,
+    compression_level: CompressionLevel,
     padding: u32,
+    max_padding: Option<u32>,
+    lax: bool,
+    limit_min_bitrate: bool,
+    preflight: bool,
+    atomic_write: bool,
+    temp_dir: Option<PathBuf>,
+    encoder_settings_tag: bool,
+    silence_detection: Option<SilenceSettings>,
+    samples_per_peak: Option<usize>,
+    channel_stats: bool,
+    verify_failure_policy: VerifyFailurePolicy,
+    tag_profile: Option<TagProfile>,
+    normalize_numeric_tags: bool,
+    empty_input_policy: EmptyInputPolicy,
+    limits: Limits,
+    metadata_warning_bytes: Option<usize>,
+    silent_input_policy: SilentInputPolicy,
+    dual_mono_policy: DualMonoPolicy,
+    /// Set for the current output when the policy found the channels identical.
+    collapse_to_mono: bool,
+    trace_chunk_times: bool,
+    output_hash: Option<HashAlgorithm>,
+    /// Start of the current encode attempt, for `EncodeTimings`.
+    encode_start: Instant,
+    event_handler: Option<Box<dyn FnMut(EncoderEvent) + 'data>>,
+    progress_handler: Option<ProgressHandler<'data>>,
+    cancel_token: Option<Arc<AtomicBool>>,
+    processor: Option<Box<dyn Processor + 'data>>,
+    yield_hook: Option<Box<dyn FnMut() + 'data>>,
     vorbis_comments: Vec<(CString, CString)>,
-    metadata_blocks: Vec<*mut FLAC__StreamMetadata>,
+    annotations: Vec<(Duration, String)>,
+    pictures: Vec<Picture>,
+    seek_table: Option<SeekTableSpec>,
+    cue_sheet: Option<CueSheet>,
+    metadata: MetadataSession,
 }
 
 impl<'data, Sample: IntoSample> FlacBuilder<'data, Sample> {
     /// New with planar audio data. The input data must be a list of channels where each channel is
-    /// a list of frames/samples. Samples can be either `f32` or `f64` in range [-1.0, 1.0] or
-    /// anything you implement `IntoSample` on.
+    /// a list of frames/samples. Samples can be `f32` or `f64` in range [-1.0, 1.0], integer
+    /// PCM, or anything you implement `IntoSample` on.
     pub fn from_planar(data: &'data [Vec<Sample>], sample_rate: u32) -> Self {
         Self::new(InputData::Planar(data), sample_rate)
     }
 
-    /// New with interleaved (e.g. LRLRLRLRLRLR) audio data. Samples can be either `f32` or `f64` 
-    /// in range [-1.0, 1.0] or anything you implement `IntoSample` on.
+    /// New with interleaved (e.g. LRLRLRLRLRLR) audio data. Samples can be `f32` or `f64` in
+    /// range [-1.0, 1.0], integer PCM, or anything you implement `IntoSample` on.
     pub fn from_interleaved(data: &'data [Sample], channels: usize, sample_rate: u32) -> Self {
         Self::new(InputData::Interleaved { data, channels }, sample_rate)
     }
@@ -42,32 +201,410 @@ impl<'data, Sample: IntoSample> FlacBuilder<'data, Sample> {
     fn new(data: InputData<'data, Sample>, sample_rate: u32) -> Self {
         FlacBuilder {
             data,
+            audio_source: None,
             sample_rate,
             bps: BpsLevel::Bps16,
-            compression_level: 5,
+            source_bps: None,
+            soft_clip: None,
+            fade_in: Duration::ZERO,
+            fade_out: Duration::ZERO,
+            compression_level: CompressionLevel::default(),
             padding: 500,
+            max_padding: None,
+            lax: false,
+            limit_min_bitrate: false,
+            preflight: false,
+            atomic_write: false,
+            temp_dir: None,
+            encoder_settings_tag: false,
+            silence_detection: None,
+            samples_per_peak: None,
+            channel_stats: false,
+            verify_failure_policy: VerifyFailurePolicy::Error,
+            tag_profile: None,
+            normalize_numeric_tags: false,
+            empty_input_policy: EmptyInputPolicy::Error,
+            limits: Limits::default(),
+            metadata_warning_bytes: None,
+            silent_input_policy: SilentInputPolicy::Encode,
+            dual_mono_policy: DualMonoPolicy::Encode,
+            collapse_to_mono: false,
+            trace_chunk_times: false,
+            output_hash: None,
+            encode_start: Instant::now(),
+            event_handler: None,
+            progress_handler: None,
+            cancel_token: None,
+            processor: None,
+            yield_hook: None,
             vorbis_comments: vec![],
-            metadata_blocks: vec![],
+            annotations: vec![],
+            pictures: vec![],
+            seek_table: None,
+            cue_sheet: None,
+            metadata: MetadataSession::new(),
         }
     }
 
-    /// See [here](https://xiph.org/flac/api/group__flac__stream__encoder.html#gaacc01aab02849119f929b8516420fcd3).
-    pub fn compression_level(mut self, level: u32) -> Self {
+    /// A libFLAC preset, [`CompressionLevel::L5`] by default, or custom encoder settings.
+    pub fn compression_level(mut self, level: CompressionLevel) -> Self {
         self.compression_level = level;
         self
     }
 
-    /// Set bits per sample.
+    /// Picks the compression level with the best ratio whose encode of the whole input should
+    /// fit within `budget`, by timing a few presets on up to ten seconds from the middle of the
+    /// input. Falls back to [`CompressionLevel::L0`] when even that looks too slow, and leaves
+    /// the level as it was for input that can't be encoded or is read from a source. Fails with
+    /// [`EncoderError::NoData`] for input with no channels.
+    pub fn auto_tune(mut self, budget: Duration) -> Result<Self, EncoderError> {
+        const CANDIDATES: [CompressionLevel; 4] = [
+            CompressionLevel::L0,
+            CompressionLevel::L2,
+            CompressionLevel::L5,
+            CompressionLevel::L8,
+        ];
+
+        if self.data.channel_count() == 0 {
+            return Err(EncoderError::NoData);
+        }
+        let frames = self.data.samples_per_channel();
+        if frames == 0 || !self.data.channel_sizes_match() || self.is_streamed() {
+            return Ok(self);
+        }
+
+        let sample_frames = frames.min(self.sample_rate as usize * 10).max(1);
+        let block = AudioBlock {
+            channels: self.data.channel_count(),
+            bps: self.bps.to_u32(),
+            sample_rate: self.sample_rate,
+            samples: self.convert_chunk((frames - sample_frames) / 2, sample_frames),
+        };
+        let scale = frames as f64 / sample_frames as f64;
+
+        let mut best: Option<(CompressionLevel, usize)> = None;
+
+        for level in CANDIDATES {
+            let mut trial = self.with_input::<Sample>(InputData::Block(&block));
+            trial.source_bps = None;
+            trial.fade_in = Duration::ZERO;
+            trial.fade_out = Duration::ZERO;
+            trial.padding = 0;
+            trial.compression_level = level.clone();
+
+            let start = Instant::now();
+            let Ok((data, _)) = trial.build_once(false) else {
+                return Ok(self);
+            };
+
+            // Presets only get slower, so there's no point trying the next one.
+            if start.elapsed().mul_f64(scale) > budget {
+                break;
+            }
+            if best.as_ref().is_none_or(|(_, size)| data.len() < *size) {
+                best = Some((level, data.len()));
+            }
+        }
+
+        self.compression_level = best.map_or(CompressionLevel::L0, |(level, _)| level);
+        Ok(self)
+    }
+
+    /// Set bits per sample. [`BpsLevel::Bps32`] needs [`lax`](Self::lax) and libFLAC 1.4.0,
+    /// failing with [`EncoderError::UnsupportedByLibFlac`] on older versions.
     pub fn bps(mut self, bps: BpsLevel) -> Self {
         self.bps = bps;
         self
     }
 
+    /// The bit depth the float input originally had, e.g. 20-bit audio being encoded at 24 bps.
+    /// Samples are then quantized at that depth and shifted up, so their low bits are exactly
+    /// zero and libFLAC's wasted-bits detection stores them for free. Integer input is already
+    /// exact and doesn't need this.
+    pub fn source_bps(mut self, bps: BpsLevel) -> Self {
+        self.source_bps = Some(bps);
+        self
+    }
+
+    /// Soft-clip float samples that get near `ceiling_db` (dBFS, e.g. `-0.3`) before they are
+    /// quantized, so a hot mix is rounded off instead of hard clipping at full scale. Samples
+    /// more than 6 dB below the ceiling are untouched. Integer input isn't affected.
+    pub fn soft_clip(mut self, ceiling_db: f64) -> Self {
+        self.soft_clip = Some(10f64.powf(ceiling_db / 20.0));
+        self
+    }
+
+    /// Ramp the start of the audio up from silence over `duration`, e.g. a few milliseconds to
+    /// avoid a click where a clip was cut out of a longer recording. The ramp is linear.
+    pub fn fade_in(mut self, duration: Duration) -> Self {
+        self.fade_in = duration;
+        self
+    }
+
+    /// Like [`fade_in`](Self::fade_in) but ramps the end of the audio down to silence.
+    pub fn fade_out(mut self, duration: Duration) -> Self {
+        self.fade_out = duration;
+        self
+    }
+
+    /// Set the smallest bps that keeps every sample exact, see [`AudioBlock::lossless_bps`].
+    /// Integer slices are inspected as 24-bit, the width [`IntoSample`] gives them. Float
+    /// samples have no exact width, and a source can't be looked at ahead of the encode, so
+    /// this does nothing for them.
+    pub fn bps_auto(mut self) -> Self {
+        let bps = match self.data {
+            InputData::Block(block) => block.lossless_bps(),
+            InputData::Interleaved { data, .. } if Sample::default().to_f64().is_none() => {
+                block::lossless_bps(data.iter().map(IntoSample::to_i24), 24)
+            }
+            InputData::Planar(data) if Sample::default().to_f64().is_none() => {
+                block::lossless_bps(data.iter().flatten().map(IntoSample::to_i24), 24)
+            }
+            _ => None,
+        };
+
+        if let Some(bps) = bps {
+            self.bps = BpsLevel::at_least(bps);
+        }
+        self
+    }
+
     pub fn padding(mut self, padding: u32) -> Self {
         self.padding = padding;
         self
     }
 
+    /// Once a file written by [`write_file`](Self::write_file) is complete, cut any padding
+    /// block over `max_padding` bytes down to that size with [`reclaim_padding`], e.g. when
+    /// [`padding`](Self::padding) comes from a conservative shared default but these files are
+    /// final. Costs a rewrite of the file when it applies. Streams and in-memory outputs are
+    /// left as they are.
+    pub fn reclaim_padding(mut self, max_padding: u32) -> Self {
+        self.max_padding = Some(max_padding);
+        self
+    }
+
+    /// Before encoding, check that the output will fit, so a long encode doesn't fail at the
+    /// end. [`write_file`](Self::write_file) checks that the destination can be opened for
+    /// writing and, on Unix, that its file system has room for the output; [`build`](Self::build)
+    /// checks, on Linux, that there's memory for it. The output is taken to be as large as the
+    /// raw PCM, which FLAC only exceeds slightly for incompressible input. Failures are
+    /// [`EncoderError::PreflightFailed`].
+    pub fn preflight(mut self) -> Self {
+        self.preflight = true;
+        self
+    }
+
+    /// Have [`write_file`](Self::write_file) write to a temporary file and rename it to the
+    /// destination once the encode has succeeded, so the destination never holds a partial
+    /// stream. The temporary file is removed if the encode fails.
+    pub fn atomic_write(mut self) -> Self {
+        self.atomic_write = true;
+        self
+    }
+
+    /// Where [`atomic_write`](Self::atomic_write) creates its temporary file, the destination's
+    /// directory by default. Must be on the same file system as the destination, as checked
+    /// before encoding on Unix, since the final rename can't cross file systems; a small
+    /// `/tmp` on another mount usually isn't. A [`FlacStreamEncoder`] also writes its snapshots
+    /// here before renaming them, and keeps its copy of the frames for them here rather than in
+    /// the system's temporary directory.
+    pub fn temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = Some(dir.into());
+        self
+    }
+
+    /// An upper bound on the encoded size for `preflight`.
+    fn estimated_output_bytes(&self) -> u64 {
+        let pcm_bytes = self.input_report().pcm_bytes as u64;
+        let picture_bytes: usize = self.pictures.iter().map(|p| p.data.len()).sum();
+        pcm_bytes + pcm_bytes / 100 + picture_bytes as u64 + self.padding as u64 + 64 * 1024
+    }
+
+    /// Allow encoding outside of FLAC's
+    /// [streamable subset](https://xiph.org/flac/format.html#subset). This is required for
+    /// sample rates that can't be expressed in a frame header, e.g. anything above 655350 Hz.
+    /// Some hardware players won't play non-subset files.
+    pub fn lax(mut self) -> Self {
+        self.lax = true;
+        self
+    }
+
+    /// Keep the bitrate of every frame above 1 bit per sample by not using constant subframes,
+    /// for streaming protocols that drop the connection when no data arrives for a while, e.g.
+    /// during digital silence. Needs libFLAC 1.4.0: the crate calls its setter directly, so
+    /// building against an older libFLAC fails to link, and a library that reports an older
+    /// version fails with [`EncoderError::UnsupportedByLibFlac`].
+    pub fn limit_min_bitrate(mut self) -> Self {
+        self.limit_min_bitrate = true;
+        self
+    }
+
+    /// Write an `ENCODERSETTINGS` comment recording the compression settings and the crate and
+    /// libFLAC versions, so files made with poor settings can be found and re-encoded later.
+    pub fn encoder_settings_tag(mut self) -> Self {
+        self.encoder_settings_tag = true;
+        self
+    }
+
+    /// Report runs of at least `min_duration` where every channel stays below `threshold_db`
+    /// (dBFS, e.g. `-60.0`) in [`EncodeReport::silent_regions`]. Detection happens on the
+    /// samples as they are encoded so it doesn't need a second pass over the input.
+    pub fn detect_silence(mut self, threshold_db: f64, min_duration: Duration) -> Self {
+        self.silence_detection = Some(SilenceSettings {
+            threshold_db,
+            min_duration,
+        });
+        self
+    }
+
+    /// Collect min/max waveform peaks every `samples_per_peak` samples while encoding, returned
+    /// in [`EncodeReport::peaks`], so the new file can be displayed without decoding it again.
+    pub fn peaks(mut self, samples_per_peak: usize) -> Self {
+        self.samples_per_peak = Some(samples_per_peak);
+        self
+    }
+
+    /// Report how many bits each channel really uses and its peak and RMS levels in
+    /// [`EncodeReport::channel_stats`], e.g. to check that a 24-bit delivery isn't 16-bit audio
+    /// padded with zeros. Collected on the samples as they are encoded.
+    pub fn channel_stats(mut self) -> Self {
+        self.channel_stats = true;
+        self
+    }
+
+    /// What to do when libFLAC's verification finds that the encoded audio doesn't decode back
+    /// to the input. Defaults to [`VerifyFailurePolicy::Error`]. Applies to
+    /// [`build`](Self::build) and [`write_file`](Self::write_file) and their `_with_report`
+    /// variants. Output that can't be taken back, a [`ByteSink`] or a named pipe, fails the
+    /// encode on a mismatch whatever the policy, as encoding again would write a second
+    /// stream after the first.
+    pub fn on_verify_failure(mut self, policy: VerifyFailurePolicy) -> Self {
+        self.verify_failure_policy = policy;
+        self
+    }
+
+    /// Check the vorbis comments against `profile` before encoding, failing with
+    /// [`EncoderError::InvalidTags`] listing every problem found.
+    pub fn tag_profile(mut self, profile: TagProfile) -> Self {
+        self.tag_profile = Some(profile);
+        self
+    }
+
+    /// Rewrite numeric tags such as `TRACKNUMBER`, `DATE` and `BPM` to plain ASCII before
+    /// encoding, see [`normalize_numeric_tag`], so values typed or formatted in another locale
+    /// (e.g. `٣` for 3) don't end up in the file. Values that can't be normalized fail the
+    /// encode with [`EncoderError::InvalidTags`].
+    pub fn normalize_numeric_tags(mut self) -> Self {
+        self.normalize_numeric_tags = true;
+        self
+    }
+
+    /// Call `handler` with progress, warnings and lifecycle events while encoding. For
+    /// [`build_tee`](Self::build_tee) only this builder's handler is called.
+    pub fn on_event(mut self, handler: impl FnMut(EncoderEvent) + 'data) -> Self {
+        self.event_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Like [`on_event`](Self::on_event) but sends the events to a channel, e.g. one drained by
+    /// a UI thread. Events are dropped once the receiver is gone.
+    pub fn events(self, sender: Sender<EncoderEvent>) -> Self {
+        self.on_event(move |event| {
+            let _ = sender.send(event);
+        })
+    }
+
+    /// Call `handler` with `(encoded_samples, total_samples, bytes_written)` after each chunk
+    /// of input and once more when the encode is done, e.g. to drive a progress bar or show
+    /// the bitrate so far. Samples are per channel and only count audio libFLAC has written,
+    /// which lags the input by up to a block. `total_samples` is 0 for
+    /// [`FlacStreamEncoder`], which doesn't know the length. If the verify failure policy
+    /// encodes again, progress starts over. Not called by [`build_tee`](Self::build_tee).
+    pub fn on_progress(mut self, handler: impl FnMut(u64, u64, u64) + 'data) -> Self {
+        self.progress_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Abort the encode with [`EncoderError::Cancelled`] once `token` is set, e.g. from a UI
+    /// thread's cancel button. It is checked before each chunk of input. A file being written
+    /// by [`write_file`](Self::write_file) is deleted; what was already handed to a
+    /// [`ByteSink`] stays there.
+    pub fn cancel_token(mut self, token: Arc<AtomicBool>) -> Self {
+        self.cancel_token = Some(token);
+        self
+    }
+
+    /// Run `processor` on the samples after conversion to the target bps and before they are
+    /// encoded. Analysis such as [`peaks`](Self::peaks) sees the processed samples. Not run by
+    /// [`build_tee`](Self::build_tee) or [`export_looped`](Self::export_looped).
+    pub fn processor(mut self, processor: impl Processor + 'data) -> Self {
+        self.processor = Some(Box::new(processor));
+        self
+    }
+
+    /// Call `hook` between chunks of input, every 1024 frames, so a single-threaded event loop
+    /// or cooperative scheduler can run other work during a long encode, e.g. pump UI events.
+    /// The encode is paused while the hook runs.
+    pub fn yield_hook(mut self, hook: impl FnMut() + 'data) -> Self {
+        self.yield_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// What to do when the input has no samples. Defaults to [`EmptyInputPolicy::Error`].
+    pub fn on_empty_input(mut self, policy: EmptyInputPolicy) -> Self {
+        self.empty_input_policy = policy;
+        self
+    }
+
+    /// Refuse to encode input over these limits, failing with
+    /// [`EncoderError::LimitExceeded`] before any audio is encoded. All but
+    /// [`Limits::max_metadata_bytes`] are checked before anything is handed to libFLAC; the
+    /// metadata size is only known once libFLAC has built the blocks, so that one is checked
+    /// after the encoder is created but before it writes anything.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Emit an [`EncoderEvent::Warning`] if the metadata ahead of the audio (tags, pictures,
+    /// padding) comes to more than `max_bytes`, e.g.
+    /// [`Limits::EMBEDDED_PLAYER_METADATA_BYTES`]. To fail instead, set
+    /// [`Limits::max_metadata_bytes`].
+    pub fn warn_metadata_over(mut self, max_bytes: usize) -> Self {
+        self.metadata_warning_bytes = Some(max_bytes);
+        self
+    }
+
+    /// What to do when every sample of the input is zero. Defaults to
+    /// [`SilentInputPolicy::Encode`], which doesn't check.
+    pub fn on_silent_input(mut self, policy: SilentInputPolicy) -> Self {
+        self.silent_input_policy = policy;
+        self
+    }
+
+    /// What to do when every channel carries the same audio, as happens with voice recorded
+    /// to a stereo track. Defaults to [`DualMonoPolicy::Encode`], which doesn't check.
+    pub fn on_dual_mono(mut self, policy: DualMonoPolicy) -> Self {
+        self.dual_mono_policy = policy;
+        self
+    }
+
+    /// Record when each chunk of input was done in [`EncodeTimings::chunks`].
+    pub fn trace_chunk_times(mut self) -> Self {
+        self.trace_chunk_times = true;
+        self
+    }
+
+    /// Digest the encoded stream into [`EncodeReport::output_hash`], e.g. for upload checksums.
+    /// Pipes and other stream outputs are hashed as they are written. libFLAC seeks back to
+    /// finalize the header of regular files once the audio is done, so those are read back once
+    /// at the end instead.
+    pub fn hash_output(mut self, algorithm: HashAlgorithm) -> Self {
+        self.output_hash = Some(algorithm);
+        self
+    }
+
     pub fn artist(self, artist: &str) -> Self {
         self.vorbis_comment("ARTIST", artist)
     }
@@ -88,6 +625,31 @@ impl<'data, Sample: IntoSample> FlacBuilder<'data, Sample> {
         self.vorbis_comment("TRACKNUMBER", &number.to_string())
     }
 
+    /// Tag `DATE` with when the audio was recorded, as RFC 3339, e.g.
+    /// `2024-05-01T09:30:00+02:00`.
+    #[cfg(feature = "chrono")]
+    pub fn recorded_at<Tz: chrono::TimeZone>(self, time: chrono::DateTime<Tz>) -> Self
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        self.vorbis_comment(
+            "DATE",
+            &time.to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+        )
+    }
+
+    /// Tag `LOCATION` with where the audio was recorded, in degrees, as an ISO 6709 string,
+    /// e.g. `+51.507400-000.127800/`.
+    pub fn location(self, latitude: f64, longitude: f64) -> Self {
+        self.vorbis_comment("LOCATION", &format!("{latitude:+010.6}{longitude:+011.6}/"))
+    }
+
+    /// Tag the `MUSICBRAINZ_DISCID` of the CD this was ripped from. Fails with
+    /// [`EncoderError::InvalidDiscToc`] if `toc` doesn't pass [`DiscToc::check`].
+    pub fn musicbrainz_disc_id(self, toc: &DiscToc) -> Result<Self, EncoderError> {
+        Ok(self.vorbis_comment("MUSICBRAINZ_DISCID", &toc.musicbrainz_id()?))
+    }
+
     pub fn vorbis_comment(mut self, key: &str, value: &str) -> Self {
         self.vorbis_comments.push((
             CString::from_str(key).unwrap_or_default(),
@@ -96,32 +658,180 @@ impl<'data, Sample: IntoSample> FlacBuilder<'data, Sample> {
         self
     }
 
-    unsafe fn prepare(&mut self) -> Result<*mut FLAC__StreamEncoder, EncoderError> {
+    /// Mark a point in the audio with a note, e.g. "take 3 starts here" from a recorder's UI.
+    /// Annotations are stored in an `APPLICATION` block and read back with
+    /// [`read_annotations`].
+    pub fn annotate(mut self, at: Duration, text: &str) -> Self {
+        self.annotations.push((at, text.to_string()));
+        self
+    }
+
+    /// Embed a picture, e.g. album art. `mime_type` is like `image/jpeg`, or `-->` when `data`
+    /// is a URL to the picture instead. The width, height and colour depth are left at 0 as
+    /// they aren't read from the image; pass a [`Picture`] with them filled in to
+    /// [`picture_block`](Self::picture_block) if players need them. Checked before encoding,
+    /// failing with [`EncoderError::InvalidPicture`] or against
+    /// [`Limits::max_picture_bytes`].
+    pub fn picture(
+        self,
+        data: impl Into<Vec<u8>>,
+        mime_type: &str,
+        picture_type: PictureType,
+        description: &str,
+    ) -> Self {
+        self.picture_block(Picture {
+            picture_type,
+            mime_type: mime_type.to_string(),
+            description: description.to_string(),
+            width: 0,
+            height: 0,
+            depth: 0,
+            colors: 0,
+            data: data.into(),
+        })
+    }
+
+    /// Add a `SEEKTABLE` block so players can jump straight to a point in the stream instead of
+    /// searching for it, which matters most when streaming over a network. libFLAC fills in the
+    /// points as it encodes and writes them when it finalizes the header, so they are left
+    /// empty when the output can't be overwritten, e.g. a pipe. A [`FlacStreamEncoder`] only
+    /// uses [`SeekTableSpec::At`] points, as the length isn't known up front.
+    pub fn seek_table(mut self, points: SeekTableSpec) -> Self {
+        self.seek_table = Some(points);
+        self
+    }
+
+    /// Add a `CUESHEET` block with the track layout of the disc, so a rip of a whole CD keeps
+    /// its track boundaries and TOC. Checked by libFLAC before encoding, failing with
+    /// [`EncoderError::InvalidCueSheet`].
+    pub fn cuesheet(mut self, cue_sheet: CueSheet) -> Self {
+        self.cue_sheet = Some(cue_sheet);
+        self
+    }
+
+    /// Like [`picture`](Self::picture) with every field of the block given.
+    pub fn picture_block(mut self, picture: Picture) -> Self {
+        self.pictures.push(picture);
+        self
+    }
+
+    /// Add every value of every field in `tags` as a vorbis comment.
+    pub fn tags(self, tags: &TagMap) -> Self {
+        map_to_comments(tags)
+            .iter()
+            .fold(self, |builder, (key, value)| {
+                builder.vorbis_comment(key, value)
+            })
+    }
+
+    unsafe fn prepare(&mut self, verify: bool) -> Result<EncoderHandle, EncoderError> {
+        self.limits
+            .check(LimitKind::Channels, self.data.channel_count())?;
+        self.limits
+            .check(LimitKind::Samples, self.data.total_samples())?;
+        self.limits.check(
+            LimitKind::TagBytes,
+            self.vorbis_comments
+                .iter()
+                .map(|(key, value)| key.as_bytes().len() + 1 + value.as_bytes().len())
+                .sum(),
+        )?;
+        for picture in &self.pictures {
+            self.limits.check_picture(picture)?;
+        }
+
+        if self.data.channel_count() == 0 {
+            return Err(EncoderError::NoData);
+        }
+
         if !self.data.channel_sizes_match() {
             return Err(EncoderError::MismatchedSampleCountPerChannels);
         }
 
-        if self.data.total_samples() == 0 {
+        // A source's length is only known once it has been read.
+        if self.data.total_samples() == 0
+            && self.empty_input_policy == EmptyInputPolicy::Error
+            && !self.is_streamed()
+        {
             return Err(EncoderError::NoData);
         }
 
-        let encoder = FLAC__stream_encoder_new();
+        if matches!(self.data, InputData::Source { frames: None, .. })
+            && self.fade_out > Duration::ZERO
+        {
+            return Err(EncoderError::NeedsWholeInput("fade_out"));
+        }
 
-        if encoder.is_null() {
-            return Err(EncoderError::InitializationError);
+        if self.sample_rate == 0 || self.sample_rate > MAX_SAMPLE_RATE {
+            return Err(EncoderError::InvalidSampleRate);
+        }
+
+        if !self.lax && !is_subset_sample_rate(self.sample_rate) {
+            return Err(EncoderError::SampleRateRequiresLax(self.sample_rate));
+        }
+
+        // Older versions would fail at init, or ignore the setting, without saying why.
+        let capabilities = libflac_capabilities();
+        if self.bps == BpsLevel::Bps32 {
+            capabilities.require(LibFlacFeature::Bps32)?;
+        }
+        if self.limit_min_bitrate {
+            capabilities.require(LibFlacFeature::LimitMinBitrate)?;
+        }
+
+        if self.normalize_numeric_tags {
+            let mut issues = vec![];
+
+            for (key, value) in &mut self.vorbis_comments {
+                let field = key.to_string_lossy();
+                match normalize_numeric_tag(&field, &value.to_string_lossy()) {
+                    Ok(normalized) => *value = CString::new(normalized).unwrap_or_default(),
+                    Err(problem) => issues.push(TagIssue {
+                        field: field.into_owned(),
+                        problem,
+                    }),
+                }
+            }
+
+            if !issues.is_empty() {
+                return Err(EncoderError::InvalidTags(issues));
+            }
+        }
+
+        if let Some(profile) = &self.tag_profile {
+            let comments: Vec<(String, String)> = self
+                .vorbis_comments
+                .iter()
+                .map(|(k, v)| (k.to_string_lossy().into(), v.to_string_lossy().into()))
+                .collect();
+
+            let issues = profile.check(&comments);
+            if !issues.is_empty() {
+                return Err(EncoderError::InvalidTags(issues));
+            }
         }
 
-        if 0 == FLAC__stream_encoder_set_verify(encoder, 1) {
+        // Blocks from an earlier attempt are no longer referenced by any encoder.
+        self.metadata = MetadataSession::new();
+
+        let handle = EncoderHandle::new()?;
+        let encoder = handle.as_ptr();
+
+        if 0 == FLAC__stream_encoder_set_verify(encoder, verify as FLAC__bool) {
             return Err(EncoderError::VerificationError);
         }
 
-        if 0 == FLAC__stream_encoder_set_compression_level(encoder, self.compression_level) {
-            return Err(EncoderError::InvalidCompressionLevel);
+        if 0 == FLAC__stream_encoder_set_streamable_subset(encoder, !self.lax as FLAC__bool) {
+            return Err(EncoderError::InitializationError);
         }
 
-        let channels = self.data.channel_count();
+        self.compression_level.apply(encoder)?;
 
-        if 0 == FLAC__stream_encoder_set_channels(encoder, channels as u32) {
+        if self.limit_min_bitrate && 0 == FLAC__stream_encoder_set_limit_min_bitrate(encoder, 1) {
+            return Err(EncoderError::InitializationError);
+        }
+
+        if 0 == FLAC__stream_encoder_set_channels(encoder, self.encoded_channels() as u32) {
             return Err(EncoderError::InvalidChannelCount);
         }
 
@@ -133,27 +843,54 @@ impl<'data, Sample: IntoSample> FlacBuilder<'data, Sample> {
             return Err(EncoderError::InvalidSampleRate);
         }
 
+        // STREAMINFO counts samples per channel. Sinks that can't overwrite keep this value.
         if 0 == FLAC__stream_encoder_set_total_samples_estimate(
             encoder,
-            self.data.total_samples() as u64,
+            self.data.samples_per_channel() as u64,
         ) {
             return Err(EncoderError::TooManyOrTooFewSamples);
         }
 
-        if self.vorbis_comments.is_empty() {
-            if 0 == FLAC__stream_encoder_set_metadata(encoder, null_mut(), 0) {
-                return Err(EncoderError::FailedToSetMetadata);
+        if let Some(spec) = &self.seek_table {
+            let mut points = spec.points(self.sample_rate, self.data.samples_per_channel() as u64);
+
+            if !points.is_empty() {
+                let metadata_block = self.metadata.new_block(FLAC__METADATA_TYPE_SEEKTABLE)?;
+
+                if 0 == FLAC__metadata_object_seektable_template_append_points(
+                    metadata_block,
+                    points.as_mut_ptr(),
+                    points.len() as u32,
+                ) || 0 == FLAC__metadata_object_seektable_template_sort(metadata_block, 1)
+                {
+                    return Err(EncoderError::FailedToSetMetadata);
+                }
+
+                if (*metadata_block).length as usize > raw::MAX_BLOCK_LENGTH {
+                    return Err(EncoderError::MetadataBlockTooLarge);
+                }
             }
         }
 
-        if !self.vorbis_comments.is_empty() {
-            let metadata_block = FLAC__metadata_object_new(FLAC__METADATA_TYPE_VORBIS_COMMENT);
+        if let Some(cue_sheet) = &self.cue_sheet {
+            cue_sheet.fill(self.metadata.new_block(FLAC__METADATA_TYPE_CUESHEET)?)?;
+        }
+
+        let mut vorbis_comments = self.vorbis_comments.clone();
 
-            if metadata_block.is_null() {
-                return Err(EncoderError::InitializationError);
-            }
+        if self.encoder_settings_tag {
+            vorbis_comments.push((
+                CString::from_str("ENCODERSETTINGS").unwrap_or_default(),
+                CString::from_str(&self.encoder_settings(encoder)).unwrap_or_default(),
+            ));
+        }
+
+        if !vorbis_comments.is_empty() {
+            let metadata_block = self
+                .metadata
+                .new_block(FLAC__METADATA_TYPE_VORBIS_COMMENT)?;
 
-            for (key, value) in &self.vorbis_comments {
+            for (key, value) in &vorbis_comments {
                 let mut entry: FLAC__StreamMetadata_VorbisComment_Entry = zeroed();
 
                 if 0 == FLAC__metadata_object_vorbiscomment_entry_from_name_value_pair(
@@ -171,184 +908,1278 @@ impl<'data, Sample: IntoSample> FlacBuilder<'data, Sample> {
                     return Err(EncoderError::FailedToSetMetadata);
                 }
             }
+        }
 
-            self.metadata_blocks.push(metadata_block);
+        if !self.annotations.is_empty() {
+            let annotations: Vec<Annotation> = self
+                .annotations
+                .iter()
+                .map(|(at, text)| Annotation {
+                    position: (at.as_secs_f64() * self.sample_rate as f64).round() as u64,
+                    text: text.clone(),
+                })
+                .collect();
+            let mut data = annotations::to_block_data(&annotations);
+
+            let metadata_block = self.metadata.new_block(FLAC__METADATA_TYPE_APPLICATION)?;
+            (*metadata_block).data.application.id = ANNOTATION_APPLICATION_ID;
+
+            if 0 == FLAC__metadata_object_application_set_data(
+                metadata_block,
+                data.as_mut_ptr(),
+                data.len() as u32,
+                1,
+            ) {
+                return Err(EncoderError::FailedToSetMetadata);
+            }
         }
 
-        let padding_block = FLAC__metadata_object_new(FLAC__METADATA_TYPE_PADDING);
-        if !padding_block.is_null() {
-            (*padding_block).length = self.padding;
-            self.metadata_blocks.push(padding_block);
+        for picture in &self.pictures {
+            picture.fill(self.metadata.new_block(FLAC__METADATA_TYPE_PICTURE)?)?;
         }
 
-        if 0 == FLAC__stream_encoder_set_metadata(
-            encoder,
-            self.metadata_blocks.as_mut_ptr(),
-            self.metadata_blocks.len() as u32,
-        ) {
-            return Err(EncoderError::FailedToSetMetadata);
+        let padding_block = self.metadata.new_block(FLAC__METADATA_TYPE_PADDING)?;
+        (*padding_block).length = self.padding;
+
+        let metadata_bytes = self.metadata.encoded_length();
+        self.limits
+            .check(LimitKind::MetadataBytes, metadata_bytes)?;
+
+        if let Some(max) = self.metadata_warning_bytes {
+            if metadata_bytes > max {
+                self.emit(EncoderEvent::Warning(format!(
+                    "metadata is {metadata_bytes} bytes, over {max}; some players may not read it"
+                )));
+            }
         }
 
-        Ok(encoder)
+        self.metadata.set_on(encoder)?;
+
+        Ok(handle)
     }
 
-    pub fn write_file(mut self, path: impl AsRef<Path>) -> Result<(), EncoderError> {
-        unsafe {
-            let encoder = self.prepare()?;
+    /// Describes the settings used, for the `ENCODERSETTINGS` comment. Must be called after the
+    /// encoder has been configured.
+    unsafe fn encoder_settings(&self, encoder: *mut FLAC__StreamEncoder) -> String {
+        let compression = match self.compression_level.preset() {
+            Some(preset) => preset.to_string(),
+            None => "custom".to_string(),
+        };
+        // libFLAC only picks the default block size in init, the same way as here.
+        let blocksize = match FLAC__stream_encoder_get_blocksize(encoder) {
+            0 if FLAC__stream_encoder_get_max_lpc_order(encoder) == 0 => 1152,
+            0 => 4096,
+            blocksize => blocksize,
+        };
+        let apodization = self.compression_level.settings().apodization;
+
+        format!(
+            "compression={compression} blocksize={blocksize} apodization={apodization} flac-encoder={} libFLAC={}",
+            env!("CARGO_PKG_VERSION"),
+            libflac_version(),
+        )
+    }
 
-            let Ok(path) = CString::from_str(&path.as_ref().to_string_lossy()) else {
-                return Err(EncoderError::NullCharInPath);
-            };
+    /// Writes the encoded stream to `path`. If it is a named pipe or character device, e.g.
+    /// `/dev/stdout`, the stream is written straight through without seeking back to finalize
+    /// the header, and a verify failure fails the encode whatever the verify failure policy.
+    pub fn write_file(self, path: impl AsRef<Path>) -> Result<(), EncoderError> {
+        self.write_file_with_report(path).map(|_| ())
+    }
 
-            FLAC__stream_encoder_init_file(
-                encoder,
-                path.as_bytes().as_ptr() as *const _,
-                None,
-                null_mut(),
-            );
+    /// Like [`write_file`](Self::write_file) but also returns what was found out about the input.
+    pub fn write_file_with_report(
+        mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<EncodeReport, EncoderError> {
+        let path = path.as_ref();
+
+        let write_path = if self.atomic_write && !is_stream_path(path) {
+            atomic::temp_path(path, self.temp_dir.as_deref())?
+        } else {
+            path.to_path_buf()
+        };
+
+        if self.preflight && !is_stream_path(path) {
+            preflight::check_destination(&write_path, self.estimated_output_bytes())?;
+        }
 
-            self.feed_entire_input(encoder)?;
+        let result = self
+            .with_verify_policy(!is_stream_path(&write_path), |builder, verify| {
+                builder.write_file_once(&write_path, verify)
+            })
+            .map(|((), report)| report);
 
-            finish(encoder)?;
+        if write_path != path {
+            let result = result.and_then(|report| {
+                std::fs::rename(&write_path, path).map_err(EncoderError::Io)?;
+                Ok(report)
+            });
+
+            if result.is_err() {
+                let _ = std::fs::remove_file(&write_path);
+            }
 
-            Ok(())
+            return result;
         }
+
+        result
     }
 
-    pub fn build(mut self) -> Result<Vec<u8>, EncoderError> {
-        unsafe {
-            let encoder = self.prepare()?;
+    /// The file is opened here rather than by libFLAC so that any path Rust can open works,
+    /// including long and non-ANSI paths on Windows.
+    fn write_file_once(
+        &mut self,
+        path: &Path,
+        verify: bool,
+    ) -> Result<((), EncodeReport), EncoderError> {
+        if is_stream_path(path) {
+            let file = OpenOptions::new()
+                .write(true)
+                .open(path)
+                .map_err(EncoderError::Io)?;
+            return self.encode_to_sink(Streamed(BufWriter::new(file)), verify);
+        }
 
-            let mut callback_data = WriteCallbackData {
-                data: Vec::with_capacity(self.data.total_samples()),
-                cursor: 0,
-            };
+        let file = File::create(path).map_err(EncoderError::Io)?;
+        let sink = Seekable::new(BufWriter::new(file)).map_err(EncoderError::Io)?;
+        let result = self.encode_to_sink(sink, verify);
+        self.finalize_written_file(path, result)
+    }
 
-            FLAC__stream_encoder_init_stream(
-                encoder,
-                Some(write_callback),
-                Some(seek_callback),
-                Some(tell_callback),
-                None,
-                &mut callback_data as *mut _ as *mut c_void,
-            );
+    /// Shrinks the padding if asked to and fills in the output hash of a file whose header was
+    /// rewritten after the audio. A cancelled encode's file is deleted.
+    fn finalize_written_file(
+        &self,
+        path: &Path,
+        result: Result<((), EncodeReport), EncoderError>,
+    ) -> Result<((), EncodeReport), EncoderError> {
+        if let Err(EncoderError::Cancelled) = result {
+            let _ = std::fs::remove_file(path);
+        }
 
-            self.feed_entire_input(encoder)?;
+        let ((), mut report) = result?;
 
-            finish(encoder)?;
+        if let Some(max_padding) = self.max_padding {
+            report.encoded_bytes -= reclaim_padding(path, max_padding)? as usize;
+        }
 
-            Ok(callback_data.data)
+        if let Some(algorithm) = self.output_hash {
+            report.output_hash =
+                Some(OutputHash::of_file(algorithm, path).map_err(EncoderError::Io)?);
         }
-    }
 
-    fn feed_entire_input(&mut self, encoder: *mut FLAC__StreamEncoder) -> Result<(), EncoderError> {
-        let mut input_cursor = 0;
+        Ok(((), report))
+    }
 
-        while input_cursor < self.data.samples_per_channel() {
-            self.consume_input_chunk(encoder, &mut input_cursor, 1024)?;
+    /// Like [`write_file_with_report`](Self::write_file_with_report) but writes through
+    /// io_uring, keeping several large writes in flight while encoding continues. Experimental;
+    /// only worth it when many encodes write at once.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn write_file_with_io_uring(
+        mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<EncodeReport, EncoderError> {
+        let path = path.as_ref();
+
+        if self.preflight {
+            preflight::check_destination(path, self.estimated_output_bytes())?;
         }
 
-        Ok(())
+        self.with_verify_policy(true, |builder, verify| {
+            let file = uring::UringFile::create(path).map_err(EncoderError::Io)?;
+            let sink = Seekable::new(file).map_err(EncoderError::Io)?;
+            let result = builder.encode_to_sink(sink, verify);
+            builder.finalize_written_file(path, result)
+        })
+        .map(|((), report)| report)
     }
 
-    fn consume_input_chunk(
-        &mut self,
-        encoder: *mut FLAC__StreamEncoder,
-        input_cursor: &mut usize,
-        chunk_size: usize,
-    ) -> Result<(), EncoderError> {
-        let channels = self.data.channel_count();
+    /// Writes the encoded stream to any seekable writer, e.g. a `File` or an `io::Cursor`,
+    /// starting at the writer's current position. Writes are passed straight through, so wrap
+    /// unbuffered writers in a `BufWriter`. For writers that can't seek, like
+    /// a `TcpStream`, use [`write_to_sink`](Self::write_to_sink) with [`Streamed`].
+    pub fn write_to(self, writer: impl Write + Seek) -> Result<(), EncoderError> {
+        let sink = Seekable::new(writer).map_err(EncoderError::Io)?;
+        self.write_to_sink(sink).map(|_| ())
+    }
 
-        let mut input_data: Vec<FLAC__int32> = Vec::with_capacity(chunk_size * channels);
+    /// Encodes into any [`ByteSink`], e.g. a socket wrapped in [`Streamed`] or a channel. The
+    /// sink keeps what was written, so a verify failure fails the encode whatever the verify
+    /// failure policy. [`hash_output`](Self::hash_output) is only filled in for sinks that
+    /// can't overwrite, since the header rewrite comes after everything else.
+    pub fn write_to_sink(mut self, mut sink: impl ByteSink) -> Result<EncodeReport, EncoderError> {
+        self.with_verify_policy(false, |builder, verify| {
+            builder.encode_to_sink(&mut sink, verify)
+        })
+        .map(|((), report)| report)
+    }
 
-        for block_sample_i in 0..chunk_size {
-            for channel_i in 0..self.data.channel_count() {
-                input_data.push(
-                    match &self.data {
-                        InputData::Interleaved { data, channels } => data
-                            .get((*input_cursor + block_sample_i) * channels + channel_i)
-                            .copied()
-                            .unwrap_or(Sample::default()),
-                        InputData::Planar(data) => data
-                            .get(channel_i)
-                            .and_then(|c| c.get(*input_cursor + block_sample_i))
-                            .copied()
-                            .unwrap_or(Sample::default()),
-                    }
-                    .to_bps_level(self.bps),
-                );
-            }
+    fn encode_to_sink<S: ByteSink>(
+        &mut self,
+        sink: S,
+        verify: bool,
+    ) -> Result<((), EncodeReport), EncoderError> {
+        // Created before the encoder so it outlives it.
+        let mut sink = SinkState::new(sink);
+        if !sink.sink.can_overwrite() {
+            sink.hasher = self.output_hash.map(Hasher::new);
         }
 
-        let remaining = self.data.total_samples() - *input_cursor;
-        let actual_size = chunk_size.min(remaining);
+        let result = unsafe {
+            self.prepare(verify).and_then(|encoder| {
+                let prepared = self.encode_start.elapsed();
 
-        let block_ptr = input_data.as_ptr() as *const FLAC__int32;
+                // Both libFLAC and the progress reports go through this pointer; the reports
+                // only read it between calls into libFLAC.
+                let sink_state: *mut SinkState<S> = &mut sink;
+                init_sink(encoder.as_ptr(), &mut *sink_state)?;
+                let written = || {
+                    let sink = &*sink_state;
+                    (sink.samples_written, sink.len)
+                };
 
-        unsafe {
-            if 0 == FLAC__stream_encoder_process_interleaved(encoder, block_ptr, actual_size as u32)
-            {
-                return Err(EncoderError::EncodingError);
-            }
-        }
+                let mut report = self.feed_entire_input(encoder.as_ptr(), written)?;
 
-        *input_cursor += chunk_size;
+                encoder.finish()?;
+                self.report_progress(written());
 
-        Ok(())
-    }
+                report.timings.prepared = prepared;
+                Ok(report)
+            })
+        };
 
-    unsafe fn cleanup(&mut self) {
-        for block in self.metadata_blocks.iter() {
-            FLAC__metadata_object_delete(*block);
+        if let Some(e) = sink.error.take() {
+            return Err(EncoderError::Io(e));
         }
-        self.metadata_blocks.clear();
+
+        let mut report = result?;
+        report.encoded_bytes = sink.len as usize;
+        report.output_hash = sink.hasher.take().map(Hasher::finish);
+        sink.sink.flush().map_err(EncoderError::Io)?;
+
+        report.timings.first_frame = sink.first_frame.map(|t| t - self.encode_start);
+        report.timings.finished = self.encode_start.elapsed();
+
+        Ok(((), report))
     }
-}
 
-unsafe fn finish(encoder: *mut FLAC__StreamEncoder) -> Result<(), EncoderError> {
-    if 0 == FLAC__stream_encoder_finish(encoder) {
-        return Err(EncoderError::EncodingError);
+    pub fn build(self) -> Result<Vec<u8>, EncoderError> {
+        self.build_with_report().map(|(data, _)| data)
     }
 
-    Ok(())
-}
+    /// Like [`build`](Self::build) but returns [`FlacBytes`], whose metadata and audio frames
+    /// can be sliced out without copying.
+    #[cfg(feature = "bytes")]
+    pub fn build_bytes(self) -> Result<FlacBytes, EncoderError> {
+        FlacBytes::new(self.build()?.into())
+    }
 
-impl<'data, Sample: IntoSample> Drop for FlacBuilder<'data, Sample> {
-    fn drop(&mut self) {
-        unsafe {
-            self.cleanup();
+    /// Like [`build`](Self::build) but also returns what was found out about the input.
+    pub fn build_with_report(mut self) -> Result<(Vec<u8>, EncodeReport), EncoderError> {
+        if self.preflight {
+            preflight::check_memory(self.estimated_output_bytes())?;
         }
+
+        self.with_verify_policy(true, Self::build_once)
     }
-}
 
-#[derive(Debug, Clone, Copy)]
-pub enum BpsLevel {
-    Bps16,
-    Bps20,
-    Bps24,
-}
+    fn build_once(&mut self, verify: bool) -> Result<(Vec<u8>, EncodeReport), EncoderError> {
+        let mut data = Vec::with_capacity(self.data.total_samples());
 
-impl BpsLevel {
-    fn to_u32(&self) -> u32 {
-        match self {
-            BpsLevel::Bps16 => 16,
-            BpsLevel::Bps20 => 20,
-            BpsLevel::Bps24 => 24,
-        }
+        let ((), mut report) = self.encode_to_sink(&mut data, verify)?;
+        report.output_hash = self
+            .output_hash
+            .map(|algorithm| OutputHash::of_bytes(algorithm, &data));
+
+        Ok((data, report))
     }
-}
 
-struct WriteCallbackData {
-    data: Vec<u8>,
-    cursor: usize,
-}
+    /// Encodes `intro` followed by `loop_range`, both frame ranges of the input, for engines
+    /// that play the intro once and then repeat the loop. The loop is marked with the
+    /// `LOOPSTART` and `LOOPLENGTH` vorbis comments, in frames, which most game engines and
+    /// tools read. Fails with [`EncoderError::LoopDiscontinuity`] if jumping from the end of the
+    /// loop back to its start, or from the intro into the loop, steps further than any two
+    /// neighbouring frames inside the loop do, as that would click on every repeat. A loop of
+    /// a single frame isn't checked.
+    pub fn export_looped(
+        self,
+        intro: Range<usize>,
+        loop_range: Range<usize>,
+    ) -> Result<Vec<u8>, EncoderError> {
+        if self.is_streamed() {
+            return Err(EncoderError::NeedsWholeInput("export_looped"));
+        }
+        if self.data.channel_count() == 0 {
+            return Err(EncoderError::NoData);
+        }
 
-enum InputData<'a, Sample>
-where
+        let frames = self.data.samples_per_channel();
+
+        if loop_range.is_empty()
+            || intro.start > intro.end
+            || intro.end > frames
+            || loop_range.end > frames
+        {
+            return Err(EncoderError::InvalidLoopRange);
+        }
+        if !self.data.channel_sizes_match() {
+            return Err(EncoderError::MismatchedSampleCountPerChannels);
+        }
+
+        let channels = self.data.channel_count();
+        let intro_samples = self.convert_chunk(intro.start, intro.len());
+        let loop_samples = self.convert_chunk(loop_range.start, loop_range.len());
+
+        check_loop_continuity(&intro_samples, &loop_samples, channels)?;
+
+        let block = AudioBlock {
+            channels,
+            bps: self.bps.to_u32(),
+            sample_rate: self.sample_rate,
+            samples: [intro_samples, loop_samples].concat(),
+        };
+
+        let mut looped = self.with_input::<Sample>(InputData::Block(&block));
+        // Already quantized and faded by `convert_chunk`.
+        looped.source_bps = None;
+        looped.fade_in = Duration::ZERO;
+        looped.fade_out = Duration::ZERO;
+
+        looped
+            .vorbis_comment("LOOPSTART", &intro.len().to_string())
+            .vorbis_comment("LOOPLENGTH", &loop_range.len().to_string())
+            .build()
+    }
+
+    /// Produce two encodes from a single pass over the input, e.g. a 24-bit archival copy and a
+    /// 16-bit distribution copy. `configure_second` receives a copy of this builder (same input
+    /// and settings) to adjust for the second output. Sample conversion is shared between the
+    /// two when they use the same bps, source bps, soft clipping and fades.
+    ///
+    /// Each output follows its own silent input and dual mono policies, an output skipped as
+    /// silent being empty, and both are empty when this builder skips the input. A verify
+    /// failure in either encodes both again as this builder's verify failure policy says.
+    pub fn build_tee(
+        mut self,
+        configure_second: impl FnOnce(Self) -> Self,
+    ) -> Result<(Vec<u8>, Vec<u8>), EncoderError> {
+        let mut second = configure_second(self.duplicate());
+        let skip_second = second.apply_input_policies(Instant::now())?.is_some();
+
+        self.with_verify_policy(true, |first, verify| {
+            let outputs = first.tee_once(&mut second, skip_second, verify)?;
+            Ok((outputs, first.input_report()))
+        })
+        .map(|(outputs, _)| outputs)
+    }
+
+    fn tee_once(
+        &mut self,
+        second: &mut Self,
+        skip_second: bool,
+        verify: bool,
+    ) -> Result<(Vec<u8>, Vec<u8>), EncoderError> {
+        // Created before the encoders so they outlive them.
+        let mut first_data = SinkState::new(Vec::with_capacity(self.data.total_samples()));
+        let mut second_data = SinkState::new(Vec::new());
+
+        let result = unsafe {
+            self.tee_into(
+                second,
+                skip_second,
+                verify,
+                &mut first_data,
+                &mut second_data,
+            )
+        };
+
+        for data in [&mut first_data, &mut second_data] {
+            if let Some(e) = data.error.take() {
+                return Err(EncoderError::Io(e));
+            }
+        }
+        result?;
+
+        Ok((first_data.sink, second_data.sink))
+    }
+
+    unsafe fn tee_into(
+        &mut self,
+        second: &mut Self,
+        skip_second: bool,
+        verify: bool,
+        first_data: &mut SinkState<Vec<u8>>,
+        second_data: &mut SinkState<Vec<u8>>,
+    ) -> Result<(), EncoderError> {
+        let first_encoder = self.prepare(verify)?;
+        init_sink(first_encoder.as_ptr(), first_data)?;
+
+        let second_encoder = if skip_second {
+            None
+        } else {
+            second_data.sink.reserve(self.data.total_samples());
+            let encoder = second.prepare(verify)?;
+            init_sink(encoder.as_ptr(), second_data)?;
+            Some(encoder)
+        };
+
+        self.emit(EncoderEvent::MetadataWritten);
+
+        let shared_conversion = second.converts_like(self);
+        let mut input_cursor = 0;
+
+        loop {
+            self.check_cancelled()?;
+
+            // Both outputs convert the same chunk read from a source.
+            let read = self.read_source_chunk(input_cursor)?;
+            let first_chunk = self.convert_next(read.as_ref(), input_cursor, None);
+            if first_chunk.is_empty() {
+                break;
+            }
+            let frames = first_chunk.len() / self.encoded_channels();
+
+            process_chunk(
+                first_encoder.as_ptr(),
+                &first_chunk,
+                self.encoded_channels(),
+            )?;
+
+            if let Some(second_encoder) = &second_encoder {
+                let channels = second.encoded_channels();
+
+                if shared_conversion {
+                    process_chunk(second_encoder.as_ptr(), &first_chunk, channels)?;
+                } else {
+                    let second_chunk = second.convert_next(read.as_ref(), input_cursor, None);
+                    process_chunk(second_encoder.as_ptr(), &second_chunk, channels)?;
+                }
+            }
+
+            self.emit(EncoderEvent::ChunkDone {
+                samples_done: input_cursor + frames,
+                samples_per_channel: self.data.samples_per_channel(),
+            });
+
+            if let Some(hook) = &mut self.yield_hook {
+                hook();
+            }
+
+            input_cursor += frames;
+        }
+
+        self.check_source_read(input_cursor)?;
+
+        first_encoder.finish()?;
+        if let Some(second_encoder) = second_encoder {
+            second_encoder.finish()?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether `convert_chunk` gives the same samples for both builders.
+    fn converts_like(&self, other: &Self) -> bool {
+        self.bps == other.bps
+            && self.source_bps == other.source_bps
+            && self.soft_clip == other.soft_clip
+            && self.fade_in == other.fade_in
+            && self.fade_out == other.fade_out
+            && self.collapse_to_mono == other.collapse_to_mono
+    }
+
+    /// Applies the input policies, then runs `encode` as many times as the verify failure
+    /// policy allows, passing whether to verify. Unless `repeatable`, `encode` writes where a
+    /// second run can't replace the first, so it only runs once.
+    fn with_verify_policy<T: Default>(
+        &mut self,
+        repeatable: bool,
+        mut encode: impl FnMut(&mut Self, bool) -> Result<(T, EncodeReport), EncoderError>,
+    ) -> Result<(T, EncodeReport), EncoderError> {
+        let start = Instant::now();
+
+        if let Some(report) = self.apply_input_policies(start)? {
+            return Ok((T::default(), report));
+        }
+
+        let mut retries = match self.verify_failure_policy {
+            VerifyFailurePolicy::Retry(n) => n,
+            _ => 0,
+        };
+
+        let result = loop {
+            self.encode_start = Instant::now();
+            self.emit(EncoderEvent::Started {
+                samples_per_channel: self.data.samples_per_channel(),
+            });
+
+            match encode(self, true) {
+                // The source has already been read, or the output can't be taken back.
+                Err(EncoderError::VerifyMismatch) if self.is_streamed() || !repeatable => {
+                    break Err(EncoderError::VerifyMismatch)
+                }
+                Err(EncoderError::VerifyMismatch) if retries > 0 => {
+                    self.emit(EncoderEvent::Warning(
+                        "verification failed, encoding again".to_string(),
+                    ));
+                    retries -= 1;
+                }
+                Err(EncoderError::VerifyMismatch)
+                    if self.verify_failure_policy == VerifyFailurePolicy::Warn =>
+                {
+                    self.emit(EncoderEvent::Warning(
+                        "verification failed, encoding again without it".to_string(),
+                    ));
+                    self.encode_start = Instant::now();
+                    self.emit(EncoderEvent::Started {
+                        samples_per_channel: self.data.samples_per_channel(),
+                    });
+
+                    break encode(self, false).map(|(output, mut report)| {
+                        report.verify_failed = true;
+                        (output, report)
+                    });
+                }
+                result => break result,
+            }
+        };
+
+        let result = result.map(|(output, mut report)| {
+            report.encode_time = start.elapsed();
+            report.collapsed_to_mono = self.collapse_to_mono;
+            (output, report)
+        });
+
+        if result.is_ok() {
+            self.emit(EncoderEvent::Finished);
+        }
+
+        result
+    }
+
+    /// Applies the silent input and dual mono policies, returning the report to give back
+    /// instead of encoding if the input is skipped.
+    fn apply_input_policies(
+        &mut self,
+        start: Instant,
+    ) -> Result<Option<EncodeReport>, EncoderError> {
+        // Checked again in `prepare`, but the scans below divide by the channel count.
+        if self.data.channel_count() == 0 {
+            return Err(EncoderError::NoData);
+        }
+        if !self.data.channel_sizes_match() {
+            return Err(EncoderError::MismatchedSampleCountPerChannels);
+        }
+
+        if self.is_streamed() {
+            if self.silent_input_policy != SilentInputPolicy::Encode {
+                return Err(EncoderError::NeedsWholeInput("on_silent_input"));
+            }
+            if self.dual_mono_policy != DualMonoPolicy::Encode {
+                return Err(EncoderError::NeedsWholeInput("on_dual_mono"));
+            }
+        }
+
+        if self.silent_input_policy != SilentInputPolicy::Encode
+            && self.data.total_samples() > 0
+            && self.is_digital_silence()
+        {
+            match self.silent_input_policy {
+                SilentInputPolicy::Encode => {}
+                SilentInputPolicy::EncodeFast => self.compression_level = CompressionLevel::L0,
+                SilentInputPolicy::Skip => {
+                    let report = EncodeReport {
+                        skipped_silent_input: true,
+                        encode_time: start.elapsed(),
+                        ..self.input_report()
+                    };
+                    return Ok(Some(report));
+                }
+                SilentInputPolicy::Error => return Err(EncoderError::SilentInput),
+            }
+        }
+
+        self.collapse_to_mono = false;
+        if self.dual_mono_policy != DualMonoPolicy::Encode && self.is_dual_mono() {
+            match self.dual_mono_policy {
+                DualMonoPolicy::Encode => {}
+                DualMonoPolicy::Collapse => self.collapse_to_mono = true,
+                DualMonoPolicy::Warn => self.emit(EncoderEvent::Warning(
+                    "every channel is identical, the input could be encoded as mono".to_string(),
+                )),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// A builder with the same input and settings, without any of the prepared FFI state.
+    fn duplicate(&self) -> Self {
+        self.with_input(self.data)
+    }
+
+    /// A builder with the same settings as this one but different input.
+    fn with_input<'other, Other: IntoSample>(
+        &self,
+        data: InputData<'other, Other>,
+    ) -> FlacBuilder<'other, Other> {
+        FlacBuilder {
+            data,
+            audio_source: None,
+            bps: self.bps,
+            source_bps: self.source_bps,
+            soft_clip: self.soft_clip,
+            fade_in: self.fade_in,
+            fade_out: self.fade_out,
+            sample_rate: self.sample_rate,
+            compression_level: self.compression_level.clone(),
+            padding: self.padding,
+            max_padding: self.max_padding,
+            lax: self.lax,
+            limit_min_bitrate: self.limit_min_bitrate,
+            preflight: self.preflight,
+            atomic_write: self.atomic_write,
+            temp_dir: self.temp_dir.clone(),
+            encoder_settings_tag: self.encoder_settings_tag,
+            silence_detection: self.silence_detection,
+            samples_per_peak: self.samples_per_peak,
+            channel_stats: self.channel_stats,
+            verify_failure_policy: self.verify_failure_policy,
+            tag_profile: self.tag_profile.clone(),
+            normalize_numeric_tags: self.normalize_numeric_tags,
+            empty_input_policy: self.empty_input_policy,
+            limits: self.limits,
+            metadata_warning_bytes: self.metadata_warning_bytes,
+            silent_input_policy: self.silent_input_policy,
+            dual_mono_policy: self.dual_mono_policy,
+            collapse_to_mono: false,
+            trace_chunk_times: self.trace_chunk_times,
+            output_hash: self.output_hash,
+            encode_start: Instant::now(),
+            event_handler: None,
+            progress_handler: None,
+            cancel_token: self.cancel_token.clone(),
+            processor: None,
+            yield_hook: None,
+            vorbis_comments: self.vorbis_comments.clone(),
+            annotations: self.annotations.clone(),
+            pictures: self.pictures.clone(),
+            seek_table: self.seek_table.clone(),
+            cue_sheet: self.cue_sheet.clone(),
+            metadata: MetadataSession::new(),
+        }
+    }
+
+    /// Feeds the input to `encoder`, calling `written` for the samples and bytes written so far
+    /// to report progress.
+    fn feed_entire_input(
+        &mut self,
+        encoder: *mut FLAC__StreamEncoder,
+        written: impl Fn() -> (u64, u64),
+    ) -> Result<EncodeReport, EncoderError> {
+        let channels = self.encoded_channels();
+        let mut input_cursor = 0;
+        let mut chunk_times = vec![];
+
+        let mut silence_detector = self
+            .silence_detection
+            .map(|settings| SilenceDetector::new(settings, self.bps, self.sample_rate));
+        let mut peaks = self
+            .samples_per_peak
+            .map(|n| PeakCollector::new(n, channels, self.bps));
+        let mut stats = self
+            .channel_stats
+            .then(|| StatsCollector::new(channels, self.bps));
+        let mut loss_meter = LossMeter::new();
+
+        // Every caller initializes the encoder, which writes the metadata, before feeding it.
+        self.emit(EncoderEvent::MetadataWritten);
+
+        if let Some(processor) = &mut self.processor {
+            let tags: Vec<(String, String)> = self
+                .vorbis_comments
+                .iter()
+                .map(|(k, v)| (k.to_string_lossy().into(), v.to_string_lossy().into()))
+                .collect();
+
+            processor.start(&ProcessorContext {
+                channels,
+                sample_rate: self.sample_rate,
+                bps: self.bps.to_u32(),
+                total_frames: self.data.samples_per_channel(),
+                tags: &tags,
+            });
+        }
+
+        loop {
+            self.check_cancelled()?;
+
+            let read = self.read_source_chunk(input_cursor)?;
+            let mut chunk = self.convert_next(read.as_ref(), input_cursor, Some(&mut loss_meter));
+            if chunk.is_empty() {
+                break;
+            }
+
+            if let Some(processor) = &mut self.processor {
+                processor.process(&mut chunk, channels, input_cursor);
+            }
+            process_chunk(encoder, &chunk, channels)?;
+            self.report_progress(written());
+
+            self.emit(EncoderEvent::ChunkDone {
+                samples_done: input_cursor + chunk.len() / channels,
+                samples_per_channel: self.data.samples_per_channel(),
+            });
+
+            if let Some(detector) = &mut silence_detector {
+                detector.feed(&chunk, channels);
+            }
+
+            if let Some(peaks) = &mut peaks {
+                peaks.feed(&chunk, channels);
+            }
+
+            if let Some(stats) = &mut stats {
+                stats.feed(&chunk, channels);
+            }
+
+            if self.trace_chunk_times {
+                chunk_times.push(self.encode_start.elapsed());
+            }
+
+            if let Some(hook) = &mut self.yield_hook {
+                hook();
+            }
+
+            input_cursor += chunk.len() / channels;
+        }
+
+        self.check_source_read(input_cursor)?;
+
+        Ok(EncodeReport {
+            silent_regions: silence_detector
+                .map(SilenceDetector::finish)
+                .unwrap_or_default(),
+            peaks: peaks.map(PeakCollector::finish),
+            channel_stats: stats.map(StatsCollector::finish).unwrap_or_default(),
+            conversion_loss: loss_meter.finish(),
+            timings: EncodeTimings {
+                chunks: chunk_times,
+                ..Default::default()
+            },
+            ..self.input_report_for(input_cursor)
+        })
+    }
+
+    /// Whether the input is read from a source set by `from_source`.
+    fn is_streamed(&self) -> bool {
+        matches!(self.data, InputData::Source { .. })
+    }
+
+    /// Reads the next chunk from the source set by `from_source`, or `None` for in-memory
+    /// input. The block is empty once the source is done.
+    fn read_source_chunk(
+        &mut self,
+        input_cursor: usize,
+    ) -> Result<Option<AudioBlock>, EncoderError> {
+        let Some(audio_source) = &mut self.audio_source else {
+            return Ok(None);
+        };
+
+        let block = source::read_block(audio_source.as_mut(), CHUNK_SIZE)?;
+        self.limits.check(
+            LimitKind::Samples,
+            input_cursor * block.channels + block.samples.len(),
+        )?;
+
+        Ok(Some(block))
+    }
+
+    /// Fails if nothing at all was read from a source and empty input isn't allowed, which
+    /// can't be checked before encoding like it is for in-memory input.
+    fn check_source_read(&self, frames: usize) -> Result<(), EncoderError> {
+        if self.is_streamed() && frames == 0 && self.empty_input_policy == EmptyInputPolicy::Error {
+            return Err(EncoderError::NoData);
+        }
+
+        Ok(())
+    }
+
+    /// Interleaved samples at the target bps for the chunk at `input_cursor`: `read` if it was
+    /// read from a source, otherwise up to `CHUNK_SIZE` frames of the in-memory input. Empty
+    /// at the end of the input.
+    fn convert_next(
+        &self,
+        read: Option<&AudioBlock>,
+        input_cursor: usize,
+        loss: Option<&mut LossMeter>,
+    ) -> Vec<FLAC__int32> {
+        match read {
+            Some(block) => {
+                // A fade out ends at the source's length hint.
+                let total_frames = match self.data {
+                    InputData::Source {
+                        frames: Some(frames),
+                        ..
+                    } => frames,
+                    _ => usize::MAX,
+                };
+                let data = InputData::Block(block);
+
+                self.convert_input(&data, 0, CHUNK_SIZE, input_cursor, total_frames, loss)
+            }
+            None if input_cursor < self.data.samples_per_channel() => {
+                self.convert_chunk_measured(input_cursor, CHUNK_SIZE, loss)
+            }
+            None => vec![],
+        }
+    }
+
+    /// A report with only what is known about the input before encoding.
+    fn input_report(&self) -> EncodeReport {
+        self.input_report_for(self.data.samples_per_channel())
+    }
+
+    /// Like `input_report` for `frames` frames of input, e.g. what was read from a source.
+    fn input_report_for(&self, frames: usize) -> EncodeReport {
+        EncodeReport {
+            pcm_bytes: frames * self.data.channel_count() * self.bps.to_u32() as usize / 8,
+            input_duration: Duration::from_secs_f64(frames as f64 / self.sample_rate as f64),
+            ..Default::default()
+        }
+    }
+
+    /// Whether there's more than one channel and they all match sample for sample at the
+    /// target bps.
+    fn is_dual_mono(&self) -> bool {
+        let channels = self.data.channel_count();
+
+        channels > 1
+            && self.data.channel_sizes_match()
+            && (0..self.data.samples_per_channel())
+                .step_by(CHUNK_SIZE)
+                .all(|cursor| {
+                    self.convert_chunk(cursor, CHUNK_SIZE)
+                        .chunks_exact(channels)
+                        .all(|frame| frame.iter().all(|&s| s == frame[0]))
+                })
+    }
+
+    /// Channels handed to libFLAC, which is only the first when collapsing dual mono.
+    fn encoded_channels(&self) -> usize {
+        if self.collapse_to_mono {
+            1
+        } else {
+            self.data.channel_count()
+        }
+    }
+
+    /// Whether every sample is zero at the target bps.
+    fn is_digital_silence(&self) -> bool {
+        (0..self.data.samples_per_channel())
+            .step_by(CHUNK_SIZE)
+            .all(|cursor| {
+                self.convert_chunk(cursor, CHUNK_SIZE)
+                    .iter()
+                    .all(|&s| s == 0)
+            })
+    }
+
+    fn emit(&mut self, event: EncoderEvent) {
+        if let Some(handler) = &mut self.event_handler {
+            handler(event);
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<(), EncoderError> {
+        match &self.cancel_token {
+            Some(token) if token.load(Ordering::Relaxed) => Err(EncoderError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    fn report_progress(&mut self, (encoded_samples, bytes_written): (u64, u64)) {
+        let total_samples = self.data.samples_per_channel() as u64;
+        if let Some(handler) = &mut self.progress_handler {
+            handler(encoded_samples, total_samples, bytes_written);
+        }
+    }
+
+    /// Interleaved samples at the target bps for up to `chunk_size` frames from `input_cursor`.
+    fn convert_chunk(&self, input_cursor: usize, chunk_size: usize) -> Vec<FLAC__int32> {
+        self.convert_chunk_measured(input_cursor, chunk_size, None)
+    }
+
+    /// Like `convert_chunk`, also recording the quantization error of float samples in `loss`.
+    fn convert_chunk_measured(
+        &self,
+        input_cursor: usize,
+        chunk_size: usize,
+        loss: Option<&mut LossMeter>,
+    ) -> Vec<FLAC__int32> {
+        let total_frames = self.data.samples_per_channel();
+        self.convert_input(&self.data, input_cursor, chunk_size, 0, total_frames, loss)
+    }
+
+    /// Like [`convert_chunk_measured`](Self::convert_chunk_measured) but for other input with
+    /// the same format, where `data` starts `first_frame` frames into an output of
+    /// `total_frames`, for the fades.
+    fn convert_input(
+        &self,
+        data: &InputData<'_, Sample>,
+        input_cursor: usize,
+        chunk_size: usize,
+        first_frame: usize,
+        total_frames: usize,
+        mut loss: Option<&mut LossMeter>,
+    ) -> Vec<FLAC__int32> {
+        let channels = data.channel_count();
+        let frames = chunk_size.min(data.samples_per_channel() - input_cursor);
+
+        let mut input_data: Vec<FLAC__int32> = Vec::with_capacity(frames * self.encoded_channels());
+
+        let source_bps = match self.source_bps {
+            Some(source) if source.to_u32() < self.bps.to_u32() => source,
+            _ => self.bps,
+        };
+        let mut convert = |sample: Sample, gain: f64| {
+            let (float, quantized) = match sample.to_f64() {
+                Some(float) if gain < 1.0 || self.soft_clip.is_some() => {
+                    let float = match self.soft_clip {
+                        Some(ceiling) => dsp::soft_clip(float * gain, ceiling),
+                        None => float * gain,
+                    };
+                    (Some(float), float.to_bps_level(source_bps))
+                }
+                // Integers are faded like block samples, without going through floats.
+                None if gain < 1.0 => {
+                    let sample = sample.to_bps_level(source_bps) as f64 * gain;
+                    (None, sample.round() as FLAC__int32)
+                }
+                float => (float, sample.to_bps_level(source_bps)),
+            };
+
+            if let (Some(loss), Some(float)) = (loss.as_deref_mut(), float) {
+                loss.record(float, quantized, source_bps.to_u32());
+            }
+
+            rescale(quantized, source_bps.to_u32(), self.bps.to_u32())
+        };
+
+        let frames_for = |duration: Duration| {
+            (duration.as_secs_f64() * self.sample_rate as f64).round() as usize
+        };
+        let (fade_in, fade_out) = (frames_for(self.fade_in), frames_for(self.fade_out));
+
+        // Integer input with nothing to apply is converted in one pass, which is where most of
+        // the time goes for plain 16-bit input. Float input still goes through `convert` so the
+        // conversion loss is measured.
+        let is_plain = fade_in == 0
+            && fade_out == 0
+            && self.soft_clip.is_none()
+            && source_bps == self.bps
+            && self.encoded_channels() == channels
+            && Sample::default().to_f64().is_none();
+        if let (InputData::Interleaved { data, .. }, true) = (data, is_plain) {
+            let start = input_cursor * channels;
+            Sample::widen_slice(
+                &data[start..start + frames * channels],
+                self.bps,
+                &mut input_data,
+            );
+            return input_data;
+        }
+
+        for block_sample_i in 0..frames {
+            let gain = dsp::fade_gain(
+                first_frame + input_cursor + block_sample_i,
+                total_frames,
+                fade_in,
+                fade_out,
+            );
+
+            for channel_i in 0..self.encoded_channels() {
+                input_data.push(match data {
+                    InputData::Interleaved { data, channels } => convert(
+                        data.get((input_cursor + block_sample_i) * channels + channel_i)
+                            .copied()
+                            .unwrap_or(Sample::default()),
+                        gain,
+                    ),
+                    InputData::Planar(data) => convert(
+                        data.get(channel_i)
+                            .and_then(|c| c.get(input_cursor + block_sample_i))
+                            .copied()
+                            .unwrap_or(Sample::default()),
+                        gain,
+                    ),
+                    InputData::Block(block) => {
+                        let sample = block
+                            .samples
+                            .get((input_cursor + block_sample_i) * channels + channel_i)
+                            .copied()
+                            .unwrap_or(0);
+                        let sample = if gain < 1.0 {
+                            (sample as f64 * gain).round() as i32
+                        } else {
+                            sample
+                        };
+
+                        rescale(sample, block.bps, self.bps.to_u32())
+                    }
+                    // Read a chunk at a time and converted as a block, see `convert_next`.
+                    InputData::Source { .. } => 0,
+                });
+            }
+        }
+
+        input_data
+    }
+}
+
+impl<'data> FlacBuilder<'data, i32> {
+    /// New with an [`AudioBlock`], using its sample rate. The bps is set to the block's bps, or
+    /// the next one up if FLAC can't encode it; a block that doesn't fit in 24 bits is scaled
+    /// down. Block samples are integers, like the builder's `i32` sample type.
+    pub fn from_block(block: &'data AudioBlock) -> Self {
+        Self::new(InputData::Block(block), block.sample_rate).bps(BpsLevel::at_least(block.bps))
+    }
+
+    /// New with audio read from `source` a chunk at a time as it is encoded, so it never has to
+    /// be in memory at once, e.g. a [`PcmReader`] over a pipe. The bps is picked as for
+    /// [`from_block`](Self::from_block).
+    ///
+    /// The source can only be read once, so settings that need the whole input first fail with
+    /// [`EncoderError::NeedsWholeInput`]: the silent input and dual mono policies,
+    /// [`export_looped`](Self::export_looped), and a fade out when the source has no
+    /// [`len_hint`](AudioSource::len_hint), which is where a fade out ends.
+    /// [`auto_tune`](Self::auto_tune) and [`bps_auto`](Self::bps_auto) leave the settings as
+    /// they are, and a verify failure fails the encode whatever the verify failure policy.
+    pub fn from_source(source: impl AudioSource + 'data) -> Self {
+        let data = InputData::Source {
+            channels: source.channels(),
+            frames: source.len_hint(),
+        };
+        let bps = BpsLevel::at_least(source.bps());
+
+        let mut builder = Self::new(data, source.sample_rate()).bps(bps);
+        builder.audio_source = Some(Box::new(source));
+        builder
+    }
+}
+
+/// Checks that the frames played either side of the loop points are no further apart than
+/// neighbouring frames inside the loop. All slices are interleaved. A single frame loop has no
+/// neighbours to compare with and repeats without a jump, so it always passes.
+fn check_loop_continuity(
+    intro: &[FLAC__int32],
+    looped: &[FLAC__int32],
+    channels: usize,
+) -> Result<(), EncoderError> {
+    if looped.len() < 2 * channels {
+        return Ok(());
+    }
+
+    let first = &looped[..channels];
+
+    let mut boundaries = vec![&looped[looped.len() - channels..]];
+    if !intro.is_empty() {
+        boundaries.push(&intro[intro.len() - channels..]);
+    }
+
+    for channel in 0..channels {
+        let largest_step = looped
+            .iter()
+            .skip(channel)
+            .step_by(channels)
+            .zip(looped.iter().skip(channels + channel).step_by(channels))
+            .map(|(a, b)| (*a as i64 - *b as i64).abs())
+            .max()
+            .unwrap_or(0);
+
+        for last in &boundaries {
+            let jump = (last[channel] as i64 - first[channel] as i64).abs();
+            if jump > largest_step {
+                return Err(EncoderError::LoopDiscontinuity { channel });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves an integer sample from `from_bps` to `to_bps` by shifting.
+fn rescale(sample: i32, from_bps: u32, to_bps: u32) -> FLAC__int32 {
+    if to_bps >= from_bps {
+        sample << (to_bps - from_bps)
+    } else {
+        sample >> (from_bps - to_bps)
+    }
+}
+
+/// The version of the linked libFLAC, e.g. `1.4.3`.
+pub fn libflac_version() -> String {
+    unsafe { CStr::from_ptr(FLAC__VERSION_STRING) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Whether `path` is something that can't seek, like a named pipe or terminal.
+fn is_stream_path(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        std::fs::metadata(path)
+            .map(|m| m.file_type().is_fifo() || m.file_type().is_char_device())
+            .unwrap_or(false)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        false
+    }
+}
+
+/// The largest sample rate the FLAC format can store (20 bits in STREAMINFO).
+const MAX_SAMPLE_RATE: u32 = (1 << 20) - 1;
+
+/// Whether a frame header can express the sample rate, which the streamable subset requires.
+/// Frame headers can store the rate in Hz up to 65535, or in tens of Hz up to 655350.
+fn is_subset_sample_rate(sample_rate: u32) -> bool {
+    sample_rate <= u16::MAX as u32 || (sample_rate.is_multiple_of(10) && sample_rate <= 655350)
+}
+
+const CHUNK_SIZE: usize = 1024;
+
+fn process_chunk(
+    encoder: *mut FLAC__StreamEncoder,
+    chunk: &[FLAC__int32],
+    channels: usize,
+) -> Result<(), EncoderError> {
+    let frames = chunk.len() / channels;
+
+    unsafe {
+        if 0 == FLAC__stream_encoder_process_interleaved(encoder, chunk.as_ptr(), frames as u32) {
+            if FLAC__stream_encoder_get_state(encoder)
+                == FLAC__STREAM_ENCODER_VERIFY_MISMATCH_IN_AUDIO_DATA
+            {
+                return Err(EncoderError::VerifyMismatch);
+            }
+            let state = CStr::from_ptr(FLAC__stream_encoder_get_resolved_state_string(encoder));
+            return Err(EncoderError::EncodingError(
+                state.to_string_lossy().into_owned(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// See [`FlacBuilder::on_empty_input`]. Input without any channels is always an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyInputPolicy {
+    /// Fail with [`EncoderError::NoData`].
+    #[default]
+    Error,
+    /// Write a valid stream with no audio frames but all the metadata, e.g. a tag-only
+    /// placeholder.
+    EncodeEmpty,
+}
+
+/// See [`FlacBuilder::on_silent_input`]. Anything other than `Encode` costs an extra pass over
+/// the input to check it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SilentInputPolicy {
+    /// Encode without checking.
+    #[default]
+    Encode,
+    /// Encode, but at compression level 0 if the input is silent. Silence encodes to constant
+    /// subframes at any level, so this gives nearly the same size while skipping the analysis
+    /// that higher levels do.
+    EncodeFast,
+    /// Don't encode silent input; nothing is written and [`EncodeReport::skipped_silent_input`]
+    /// is set. [`build`](FlacBuilder::build) returns no bytes.
+    Skip,
+    /// Fail with [`EncoderError::SilentInput`].
+    Error,
+}
+
+/// See [`FlacBuilder::on_dual_mono`]. Anything other than `Encode` costs an extra pass over the
+/// input to check it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DualMonoPolicy {
+    /// Encode without checking.
+    #[default]
+    Encode,
+    /// Encode only the first channel, as a mono stream, and set
+    /// [`EncodeReport::collapsed_to_mono`]. FLAC's stereo decorrelation already makes identical
+    /// channels cheap, but mono also halves what players have to decode and move around.
+    Collapse,
+    /// Encode as is and emit an [`EncoderEvent::Warning`].
+    Warn,
+}
+
+/// See [`FlacBuilder::on_verify_failure`]. libFLAC can't redo a single block once verification
+/// fails, so retries and warnings apply to the whole encode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VerifyFailurePolicy {
+    /// Fail the encode with [`EncoderError::VerifyMismatch`].
+    #[default]
+    Error,
+    /// Encode again without verification and set [`EncodeReport::verify_failed`].
+    Warn,
+    /// Encode again up to this many times before failing.
+    Retry(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BpsLevel {
+    Bps16,
+    Bps20,
+    Bps24,
+    /// Outside the streamable subset, see [`FlacBuilder::bps`].
+    Bps32,
+}
+
+impl BpsLevel {
+    /// The smallest level with at least `bits`, or 24 if none has.
+    fn at_least(bits: u32) -> Self {
+        match bits {
+            0..=16 => BpsLevel::Bps16,
+            17..=20 => BpsLevel::Bps20,
+            _ => BpsLevel::Bps24,
+        }
+    }
+
+    fn to_u32(&self) -> u32 {
+        match self {
+            BpsLevel::Bps16 => 16,
+            BpsLevel::Bps20 => 20,
+            BpsLevel::Bps24 => 24,
+            BpsLevel::Bps32 => 32,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum InputData<'a, Sample>
+where
     Sample: IntoSample,
 {
-    Interleaved { data: &'a [Sample], channels: usize },
+    Interleaved {
+        data: &'a [Sample],
+        channels: usize,
+    },
     Planar(&'a [Vec<Sample>]),
+    Block(&'a AudioBlock),
+    /// Read through `FlacBuilder::audio_source`; `frames` is its length hint.
+    Source {
+        channels: usize,
+        frames: Option<usize>,
+    },
 }
 
 impl<'a, Sample: IntoSample> InputData<'a, Sample> {
@@ -356,6 +2187,8 @@ impl<'a, Sample: IntoSample> InputData<'a, Sample> {
         match self {
             InputData::Interleaved { channels, .. } => *channels,
             InputData::Planar(data) => data.len(),
+            InputData::Block(block) => block.channels,
+            InputData::Source { channels, .. } => *channels,
         }
     }
 
@@ -368,6 +2201,8 @@ impl<'a, Sample: IntoSample> InputData<'a, Sample> {
                 }
                 data[0].len()
             }
+            InputData::Block(block) => block.frames(),
+            InputData::Source { frames, .. } => frames.unwrap_or(0),
         }
     }
 
@@ -375,6 +2210,8 @@ impl<'a, Sample: IntoSample> InputData<'a, Sample> {
         match self {
             InputData::Interleaved { data, .. } => data.len(),
             InputData::Planar(data) => data.iter().map(|channel| channel.len()).sum(),
+            InputData::Block(block) => block.samples.len(),
+            InputData::Source { channels, frames } => frames.unwrap_or(0) * channels,
         }
     }
 
@@ -388,63 +2225,16 @@ impl<'a, Sample: IntoSample> InputData<'a, Sample> {
                 let size = data[0].len();
                 data.iter().all(|channel| channel.len() == size)
             }
+            InputData::Block(block) => block.samples.len() % block.channels == 0,
+            InputData::Source { .. } => true,
         }
     }
 }
 
-#[no_mangle]
-unsafe extern "C" fn write_callback(
-    _encoder: *const FLAC__StreamEncoder,
-    buffer: *const FLAC__byte,
-    bytes: usize,
-    _samples: u32,
-    _current_frame: u32,
-    client_data: *mut std::ffi::c_void,
-) -> u32 {
-    let data = unsafe { &mut *(client_data as *mut WriteCallbackData) };
-
-    if data.cursor + bytes > data.data.len() {
-        let needed = (data.cursor + bytes) - data.data.len();
-        data.data.extend(vec![0u8; needed]);
-    }
-
-    let new_data = from_raw_parts(buffer, bytes);
-
-    for i in 0..bytes {
-        data.data[data.cursor] = new_data[i];
-        data.cursor += 1;
-    }
-
-    0
-}
-
-#[no_mangle]
-unsafe extern "C" fn seek_callback(
-    _encoder: *const FLAC__StreamEncoder,
-    absolute_byte_offset: u64,
-    client_data: *mut std::ffi::c_void,
-) -> u32 {
-    let data = unsafe { &mut *(client_data as *mut WriteCallbackData) };
-
-    data.cursor = absolute_byte_offset as usize;
-
-    FLAC__STREAM_ENCODER_SEEK_STATUS_OK
-}
-
-#[no_mangle]
-unsafe extern "C" fn tell_callback(
-    _encoder: *const FLAC__StreamEncoder,
-    absolute_byte_offset: *mut u64,
-    client_data: *mut std::ffi::c_void,
-) -> u32 {
-    let data = unsafe { &mut *(client_data as *mut WriteCallbackData) };
-
-    *absolute_byte_offset = data.cursor as u64;
-
-    FLAC__STREAM_ENCODER_SEEK_STATUS_OK
-}
-
+/// Not exhaustive, as new failure modes come with new features; match on
+/// [`code`](Self::code) where a stable value is needed.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum EncoderError {
     NoData,
     InitializationError,
@@ -454,27 +2244,387 @@ pub enum EncoderError {
     InvalidSampleType,
     TooManyOrTooFewSamples,
     MismatchedSampleCountPerChannels,
-    FailedToInitializeEncoder,
+    /// libFLAC refused to start the encode; holds its description of the init status.
+    FailedToInitializeEncoder(String),
     InvalidVorbisComment(String),
     FailedToSetMetadata,
-    EncodingError,
+    /// libFLAC failed to encode a chunk; holds its description of the encoder state.
+    EncodingError(String),
     InvalidSampleRate,
+    /// The sample rate is valid FLAC but outside the streamable subset; see `FlacBuilder::lax`.
+    SampleRateRequiresLax(u32),
+    /// Finishing the encode failed; holds libFLAC's description of the encoder state, e.g. a
+    /// verify mismatch.
+    FinishFailed(String),
+    /// Verification found that the encoded audio doesn't decode back to the input; see
+    /// `FlacBuilder::on_verify_failure`.
+    VerifyMismatch,
+    /// The vorbis comments don't pass the builder's `TagProfile`.
+    InvalidTags(Vec<TagIssue>),
+    /// An input's sample rate isn't the one the stream was set up with. Encoding it anyway
+    /// would play at the wrong speed, and it isn't resampled.
+    SampleRateMismatch {
+        expected: u32,
+        found: u32,
+    },
+    /// `WavReader` can't read the input; holds what is wrong with it.
+    InvalidWav(String),
+    /// Every sample is zero and the builder's `SilentInputPolicy` is `Error`.
+    SilentInput,
+    /// The ranges passed to `FlacBuilder::export_looped` are out of bounds or the loop is empty.
+    InvalidLoopRange,
+    /// A loop point of `FlacBuilder::export_looped` would jump further than the audio inside
+    /// the loop does.
+    LoopDiscontinuity {
+        channel: usize,
+    },
+    /// The named setting needs the whole input up front, which `FlacBuilder::from_source`
+    /// doesn't have.
+    NeedsWholeInput(&'static str),
+    /// The input is over one of the builder's `Limits`.
+    LimitExceeded {
+        kind: LimitKind,
+        max: usize,
+        actual: usize,
+    },
+    /// `Player` couldn't play the file on the output device; holds what went wrong.
+    Playback(String),
+    /// A `FlacStreamEncoder` was pushed a different number of frames than it was told to
+    /// expect.
+    FrameCountMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    /// A `SharedEncoder` has finished or failed, so it takes no more audio.
+    StreamClosed,
+    /// A `SharedEncoder` finished while holding buffers that come after one never pushed.
+    MissingBuffer {
+        sequence: u64,
+    },
+    /// `EncoderPool::run` was called after `EncoderPool::shutdown`, or was still waiting for a
+    /// thread when an immediate shutdown happened.
+    PoolShutDown,
+    /// `FlacStreamEncoder::snapshot` was called without snapshots enabled, or
+    /// `enable_snapshots` after audio had been encoded.
+    SnapshotUnavailable,
+    /// The linked libFLAC reports a version too old for a setting that was asked for; see
+    /// `libflac_capabilities`.
+    UnsupportedByLibFlac {
+        feature: LibFlacFeature,
+        version: String,
+    },
+    /// `VoiceMemoRecorder` couldn't record from the input device; holds what went wrong.
+    Capture(String),
+    /// The named `FlacStreamEncoder` method rewrites the header once the stream is done, which
+    /// its sink can't.
+    NeedsOverwritableSink(&'static str),
+    /// The header's padding is too small for what is being added to the metadata.
+    PaddingExhausted {
+        needed: usize,
+        available: usize,
+    },
+    /// A check enabled by `FlacBuilder::preflight` found the encode wouldn't fit.
+    PreflightFailed(PreflightProblem),
+    /// The directory set with `FlacBuilder::temp_dir` isn't on the destination's file system,
+    /// so the atomic rename would fail.
+    TempDirOnOtherFileSystem(PathBuf),
+    /// [`self_test`] found a problem; holds a description of it.
+    SelfTestFailed(String),
+    /// A picture passed to `FlacBuilder::picture` isn't valid; holds what is wrong with it.
+    InvalidPicture(String),
+    /// The cue sheet passed to `FlacBuilder::cuesheet` isn't valid; holds what is wrong with it.
+    InvalidCueSheet(String),
+    /// The token passed to `FlacBuilder::cancel_token` was set during the encode.
+    Cancelled,
+    /// A `DiscToc` isn't a valid CD table of contents; holds what is wrong with it.
+    InvalidDiscToc(String),
+    /// A pointer passed to the C interface was NULL; holds the parameter's name.
+    NullArgument(&'static str),
     NullCharInPath,
+    MalformedFlacData,
+    Io(std::io::Error),
+    /// libFLAC couldn't decode the stream, e.g. a frame failed its CRC or the MD5 didn't match.
+    DecodeFailed(String),
+    MetadataBlockTooLarge,
+    MetadataIteratorError(String),
+    NotPadding,
+}
+
+impl EncoderError {
+    /// A number identifying the variant, for FFI consumers and log aggregation. A code is never
+    /// reused or changed once released; new variants get the next free one.
+    pub fn code(&self) -> u32 {
+        match self {
+            EncoderError::NoData => 1,
+            EncoderError::InitializationError => 2,
+            EncoderError::VerificationError => 3,
+            EncoderError::InvalidCompressionLevel => 4,
+            EncoderError::InvalidChannelCount => 5,
+            EncoderError::InvalidSampleType => 6,
+            EncoderError::TooManyOrTooFewSamples => 7,
+            EncoderError::MismatchedSampleCountPerChannels => 8,
+            EncoderError::FailedToInitializeEncoder(_) => 9,
+            EncoderError::InvalidVorbisComment(_) => 10,
+            EncoderError::FailedToSetMetadata => 11,
+            EncoderError::EncodingError(_) => 12,
+            EncoderError::InvalidSampleRate => 13,
+            EncoderError::SampleRateRequiresLax(_) => 14,
+            EncoderError::FinishFailed(_) => 15,
+            EncoderError::VerifyMismatch => 16,
+            EncoderError::InvalidTags(_) => 17,
+            EncoderError::SilentInput => 18,
+            EncoderError::InvalidLoopRange => 19,
+            EncoderError::LoopDiscontinuity { .. } => 20,
+            EncoderError::LimitExceeded { .. } => 21,
+            EncoderError::PoolShutDown => 22,
+            EncoderError::NullCharInPath => 23,
+            EncoderError::MalformedFlacData => 24,
+            EncoderError::MetadataBlockTooLarge => 25,
+            EncoderError::MetadataIteratorError(_) => 26,
+            EncoderError::NotPadding => 27,
+            EncoderError::Io(_) => 28,
+            EncoderError::DecodeFailed(_) => 29,
+            EncoderError::SampleRateMismatch { .. } => 30,
+            EncoderError::InvalidWav(_) => 31,
+            EncoderError::Playback(_) => 32,
+            EncoderError::NeedsWholeInput(_) => 33,
+            EncoderError::FrameCountMismatch { .. } => 34,
+            EncoderError::StreamClosed => 35,
+            EncoderError::MissingBuffer { .. } => 36,
+            EncoderError::SnapshotUnavailable => 37,
+            EncoderError::UnsupportedByLibFlac { .. } => 38,
+            EncoderError::Capture(_) => 39,
+            EncoderError::NeedsOverwritableSink(_) => 40,
+            EncoderError::PaddingExhausted { .. } => 41,
+            EncoderError::PreflightFailed(_) => 42,
+            EncoderError::TempDirOnOtherFileSystem(_) => 43,
+            EncoderError::SelfTestFailed(_) => 44,
+            EncoderError::InvalidPicture(_) => 45,
+            EncoderError::InvalidCueSheet(_) => 46,
+            EncoderError::Cancelled => 47,
+            EncoderError::InvalidDiscToc(_) => 48,
+            EncoderError::NullArgument(_) => 49,
+        }
+    }
+
+    /// The variant's name, e.g. `"Io"`, for grouping errors in logs and statistics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EncoderError::NoData => "NoData",
+            EncoderError::InitializationError => "InitializationError",
+            EncoderError::VerificationError => "VerificationError",
+            EncoderError::InvalidCompressionLevel => "InvalidCompressionLevel",
+            EncoderError::InvalidChannelCount => "InvalidChannelCount",
+            EncoderError::InvalidSampleType => "InvalidSampleType",
+            EncoderError::TooManyOrTooFewSamples => "TooManyOrTooFewSamples",
+            EncoderError::MismatchedSampleCountPerChannels => "MismatchedSampleCountPerChannels",
+            EncoderError::FailedToInitializeEncoder(_) => "FailedToInitializeEncoder",
+            EncoderError::InvalidVorbisComment(_) => "InvalidVorbisComment",
+            EncoderError::FailedToSetMetadata => "FailedToSetMetadata",
+            EncoderError::EncodingError(_) => "EncodingError",
+            EncoderError::InvalidSampleRate => "InvalidSampleRate",
+            EncoderError::SampleRateRequiresLax(_) => "SampleRateRequiresLax",
+            EncoderError::FinishFailed(_) => "FinishFailed",
+            EncoderError::VerifyMismatch => "VerifyMismatch",
+            EncoderError::InvalidTags(_) => "InvalidTags",
+            EncoderError::SilentInput => "SilentInput",
+            EncoderError::InvalidLoopRange => "InvalidLoopRange",
+            EncoderError::LoopDiscontinuity { .. } => "LoopDiscontinuity",
+            EncoderError::LimitExceeded { .. } => "LimitExceeded",
+            EncoderError::PoolShutDown => "PoolShutDown",
+            EncoderError::NullCharInPath => "NullCharInPath",
+            EncoderError::MalformedFlacData => "MalformedFlacData",
+            EncoderError::MetadataBlockTooLarge => "MetadataBlockTooLarge",
+            EncoderError::MetadataIteratorError(_) => "MetadataIteratorError",
+            EncoderError::NotPadding => "NotPadding",
+            EncoderError::Io(_) => "Io",
+            EncoderError::DecodeFailed(_) => "DecodeFailed",
+            EncoderError::SampleRateMismatch { .. } => "SampleRateMismatch",
+            EncoderError::InvalidWav(_) => "InvalidWav",
+            EncoderError::Playback(_) => "Playback",
+            EncoderError::NeedsWholeInput(_) => "NeedsWholeInput",
+            EncoderError::FrameCountMismatch { .. } => "FrameCountMismatch",
+            EncoderError::StreamClosed => "StreamClosed",
+            EncoderError::MissingBuffer { .. } => "MissingBuffer",
+            EncoderError::SnapshotUnavailable => "SnapshotUnavailable",
+            EncoderError::UnsupportedByLibFlac { .. } => "UnsupportedByLibFlac",
+            EncoderError::Capture(_) => "Capture",
+            EncoderError::NeedsOverwritableSink(_) => "NeedsOverwritableSink",
+            EncoderError::PaddingExhausted { .. } => "PaddingExhausted",
+            EncoderError::PreflightFailed(_) => "PreflightFailed",
+            EncoderError::TempDirOnOtherFileSystem(_) => "TempDirOnOtherFileSystem",
+            EncoderError::SelfTestFailed(_) => "SelfTestFailed",
+            EncoderError::InvalidPicture(_) => "InvalidPicture",
+            EncoderError::InvalidCueSheet(_) => "InvalidCueSheet",
+            EncoderError::Cancelled => "Cancelled",
+            EncoderError::InvalidDiscToc(_) => "InvalidDiscToc",
+            EncoderError::NullArgument(_) => "NullArgument",
+        }
+    }
 }
 
-/// `f32` and `f64` in `[-1.0, 1.0]`.
+impl fmt::Display for EncoderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncoderError::NoData => write!(f, "no audio data was given"),
+            EncoderError::InitializationError => write!(f, "failed to allocate a libFLAC object"),
+            EncoderError::VerificationError => write!(f, "failed to enable verification"),
+            EncoderError::InvalidCompressionLevel => write!(f, "invalid compression level"),
+            EncoderError::InvalidChannelCount => write!(f, "unsupported number of channels"),
+            EncoderError::InvalidSampleType => write!(f, "unsupported sample type or bps"),
+            EncoderError::TooManyOrTooFewSamples => {
+                write!(f, "number of samples is out of range")
+            }
+            EncoderError::MismatchedSampleCountPerChannels => {
+                write!(f, "channels have different numbers of samples")
+            }
+            EncoderError::FailedToInitializeEncoder(status) => {
+                write!(f, "failed to initialize the encoder: {status}")
+            }
+            EncoderError::InvalidVorbisComment(comment) => {
+                write!(f, "invalid vorbis comment {comment:?}")
+            }
+            EncoderError::FailedToSetMetadata => write!(f, "libFLAC rejected a metadata block"),
+            EncoderError::EncodingError(state) => write!(f, "encoding failed: {state}"),
+            EncoderError::InvalidSampleRate => write!(f, "invalid sample rate"),
+            EncoderError::SampleRateRequiresLax(rate) => write!(
+                f,
+                "sample rate {rate} Hz is outside the streamable subset, use `lax` to allow it"
+            ),
+            EncoderError::FinishFailed(state) => write!(f, "finishing the encode failed: {state}"),
+            EncoderError::VerifyMismatch => {
+                write!(
+                    f,
+                    "verification found the encoded audio differs from the input"
+                )
+            }
+            EncoderError::InvalidTags(issues) => {
+                write!(f, "invalid tags: ")?;
+                for (i, issue) in issues.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{issue}")?;
+                }
+                Ok(())
+            }
+            EncoderError::SampleRateMismatch { expected, found } => write!(
+                f,
+                "the source is {found} Hz but the stream is {expected} Hz"
+            ),
+            EncoderError::InvalidWav(problem) => write!(f, "invalid WAV file: {problem}"),
+            EncoderError::SilentInput => write!(f, "every sample of the input is zero"),
+            EncoderError::InvalidLoopRange => write!(f, "loop range is empty or out of bounds"),
+            EncoderError::LoopDiscontinuity { channel } => {
+                write!(f, "loop point would click on channel {channel}")
+            }
+            EncoderError::NeedsWholeInput(setting) => write!(
+                f,
+                "{setting} needs the whole input up front, which an audio source doesn't have"
+            ),
+            EncoderError::LimitExceeded { kind, max, actual } => {
+                write!(f, "{kind:?} of {actual} is over the limit of {max}")
+            }
+            EncoderError::Playback(problem) => write!(f, "playback failed: {problem}"),
+            EncoderError::FrameCountMismatch { expected, actual } => {
+                write!(f, "expected {expected} frames but got {actual}")
+            }
+            EncoderError::StreamClosed => write!(f, "the encoder has finished or failed"),
+            EncoderError::MissingBuffer { sequence } => {
+                write!(f, "buffer {sequence} was never pushed")
+            }
+            EncoderError::PoolShutDown => write!(f, "the encoder pool has been shut down"),
+            EncoderError::SnapshotUnavailable => {
+                write!(f, "snapshots weren't enabled before the first frame")
+            }
+            EncoderError::UnsupportedByLibFlac { feature, version } => {
+                let (major, minor, patch) = feature.since();
+                write!(
+                    f,
+                    "{feature:?} needs libFLAC {major}.{minor}.{patch}, but {version} is linked"
+                )
+            }
+            EncoderError::Capture(problem) => write!(f, "recording failed: {problem}"),
+            EncoderError::NeedsOverwritableSink(method) => {
+                write!(f, "{method} needs a sink that can overwrite the header")
+            }
+            EncoderError::PaddingExhausted { needed, available } => write!(
+                f,
+                "the metadata needs {needed} more bytes but the padding only has {available}"
+            ),
+            EncoderError::PreflightFailed(problem) => write!(f, "preflight failed: {problem}"),
+            EncoderError::TempDirOnOtherFileSystem(dir) => write!(
+                f,
+                "temporary directory {} isn't on the destination's file system",
+                dir.display()
+            ),
+            EncoderError::SelfTestFailed(problem) => write!(f, "self test failed: {problem}"),
+            EncoderError::InvalidPicture(problem) => write!(f, "invalid picture: {problem}"),
+            EncoderError::InvalidCueSheet(problem) => write!(f, "invalid cue sheet: {problem}"),
+            EncoderError::Cancelled => write!(f, "the encode was cancelled"),
+            EncoderError::InvalidDiscToc(problem) => {
+                write!(f, "invalid table of contents: {problem}")
+            }
+            EncoderError::NullArgument(name) => write!(f, "`{name}` is NULL"),
+            EncoderError::NullCharInPath => write!(f, "path contains a NUL character"),
+            EncoderError::MalformedFlacData => write!(f, "malformed FLAC data"),
+            EncoderError::Io(e) => write!(f, "I/O error: {e}"),
+            EncoderError::DecodeFailed(problem) => write!(f, "decoding failed: {problem}"),
+            EncoderError::MetadataBlockTooLarge => {
+                write!(f, "metadata block is over the 16 MiB FLAC limit")
+            }
+            EncoderError::MetadataIteratorError(status) => {
+                write!(f, "metadata editing failed: {status}")
+            }
+            EncoderError::NotPadding => write!(f, "the current metadata block isn't padding"),
+        }
+    }
+}
+
+impl std::error::Error for EncoderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EncoderError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// `f32` and `f64` in `[-1.0, 1.0]`, and integer PCM: `i8`, `u8` (offset by 128 as in WAV),
+/// `i16` and `i32` holding 24-bit samples. Integers are shifted to the target bps without
+/// going through floats, so they encode losslessly at their own width or wider; narrowing drops
+/// the low bits.
 pub trait IntoSample: Copy + Default {
     fn to_i16(&self) -> i16;
     fn to_i20(&self) -> i32;
     fn to_i24(&self) -> i32;
 
+    /// Defaults to the 24-bit sample shifted up, which is exact for everything but `f64`.
+    fn to_i32(&self) -> i32 {
+        self.to_i24() << 8
+    }
+
+    /// The sample as a float in `[-1.0, 1.0]`, for processing done before quantization like
+    /// [`FlacBuilder::soft_clip`] and fades. Other sample types return `None` and skip them.
+    fn to_f64(&self) -> Option<f64> {
+        None
+    }
+
     fn to_bps_level(&self, bps: BpsLevel) -> FLAC__int32 {
         match bps {
             BpsLevel::Bps16 => self.to_i16() as FLAC__int32,
             BpsLevel::Bps20 => self.to_i20(),
             BpsLevel::Bps24 => self.to_i24(),
+            BpsLevel::Bps32 => self.to_i32(),
         }
     }
+
+    /// Appends each of `samples` at `bps` to `out`, the same as
+    /// [`to_bps_level`](Self::to_bps_level) on each. Types whose conversion is a plain shift
+    /// can override it with a loop the compiler vectorizes.
+    fn widen_slice(samples: &[Self], bps: BpsLevel, out: &mut Vec<FLAC__int32>) {
+        out.extend(samples.iter().map(|sample| sample.to_bps_level(bps)));
+    }
 }
 
 impl IntoSample for f32 {
@@ -492,6 +2642,10 @@ impl IntoSample for f32 {
         let max = (1 << 23) - 1;
         ((self.clamp(-1.0, 1.0) * max as f32) as i32).clamp(-max, max)
     }
+
+    fn to_f64(&self) -> Option<f64> {
+        Some(*self as f64)
+    }
 }
 
 impl IntoSample for f64 {
@@ -509,4 +2663,834 @@ impl IntoSample for f64 {
         let max = (1 << 23) - 1;
         ((self.clamp(-1.0, 1.0) * max as f64) as i32).clamp(-max, max)
     }
+
+    fn to_i32(&self) -> i32 {
+        let max = i32::MAX;
+        ((self.clamp(-1.0, 1.0) * max as f64) as i32).clamp(-max, max)
+    }
+
+    fn to_f64(&self) -> Option<f64> {
+        Some(*self)
+    }
+}
+
+impl IntoSample for i8 {
+    fn to_i16(&self) -> i16 {
+        (*self as i16) << 8
+    }
+
+    fn to_i20(&self) -> i32 {
+        (*self as i32) << 12
+    }
+
+    fn to_i24(&self) -> i32 {
+        (*self as i32) << 16
+    }
+}
+
+impl IntoSample for u8 {
+    fn to_i16(&self) -> i16 {
+        (self.wrapping_sub(128) as i8).to_i16()
+    }
+
+    fn to_i20(&self) -> i32 {
+        (self.wrapping_sub(128) as i8).to_i20()
+    }
+
+    fn to_i24(&self) -> i32 {
+        (self.wrapping_sub(128) as i8).to_i24()
+    }
+}
+
+impl IntoSample for i16 {
+    fn to_i16(&self) -> i16 {
+        *self
+    }
+
+    fn to_i20(&self) -> i32 {
+        (*self as i32) << 4
+    }
+
+    fn to_i24(&self) -> i32 {
+        (*self as i32) << 8
+    }
+
+    fn widen_slice(samples: &[Self], bps: BpsLevel, out: &mut Vec<FLAC__int32>) {
+        let shift = bps.to_u32() - 16;
+        out.extend(samples.iter().map(|&sample| (sample as i32) << shift));
+    }
+}
+
+/// Right-justified 24-bit samples, as libFLAC takes them; values outside 24 bits are clamped.
+/// Shift full-range 32-bit PCM right by 8 first.
+impl IntoSample for i32 {
+    fn to_i16(&self) -> i16 {
+        (self.to_i24() >> 8) as i16
+    }
+
+    fn to_i20(&self) -> i32 {
+        self.to_i24() >> 4
+    }
+
+    fn to_i24(&self) -> i32 {
+        (*self).clamp(-(1 << 23), (1 << 23) - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A second of a 440 Hz sine, one channel.
+    fn sine(sample_rate: u32) -> Vec<f32> {
+        (0..sample_rate)
+            .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / sample_rate as f32).sin() * 0.5)
+            .collect()
+    }
+
+    #[test]
+    fn rejects_sample_rates_flac_cant_store() {
+        let samples = sine(100);
+
+        for sample_rate in [0, MAX_SAMPLE_RATE + 1] {
+            let result = FlacBuilder::from_interleaved(&samples, 1, sample_rate)
+                .lax()
+                .build();
+            assert!(matches!(result, Err(EncoderError::InvalidSampleRate)));
+        }
+    }
+
+    #[test]
+    fn non_subset_sample_rates_need_lax() {
+        let samples = sine(700_000);
+
+        let result = FlacBuilder::from_interleaved(&samples, 1, 700_000).build();
+        assert!(matches!(
+            result,
+            Err(EncoderError::SampleRateRequiresLax(700_000))
+        ));
+
+        let bytes = FlacBuilder::from_interleaved(&samples, 1, 700_000)
+            .lax()
+            .build()
+            .unwrap();
+        assert_eq!(FlacDecoder::new(&bytes[..]).unwrap().sample_rate(), 700_000);
+    }
+
+    #[test]
+    fn bps32_and_min_bitrate_follow_the_linked_libflac() {
+        let samples = sine(8000);
+        let result = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .bps(BpsLevel::Bps32)
+            .lax()
+            .limit_min_bitrate()
+            .build();
+
+        if libflac_capabilities().supports(LibFlacFeature::Bps32) {
+            let bytes = result.unwrap();
+            assert_eq!(FlacDecoder::new(&bytes[..]).unwrap().bps(), 32);
+        } else {
+            assert!(matches!(
+                result,
+                Err(EncoderError::UnsupportedByLibFlac {
+                    feature: LibFlacFeature::Bps32,
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn tee_encodes_both_outputs_from_one_pass() {
+        let samples = sine(44100);
+        let (first, second) = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .title("Master")
+            .build_tee(|builder| {
+                builder
+                    .bps(BpsLevel::Bps24)
+                    .compression_level(CompressionLevel::L8)
+            })
+            .unwrap();
+
+        let decode = |bytes: &[u8]| {
+            let mut decoder = FlacDecoder::new(bytes).unwrap();
+            let mut buffer = vec![0; samples.len() + 1];
+            let n = decoder.fill(&mut buffer).unwrap();
+            buffer.truncate(n);
+            (decoder.bps(), read_comments(bytes).unwrap(), buffer)
+        };
+
+        let (bps, comments, decoded) = decode(&first);
+        assert_eq!(bps, 16);
+        assert_eq!(comments, [("TITLE".to_string(), "Master".to_string())]);
+        let expected: Vec<i32> = samples.iter().map(|s| s.to_i16() as i32).collect();
+        assert_eq!(decoded, expected);
+
+        let (bps, comments, decoded) = decode(&second);
+        assert_eq!(bps, 24);
+        assert_eq!(comments, [("TITLE".to_string(), "Master".to_string())]);
+        let expected: Vec<i32> = samples.iter().map(|s| s.to_i24()).collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn tee_applies_the_input_policies_to_each_output() {
+        let silence = [0.0f32; 8000];
+
+        let (first, second) = FlacBuilder::from_interleaved(&silence, 2, 44100)
+            .build_tee(|builder| builder.on_silent_input(SilentInputPolicy::Skip))
+            .unwrap();
+        assert!(!first.is_empty());
+        assert!(second.is_empty());
+
+        let result = FlacBuilder::from_interleaved(&silence, 2, 44100)
+            .on_silent_input(SilentInputPolicy::Error)
+            .build_tee(|builder| builder);
+        assert!(matches!(result, Err(EncoderError::SilentInput)));
+    }
+
+    #[test]
+    fn tee_converts_again_when_the_fades_differ() {
+        let samples = sine(44100);
+        let fade = Duration::from_millis(100);
+        let (_, second) = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .build_tee(|builder| builder.fade_in(fade))
+            .unwrap();
+
+        let alone = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .fade_in(fade)
+            .build()
+            .unwrap();
+        assert_eq!(second, alone);
+    }
+
+    #[test]
+    fn encoder_settings_tag_records_the_settings() {
+        let samples = sine(44100);
+
+        for (level, blocksize) in [(0, 1152), (8, 4096)] {
+            let bytes = FlacBuilder::from_interleaved(&samples, 1, 44100)
+                .compression_level(CompressionLevel::PRESETS[level].clone())
+                .encoder_settings_tag()
+                .build()
+                .unwrap();
+
+            let comments = read_comments(&bytes).unwrap();
+            let (key, settings) = &comments[0];
+
+            assert_eq!(key, "ENCODERSETTINGS");
+            let apodization = CompressionLevel::PRESETS[level].settings().apodization;
+            assert!(settings.starts_with(&format!(
+                "compression={level} blocksize={blocksize} apodization={apodization} "
+            )));
+            assert!(settings.ends_with(&format!("libFLAC={}", libflac_version())));
+        }
+    }
+
+    /// Runs the verify policy over an encode that fails verification `failures` times,
+    /// returning the result and whether each attempt verified.
+    fn run_verify_policy(
+        policy: VerifyFailurePolicy,
+        failures: usize,
+        repeatable: bool,
+    ) -> (Result<((), EncodeReport), EncoderError>, Vec<bool>) {
+        let samples = sine(100);
+        let mut builder = FlacBuilder::from_interleaved(&samples, 1, 100).on_verify_failure(policy);
+        let mut attempts = vec![];
+
+        let result = builder.with_verify_policy(repeatable, |_, verify| {
+            attempts.push(verify);
+            if verify && attempts.len() <= failures {
+                Err(EncoderError::VerifyMismatch)
+            } else {
+                Ok(((), EncodeReport::default()))
+            }
+        });
+        (result, attempts)
+    }
+
+    #[test]
+    fn verify_failure_policies() {
+        let (result, attempts) = run_verify_policy(VerifyFailurePolicy::Error, 1, true);
+        assert!(matches!(result, Err(EncoderError::VerifyMismatch)));
+        assert_eq!(attempts, [true]);
+
+        let (result, attempts) = run_verify_policy(VerifyFailurePolicy::Warn, 1, true);
+        assert!(result.unwrap().1.verify_failed);
+        assert_eq!(attempts, [true, false]);
+
+        let (result, attempts) = run_verify_policy(VerifyFailurePolicy::Retry(2), 2, true);
+        assert!(!result.unwrap().1.verify_failed);
+        assert_eq!(attempts, [true, true, true]);
+
+        let (result, attempts) = run_verify_policy(VerifyFailurePolicy::Retry(1), 2, true);
+        assert!(matches!(result, Err(EncoderError::VerifyMismatch)));
+        assert_eq!(attempts, [true, true]);
+
+        // Output that can't be taken back is only written once.
+        let (result, attempts) = run_verify_policy(VerifyFailurePolicy::Retry(2), 1, false);
+        assert!(matches!(result, Err(EncoderError::VerifyMismatch)));
+        assert_eq!(attempts, [true]);
+    }
+
+    #[test]
+    fn events_follow_the_encode() {
+        let samples = sine(3000);
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        FlacBuilder::from_interleaved(&samples, 1, 3000)
+            .events(sender)
+            .build()
+            .unwrap();
+        let events: Vec<EncoderEvent> = receiver.iter().collect();
+
+        assert_eq!(
+            events,
+            [
+                EncoderEvent::Started {
+                    samples_per_channel: 3000
+                },
+                EncoderEvent::MetadataWritten,
+                EncoderEvent::ChunkDone {
+                    samples_done: 1024,
+                    samples_per_channel: 3000
+                },
+                EncoderEvent::ChunkDone {
+                    samples_done: 2048,
+                    samples_per_channel: 3000
+                },
+                EncoderEvent::ChunkDone {
+                    samples_done: 3000,
+                    samples_per_channel: 3000
+                },
+                EncoderEvent::Finished,
+            ]
+        );
+    }
+
+    #[test]
+    fn a_retry_warns_and_starts_again() {
+        let samples = sine(100);
+        let mut events = vec![];
+        let mut builder = FlacBuilder::from_interleaved(&samples, 1, 100)
+            .on_verify_failure(VerifyFailurePolicy::Retry(1))
+            .on_event(|event| events.push(event));
+        let mut failed = false;
+
+        builder
+            .with_verify_policy(true, |_, _| {
+                if std::mem::replace(&mut failed, true) {
+                    Ok(((), EncodeReport::default()))
+                } else {
+                    Err(EncoderError::VerifyMismatch)
+                }
+            })
+            .unwrap();
+        drop(builder);
+
+        let started = EncoderEvent::Started {
+            samples_per_channel: 100,
+        };
+        assert_eq!(
+            events,
+            [
+                started.clone(),
+                EncoderEvent::Warning("verification failed, encoding again".to_string()),
+                started,
+                EncoderEvent::Finished,
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_input_policies() {
+        let empty: [f32; 0] = [];
+
+        let result = FlacBuilder::from_interleaved(&empty, 2, 44100).build();
+        assert!(matches!(result, Err(EncoderError::NoData)));
+
+        let bytes = FlacBuilder::from_interleaved(&empty, 2, 44100)
+            .on_empty_input(EmptyInputPolicy::EncodeEmpty)
+            .title("Placeholder")
+            .build()
+            .unwrap();
+        let mut decoder = decoder::FlacDecoder::new(&bytes[..]).unwrap();
+        assert_eq!(decoder.total_samples(), 0);
+        assert_eq!(decoder.fill(&mut [0; 16]).unwrap(), 0);
+        assert_eq!(
+            read_comments(&bytes).unwrap(),
+            [("TITLE".to_string(), "Placeholder".to_string())]
+        );
+
+        let no_channels: [Vec<f32>; 0] = [];
+        let result = FlacBuilder::from_planar(&no_channels, 44100)
+            .on_empty_input(EmptyInputPolicy::EncodeEmpty)
+            .build();
+        assert!(matches!(result, Err(EncoderError::NoData)));
+    }
+
+    #[test]
+    fn silent_input_policies() {
+        let silence = [0.0f32; 8000];
+        let tone: Vec<f32> = (0..8000).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+
+        let encoded = FlacBuilder::from_interleaved(&silence, 2, 44100)
+            .build()
+            .unwrap();
+        let fast = FlacBuilder::from_interleaved(&silence, 2, 44100)
+            .on_silent_input(SilentInputPolicy::EncodeFast)
+            .build()
+            .unwrap();
+        let mut samples = [1; 8000];
+        let n = decoder::FlacDecoder::new(&fast[..])
+            .unwrap()
+            .fill(&mut samples)
+            .unwrap();
+        assert_eq!(n, 8000);
+        assert!(samples.iter().all(|&s| s == 0));
+        assert!(fast.len() <= encoded.len() + 64);
+
+        let (bytes, report) = FlacBuilder::from_interleaved(&silence, 2, 44100)
+            .on_silent_input(SilentInputPolicy::Skip)
+            .build_with_report()
+            .unwrap();
+        assert!(bytes.is_empty());
+        assert!(report.skipped_silent_input);
+        assert_eq!(report.pcm_bytes, 8000 * 2);
+
+        let result = FlacBuilder::from_interleaved(&silence, 2, 44100)
+            .on_silent_input(SilentInputPolicy::Error)
+            .build();
+        assert!(matches!(result, Err(EncoderError::SilentInput)));
+
+        let (bytes, report) = FlacBuilder::from_interleaved(&tone, 2, 44100)
+            .on_silent_input(SilentInputPolicy::Error)
+            .build_with_report()
+            .unwrap();
+        assert!(!bytes.is_empty());
+        assert!(!report.skipped_silent_input);
+
+        let no_channels = FlacBuilder::from_interleaved(&silence, 0, 44100)
+            .on_silent_input(SilentInputPolicy::Skip)
+            .build();
+        assert!(matches!(no_channels, Err(EncoderError::NoData)));
+        let uneven = [vec![0.0f32; 100], vec![0.0f32; 50]];
+        let uneven = FlacBuilder::from_planar(&uneven, 44100)
+            .on_silent_input(SilentInputPolicy::Skip)
+            .build();
+        assert!(matches!(
+            uneven,
+            Err(EncoderError::MismatchedSampleCountPerChannels)
+        ));
+    }
+
+    #[test]
+    fn location_is_iso_6709() {
+        let bytes = FlacBuilder::from_interleaved(&[0.0f32; 64], 2, 44100)
+            .location(51.5074, -0.1278)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            read_comments(&bytes).unwrap(),
+            [("LOCATION".to_string(), "+51.507400-000.127800/".to_string())]
+        );
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn recorded_at_is_rfc_3339() {
+        use chrono::TimeZone;
+
+        let time = chrono::FixedOffset::east_opt(2 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2024, 5, 1, 9, 30, 0)
+            .unwrap();
+        let bytes = FlacBuilder::from_interleaved(&[0.0f32; 64], 2, 44100)
+            .recorded_at(time)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            read_comments(&bytes).unwrap(),
+            [("DATE".to_string(), "2024-05-01T09:30:00+02:00".to_string())]
+        );
+    }
+
+    #[test]
+    fn source_bps_keeps_the_padding_bits_zero() {
+        let samples: Vec<f32> = (0..20_000).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+        let encode = |builder: FlacBuilder<'_, f32>| {
+            let bytes = builder.bps(BpsLevel::Bps24).build().unwrap();
+            let mut decoded = vec![0; 20_000];
+            decoder::FlacDecoder::new(&bytes[..])
+                .unwrap()
+                .fill(&mut decoded)
+                .unwrap();
+            (bytes.len(), decoded)
+        };
+
+        let (plain_len, plain) = encode(FlacBuilder::from_interleaved(&samples, 2, 44100));
+        let (hinted_len, hinted) =
+            encode(FlacBuilder::from_interleaved(&samples, 2, 44100).source_bps(BpsLevel::Bps20));
+
+        assert!(plain.iter().any(|s| s % 16 != 0));
+        assert!(hinted.iter().all(|s| s % 16 == 0));
+        assert!(hinted_len < plain_len);
+        // Within a 20-bit step, give or take the scaling and truncation at each depth.
+        for (hinted, plain) in hinted.iter().zip(&plain) {
+            assert!((hinted - plain).abs() < 32);
+        }
+    }
+
+    #[test]
+    fn export_looped_tags_the_loop_and_checks_its_seams() {
+        // One period of a sine every 100 frames, so a loop of whole periods is seamless.
+        let samples: Vec<f32> = (0..2000)
+            .map(|i| (i as f32 * std::f32::consts::TAU / 100.0).sin() * 0.5)
+            .collect();
+
+        let bytes = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .export_looped(0..150, 200..1200)
+            .unwrap();
+        assert_eq!(
+            read_comments(&bytes).unwrap(),
+            [
+                ("LOOPSTART".to_string(), "150".to_string()),
+                ("LOOPLENGTH".to_string(), "1000".to_string()),
+            ]
+        );
+        let mut decoded = vec![0; 4096];
+        let n = decoder::FlacDecoder::new(&bytes[..])
+            .unwrap()
+            .fill(&mut decoded)
+            .unwrap();
+        let expected: Vec<i32> = [&samples[0..150], &samples[200..1200]]
+            .concat()
+            .iter()
+            .map(|s| s.to_i16() as i32)
+            .collect();
+        assert_eq!(decoded[..n], expected);
+
+        let result =
+            FlacBuilder::from_interleaved(&samples, 1, 44100).export_looped(0..0, 200..1225);
+        assert!(matches!(
+            result,
+            Err(EncoderError::LoopDiscontinuity { channel: 0 })
+        ));
+
+        let result =
+            FlacBuilder::from_interleaved(&samples, 1, 44100).export_looped(0..10, 0..3000);
+        assert!(matches!(result, Err(EncoderError::InvalidLoopRange)));
+
+        let single_frame =
+            FlacBuilder::from_interleaved(&samples, 1, 44100).export_looped(0..25, 500..501);
+        assert!(single_frame.is_ok());
+    }
+
+    #[test]
+    fn i16_input_encodes_losslessly() {
+        let samples: Vec<i16> = (0..20_000)
+            .map(|i| ((i as f32 * 0.01).sin() * 20_000.0) as i16)
+            .collect();
+
+        for (bps, shift) in [(BpsLevel::Bps16, 0), (BpsLevel::Bps24, 8)] {
+            let bytes = FlacBuilder::from_interleaved(&samples, 2, 44100)
+                .bps(bps)
+                .build()
+                .unwrap();
+
+            let mut decoded = vec![0; 20_000];
+            decoder::FlacDecoder::new(&bytes[..])
+                .unwrap()
+                .fill(&mut decoded)
+                .unwrap();
+            let expected: Vec<i32> = samples.iter().map(|&s| (s as i32) << shift).collect();
+            assert_eq!(decoded, expected);
+        }
+    }
+
+    #[test]
+    fn bps_auto_inspects_integer_slices() {
+        let samples: Vec<i16> = (0..8192).map(|i| (i % 512) as i16 - 256).collect();
+
+        let bytes = FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .bps(BpsLevel::Bps24)
+            .bps_auto()
+            .build()
+            .unwrap();
+        assert_eq!(FlacDecoder::new(&bytes[..]).unwrap().bps(), 16);
+    }
+
+    #[test]
+    fn byte_and_24_bit_input_is_shifted_not_rescaled() {
+        let decode = |bytes: Vec<u8>| {
+            let mut decoded = vec![0; 4];
+            decoder::FlacDecoder::new(&bytes[..])
+                .unwrap()
+                .fill(&mut decoded)
+                .unwrap();
+            decoded
+        };
+
+        let signed: [i8; 4] = [-128, -1, 0, 127];
+        let bytes = FlacBuilder::from_interleaved(&signed, 1, 44100)
+            .build()
+            .unwrap();
+        assert_eq!(decode(bytes), [-128 << 8, -1 << 8, 0, 127 << 8]);
+
+        let unsigned: [u8; 4] = [0, 127, 128, 255];
+        let bytes = FlacBuilder::from_interleaved(&unsigned, 1, 44100)
+            .build()
+            .unwrap();
+        assert_eq!(decode(bytes), [-128 << 8, -1 << 8, 0, 127 << 8]);
+
+        let wide: [i32; 4] = [-(1 << 23), -1, (1 << 23) - 1, 1 << 30];
+        let bytes = FlacBuilder::from_interleaved(&wide, 1, 44100)
+            .bps(BpsLevel::Bps24)
+            .build()
+            .unwrap();
+        assert_eq!(
+            decode(bytes),
+            [-(1 << 23), -1, (1 << 23) - 1, (1 << 23) - 1]
+        );
+    }
+
+    #[test]
+    fn soft_clip_rounds_off_hot_float_input() {
+        let ceiling = 10f64.powf(-1.0 / 20.0);
+        let hot: Vec<f32> = (0..4000).map(|i| (i as f32 * 0.01).sin() * 1.5).collect();
+
+        let decode = |bytes: Vec<u8>| {
+            let mut decoded = vec![0; 4000];
+            decoder::FlacDecoder::new(&bytes[..])
+                .unwrap()
+                .fill(&mut decoded)
+                .unwrap();
+            decoded
+        };
+        let clipped = decode(
+            FlacBuilder::from_interleaved(&hot, 1, 44100)
+                .soft_clip(-1.0)
+                .build()
+                .unwrap(),
+        );
+        let plain = decode(
+            FlacBuilder::from_interleaved(&hot, 1, 44100)
+                .build()
+                .unwrap(),
+        );
+
+        let limit = (ceiling * i16::MAX as f64) as i32;
+        assert!(clipped.iter().all(|s| s.abs() <= limit));
+        assert!(plain.iter().any(|s| s.abs() == i16::MAX as i32));
+        // Quiet samples, below the knee 6 dB under the ceiling, pass through.
+        for (clipped, plain) in clipped.iter().zip(&plain) {
+            if plain.abs() < limit / 2 {
+                assert_eq!(clipped, plain);
+            }
+        }
+
+        let integers = [30_000i16, -30_000];
+        let bytes = FlacBuilder::from_interleaved(&integers, 1, 44100)
+            .soft_clip(-1.0)
+            .build()
+            .unwrap();
+        assert_eq!(decode(bytes)[..2], [30_000, -30_000]);
+    }
+
+    #[test]
+    fn fades_ramp_the_ends() {
+        let samples = [10_000i16; 1000];
+        let bytes = FlacBuilder::from_interleaved(&samples, 1, 1000)
+            .fade_in(Duration::from_millis(100))
+            .fade_out(Duration::from_millis(200))
+            .build()
+            .unwrap();
+
+        let mut decoded = vec![0; 1000];
+        decoder::FlacDecoder::new(&bytes[..])
+            .unwrap()
+            .fill(&mut decoded)
+            .unwrap();
+
+        assert_eq!(decoded[..3], [0, 100, 200]);
+        assert_eq!(decoded[100..800], [10_000; 700]);
+        assert_eq!(decoded[997..], [100, 50, 0]);
+        assert!(decoded.windows(2).take(100).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn auto_tune_stays_within_the_budget() {
+        let samples = sine(44100);
+
+        let rushed = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .compression_level(CompressionLevel::L8)
+            .auto_tune(Duration::ZERO)
+            .unwrap();
+        assert_eq!(rushed.compression_level, CompressionLevel::L0);
+
+        let relaxed = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .auto_tune(Duration::from_secs(60))
+            .unwrap();
+        let tuned = relaxed.build().unwrap();
+        let fastest = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .compression_level(CompressionLevel::L0)
+            .build()
+            .unwrap();
+        assert!(tuned.len() <= fastest.len());
+
+        let no_channels = FlacBuilder::<f32>::from_planar(&[], 44100).auto_tune(Duration::ZERO);
+        assert!(matches!(no_channels, Err(EncoderError::NoData)));
+    }
+
+    #[test]
+    fn dual_mono_policies() {
+        let mono = sine(8000);
+        let stereo: Vec<f32> = mono.iter().flat_map(|&s| [s, s]).collect();
+
+        let (bytes, report) = FlacBuilder::from_interleaved(&stereo, 2, 8000)
+            .on_dual_mono(DualMonoPolicy::Collapse)
+            .build_with_report()
+            .unwrap();
+        assert!(report.collapsed_to_mono);
+        let decoder = decoder::FlacDecoder::new(&bytes[..]).unwrap();
+        assert_eq!(decoder.channels(), 1);
+        let as_mono = FlacBuilder::from_interleaved(&mono, 1, 8000)
+            .build()
+            .unwrap();
+        assert_eq!(bytes, as_mono);
+
+        let mut warnings = 0;
+        let (_, report) = FlacBuilder::from_interleaved(&stereo, 2, 8000)
+            .on_dual_mono(DualMonoPolicy::Warn)
+            .on_event(|event| warnings += matches!(event, EncoderEvent::Warning(_)) as usize)
+            .build_with_report()
+            .unwrap();
+        assert!(!report.collapsed_to_mono);
+        assert_eq!(warnings, 1);
+
+        let mut different = stereo.clone();
+        different[1001] = 0.25;
+        let (_, report) = FlacBuilder::from_interleaved(&different, 2, 8000)
+            .on_dual_mono(DualMonoPolicy::Collapse)
+            .build_with_report()
+            .unwrap();
+        assert!(!report.collapsed_to_mono);
+    }
+
+    #[test]
+    fn yield_hook_runs_between_chunks() {
+        let samples = vec![0.0f32; CHUNK_SIZE * 4 + 100];
+        let mut calls = 0;
+        FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .yield_hook(|| calls += 1)
+            .build()
+            .unwrap();
+
+        assert_eq!(calls, 5);
+    }
+
+    #[test]
+    fn progress_counts_up_to_the_whole_output() {
+        let samples = sine(44100);
+        let mut calls = vec![];
+        let bytes = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .on_progress(|samples, total, bytes| calls.push((samples, total, bytes)))
+            .build()
+            .unwrap();
+
+        assert!(calls.len() > 2);
+        assert!(calls
+            .windows(2)
+            .all(|w| w[0].0 <= w[1].0 && w[0].2 <= w[1].2));
+        assert!(calls.iter().all(|&(_, total, _)| total == 44100));
+        assert_eq!(*calls.last().unwrap(), (44100, 44100, bytes.len() as u64));
+    }
+
+    #[test]
+    fn cancelling_mid_encode_deletes_the_file() {
+        let samples = sine(44100);
+        let path = std::env::temp_dir().join(format!("cancelled-{}.flac", std::process::id()));
+        let token = Arc::new(AtomicBool::new(false));
+
+        let result = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .cancel_token(token.clone())
+            .on_progress(|_, _, _| token.store(true, Ordering::Relaxed))
+            .write_file(&path);
+
+        assert!(matches!(result, Err(EncoderError::Cancelled)));
+        assert!(!path.exists());
+        assert!(matches!(
+            FlacBuilder::from_interleaved(&samples, 1, 44100)
+                .cancel_token(token.clone())
+                .build(),
+            Err(EncoderError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn write_to_matches_build() {
+        let samples = sine(44100);
+        let mut cursor = std::io::Cursor::new(vec![]);
+        FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .write_to(&mut cursor)
+            .unwrap();
+
+        let built = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .build()
+            .unwrap();
+        assert_eq!(cursor.into_inner(), built);
+    }
+
+    #[test]
+    fn pictures_are_checked_before_encoding() {
+        let samples = sine(8000);
+        let result = FlacBuilder::from_interleaved(&samples, 1, 8000)
+            .picture(vec![1, 2, 3], "jpeg", PictureType::FrontCover, "")
+            .build();
+        assert!(matches!(result, Err(EncoderError::InvalidPicture(_))));
+    }
+
+    #[test]
+    fn error_codes_are_stable() {
+        assert_eq!(EncoderError::NoData.code(), 1);
+        assert_eq!(EncoderError::InvalidSampleType.code(), 6);
+        assert_eq!(
+            EncoderError::Io(std::io::ErrorKind::Other.into()).code(),
+            28
+        );
+        assert_eq!(EncoderError::MissingBuffer { sequence: 0 }.code(), 36);
+    }
+
+    #[test]
+    fn errors_describe_themselves() {
+        let samples = sine(700_000);
+        let error = FlacBuilder::from_interleaved(&samples, 1, 700_000)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "sample rate 700000 Hz is outside the streamable subset, use `lax` to allow it"
+        );
+        assert!(std::error::Error::source(&error).is_none());
+
+        let io = EncoderError::Io(std::io::Error::other("disk full"));
+        assert_eq!(io.to_string(), "I/O error: disk full");
+        assert_eq!(
+            std::error::Error::source(&io).unwrap().to_string(),
+            "disk full"
+        );
+    }
+
+    #[test]
+    fn errors_name_their_kind() {
+        let error = FlacBuilder::from_interleaved(&sine(700_000), 1, 700_000)
+            .build()
+            .unwrap_err();
+        assert_eq!(error.kind(), "SampleRateRequiresLax");
+        assert_eq!(EncoderError::NoData.kind(), "NoData");
+        let discontinuity = EncoderError::LoopDiscontinuity { channel: 1 };
+        assert_eq!(discontinuity.kind(), "LoopDiscontinuity");
+    }
 }
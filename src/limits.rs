@@ -0,0 +1,194 @@
+//! Hard limits on what a builder will encode, for services encoding untrusted input.
+
+use crate::{EncoderError, Picture};
+
+/// Bounds checked before any audio is encoded, see
+/// [`FlacBuilder::limits`](crate::FlacBuilder::limits). `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub max_channels: Option<usize>,
+    /// Samples across all channels.
+    pub max_samples: Option<usize>,
+    /// Total size of the vorbis comments as `KEY=value` entries.
+    pub max_tag_bytes: Option<usize>,
+    /// Size of any one picture's image data.
+    pub max_picture_bytes: Option<usize>,
+    /// Size of everything ahead of the audio: tags, pictures, padding and the block headers.
+    /// Unlike the others this is checked once libFLAC has built the blocks. See
+    /// [`EMBEDDED_PLAYER_METADATA_BYTES`](Self::EMBEDDED_PLAYER_METADATA_BYTES).
+    pub max_metadata_bytes: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    Channels,
+    Samples,
+    TagBytes,
+    PictureBytes,
+    MetadataBytes,
+}
+
+impl Limits {
+    /// Some hardware and embedded players read the whole metadata section into a small buffer
+    /// and refuse or skip files where it doesn't fit, usually because of large cover art. This
+    /// is a conservative bound for those, for `max_metadata_bytes` or
+    /// [`FlacBuilder::warn_metadata_over`](crate::FlacBuilder::warn_metadata_over).
+    pub const EMBEDDED_PLAYER_METADATA_BYTES: usize = 1 << 20;
+
+    pub(crate) fn check(&self, kind: LimitKind, actual: usize) -> Result<(), EncoderError> {
+        let max = match kind {
+            LimitKind::Channels => self.max_channels,
+            LimitKind::Samples => self.max_samples,
+            LimitKind::TagBytes => self.max_tag_bytes,
+            LimitKind::PictureBytes => self.max_picture_bytes,
+            LimitKind::MetadataBytes => self.max_metadata_bytes,
+        };
+
+        match max {
+            Some(max) if actual > max => Err(EncoderError::LimitExceeded { kind, max, actual }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks `picture` against `max_picture_bytes`, e.g. before
+    /// [`replace_picture`](crate::replace_picture).
+    pub fn check_picture(&self, picture: &Picture) -> Result<(), EncoderError> {
+        self.check(LimitKind::PictureBytes, picture.data.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FlacBuilder, FlacStreamEncoder, PictureType};
+
+    fn exceeded<T: std::fmt::Debug>(result: Result<T, EncoderError>) -> (LimitKind, usize, usize) {
+        match result {
+            Err(EncoderError::LimitExceeded { kind, max, actual }) => (kind, max, actual),
+            other => panic!("expected LimitExceeded, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn builder_checks_before_encoding() {
+        let samples = [0.0f32; 1000];
+        let build = |limits| {
+            FlacBuilder::from_interleaved(&samples, 2, 44100)
+                .title("Song")
+                .limits(limits)
+                .build()
+        };
+
+        assert!(build(Limits::default()).is_ok());
+        assert_eq!(
+            exceeded(build(Limits {
+                max_channels: Some(1),
+                ..Default::default()
+            })),
+            (LimitKind::Channels, 1, 2)
+        );
+        assert_eq!(
+            exceeded(build(Limits {
+                max_samples: Some(999),
+                ..Default::default()
+            })),
+            (LimitKind::Samples, 999, 1000)
+        );
+        assert_eq!(
+            exceeded(build(Limits {
+                max_tag_bytes: Some(5),
+                ..Default::default()
+            })),
+            (LimitKind::TagBytes, 5, "TITLE=Song".len())
+        );
+    }
+
+    #[test]
+    fn stream_pushes_count_towards_the_sample_limit() {
+        let limits = Limits {
+            max_samples: Some(3000),
+            ..Default::default()
+        };
+        let mut encoder = FlacStreamEncoder::new(2, 44100, vec![], |builder: FlacBuilder<f32>| {
+            builder.limits(limits)
+        })
+        .unwrap();
+
+        encoder.push_interleaved(&[0.0; 2000]).unwrap();
+        assert_eq!(
+            exceeded(encoder.push_interleaved(&[0.0; 2000])),
+            (LimitKind::Samples, 3000, 4000)
+        );
+    }
+
+    #[test]
+    fn pictures_are_checked_on_request() {
+        let limits = Limits {
+            max_picture_bytes: Some(2),
+            ..Default::default()
+        };
+        let picture = Picture {
+            picture_type: PictureType::FrontCover,
+            mime_type: "image/png".to_string(),
+            description: String::new(),
+            width: 1,
+            height: 1,
+            depth: 24,
+            colors: 0,
+            data: vec![1, 2, 3],
+        };
+
+        assert_eq!(
+            exceeded(limits.check_picture(&picture)),
+            (LimitKind::PictureBytes, 2, 3)
+        );
+    }
+
+    #[test]
+    fn metadata_size_fails_over_the_limit_and_warns_over_the_threshold() {
+        let samples = [0.0f32; 1000];
+        let builder = || {
+            FlacBuilder::from_interleaved(&samples, 2, 44100)
+                .title("Song")
+                .padding(1000)
+        };
+
+        // Walk the block headers to find where the audio starts.
+        let bytes = builder().build().unwrap();
+        let mut metadata_bytes = 4;
+        loop {
+            let header = &bytes[metadata_bytes..metadata_bytes + 4];
+            metadata_bytes += 4 + u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+            if header[0] & 0x80 != 0 {
+                break;
+            }
+        }
+
+        let limits = |max| Limits {
+            max_metadata_bytes: Some(max),
+            ..Default::default()
+        };
+        assert!(builder().limits(limits(metadata_bytes)).build().is_ok());
+        assert_eq!(
+            exceeded(builder().limits(limits(metadata_bytes - 1)).build()),
+            (LimitKind::MetadataBytes, metadata_bytes - 1, metadata_bytes)
+        );
+
+        let mut warnings = vec![];
+        builder()
+            .warn_metadata_over(1000)
+            .on_event(|event| {
+                if let crate::EncoderEvent::Warning(warning) = event {
+                    warnings.push(warning);
+                }
+            })
+            .build()
+            .unwrap();
+        assert_eq!(
+            warnings,
+            [format!(
+                "metadata is {metadata_bytes} bytes, over 1000; some players may not read it"
+            )]
+        );
+    }
+}
@@ -0,0 +1,440 @@
+//! Loudness measurement per ITU-R BS.1770 / EBU R128, on its own or for ReplayGain tags.
+
+use std::{f64::consts::PI, path::Path};
+
+use crate::{AudioSource, EncoderError, FlacDecoder, IntoSample, MetadataEditor};
+
+/// ReplayGain 2.0 plays everything back at this loudness.
+const REPLAYGAIN_REFERENCE_LUFS: f64 = -18.0;
+
+/// Loudness of one track, or of a whole album, as measured by [`analyze`] or
+/// [`tag_album_gain`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoudnessReport {
+    /// Gated integrated loudness in LUFS, `f64::NEG_INFINITY` for silence or input shorter than
+    /// one 400 ms measurement block.
+    pub integrated_lufs: f64,
+    /// Largest absolute sample value, where `1.0` is full scale.
+    pub sample_peak: f64,
+    /// Samples at or beyond full scale. A handful in a row usually means the material was
+    /// clipped before it got here.
+    pub clipped_samples: usize,
+}
+
+impl LoudnessReport {
+    /// The ReplayGain 2.0 gain, for the `REPLAYGAIN_TRACK_GAIN` tag, or `REPLAYGAIN_ALBUM_GAIN`
+    /// for [`AlbumLoudness::album`].
+    pub fn replaygain_track_gain_db(&self) -> f64 {
+        REPLAYGAIN_REFERENCE_LUFS - self.integrated_lufs
+    }
+
+    pub fn is_clipped(&self) -> bool {
+        self.clipped_samples > 0
+    }
+}
+
+/// Measures interleaved samples without encoding them, e.g. to reject clipped or far too quiet
+/// uploads before spending time on an encode. Float samples are taken as they are; other
+/// sample types are measured through their 24-bit conversion.
+pub fn analyze<S: IntoSample>(
+    samples: &[S],
+    channels: usize,
+    sample_rate: u32,
+) -> Result<LoudnessReport, EncoderError> {
+    if channels == 0 {
+        return Err(EncoderError::InvalidChannelCount);
+    }
+    if !samples.len().is_multiple_of(channels) {
+        return Err(EncoderError::MismatchedSampleCountPerChannels);
+    }
+    if sample_rate == 0 {
+        return Err(EncoderError::InvalidSampleRate);
+    }
+
+    let full_scale = ((1 << 23) - 1) as f64;
+    let to_float = |sample: &S| {
+        sample
+            .to_f64()
+            .unwrap_or_else(|| sample.to_i24() as f64 / full_scale)
+    };
+
+    let mut meter = Meter::new(channels, sample_rate);
+    for frame in samples.chunks_exact(channels) {
+        meter.push(frame.iter().map(to_float));
+    }
+
+    Ok(meter.report(&meter.blocks()))
+}
+
+/// What [`tag_album_gain`] measured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlbumLoudness {
+    /// One report per file, in the order given.
+    pub tracks: Vec<LoudnessReport>,
+    /// All files measured as one, gated over every track's blocks as ReplayGain 2.0 does.
+    pub album: LoudnessReport,
+}
+
+/// Measures every FLAC file in `paths` as one album, then writes `REPLAYGAIN_TRACK_GAIN`,
+/// `REPLAYGAIN_TRACK_PEAK` and the same `REPLAYGAIN_ALBUM_GAIN` and `REPLAYGAIN_ALBUM_PEAK`
+/// into each, replacing any already there. This needs every track at once, so it runs after
+/// the files are encoded. Nothing is written unless every file could be decoded; the gain tags
+/// are left out for silent audio, which has no gain.
+pub fn tag_album_gain<P: AsRef<Path>>(paths: &[P]) -> Result<AlbumLoudness, EncoderError> {
+    let mut tracks = vec![];
+    let mut album_blocks = vec![];
+
+    for path in paths {
+        let mut decoder = FlacDecoder::open(path)?;
+        let channels = decoder.channels();
+        let full_scale = ((1u64 << (decoder.bps() - 1)) - 1) as f64;
+
+        let mut meter = Meter::new(channels, decoder.sample_rate());
+        let mut buffer = vec![0; 4096 * channels];
+
+        loop {
+            let n = decoder.fill(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+
+            for frame in buffer[..n].chunks_exact(channels) {
+                meter.push(frame.iter().map(|&sample| sample as f64 / full_scale));
+            }
+        }
+
+        let blocks = meter.blocks();
+        tracks.push(meter.report(&blocks));
+        album_blocks.extend(blocks);
+    }
+
+    let album = LoudnessReport {
+        integrated_lufs: gated_loudness(&album_blocks),
+        sample_peak: tracks.iter().map(|t| t.sample_peak).fold(0.0, f64::max),
+        clipped_samples: tracks.iter().map(|t| t.clipped_samples).sum(),
+    };
+
+    for (path, track) in paths.iter().zip(&tracks) {
+        let mut editor = MetadataEditor::open(path)?;
+
+        write_gain_tags(&mut editor, "TRACK", track)?;
+        write_gain_tags(&mut editor, "ALBUM", &album)?;
+
+        editor.save()?;
+    }
+
+    Ok(AlbumLoudness { tracks, album })
+}
+
+/// Sets `REPLAYGAIN_{scope}_GAIN` and `_PEAK` in the format foobar2000 and metaflac use.
+fn write_gain_tags(
+    editor: &mut MetadataEditor,
+    scope: &str,
+    report: &LoudnessReport,
+) -> Result<(), EncoderError> {
+    let gain_key = format!("REPLAYGAIN_{scope}_GAIN");
+    let gain = report.replaygain_track_gain_db();
+
+    if gain.is_finite() {
+        editor.set_comment(&gain_key, &format!("{gain:.2} dB"))?;
+    } else {
+        editor.remove_comments(&gain_key)?;
+    }
+
+    editor.set_comment(
+        &format!("REPLAYGAIN_{scope}_PEAK"),
+        &format!("{:.6}", report.sample_peak),
+    )
+}
+
+/// BS.1770 measurement fed a frame at a time.
+struct Meter {
+    weights: Vec<f64>,
+    filters: Vec<KWeighting>,
+    /// Frames per 100 ms step; BS.1770 blocks are four steps.
+    step: usize,
+    /// Mean square power summed over channels, per step.
+    steps: Vec<f64>,
+    step_power: f64,
+    in_step: usize,
+    sample_peak: f64,
+    clipped_samples: usize,
+}
+
+impl Meter {
+    fn new(channels: usize, sample_rate: u32) -> Self {
+        Meter {
+            weights: channel_weights(channels),
+            filters: vec![KWeighting::new(sample_rate); channels],
+            step: (sample_rate / 10).max(1) as usize,
+            steps: vec![],
+            step_power: 0.0,
+            in_step: 0,
+            sample_peak: 0.0,
+            clipped_samples: 0,
+        }
+    }
+
+    /// Adds one frame of samples where `1.0` is full scale.
+    fn push(&mut self, frame: impl Iterator<Item = f64>) {
+        for ((sample, filter), weight) in frame.zip(&mut self.filters).zip(&self.weights) {
+            self.sample_peak = self.sample_peak.max(sample.abs());
+            if sample.abs() >= 1.0 {
+                self.clipped_samples += 1;
+            }
+
+            let filtered = filter.process(sample);
+            self.step_power += weight * filtered * filtered;
+        }
+
+        self.in_step += 1;
+        if self.in_step == self.step {
+            self.steps.push(self.step_power / self.step as f64);
+            self.step_power = 0.0;
+            self.in_step = 0;
+        }
+    }
+
+    /// Power of each overlapping 400 ms block so far.
+    fn blocks(&self) -> Vec<f64> {
+        self.steps
+            .windows(4)
+            .map(|w| w.iter().sum::<f64>() / 4.0)
+            .collect()
+    }
+
+    fn report(&self, blocks: &[f64]) -> LoudnessReport {
+        LoudnessReport {
+            integrated_lufs: gated_loudness(blocks),
+            sample_peak: self.sample_peak,
+            clipped_samples: self.clipped_samples,
+        }
+    }
+}
+
+fn loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// Integrated loudness of 400 ms block powers with the -70 LUFS absolute and -10 LU relative
+/// gates.
+fn gated_loudness(blocks: &[f64]) -> f64 {
+    let mean_above = |threshold: f64| {
+        let gated: Vec<f64> = blocks
+            .iter()
+            .copied()
+            .filter(|power| loudness(*power) > threshold)
+            .collect();
+
+        if gated.is_empty() {
+            None
+        } else {
+            Some(gated.iter().sum::<f64>() / gated.len() as f64)
+        }
+    };
+
+    let Some(ungated) = mean_above(-70.0) else {
+        return f64::NEG_INFINITY;
+    };
+
+    mean_above(loudness(ungated) - 10.0)
+        .map(loudness)
+        .unwrap_or(f64::NEG_INFINITY)
+}
+
+/// BS.1770 channel weights in FLAC's channel order. Surround channels count for more and LFE
+/// isn't counted.
+fn channel_weights(channels: usize) -> Vec<f64> {
+    (0..channels)
+        .map(|channel| match (channels, channel) {
+            (5, 3 | 4) => 1.41,
+            (6.., 3) => 0.0,
+            (6.., 4 | 5) => 1.41,
+            _ => 1.0,
+        })
+        .collect()
+}
+
+/// The two-stage K-weighting pre-filter, designed for the sample rate as in libebur128 rather
+/// than using the 48 kHz coefficients from the standard.
+#[derive(Debug, Clone)]
+struct KWeighting {
+    stages: [Biquad; 2],
+}
+
+impl KWeighting {
+    fn new(sample_rate: u32) -> Self {
+        let rate = sample_rate as f64;
+
+        // High shelf modelling the acoustic effect of the head.
+        let k = (PI * 1681.974450955533 / rate).tan();
+        let q = 0.7071752369554196;
+        let vh = 10f64.powf(3.999843853973347 / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        // RLB high pass.
+        let k = (PI * 38.13547087602444 / rate).tan();
+        let q = 0.5003270373238773;
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        KWeighting {
+            stages: [shelf, high_pass],
+        }
+    }
+
+    fn process(&mut self, sample: f64) -> f64 {
+        self.stages
+            .iter_mut()
+            .fold(sample, |sample, stage| stage.process(sample))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    state: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad {
+            b,
+            a,
+            state: [0.0; 2],
+        }
+    }
+
+    /// Transposed direct form II.
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.state[0];
+        self.state[0] = self.b[1] * x - self.a[0] * y + self.state[1];
+        self.state[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{read_comments, FlacBuilder};
+
+    /// Two seconds of a 997 Hz sine at 48 kHz, stereo.
+    fn tone(amplitude: f32) -> Vec<f32> {
+        (0..96_000)
+            .flat_map(|i| {
+                let sample = (i as f32 * 997.0 * std::f32::consts::TAU / 48_000.0).sin();
+                [sample * amplitude; 2]
+            })
+            .collect()
+    }
+
+    fn comment<'a>(comments: &'a [(String, String)], key: &str) -> Option<&'a str> {
+        comments
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn full_scale_sine_measures_minus_three_lufs() {
+        let mut meter = Meter::new(1, 48_000);
+        for i in 0..48_000 {
+            meter.push(std::iter::once(
+                (i as f64 * 997.0 * std::f64::consts::TAU / 48_000.0).sin(),
+            ));
+        }
+
+        let report = meter.report(&meter.blocks());
+
+        assert!((report.integrated_lufs + 3.01).abs() < 0.05);
+        assert!(report.is_clipped());
+    }
+
+    #[test]
+    fn album_gain_is_shared_by_every_track() {
+        let dir = std::env::temp_dir();
+        let paths = [
+            dir.join(format!("album-loud-{}.flac", std::process::id())),
+            dir.join(format!("album-quiet-{}.flac", std::process::id())),
+        ];
+
+        for (path, amplitude) in paths.iter().zip([0.5, 0.125]) {
+            let bytes = FlacBuilder::from_interleaved(&tone(amplitude), 2, 48_000)
+                .title("Tone")
+                .vorbis_comment("REPLAYGAIN_ALBUM_GAIN", "+1.00 dB")
+                .build()
+                .unwrap();
+            fs::write(path, bytes).unwrap();
+        }
+
+        let loudness = tag_album_gain(&paths).unwrap();
+        let comments: Vec<_> = paths
+            .iter()
+            .map(|path| read_comments(&fs::read(path).unwrap()).unwrap())
+            .collect();
+        for path in &paths {
+            fs::remove_file(path).unwrap();
+        }
+
+        let [loud, quiet] = [loudness.tracks[0], loudness.tracks[1]];
+        assert!((quiet.integrated_lufs - loud.integrated_lufs + 12.04).abs() < 0.1);
+        assert!(loudness.album.integrated_lufs > quiet.integrated_lufs);
+        assert!(loudness.album.integrated_lufs < loud.integrated_lufs);
+        assert_eq!(loudness.album.sample_peak, loud.sample_peak);
+
+        let album_gain = format!("{:.2} dB", loudness.album.replaygain_track_gain_db());
+        for (comments, track) in comments.iter().zip(&loudness.tracks) {
+            let track_gain = format!("{:.2} dB", track.replaygain_track_gain_db());
+
+            assert_eq!(comment(comments, "TITLE"), Some("Tone"));
+            assert_eq!(
+                comment(comments, "REPLAYGAIN_TRACK_GAIN"),
+                Some(&*track_gain)
+            );
+            assert_eq!(
+                comment(comments, "REPLAYGAIN_ALBUM_GAIN"),
+                Some(&*album_gain)
+            );
+            assert_eq!(comments.len(), 5);
+        }
+    }
+
+    #[test]
+    fn analyze_matches_across_sample_types() {
+        let floats = tone(0.5);
+        let ints: Vec<i16> = floats.iter().map(|s| s.to_i16()).collect();
+
+        let from_floats = analyze(&floats, 2, 48_000).unwrap();
+        let from_ints = analyze(&ints, 2, 48_000).unwrap();
+
+        // A full-scale sine in both channels measures 0 LUFS; half of it is 6.02 dB down.
+        assert!((from_floats.integrated_lufs + 6.02).abs() < 0.05);
+        assert!((from_floats.integrated_lufs - from_ints.integrated_lufs).abs() < 0.01);
+        assert!(!from_floats.is_clipped());
+
+        assert!(matches!(
+            analyze(&floats[1..], 2, 48_000),
+            Err(EncoderError::MismatchedSampleCountPerChannels)
+        ));
+        assert!(matches!(
+            analyze(&floats, 0, 48_000),
+            Err(EncoderError::InvalidChannelCount)
+        ));
+    }
+}
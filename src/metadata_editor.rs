@@ -0,0 +1,460 @@
+//! Editing the metadata of an existing file with libFLAC's level 2 chain interface.
+
+use std::{
+    ffi::{CStr, CString},
+    path::Path,
+    slice::from_raw_parts,
+    str::FromStr,
+};
+
+use libflac_sys::*;
+
+use crate::{EncoderError, Picture, PictureType};
+
+/// Reads all metadata blocks of a FLAC file into memory so tags, pictures and padding can be
+/// changed freely, then writes them back with [`save`](Self::save) without touching the audio.
+///
+/// Nothing is written until `save`. libFLAC uses padding to avoid moving the audio where it
+/// can, and otherwise rewrites the whole file through a temporary file next to it.
+pub struct MetadataEditor {
+    chain: *mut FLAC__Metadata_Chain,
+}
+
+impl MetadataEditor {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, EncoderError> {
+        let Ok(path) = CString::from_str(&path.as_ref().to_string_lossy()) else {
+            return Err(EncoderError::NullCharInPath);
+        };
+
+        unsafe {
+            let chain = FLAC__metadata_chain_new();
+
+            if chain.is_null() {
+                return Err(EncoderError::InitializationError);
+            }
+
+            let this = MetadataEditor { chain };
+
+            if 0 == FLAC__metadata_chain_read(chain, path.as_ptr()) {
+                return Err(this.status_error());
+            }
+
+            Ok(this)
+        }
+    }
+
+    /// Vorbis comments in the order they are stored. Entries without a `=`, which other
+    /// taggers sometimes write, are skipped; they are kept in the file on save.
+    pub fn comments(&self) -> Result<Vec<(String, String)>, EncoderError> {
+        let mut cursor = Cursor::new(self.chain)?;
+        if !cursor.seek(FLAC__METADATA_TYPE_VORBIS_COMMENT) {
+            return Ok(vec![]);
+        }
+
+        unsafe {
+            let block = &(*cursor.block()).data.vorbis_comment;
+
+            let comments = (0..block.num_comments as usize)
+                .filter_map(|i| {
+                    let entry = &*block.comments.add(i);
+                    if entry.entry.is_null() {
+                        return None;
+                    }
+
+                    let entry =
+                        String::from_utf8_lossy(from_raw_parts(entry.entry, entry.length as usize));
+                    let (key, value) = entry.split_once('=')?;
+
+                    Some((key.to_string(), value.to_string()))
+                })
+                .collect();
+
+            Ok(comments)
+        }
+    }
+
+    /// Replaces every comment named `key` (ignoring case) with one holding `value`.
+    pub fn set_comment(&mut self, key: &str, value: &str) -> Result<(), EncoderError> {
+        self.remove_comments(key)?;
+        self.add_comment(key, value)
+    }
+
+    /// Adds a comment after the existing ones, keeping any others with the same name.
+    pub fn add_comment(&mut self, key: &str, value: &str) -> Result<(), EncoderError> {
+        let invalid = || EncoderError::InvalidVorbisComment(format!("{key}={value}"));
+        let (Ok(c_key), Ok(c_value)) = (CString::new(key), CString::new(value)) else {
+            return Err(invalid());
+        };
+
+        unsafe {
+            let block = self.vorbis_comment_block()?;
+
+            let mut entry: FLAC__StreamMetadata_VorbisComment_Entry = std::mem::zeroed();
+            if 0 == FLAC__metadata_object_vorbiscomment_entry_from_name_value_pair(
+                &mut entry,
+                c_key.as_ptr(),
+                c_value.as_ptr(),
+            ) {
+                return Err(invalid());
+            }
+
+            // Takes ownership of the entry's buffer.
+            if 0 == FLAC__metadata_object_vorbiscomment_append_comment(block, entry, 0) {
+                return Err(EncoderError::FailedToSetMetadata);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes every comment named `key` (ignoring case), returning how many there were.
+    pub fn remove_comments(&mut self, key: &str) -> Result<usize, EncoderError> {
+        let Ok(c_key) = CString::new(key) else {
+            return Err(EncoderError::InvalidVorbisComment(key.to_string()));
+        };
+
+        let mut cursor = Cursor::new(self.chain)?;
+        if !cursor.seek(FLAC__METADATA_TYPE_VORBIS_COMMENT) {
+            return Ok(0);
+        }
+
+        let removed = unsafe {
+            FLAC__metadata_object_vorbiscomment_remove_entries_matching(
+                cursor.block(),
+                c_key.as_ptr(),
+            )
+        };
+
+        if removed < 0 {
+            return Err(EncoderError::FailedToSetMetadata);
+        }
+
+        Ok(removed as usize)
+    }
+
+    pub fn pictures(&self) -> Result<Vec<Picture>, EncoderError> {
+        let mut cursor = Cursor::new(self.chain)?;
+        let mut pictures = vec![];
+
+        while cursor.seek(FLAC__METADATA_TYPE_PICTURE) {
+            pictures.push(unsafe { Picture::from_block(&(*cursor.block()).data.picture) });
+
+            if !cursor.next() {
+                break;
+            }
+        }
+
+        Ok(pictures)
+    }
+
+    /// Adds a picture after the last metadata block.
+    pub fn add_picture(&mut self, picture: &Picture) -> Result<(), EncoderError> {
+        let mut cursor = Cursor::new(self.chain)?;
+        while cursor.next() {}
+
+        unsafe {
+            let block = new_block(FLAC__METADATA_TYPE_PICTURE)?;
+
+            if let Err(e) = picture.fill(block) {
+                FLAC__metadata_object_delete(block);
+                return Err(e);
+            }
+
+            cursor.insert_after(block)
+        }
+    }
+
+    /// Removes the pictures of type `picture_type`, or all of them for `None`, returning how
+    /// many there were. They are replaced by padding so the audio doesn't have to move.
+    pub fn remove_pictures(
+        &mut self,
+        picture_type: Option<PictureType>,
+    ) -> Result<usize, EncoderError> {
+        let mut cursor = Cursor::new(self.chain)?;
+        let mut removed = 0;
+
+        while cursor.seek(FLAC__METADATA_TYPE_PICTURE) {
+            let is_match = picture_type.is_none_or(|picture_type| unsafe {
+                (*cursor.block()).data.picture.type_ == picture_type.to_u32()
+            });
+
+            if is_match {
+                cursor.delete(true)?;
+                removed += 1;
+            }
+
+            if !cursor.next() {
+                break;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Bytes of padding in the file, over all padding blocks.
+    pub fn padding(&self) -> Result<u32, EncoderError> {
+        let mut cursor = Cursor::new(self.chain)?;
+        let mut padding = 0;
+
+        while cursor.seek(FLAC__METADATA_TYPE_PADDING) {
+            padding += unsafe { (*cursor.block()).length };
+
+            if !cursor.next() {
+                break;
+            }
+        }
+
+        Ok(padding)
+    }
+
+    /// Replaces all padding with a single block of `length` bytes at the end of the metadata,
+    /// or none for `0`. [`save`](Self::save) still uses up padding it needs to avoid moving the
+    /// audio and turns what is left over into padding.
+    pub fn set_padding(&mut self, length: u32) -> Result<(), EncoderError> {
+        let mut cursor = Cursor::new(self.chain)?;
+
+        while cursor.seek(FLAC__METADATA_TYPE_PADDING) {
+            cursor.delete(false)?;
+        }
+
+        if length == 0 {
+            return Ok(());
+        }
+
+        while cursor.next() {}
+
+        unsafe {
+            let block = new_block(FLAC__METADATA_TYPE_PADDING)?;
+            (*block).length = length;
+
+            cursor.insert_after(block)
+        }
+    }
+
+    /// Writes the changes back to the file, keeping its permissions and modification time.
+    pub fn save(&mut self) -> Result<(), EncoderError> {
+        unsafe {
+            FLAC__metadata_chain_sort_padding(self.chain);
+
+            if 0 == FLAC__metadata_chain_write(self.chain, 1, 1) {
+                return Err(self.status_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The `VORBIS_COMMENT` block, added after STREAMINFO if there isn't one.
+    unsafe fn vorbis_comment_block(&mut self) -> Result<*mut FLAC__StreamMetadata, EncoderError> {
+        let mut cursor = Cursor::new(self.chain)?;
+        if cursor.seek(FLAC__METADATA_TYPE_VORBIS_COMMENT) {
+            return Ok(cursor.block());
+        }
+
+        // Back at STREAMINFO, which is always first.
+        let mut cursor = Cursor::new(self.chain)?;
+        let block = new_block(FLAC__METADATA_TYPE_VORBIS_COMMENT)?;
+        cursor.insert_after(block)?;
+
+        Ok(block)
+    }
+
+    fn status_error(&self) -> EncoderError {
+        unsafe {
+            let status = FLAC__metadata_chain_status(self.chain);
+            let message = *FLAC__Metadata_ChainStatusString
+                .as_ptr()
+                .add(status as usize);
+
+            EncoderError::MetadataIteratorError(CStr::from_ptr(message).to_string_lossy().into())
+        }
+    }
+}
+
+impl Drop for MetadataEditor {
+    fn drop(&mut self) {
+        unsafe {
+            FLAC__metadata_chain_delete(self.chain);
+        }
+    }
+}
+
+unsafe fn new_block(
+    block_type: FLAC__MetadataType,
+) -> Result<*mut FLAC__StreamMetadata, EncoderError> {
+    let block = FLAC__metadata_object_new(block_type);
+
+    if block.is_null() {
+        return Err(EncoderError::InitializationError);
+    }
+
+    Ok(block)
+}
+
+/// Owns a `FLAC__Metadata_Iterator` over the chain, starting at STREAMINFO.
+struct Cursor(*mut FLAC__Metadata_Iterator);
+
+impl Cursor {
+    fn new(chain: *mut FLAC__Metadata_Chain) -> Result<Self, EncoderError> {
+        unsafe {
+            let iterator = FLAC__metadata_iterator_new();
+
+            if iterator.is_null() {
+                return Err(EncoderError::InitializationError);
+            }
+
+            FLAC__metadata_iterator_init(iterator, chain);
+
+            Ok(Cursor(iterator))
+        }
+    }
+
+    fn next(&mut self) -> bool {
+        unsafe { 0 != FLAC__metadata_iterator_next(self.0) }
+    }
+
+    /// Moves to the first block of `block_type` from the current one on, returning whether
+    /// there was one.
+    fn seek(&mut self, block_type: FLAC__MetadataType) -> bool {
+        loop {
+            if unsafe { FLAC__metadata_iterator_get_block_type(self.0) } == block_type {
+                return true;
+            }
+
+            if !self.next() {
+                return false;
+            }
+        }
+    }
+
+    /// The current block, owned by the chain.
+    fn block(&mut self) -> *mut FLAC__StreamMetadata {
+        unsafe { FLAC__metadata_iterator_get_block(self.0) }
+    }
+
+    /// Deletes the current block, moving back to the one before it.
+    fn delete(&mut self, replace_with_padding: bool) -> Result<(), EncoderError> {
+        unsafe {
+            if 0 == FLAC__metadata_iterator_delete_block(self.0, replace_with_padding as FLAC__bool)
+            {
+                return Err(EncoderError::FailedToSetMetadata);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Inserts `block` after the current one, handing it to the chain. It is deleted if that
+    /// fails.
+    unsafe fn insert_after(
+        &mut self,
+        block: *mut FLAC__StreamMetadata,
+    ) -> Result<(), EncoderError> {
+        if 0 == FLAC__metadata_iterator_insert_block_after(self.0, block) {
+            FLAC__metadata_object_delete(block);
+            return Err(EncoderError::FailedToSetMetadata);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Cursor {
+    fn drop(&mut self) {
+        unsafe {
+            FLAC__metadata_iterator_delete(self.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{extract_pictures, read_comments, FlacBuilder, FlacReader};
+
+    fn cover(picture_type: PictureType) -> Picture {
+        Picture {
+            picture_type,
+            mime_type: "image/png".to_string(),
+            description: String::new(),
+            width: 1,
+            height: 1,
+            depth: 24,
+            colors: 0,
+            data: vec![1, 2, 3],
+        }
+    }
+
+    #[test]
+    fn edits_tags_pictures_and_padding_without_touching_the_audio() {
+        let path = std::env::temp_dir().join(format!("editor-{}.flac", std::process::id()));
+        let samples: Vec<f32> = (0..20_000).map(|i| (i as f32 / 50.0).sin() * 0.5).collect();
+        let bytes = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .title("Old")
+            .artist("Someone")
+            .picture_block(cover(PictureType::FrontCover))
+            .picture_block(cover(PictureType::BackCover))
+            .build()
+            .unwrap();
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut editor = MetadataEditor::open(&path).unwrap();
+        assert!(editor
+            .comments()
+            .unwrap()
+            .contains(&("TITLE".to_string(), "Old".to_string())));
+        assert_eq!(editor.pictures().unwrap().len(), 2);
+
+        editor.set_comment("title", "New").unwrap();
+        editor.add_comment("ARTIST", "Someone Else").unwrap();
+        assert_eq!(
+            editor
+                .remove_pictures(Some(PictureType::BackCover))
+                .unwrap(),
+            1
+        );
+        editor.set_padding(100).unwrap();
+        editor.save().unwrap();
+        drop(editor);
+
+        assert!(MetadataEditor::open(&path).unwrap().padding().unwrap() >= 100);
+        let edited = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let comments = read_comments(&edited).unwrap();
+        assert!(!comments.iter().any(|(_, value)| value == "Old"));
+        assert!(comments.contains(&("title".to_string(), "New".to_string())));
+        let artists = comments.iter().filter(|(key, _)| key == "ARTIST").count();
+        assert_eq!(artists, 2);
+        assert_eq!(
+            extract_pictures(&edited).unwrap(),
+            [cover(PictureType::FrontCover)]
+        );
+
+        assert_eq!(
+            FlacReader::from_bytes(&edited).unwrap().interleaved(),
+            FlacReader::from_bytes(&bytes).unwrap().interleaved()
+        );
+    }
+
+    #[test]
+    fn comments_skip_entries_without_an_equals_sign() {
+        let path = std::env::temp_dir().join(format!("editor-eq-{}.flac", std::process::id()));
+        let mut bytes = FlacBuilder::from_interleaved(&[0.0f32; 4096], 1, 44100)
+            .title("Old")
+            .artist("Someone")
+            .build()
+            .unwrap();
+        let at = bytes.windows(9).position(|w| w == b"TITLE=Old").unwrap();
+        bytes[at + 5] = b' ';
+        std::fs::write(&path, &bytes).unwrap();
+
+        let comments = MetadataEditor::open(&path).unwrap().comments();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            comments.unwrap(),
+            [("ARTIST".to_string(), "Someone".to_string())]
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! `IntoSample` for any float type via `num-traits`.
+
+use num_traits::Float;
+
+use crate::IntoSample;
+
+/// Wraps any [`num_traits::Float`] so it can be used as a sample, in range [-1.0, 1.0] like
+/// `f32` and `f64`. This is a wrapper rather than a blanket impl so it doesn't conflict with the
+/// built-in impls; [`wrap_slice`](Self::wrap_slice) and [`wrap_planar`](Self::wrap_planar)
+/// convert existing buffers without copying.
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+#[repr(transparent)]
+pub struct NumSample<T>(pub T);
+
+impl<T: Float> NumSample<T> {
+    pub fn wrap_slice(samples: &[T]) -> &[NumSample<T>] {
+        // SAFETY: `NumSample<T>` is `repr(transparent)` over `T`.
+        unsafe {
+            std::slice::from_raw_parts(samples.as_ptr() as *const NumSample<T>, samples.len())
+        }
+    }
+
+    pub fn wrap_planar(channels: Vec<Vec<T>>) -> Vec<Vec<NumSample<T>>> {
+        channels
+            .into_iter()
+            .map(|channel| {
+                let mut channel = std::mem::ManuallyDrop::new(channel);
+                // SAFETY: `NumSample<T>` is `repr(transparent)` over `T` so the allocation has
+                // the same layout.
+                unsafe {
+                    Vec::from_raw_parts(
+                        channel.as_mut_ptr() as *mut NumSample<T>,
+                        channel.len(),
+                        channel.capacity(),
+                    )
+                }
+            })
+            .collect()
+    }
+
+    fn as_f64(self) -> f64 {
+        self.0.to_f64().unwrap_or(0.0)
+    }
+}
+
+impl<T: Float + Default> IntoSample for NumSample<T> {
+    fn to_i16(&self) -> i16 {
+        self.as_f64().to_i16()
+    }
+
+    fn to_i20(&self) -> i32 {
+        self.as_f64().to_i20()
+    }
+
+    fn to_i24(&self) -> i32 {
+        self.as_f64().to_i24()
+    }
+
+    fn to_f64(&self) -> Option<f64> {
+        Some(self.as_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_like_the_builtin_floats() {
+        let samples = [0.5f64, -1.0, 0.25];
+
+        for (wrapped, sample) in NumSample::wrap_slice(&samples).iter().zip(samples) {
+            assert_eq!(wrapped.to_i16(), sample.to_i16());
+            assert_eq!(wrapped.to_i24(), sample.to_i24());
+        }
+
+        let planar = NumSample::wrap_planar(vec![vec![0.5f32], vec![-0.5]]);
+        assert_eq!(planar, [[NumSample(0.5)], [NumSample(-0.5)]]);
+    }
+}
@@ -0,0 +1,334 @@
+//! `PICTURE` metadata blocks.
+
+use std::{
+    ffi::{c_char, CStr, CString},
+    ptr::null,
+};
+
+use libflac_sys::*;
+
+use crate::{raw::MAX_BLOCK_LENGTH, EncoderError};
+
+/// The picture types from the ID3v2 APIC frame, which FLAC reuses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PictureType {
+    Other,
+    /// 32x32 PNG only.
+    FileIcon,
+    OtherFileIcon,
+    FrontCover,
+    BackCover,
+    LeafletPage,
+    /// E.g. the label side of a CD.
+    Media,
+    LeadArtist,
+    Artist,
+    Conductor,
+    Band,
+    Composer,
+    Lyricist,
+    RecordingLocation,
+    DuringRecording,
+    DuringPerformance,
+    VideoScreenCapture,
+    BrightColouredFish,
+    Illustration,
+    BandLogotype,
+    PublisherLogotype,
+}
+
+impl PictureType {
+    const ALL: [PictureType; 21] = [
+        PictureType::Other,
+        PictureType::FileIcon,
+        PictureType::OtherFileIcon,
+        PictureType::FrontCover,
+        PictureType::BackCover,
+        PictureType::LeafletPage,
+        PictureType::Media,
+        PictureType::LeadArtist,
+        PictureType::Artist,
+        PictureType::Conductor,
+        PictureType::Band,
+        PictureType::Composer,
+        PictureType::Lyricist,
+        PictureType::RecordingLocation,
+        PictureType::DuringRecording,
+        PictureType::DuringPerformance,
+        PictureType::VideoScreenCapture,
+        PictureType::BrightColouredFish,
+        PictureType::Illustration,
+        PictureType::BandLogotype,
+        PictureType::PublisherLogotype,
+    ];
+
+    pub fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    /// Values outside the ID3v2 range are treated as `Other`.
+    pub fn from_u32(value: u32) -> Self {
+        Self::ALL
+            .get(value as usize)
+            .copied()
+            .unwrap_or(PictureType::Other)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Picture {
+    pub picture_type: PictureType,
+    pub mime_type: String,
+    pub description: String,
+    pub width: u32,
+    pub height: u32,
+    /// Colour depth in bits per pixel.
+    pub depth: u32,
+    /// Number of colours for indexed-colour pictures, otherwise 0.
+    pub colors: u32,
+    pub data: Vec<u8>,
+}
+
+impl Picture {
+    /// Parses the body of a `PICTURE` block (without the block header).
+    pub(crate) fn from_block_data(data: &[u8]) -> Result<Self, EncoderError> {
+        let mut reader = BeReader { data, cursor: 0 };
+
+        let picture_type = PictureType::from_u32(reader.u32()?);
+        let mime_length = reader.u32()? as usize;
+        let mime_type = String::from_utf8_lossy(reader.bytes(mime_length)?).to_string();
+        let description_length = reader.u32()? as usize;
+        let description = String::from_utf8_lossy(reader.bytes(description_length)?).to_string();
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let depth = reader.u32()?;
+        let colors = reader.u32()?;
+        let data_length = reader.u32()? as usize;
+        let data = reader.bytes(data_length)?.to_vec();
+
+        Ok(Picture {
+            picture_type,
+            mime_type,
+            description,
+            width,
+            height,
+            depth,
+            colors,
+            data,
+        })
+    }
+
+    /// Checks what libFLAC doesn't: the MIME type must be printable ASCII of the form
+    /// `type/subtype`, or `-->` when the data is a URL, and the description can't contain NUL.
+    pub(crate) fn check(&self) -> Result<(), EncoderError> {
+        let is_printable = self.mime_type.bytes().all(|b| (0x20..=0x7e).contains(&b));
+        let has_subtype = self
+            .mime_type
+            .split_once('/')
+            .is_some_and(|(kind, subtype)| !kind.is_empty() && !subtype.is_empty());
+
+        if !(is_printable && has_subtype || self.mime_type == "-->") {
+            return Err(EncoderError::InvalidPicture(format!(
+                "MIME type {:?} isn't printable ASCII of the form type/subtype",
+                self.mime_type
+            )));
+        }
+
+        if self.description.contains('\0') {
+            return Err(EncoderError::InvalidPicture(
+                "description contains a NUL character".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Fills in an empty `PICTURE` block and checks it with libFLAC.
+    pub(crate) unsafe fn fill(&self, block: *mut FLAC__StreamMetadata) -> Result<(), EncoderError> {
+        self.check()?;
+
+        let target = &mut (*block).data.picture;
+        target.type_ = self.picture_type.to_u32();
+        target.width = self.width;
+        target.height = self.height;
+        target.depth = self.depth;
+        target.colors = self.colors;
+
+        // Checked above to have no NUL, and everything is copied.
+        let mime_type = CString::new(self.mime_type.as_str()).unwrap_or_default();
+        let description = CString::new(self.description.as_str()).unwrap_or_default();
+
+        let is_set = 0
+            != FLAC__metadata_object_picture_set_mime_type(
+                block,
+                mime_type.as_ptr() as *mut c_char,
+                1,
+            )
+            && 0 != FLAC__metadata_object_picture_set_description(
+                block,
+                description.as_ptr() as *mut FLAC__byte,
+                1,
+            )
+            && 0 != FLAC__metadata_object_picture_set_data(
+                block,
+                self.data.as_ptr() as *mut FLAC__byte,
+                self.data.len() as u32,
+                1,
+            );
+        if !is_set {
+            return Err(EncoderError::FailedToSetMetadata);
+        }
+
+        if (*block).length as usize > MAX_BLOCK_LENGTH {
+            return Err(EncoderError::MetadataBlockTooLarge);
+        }
+
+        let mut violation = null();
+        if 0 == FLAC__metadata_object_picture_is_legal(block, &mut violation) {
+            return Err(EncoderError::InvalidPicture(
+                CStr::from_ptr(violation).to_string_lossy().into_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// From a `PICTURE` block read by libFLAC.
+    pub(crate) unsafe fn from_block(block: &FLAC__StreamMetadata_Picture) -> Self {
+        let text = |ptr: *const c_char| {
+            if ptr.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(ptr).to_string_lossy().into_owned()
+            }
+        };
+        let data = if block.data.is_null() {
+            vec![]
+        } else {
+            std::slice::from_raw_parts(block.data, block.data_length as usize).to_vec()
+        };
+
+        Picture {
+            picture_type: PictureType::from_u32(block.type_),
+            mime_type: text(block.mime_type),
+            description: text(block.description as *const c_char),
+            width: block.width,
+            height: block.height,
+            depth: block.depth,
+            colors: block.colors,
+            data,
+        }
+    }
+
+    /// Serializes to the body of a `PICTURE` block (without the block header).
+    pub(crate) fn to_block_data(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(
+            32 + self.mime_type.len() + self.description.len() + self.data.len(),
+        );
+
+        out.extend(self.picture_type.to_u32().to_be_bytes());
+        out.extend((self.mime_type.len() as u32).to_be_bytes());
+        out.extend(self.mime_type.as_bytes());
+        out.extend((self.description.len() as u32).to_be_bytes());
+        out.extend(self.description.as_bytes());
+        out.extend(self.width.to_be_bytes());
+        out.extend(self.height.to_be_bytes());
+        out.extend(self.depth.to_be_bytes());
+        out.extend(self.colors.to_be_bytes());
+        out.extend((self.data.len() as u32).to_be_bytes());
+        out.extend(&self.data);
+
+        out
+    }
+}
+
+pub(crate) struct BeReader<'a> {
+    pub data: &'a [u8],
+    pub cursor: usize,
+}
+
+impl<'a> BeReader<'a> {
+    pub fn bytes(&mut self, length: usize) -> Result<&'a [u8], EncoderError> {
+        let Some(bytes) = self.data.get(self.cursor..self.cursor + length) else {
+            return Err(EncoderError::MalformedFlacData);
+        };
+        self.cursor += length;
+        Ok(bytes)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, EncoderError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, EncoderError> {
+        let mut value = [0; 8];
+        value.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cover() -> Picture {
+        Picture {
+            picture_type: PictureType::FrontCover,
+            mime_type: "image/jpeg".to_string(),
+            description: "Front ✓".to_string(),
+            width: 600,
+            height: 400,
+            depth: 24,
+            colors: 0,
+            data: vec![0xff, 0xd8, 0xff, 0xe0],
+        }
+    }
+
+    #[test]
+    fn block_data_round_trips() {
+        let data = cover().to_block_data();
+
+        // Type, then the MIME type's length and text.
+        assert_eq!(data[..4], 3u32.to_be_bytes());
+        assert_eq!(data[4..8], 10u32.to_be_bytes());
+        assert_eq!(&data[8..18], b"image/jpeg");
+
+        assert_eq!(Picture::from_block_data(&data).unwrap(), cover());
+    }
+
+    #[test]
+    fn truncated_block_data_is_malformed() {
+        let data = cover().to_block_data();
+
+        assert!(matches!(
+            Picture::from_block_data(&data[..data.len() - 1]),
+            Err(EncoderError::MalformedFlacData)
+        ));
+    }
+
+    #[test]
+    fn picture_types_follow_id3v2() {
+        assert_eq!(PictureType::from_u32(0), PictureType::Other);
+        assert_eq!(PictureType::from_u32(3), PictureType::FrontCover);
+        assert_eq!(PictureType::from_u32(20), PictureType::PublisherLogotype);
+        assert_eq!(PictureType::from_u32(21), PictureType::Other);
+        assert_eq!(PictureType::BandLogotype.to_u32(), 19);
+    }
+
+    #[test]
+    fn checks_mime_type_and_description() {
+        let with = |mime_type: &str, description: &str| Picture {
+            mime_type: mime_type.to_string(),
+            description: description.to_string(),
+            ..cover()
+        };
+
+        assert!(with("image/png", "").check().is_ok());
+        assert!(with("-->", "").check().is_ok());
+        assert!(with("image", "").check().is_err());
+        assert!(with("image/", "").check().is_err());
+        assert!(with("imäge/png", "").check().is_err());
+        assert!(with("image/png", "a\0b").check().is_err());
+    }
+}
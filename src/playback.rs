@@ -0,0 +1,199 @@
+//! Playing a FLAC file on the default output device with cpal.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, Receiver, TryRecvError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+use crate::{AudioSource, EncoderError, FlacDecoder};
+
+/// Frames per channel decoded at a time.
+const BLOCK_FRAMES: usize = 4096;
+
+/// Decoded blocks held ahead of the output, about 0.75 s at 44.1 kHz.
+const BUFFERED_BLOCKS: usize = 8;
+
+/// A FLAC file playing on the default output device, e.g. for a demo or to listen to a test
+/// encode. A thread decodes a few blocks ahead of the output device; if it falls behind the
+/// device plays silence until it catches up. Dropping the player stops it.
+pub struct Player {
+    // Declared first so the output stops before the decoder is told to.
+    stream: cpal::Stream,
+    decoder: Option<JoinHandle<Result<(), EncoderError>>>,
+    /// Set by the output callback once the decoder is done and everything has been played.
+    played: Arc<AtomicBool>,
+}
+
+impl Player {
+    /// Starts playing `path`. Fails with [`EncoderError::Playback`] if there is no output
+    /// device or it can't play the file's channel count and sample rate, which aren't
+    /// converted.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, EncoderError> {
+        let path: PathBuf = path.as_ref().to_path_buf();
+
+        // The decoder holds a libFLAC pointer, so it is made on the thread that uses it.
+        let (format_sender, format) = sync_channel(1);
+        let (block_sender, blocks) = sync_channel::<Vec<f32>>(BUFFERED_BLOCKS);
+
+        let decoder = thread::spawn(move || {
+            let mut decoder = match FlacDecoder::open(path) {
+                Ok(decoder) => decoder,
+                Err(e) => {
+                    let _ = format_sender.send(None);
+                    return Err(e);
+                }
+            };
+            let _ = format_sender.send(Some((decoder.channels(), decoder.sample_rate())));
+
+            let scale = 1.0 / (1u64 << (decoder.bps() - 1)) as f32;
+            let mut buffer = vec![0; BLOCK_FRAMES * decoder.channels()];
+            loop {
+                let n = decoder.fill(&mut buffer)?;
+                if n == 0 {
+                    return Ok(());
+                }
+
+                let block = buffer[..n].iter().map(|&s| s as f32 * scale).collect();
+                // The player is gone.
+                if block_sender.send(block).is_err() {
+                    return Ok(());
+                }
+            }
+        });
+
+        let Ok(Some((channels, sample_rate))) = format.recv() else {
+            return Err(join(decoder).err().unwrap_or(EncoderError::Playback(
+                "the decoder stopped without reading the file".to_string(),
+            )));
+        };
+
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| EncoderError::Playback("no output device".to_string()))?;
+        let config = cpal::StreamConfig {
+            channels: channels as u16,
+            sample_rate: cpal::SampleRate(sample_rate),
+            buffer_size: cpal::BufferSize::Default,
+        };
+
+        let played = Arc::new(AtomicBool::new(false));
+        let mut output = Output {
+            blocks,
+            block: vec![],
+            cursor: 0,
+            played: played.clone(),
+        };
+
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |data: &mut [f32], _| output.fill(data),
+                |_| {},
+                None,
+            )
+            .map_err(|e| EncoderError::Playback(e.to_string()))?;
+        stream
+            .play()
+            .map_err(|e| EncoderError::Playback(e.to_string()))?;
+
+        Ok(Player {
+            stream,
+            decoder: Some(decoder),
+            played,
+        })
+    }
+
+    /// Whether the whole file has been played, or decoding failed.
+    pub fn is_done(&self) -> bool {
+        self.played.load(Ordering::Acquire)
+    }
+
+    /// Blocks until the whole file has been played, returning any error decoding it.
+    pub fn wait(mut self) -> Result<(), EncoderError> {
+        while !self.is_done() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let _ = self.stream.pause();
+        self.decoder.take().map_or(Ok(()), join)
+    }
+}
+
+fn join(decoder: JoinHandle<Result<(), EncoderError>>) -> Result<(), EncoderError> {
+    decoder
+        .join()
+        .unwrap_or_else(|_| Err(EncoderError::Playback("the decoder panicked".to_string())))
+}
+
+/// State of the output callback.
+struct Output {
+    blocks: Receiver<Vec<f32>>,
+    block: Vec<f32>,
+    /// Samples of `block` already played.
+    cursor: usize,
+    played: Arc<AtomicBool>,
+}
+
+impl Output {
+    fn fill(&mut self, mut data: &mut [f32]) {
+        while !data.is_empty() {
+            if self.cursor == self.block.len() {
+                match self.blocks.try_recv() {
+                    Ok(block) => {
+                        self.block = block;
+                        self.cursor = 0;
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.played.store(true, Ordering::Release);
+                        break;
+                    }
+                }
+            }
+
+            let n = data.len().min(self.block.len() - self.cursor);
+            data[..n].copy_from_slice(&self.block[self.cursor..self.cursor + n]);
+            self.cursor += n;
+            data = &mut data[n..];
+        }
+
+        data.fill(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_plays_blocks_back_to_back_then_silence() {
+        let (sender, blocks) = sync_channel(4);
+        let played = Arc::new(AtomicBool::new(false));
+        let mut output = Output {
+            blocks,
+            block: vec![],
+            cursor: 0,
+            played: played.clone(),
+        };
+
+        sender.send(vec![0.1, 0.2, 0.3]).unwrap();
+        sender.send(vec![0.4]).unwrap();
+        let mut data = [1.0; 6];
+        output.fill(&mut data);
+        assert_eq!(data, [0.1, 0.2, 0.3, 0.4, 0.0, 0.0]);
+        assert!(!played.load(Ordering::Acquire));
+
+        drop(sender);
+        output.fill(&mut data);
+        assert_eq!(data, [0.0; 6]);
+        assert!(played.load(Ordering::Acquire));
+    }
+}
@@ -0,0 +1,230 @@
+//! Bounding CPU use across concurrent encodes.
+
+use std::sync::{Condvar, Mutex};
+
+use crate::EncoderError;
+
+/// Caps how many OS threads concurrent encodes use in total. Encodes run on the thread that
+/// calls [`run`](Self::run), which blocks until there is room in the budget. libFLAC as bundled
+/// by `libflac-sys` encodes on the calling thread only, so each encode takes one thread of the
+/// budget.
+///
+/// Share it between threads by reference or in an `Arc`.
+#[derive(Debug)]
+pub struct EncoderPool {
+    max_threads: usize,
+    state: Mutex<PoolState>,
+    /// Notified whenever an encode finishes or stops waiting.
+    released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    in_use: usize,
+    /// Callers blocked in `run` waiting for a thread.
+    waiting: usize,
+    shutdown: Option<Shutdown>,
+}
+
+/// How [`EncoderPool::shutdown`] treats encodes that were already handed to the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shutdown {
+    /// Let running encodes and those waiting for a thread finish.
+    Graceful,
+    /// Let running encodes finish but turn away those still waiting for a thread. An encode
+    /// can't be stopped partway through, so nothing is left half written either way.
+    Immediate,
+}
+
+impl EncoderPool {
+    /// `max_threads` is at least 1.
+    pub fn new(max_threads: usize) -> Self {
+        EncoderPool {
+            max_threads: max_threads.max(1),
+            state: Mutex::new(PoolState::default()),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn max_threads(&self) -> usize {
+        self.max_threads
+    }
+
+    /// Threads currently taken by running encodes.
+    pub fn in_use(&self) -> usize {
+        self.state.lock().unwrap().in_use
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.state.lock().unwrap().shutdown.is_some()
+    }
+
+    /// Runs `encode`, e.g. `|| builder.build()`, once a thread is free in the budget. Fails
+    /// with [`EncoderError::PoolShutDown`] without running it once the pool is shutting down.
+    pub fn run<R>(&self, encode: impl FnOnce() -> R) -> Result<R, EncoderError> {
+        let _permit = self.acquire()?;
+        Ok(encode())
+    }
+
+    /// Like [`run`](Self::run) but returns `None` straight away if the budget is used up or
+    /// the pool is shutting down.
+    pub fn try_run<R>(&self, encode: impl FnOnce() -> R) -> Option<R> {
+        let _permit = self.try_acquire()?;
+        Some(encode())
+    }
+
+    /// Stops the pool taking new encodes and blocks until the ones it keeps, per `mode`, are
+    /// done, so the host application can exit once this returns. Calling it again only
+    /// waits, keeping the first mode.
+    pub fn shutdown(&self, mode: Shutdown) {
+        let mut state = self.state.lock().unwrap();
+        state.shutdown.get_or_insert(mode);
+        // Wake waiters so `Immediate` can turn them away.
+        self.released.notify_all();
+
+        while state.in_use > 0 || state.waiting > 0 {
+            state = self.released.wait(state).unwrap();
+        }
+    }
+
+    fn acquire(&self) -> Result<Permit<'_>, EncoderError> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.shutdown.is_some() {
+            return Err(EncoderError::PoolShutDown);
+        }
+
+        state.waiting += 1;
+        while state.in_use >= self.max_threads && state.shutdown != Some(Shutdown::Immediate) {
+            state = self.released.wait(state).unwrap();
+        }
+        state.waiting -= 1;
+
+        if state.shutdown == Some(Shutdown::Immediate) {
+            self.released.notify_all();
+            return Err(EncoderError::PoolShutDown);
+        }
+
+        state.in_use += 1;
+        Ok(Permit { pool: self })
+    }
+
+    fn try_acquire(&self) -> Option<Permit<'_>> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.in_use >= self.max_threads || state.shutdown.is_some() {
+            return None;
+        }
+
+        state.in_use += 1;
+        Some(Permit { pool: self })
+    }
+}
+
+/// Gives its thread back to the pool when dropped, including when the encode panics.
+struct Permit<'a> {
+    pool: &'a EncoderPool,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.pool.state.lock().unwrap().in_use -= 1;
+        // Both waiting encodes and `shutdown` may be waiting on this.
+        self.pool.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::FlacBuilder;
+
+    #[test]
+    fn never_runs_more_than_max_threads() {
+        let pool = EncoderPool::new(2);
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+        let samples: Vec<f32> = (0..20_000).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+
+        thread::scope(|scope| {
+            for _ in 0..6 {
+                scope.spawn(|| {
+                    pool.run(|| {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(20));
+                        let bytes = FlacBuilder::from_interleaved(&samples, 2, 44100)
+                            .build()
+                            .unwrap();
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        bytes
+                    })
+                    .unwrap()
+                });
+            }
+        });
+
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.in_use(), 0);
+    }
+
+    #[test]
+    fn try_run_refuses_when_full() {
+        let pool = EncoderPool::new(0);
+        assert_eq!(pool.max_threads(), 1);
+
+        let inner = pool
+            .run(|| {
+                assert_eq!(pool.in_use(), 1);
+                pool.try_run(|| ())
+            })
+            .unwrap();
+        assert_eq!(inner, None);
+        assert_eq!(pool.try_run(|| 5), Some(5));
+    }
+
+    #[test]
+    fn a_panicking_encode_gives_its_thread_back() {
+        let pool = EncoderPool::new(1);
+
+        let result = catch_unwind(AssertUnwindSafe(|| pool.run(|| panic!("encode failed"))));
+        assert!(result.is_err());
+        assert_eq!(pool.in_use(), 0);
+    }
+
+    #[test]
+    fn shutdown_modes() {
+        for (mode, finished) in [(Shutdown::Graceful, 3), (Shutdown::Immediate, 1)] {
+            let pool = EncoderPool::new(1);
+            let done = AtomicUsize::new(0);
+
+            thread::scope(|scope| {
+                for _ in 0..3 {
+                    scope.spawn(|| {
+                        let _ = pool.run(|| {
+                            thread::sleep(Duration::from_millis(100));
+                            done.fetch_add(1, Ordering::SeqCst);
+                        });
+                    });
+                }
+
+                // One encode runs and the other two wait for its thread.
+                thread::sleep(Duration::from_millis(25));
+                pool.shutdown(mode);
+                assert_eq!(pool.in_use(), 0);
+            });
+
+            assert_eq!(done.load(Ordering::SeqCst), finished);
+            assert!(pool.is_shut_down());
+            assert!(matches!(pool.run(|| ()), Err(EncoderError::PoolShutDown)));
+            assert_eq!(pool.try_run(|| ()), None);
+        }
+    }
+}
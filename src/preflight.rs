@@ -0,0 +1,154 @@
+//! Resource checks done before an encode starts, see
+//! [`FlacBuilder::preflight`](crate::FlacBuilder::preflight).
+
+use std::{fmt, fs::OpenOptions, path::Path};
+
+use crate::EncoderError;
+
+/// Why a preflight check failed, in [`EncoderError::PreflightFailed`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreflightProblem {
+    /// The destination's file system has less free space than the output could take.
+    InsufficientDiskSpace { needed: u64, available: u64 },
+    /// The destination can't be opened for writing; holds the reason.
+    NotWritable(String),
+    /// Less memory is available than an in-memory output could take.
+    InsufficientMemory { needed: u64, available: u64 },
+}
+
+impl fmt::Display for PreflightProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightProblem::InsufficientDiskSpace { needed, available } => write!(
+                f,
+                "{needed} bytes of disk space needed but {available} available"
+            ),
+            PreflightProblem::NotWritable(reason) => {
+                write!(f, "destination isn't writable: {reason}")
+            }
+            PreflightProblem::InsufficientMemory { needed, available } => {
+                write!(
+                    f,
+                    "{needed} bytes of memory needed but {available} available"
+                )
+            }
+        }
+    }
+}
+
+/// Fails unless `path` can be written and its file system has `needed` bytes free. Space is
+/// only checked on Unix.
+pub(crate) fn check_destination(path: &Path, needed: u64) -> Result<(), EncoderError> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    if let Some(available) = free_disk_space(dir) {
+        if available < needed {
+            return Err(EncoderError::PreflightFailed(
+                PreflightProblem::InsufficientDiskSpace { needed, available },
+            ));
+        }
+    }
+
+    // Opened without truncating, so an existing file is left alone until the encode starts.
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(|e| EncoderError::PreflightFailed(PreflightProblem::NotWritable(e.to_string())))?;
+
+    Ok(())
+}
+
+/// Fails if the system reports less than `needed` bytes of memory available. Only checked on
+/// Linux.
+pub(crate) fn check_memory(needed: u64) -> Result<(), EncoderError> {
+    match available_memory() {
+        Some(available) if available < needed => Err(EncoderError::PreflightFailed(
+            PreflightProblem::InsufficientMemory { needed, available },
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn free_disk_space(dir: &Path) -> Option<u64> {
+    use std::{ffi::CString, mem::zeroed, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+
+    unsafe {
+        let mut stats: libc::statvfs = zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stats) != 0 {
+            return None;
+        }
+
+        Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+    }
+}
+
+#[cfg(not(unix))]
+fn free_disk_space(_dir: &Path) -> Option<u64> {
+    None
+}
+
+/// `MemAvailable` from `/proc/meminfo`, the kernel's estimate of what can be allocated without
+/// swapping.
+fn available_memory() -> Option<u64> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemAvailable:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FlacBuilder;
+
+    #[test]
+    fn an_unwritable_destination_fails_before_encoding() {
+        let path = std::env::temp_dir().join("no-such-dir").join("out.flac");
+        let result = FlacBuilder::from_interleaved(&[0.0f32; 1024], 1, 44100)
+            .preflight()
+            .write_file(&path);
+
+        assert!(matches!(
+            result,
+            Err(EncoderError::PreflightFailed(
+                PreflightProblem::NotWritable(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn space_and_memory_are_compared_with_what_is_needed() {
+        assert!(matches!(
+            check_destination(&std::env::temp_dir().join("preflight.flac"), u64::MAX),
+            Err(EncoderError::PreflightFailed(
+                PreflightProblem::InsufficientDiskSpace { .. }
+            ))
+        ));
+        if cfg!(target_os = "linux") {
+            assert!(matches!(
+                check_memory(u64::MAX),
+                Err(EncoderError::PreflightFailed(
+                    PreflightProblem::InsufficientMemory { .. }
+                ))
+            ));
+        }
+
+        FlacBuilder::from_interleaved(&[0.0f32; 1024], 1, 44100)
+            .preflight()
+            .build()
+            .unwrap();
+    }
+}
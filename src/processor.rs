@@ -0,0 +1,94 @@
+//! User-provided processing of the samples on their way to the encoder, see
+//! [`FlacBuilder::processor`](crate::FlacBuilder::processor).
+
+/// Changes the audio as it is encoded, e.g. to stamp an inaudible watermark into review copies
+/// without a separate pass over the input.
+pub trait Processor {
+    /// Called before the first chunk of every encode attempt, including verify retries.
+    fn start(&mut self, _context: &ProcessorContext<'_>) {}
+
+    /// Modifies a chunk of interleaved samples at the encoded bps in place. `first_frame` is
+    /// the position of the chunk's first frame in the input. Samples must stay within the
+    /// signed range of the bps or the encode fails.
+    fn process(&mut self, samples: &mut [i32], channels: usize, first_frame: usize);
+}
+
+/// What is being encoded, handed to [`Processor::start`].
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessorContext<'a> {
+    pub channels: usize,
+    pub sample_rate: u32,
+    pub bps: u32,
+    /// `0` for a [`FlacStreamEncoder`](crate::FlacStreamEncoder), whose length isn't known, and
+    /// for a source without a [`len_hint`](crate::AudioSource::len_hint).
+    pub total_frames: usize,
+    /// The vorbis comments set on the builder, e.g. to key the watermark on the track.
+    pub tags: &'a [(String, String)],
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{AudioSource, FlacBuilder, FlacDecoder, FlacStreamEncoder};
+
+    /// Total frames and tags of each start.
+    type Starts = Rc<RefCell<Vec<(usize, Vec<(String, String)>)>>>;
+
+    /// Sets the lowest bit of the left channel, and records what it was started with.
+    struct Stamp(Starts);
+
+    impl Processor for Stamp {
+        fn start(&mut self, context: &ProcessorContext<'_>) {
+            self.0
+                .borrow_mut()
+                .push((context.total_frames, context.tags.to_vec()));
+        }
+
+        fn process(&mut self, samples: &mut [i32], channels: usize, _first_frame: usize) {
+            for frame in samples.chunks_exact_mut(channels) {
+                frame[0] |= 1;
+            }
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Vec<i32> {
+        let mut samples = vec![0; 4000];
+        FlacDecoder::new(bytes).unwrap().fill(&mut samples).unwrap();
+        samples
+    }
+
+    #[test]
+    fn processes_the_samples_before_encoding() {
+        let starts = Rc::new(RefCell::new(vec![]));
+        let bytes = FlacBuilder::from_interleaved(&[0i16; 4000], 2, 8000)
+            .vorbis_comment("TITLE", "review copy")
+            .processor(Stamp(starts.clone()))
+            .build()
+            .unwrap();
+
+        assert!(decode(&bytes).chunks(2).all(|frame| frame == [1, 0]));
+        assert_eq!(
+            *starts.borrow(),
+            [(2000, vec![("TITLE".to_string(), "review copy".to_string())])]
+        );
+    }
+
+    #[test]
+    fn runs_on_every_push() {
+        let starts = Rc::new(RefCell::new(vec![]));
+        let mut bytes = vec![];
+        let mut encoder = FlacStreamEncoder::new(2, 8000, &mut bytes, |builder| {
+            builder.processor(Stamp(starts.clone()))
+        })
+        .unwrap();
+        for _ in 0..4 {
+            encoder.push_interleaved(&[0i16; 1000]).unwrap();
+        }
+        encoder.finalize().unwrap();
+
+        assert!(decode(&bytes).chunks(2).all(|frame| frame == [1, 0]));
+        assert_eq!(*starts.borrow(), [(0, vec![])]);
+    }
+}
@@ -0,0 +1,127 @@
+//! Python bindings, built as a wheel with maturin (see `pyproject.toml`).
+
+// The code pyo3 0.22 generates for `#[pyfunction]` return types trips this.
+#![allow(clippy::useless_conversion)]
+
+use pyo3::{buffer::PyBuffer, exceptions::PyValueError, prelude::*, types::PyBytes};
+
+use crate::{
+    comments_to_map, read_comments, BpsLevel, CompressionLevel, EncoderError, FlacBuilder, TagMap,
+};
+
+fn to_py_err(error: EncoderError) -> PyErr {
+    PyValueError::new_err(error.to_string())
+}
+
+/// Samples from any float32 or float64 buffer, e.g. a numpy array, with the channel count for
+/// 2D `(frames, channels)` buffers.
+fn samples<T: pyo3::buffer::Element>(
+    py: Python<'_>,
+    buffer: PyBuffer<T>,
+) -> PyResult<(Vec<T>, Option<usize>)> {
+    let channels = match buffer.dimensions() {
+        1 => None,
+        2 => Some(buffer.shape()[1]),
+        n => {
+            return Err(PyValueError::new_err(format!(
+                "expected a 1D interleaved or 2D (frames, channels) buffer, got {n} dimensions"
+            )))
+        }
+    };
+
+    Ok((buffer.to_vec(py)?, channels))
+}
+
+fn encode_samples<S: crate::IntoSample>(
+    samples: &[S],
+    channels: usize,
+    sample_rate: u32,
+    bps: u32,
+    compression_level: u32,
+    tags: Option<TagMap>,
+) -> PyResult<Vec<u8>> {
+    let bps = match bps {
+        16 => BpsLevel::Bps16,
+        20 => BpsLevel::Bps20,
+        24 => BpsLevel::Bps24,
+        32 => BpsLevel::Bps32,
+        _ => return Err(PyValueError::new_err("bps must be 16, 20, 24 or 32")),
+    };
+
+    FlacBuilder::from_interleaved(samples, channels, sample_rate)
+        .bps(bps)
+        .compression_level(CompressionLevel::try_from(compression_level).map_err(to_py_err)?)
+        .tags(&tags.unwrap_or_default())
+        .build()
+        .map_err(to_py_err)
+}
+
+/// encode(samples, sample_rate, channels=None, bps=16, compression_level=5, tags=None)
+///
+/// Encodes float samples in [-1.0, 1.0] to FLAC bytes. `samples` is any float32 or float64
+/// buffer, either 1D interleaved (pass `channels`) or 2D shaped `(frames, channels)`. `tags`
+/// maps field names to lists of values.
+#[pyfunction]
+#[pyo3(signature = (samples, sample_rate, channels=None, bps=16, compression_level=5, tags=None))]
+fn encode<'py>(
+    py: Python<'py>,
+    samples: &Bound<'py, PyAny>,
+    sample_rate: u32,
+    channels: Option<usize>,
+    bps: u32,
+    compression_level: u32,
+    tags: Option<TagMap>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let channel_count = |shape_channels: Option<usize>| {
+        shape_channels
+            .or(channels)
+            .ok_or_else(|| PyValueError::new_err("channels is required for 1D buffers"))
+    };
+
+    let data = if let Ok(buffer) = PyBuffer::<f32>::get_bound(samples) {
+        let (samples, shape_channels) = self::samples(py, buffer)?;
+        let channels = channel_count(shape_channels)?;
+        py.allow_threads(|| {
+            encode_samples(
+                &samples,
+                channels,
+                sample_rate,
+                bps,
+                compression_level,
+                tags,
+            )
+        })?
+    } else {
+        let (samples, shape_channels) = self::samples(py, PyBuffer::<f64>::get_bound(samples)?)?;
+        let channels = channel_count(shape_channels)?;
+        py.allow_threads(|| {
+            encode_samples(
+                &samples,
+                channels,
+                sample_rate,
+                bps,
+                compression_level,
+                tags,
+            )
+        })?
+    };
+
+    Ok(PyBytes::new_bound(py, &data))
+}
+
+/// read_tags(data)
+///
+/// The vorbis comments of FLAC bytes as a dict of upper-cased field names to lists of values.
+#[pyfunction]
+fn read_tags(data: &[u8]) -> PyResult<TagMap> {
+    read_comments(data)
+        .map(|comments| comments_to_map(&comments))
+        .map_err(to_py_err)
+}
+
+#[pymodule]
+fn flac_encoder(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(encode, m)?)?;
+    m.add_function(wrap_pyfunction!(read_tags, m)?)?;
+    Ok(())
+}
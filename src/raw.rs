@@ -0,0 +1,445 @@
+//! Parsing that works directly on in-memory FLAC bytes, without going through libFLAC.
+
+use std::io::{ErrorKind, Read};
+
+use crate::{EncoderError, Picture, PictureType};
+
+pub(crate) const BLOCK_TYPE_STREAMINFO: u8 = 0;
+pub(crate) const BLOCK_TYPE_PADDING: u8 = 1;
+pub(crate) const BLOCK_TYPE_APPLICATION: u8 = 2;
+pub(crate) const BLOCK_TYPE_SEEKTABLE: u8 = 3;
+pub(crate) const BLOCK_TYPE_VORBIS_COMMENT: u8 = 4;
+pub(crate) const BLOCK_TYPE_PICTURE: u8 = 6;
+
+/// Metadata block lengths are 24 bits.
+pub(crate) const MAX_BLOCK_LENGTH: usize = (1 << 24) - 1;
+
+/// A metadata block as laid out in the stream.
+pub(crate) struct RawBlock<'a> {
+    pub block_type: u8,
+    pub data: &'a [u8],
+}
+
+/// The metadata section of a FLAC stream.
+pub(crate) struct RawMetadata<'a> {
+    pub blocks: Vec<RawBlock<'a>>,
+    /// Offset just past the `fLaC` marker, i.e. where the first block header starts.
+    pub blocks_offset: usize,
+    /// Offset of the first audio frame.
+    pub audio_offset: usize,
+}
+
+impl<'a> RawMetadata<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, EncoderError> {
+        let stream_offset = skip_id3v2(bytes);
+
+        if bytes.get(stream_offset..stream_offset + 4) != Some(b"fLaC") {
+            return Err(EncoderError::MalformedFlacData);
+        }
+
+        let mut blocks = vec![];
+        let mut cursor = stream_offset + 4;
+
+        loop {
+            let Some(header) = bytes.get(cursor..cursor + 4) else {
+                return Err(EncoderError::MalformedFlacData);
+            };
+
+            let is_last = header[0] & 0x80 != 0;
+            let block_type = header[0] & 0x7f;
+            let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+
+            let Some(data) = bytes.get(cursor + 4..cursor + 4 + length) else {
+                return Err(EncoderError::MalformedFlacData);
+            };
+
+            blocks.push(RawBlock { block_type, data });
+
+            cursor += 4 + length;
+
+            if is_last {
+                break;
+            }
+        }
+
+        Ok(RawMetadata {
+            blocks,
+            blocks_offset: stream_offset + 4,
+            audio_offset: cursor,
+        })
+    }
+}
+
+/// Reads the start of a FLAC stream up to the first audio frame: any ID3v2 tag, the `fLaC`
+/// marker and every metadata block, for [`RawMetadata::parse`] without reading the audio.
+pub(crate) fn read_metadata_section(reader: &mut impl Read) -> Result<Vec<u8>, EncoderError> {
+    let mut bytes = vec![];
+    let mut fill_to = |bytes: &mut Vec<u8>, len: usize| {
+        if bytes.len() < len {
+            let start = bytes.len();
+            bytes.resize(len, 0);
+            reader.read_exact(&mut bytes[start..]).map_err(|e| {
+                if e.kind() == ErrorKind::UnexpectedEof {
+                    EncoderError::MalformedFlacData
+                } else {
+                    EncoderError::Io(e)
+                }
+            })?;
+        }
+        Ok(())
+    };
+
+    fill_to(&mut bytes, 10)?;
+    let mut cursor = skip_id3v2(&bytes) + 4;
+
+    loop {
+        fill_to(&mut bytes, cursor + 4)?;
+        let header = &bytes[cursor..cursor + 4];
+        let is_last = header[0] & 0x80 != 0;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+
+        cursor += 4 + length;
+        fill_to(&mut bytes, cursor)?;
+
+        if is_last {
+            return Ok(bytes);
+        }
+    }
+}
+
+/// Some taggers prepend an ID3v2 tag to FLAC files; libFLAC skips it so we do too.
+pub(crate) fn skip_id3v2(bytes: &[u8]) -> usize {
+    if bytes.len() < 10 || &bytes[..3] != b"ID3" {
+        return 0;
+    }
+
+    let size = bytes[6..10]
+        .iter()
+        .fold(0usize, |acc, b| (acc << 7) | (*b & 0x7f) as usize);
+    let has_footer = bytes[5] & 0x10 != 0;
+
+    10 + size + if has_footer { 10 } else { 0 }
+}
+
+/// Reads the vorbis comments of an in-memory FLAC stream as `(key, value)` pairs, in the order
+/// they are stored. Only the metadata section is parsed so this is cheap even for large files.
+/// Returns an empty list if the stream has no `VORBIS_COMMENT` block. Entries without a `=` are
+/// skipped, as [`FlacDecoder`](crate::FlacDecoder) does.
+pub fn read_comments(bytes: &[u8]) -> Result<Vec<(String, String)>, EncoderError> {
+    let metadata = RawMetadata::parse(bytes)?;
+
+    let Some(block) = metadata
+        .blocks
+        .iter()
+        .find(|b| b.block_type == BLOCK_TYPE_VORBIS_COMMENT)
+    else {
+        return Ok(vec![]);
+    };
+
+    parse_vorbis_comment(block.data)
+}
+
+fn parse_vorbis_comment(data: &[u8]) -> Result<Vec<(String, String)>, EncoderError> {
+    let mut reader = LeReader { data, cursor: 0 };
+
+    let vendor_length = reader.u32()? as usize;
+    reader.bytes(vendor_length)?;
+
+    let count = reader.u32()?;
+    let mut comments = Vec::with_capacity(count.min(1024) as usize);
+
+    for _ in 0..count {
+        let length = reader.u32()? as usize;
+        let entry = String::from_utf8_lossy(reader.bytes(length)?);
+
+        if let Some((key, value)) = entry.split_once('=') {
+            comments.push((key.to_string(), value.to_string()));
+        }
+    }
+
+    Ok(comments)
+}
+
+/// Vorbis comment fields are little-endian, unlike the rest of the FLAC format.
+struct LeReader<'a> {
+    data: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> LeReader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], EncoderError> {
+        let Some(bytes) = self.data.get(self.cursor..self.cursor + length) else {
+            return Err(EncoderError::MalformedFlacData);
+        };
+        self.cursor += length;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, EncoderError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+/// All pictures embedded in an in-memory FLAC stream, in the order they are stored.
+pub fn extract_pictures(bytes: &[u8]) -> Result<Vec<Picture>, EncoderError> {
+    RawMetadata::parse(bytes)?
+        .blocks
+        .iter()
+        .filter(|b| b.block_type == BLOCK_TYPE_PICTURE)
+        .map(|b| Picture::from_block_data(b.data))
+        .collect()
+}
+
+/// Returns a copy of an in-memory FLAC stream where any pictures of the same
+/// [`PictureType`] as `picture` are replaced by it. If there are none it is added after the
+/// other metadata, before any padding. Everything else, including the audio frames, is copied
+/// byte-for-byte. `picture` is checked as when encoding, failing with
+/// [`EncoderError::InvalidPicture`] for a malformed MIME type or description.
+pub fn replace_picture(bytes: &[u8], picture: &Picture) -> Result<Vec<u8>, EncoderError> {
+    picture.check()?;
+    let metadata = RawMetadata::parse(bytes)?;
+
+    let new_block = picture.to_block_data();
+
+    if new_block.len() > MAX_BLOCK_LENGTH {
+        return Err(EncoderError::MetadataBlockTooLarge);
+    }
+
+    let is_replaced = |block: &RawBlock| {
+        block.block_type == BLOCK_TYPE_PICTURE
+            && picture_type_of(block.data) == Some(picture.picture_type)
+    };
+
+    let insert_at = metadata
+        .blocks
+        .iter()
+        .position(is_replaced)
+        .or_else(|| {
+            metadata
+                .blocks
+                .iter()
+                .rposition(|b| b.block_type != BLOCK_TYPE_PADDING)
+                .map(|i| i + 1)
+        })
+        .unwrap_or(metadata.blocks.len());
+
+    let mut blocks: Vec<(u8, &[u8])> = vec![];
+
+    for (i, block) in metadata.blocks.iter().enumerate() {
+        if i == insert_at {
+            blocks.push((BLOCK_TYPE_PICTURE, &new_block));
+        }
+        if !is_replaced(block) {
+            blocks.push((block.block_type, block.data));
+        }
+    }
+
+    if insert_at == metadata.blocks.len() {
+        blocks.push((BLOCK_TYPE_PICTURE, &new_block));
+    }
+
+    let mut out = Vec::with_capacity(bytes.len() + new_block.len());
+    out.extend(&bytes[..metadata.blocks_offset]);
+
+    for (i, (block_type, data)) in blocks.iter().enumerate() {
+        write_block(&mut out, *block_type, data, i == blocks.len() - 1);
+    }
+
+    out.extend(&bytes[metadata.audio_offset..]);
+
+    Ok(out)
+}
+
+fn picture_type_of(block_data: &[u8]) -> Option<PictureType> {
+    let bytes = block_data.get(..4)?;
+    Some(PictureType::from_u32(u32::from_be_bytes([
+        bytes[0], bytes[1], bytes[2], bytes[3],
+    ])))
+}
+
+pub(crate) fn write_block(out: &mut Vec<u8>, block_type: u8, data: &[u8], is_last: bool) {
+    let length = (data.len() as u32).to_be_bytes();
+    out.push(block_type | if is_last { 0x80 } else { 0 });
+    out.extend(&length[1..]);
+    out.extend(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stream of `blocks`, the last marked as such, followed by a few bytes standing in for
+    /// the audio.
+    fn stream(blocks: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let mut out = b"fLaC".to_vec();
+        for (i, (block_type, data)) in blocks.iter().enumerate() {
+            write_block(&mut out, *block_type, data, i == blocks.len() - 1);
+        }
+        out.extend([0xff, 0xf8, 0x00]);
+        out
+    }
+
+    fn vorbis_comment(vendor: &str, entries: &[&str]) -> Vec<u8> {
+        let mut out = (vendor.len() as u32).to_le_bytes().to_vec();
+        out.extend(vendor.as_bytes());
+        out.extend((entries.len() as u32).to_le_bytes());
+        for entry in entries {
+            out.extend((entry.len() as u32).to_le_bytes());
+            out.extend(entry.as_bytes());
+        }
+        out
+    }
+
+    fn picture(picture_type: PictureType, data: &[u8]) -> Picture {
+        Picture {
+            picture_type,
+            mime_type: "image/png".to_string(),
+            description: "cover".to_string(),
+            width: 1,
+            height: 1,
+            depth: 24,
+            colors: 0,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn parses_blocks_and_finds_the_audio() {
+        let bytes = stream(&[
+            (BLOCK_TYPE_STREAMINFO, vec![0; 34]),
+            (BLOCK_TYPE_VORBIS_COMMENT, vorbis_comment("test", &[])),
+            (BLOCK_TYPE_PADDING, vec![0; 10]),
+        ]);
+
+        let metadata = RawMetadata::parse(&bytes).unwrap();
+        let types: Vec<u8> = metadata.blocks.iter().map(|b| b.block_type).collect();
+
+        assert_eq!(
+            types,
+            [
+                BLOCK_TYPE_STREAMINFO,
+                BLOCK_TYPE_VORBIS_COMMENT,
+                BLOCK_TYPE_PADDING
+            ]
+        );
+        assert_eq!(metadata.blocks[2].data.len(), 10);
+        assert_eq!(metadata.blocks_offset, 4);
+        assert_eq!(metadata.audio_offset, bytes.len() - 3);
+    }
+
+    #[test]
+    fn skips_an_id3v2_tag() {
+        let mut bytes = b"ID3\x04\x00\x00\x00\x00\x00\x05".to_vec();
+        bytes.extend([0; 5]);
+        bytes.extend(stream(&[(BLOCK_TYPE_STREAMINFO, vec![0; 34])]));
+
+        assert_eq!(skip_id3v2(&bytes), 15);
+        assert_eq!(RawMetadata::parse(&bytes).unwrap().blocks_offset, 19);
+    }
+
+    #[test]
+    fn rejects_malformed_metadata() {
+        let bytes = stream(&[(BLOCK_TYPE_STREAMINFO, vec![0; 34])]);
+
+        assert!(matches!(
+            RawMetadata::parse(&bytes[..20]),
+            Err(EncoderError::MalformedFlacData)
+        ));
+        assert!(matches!(
+            RawMetadata::parse(&bytes[1..]),
+            Err(EncoderError::MalformedFlacData)
+        ));
+    }
+
+    #[test]
+    fn reads_comments_in_order() {
+        let bytes = stream(&[
+            (BLOCK_TYPE_STREAMINFO, vec![0; 34]),
+            (
+                BLOCK_TYPE_VORBIS_COMMENT,
+                vorbis_comment("test", &["TITLE=Song", "ARTIST=Band", "COMMENT=a=b"]),
+            ),
+        ]);
+
+        assert_eq!(
+            read_comments(&bytes).unwrap(),
+            [
+                ("TITLE".to_string(), "Song".to_string()),
+                ("ARTIST".to_string(), "Band".to_string()),
+                ("COMMENT".to_string(), "a=b".to_string()),
+            ]
+        );
+
+        let no_comments = stream(&[(BLOCK_TYPE_STREAMINFO, vec![0; 34])]);
+        assert!(read_comments(&no_comments).unwrap().is_empty());
+
+        let no_separator = stream(&[
+            (BLOCK_TYPE_STREAMINFO, vec![0; 34]),
+            (
+                BLOCK_TYPE_VORBIS_COMMENT,
+                vorbis_comment("test", &["TITLE", "ARTIST=Band"]),
+            ),
+        ]);
+        assert_eq!(
+            read_comments(&no_separator).unwrap(),
+            [("ARTIST".to_string(), "Band".to_string())]
+        );
+    }
+
+    #[test]
+    fn replaces_pictures_of_the_same_type() {
+        let bytes = stream(&[
+            (BLOCK_TYPE_STREAMINFO, vec![0; 34]),
+            (BLOCK_TYPE_PADDING, vec![0; 10]),
+        ]);
+
+        let front = picture(PictureType::FrontCover, b"front");
+        let with_front = replace_picture(&bytes, &front).unwrap();
+        assert_eq!(extract_pictures(&with_front).unwrap(), [front]);
+
+        // Added before the padding, which stays last, and the audio is untouched.
+        let metadata = RawMetadata::parse(&with_front).unwrap();
+        assert_eq!(metadata.blocks[1].block_type, BLOCK_TYPE_PICTURE);
+        assert_eq!(metadata.blocks[2].block_type, BLOCK_TYPE_PADDING);
+        assert_eq!(
+            with_front[metadata.audio_offset..],
+            bytes[bytes.len() - 3..]
+        );
+
+        let new_front = picture(PictureType::FrontCover, b"new front");
+        let back = picture(PictureType::BackCover, b"back");
+        let replaced = replace_picture(&with_front, &new_front).unwrap();
+        let both = replace_picture(&replaced, &back).unwrap();
+
+        assert_eq!(extract_pictures(&both).unwrap(), [new_front, back]);
+
+        let mut bad = picture(PictureType::FrontCover, b"front");
+        bad.mime_type = "jpeg".to_string();
+        assert!(matches!(
+            replace_picture(&bytes, &bad),
+            Err(EncoderError::InvalidPicture(_))
+        ));
+    }
+
+    #[test]
+    fn reads_the_metadata_section_from_a_reader() {
+        let mut bytes = b"ID3\x04\x00\x00\x00\x00\x00\x05hello".to_vec();
+        bytes.extend(stream(&[
+            (BLOCK_TYPE_STREAMINFO, vec![0; 34]),
+            (BLOCK_TYPE_PADDING, vec![0; 10]),
+        ]));
+        // Everything but the audio `stream` ends with.
+        let metadata_len = bytes.len() - 3;
+
+        let mut reader = &bytes[..];
+        let section = read_metadata_section(&mut reader).unwrap();
+
+        assert_eq!(section, bytes[..metadata_len]);
+        assert_eq!(reader, &bytes[metadata_len..]);
+        assert!(matches!(
+            read_metadata_section(&mut &bytes[..metadata_len - 1]),
+            Err(EncoderError::MalformedFlacData)
+        ));
+    }
+}
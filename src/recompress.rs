@@ -0,0 +1,211 @@
+//! Re-encoding an existing file at another compression level without touching its metadata.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{
+    atomic, pipe,
+    raw::{
+        read_metadata_section, write_block, RawMetadata, BLOCK_TYPE_SEEKTABLE,
+        BLOCK_TYPE_STREAMINFO,
+    },
+    CompressionLevel, EncodeReport, EncoderError, FlacDecoder, SeekTableSpec,
+};
+
+/// Length of a seek point in a `SEEKTABLE` block.
+const SEEK_POINT_LENGTH: usize = 18;
+
+/// Re-encodes the FLAC file at `path` at compression `level`, e.g. to move a library to a
+/// stronger setting, without risking its tags. The audio is decoded and encoded a frame at a
+/// time, so memory use stays bounded.
+///
+/// Every metadata block other than STREAMINFO and SEEKTABLE is copied byte-for-byte and in
+/// order, as is an ID3v2 tag in front of the stream. STREAMINFO comes from the new encode,
+/// and a seek table is rebuilt with the same points since the old offsets no longer hold.
+/// The new file is written next to the old one and renamed over it only once complete, so a
+/// failure leaves the original as it was.
+pub fn recompress_in_place(
+    path: impl AsRef<Path>,
+    level: CompressionLevel,
+) -> Result<EncodeReport, EncoderError> {
+    let path = path.as_ref();
+    let write_path = atomic::temp_path(path, None)?;
+    let frames_path = write_path.with_extension("frames.tmp");
+
+    let result = recompress_to(path, &write_path, &frames_path, level).and_then(|report| {
+        std::fs::rename(&write_path, path).map_err(EncoderError::Io)?;
+        Ok(report)
+    });
+
+    let _ = std::fs::remove_file(&frames_path);
+    if result.is_err() {
+        let _ = std::fs::remove_file(&write_path);
+    }
+
+    result
+}
+
+/// Encodes the audio of `path` into `frames_path`, then writes the finished file to
+/// `write_path`.
+fn recompress_to(
+    path: &Path,
+    write_path: &Path,
+    frames_path: &Path,
+    level: CompressionLevel,
+) -> Result<EncodeReport, EncoderError> {
+    let mut input = BufReader::new(File::open(path).map_err(EncoderError::Io)?);
+    let original = read_metadata_section(&mut input)?;
+    let metadata = RawMetadata::parse(&original)?;
+
+    // Placeholder points, all ones, are left out.
+    let seek_points: Option<Vec<u64>> = metadata
+        .blocks
+        .iter()
+        .find(|b| b.block_type == BLOCK_TYPE_SEEKTABLE)
+        .map(|b| {
+            b.data
+                .chunks_exact(SEEK_POINT_LENGTH)
+                .map(|point| u64::from_be_bytes(point[..8].try_into().unwrap()))
+                .filter(|&sample| sample != u64::MAX)
+                .collect()
+        });
+
+    input.seek(SeekFrom::Start(0)).map_err(EncoderError::Io)?;
+    let decoder = FlacDecoder::new(input)?;
+
+    let mut report = pipe(decoder, frames_path, |builder| {
+        let builder = builder.compression_level(level);
+        match seek_points {
+            Some(points) => builder.seek_table(SeekTableSpec::At(points)),
+            None => builder,
+        }
+    })?;
+
+    // Only STREAMINFO, the seek table and the frames are kept from the encode; they are
+    // stitched onto the old metadata below.
+    let mut frames = BufReader::new(File::open(frames_path).map_err(EncoderError::Io)?);
+    let header = read_metadata_section(&mut frames)?;
+    let encoded = RawMetadata::parse(&header)?;
+
+    let new_block = |block_type| {
+        encoded
+            .blocks
+            .iter()
+            .find(|b| b.block_type == block_type)
+            .map(|b| (b.block_type, b.data))
+    };
+
+    let mut blocks: Vec<(u8, &[u8])> = vec![];
+    blocks.extend(new_block(BLOCK_TYPE_STREAMINFO));
+    blocks.extend(new_block(BLOCK_TYPE_SEEKTABLE));
+    blocks.extend(
+        metadata
+            .blocks
+            .iter()
+            .filter(|b| ![BLOCK_TYPE_STREAMINFO, BLOCK_TYPE_SEEKTABLE].contains(&b.block_type))
+            .map(|b| (b.block_type, b.data)),
+    );
+
+    let mut out_header = original[..metadata.blocks_offset].to_vec();
+    for (i, (block_type, data)) in blocks.iter().enumerate() {
+        write_block(&mut out_header, *block_type, data, i == blocks.len() - 1);
+    }
+
+    let mut write = || -> io::Result<u64> {
+        let mut out = BufWriter::new(File::create(write_path)?);
+        out.write_all(&out_header)?;
+        let frames_len = io::copy(&mut frames, &mut out)?;
+        out.flush()?;
+        Ok(frames_len)
+    };
+    let frames_len = write().map_err(EncoderError::Io)?;
+
+    report.encoded_bytes = out_header.len() + frames_len as usize;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        extract_pictures, read_comments, replace_picture, AudioSource, Picture, PictureType,
+    };
+
+    #[test]
+    fn keeps_metadata_and_audio() {
+        let path = std::env::temp_dir().join(format!("recompress-{}.flac", std::process::id()));
+        let samples: Vec<f32> = (0..20_000).map(|i| (i as f32 / 50.0).sin() * 0.5).collect();
+
+        let bytes = crate::FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .compression_level(CompressionLevel::L0)
+            .title("Song")
+            .seek_table(SeekTableSpec::EverySamples(5000))
+            .build()
+            .unwrap();
+        let cover = Picture {
+            picture_type: PictureType::FrontCover,
+            mime_type: "image/png".to_string(),
+            description: String::new(),
+            width: 1,
+            height: 1,
+            depth: 24,
+            colors: 0,
+            data: vec![1, 2, 3],
+        };
+        std::fs::write(&path, replace_picture(&bytes, &cover).unwrap()).unwrap();
+
+        let report = recompress_in_place(&path, CompressionLevel::L8).unwrap();
+        let recompressed = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(recompressed.len() < bytes.len());
+        assert_eq!(report.encoded_bytes, recompressed.len());
+        assert_eq!(
+            read_comments(&recompressed).unwrap(),
+            read_comments(&bytes).unwrap()
+        );
+        assert_eq!(extract_pictures(&recompressed).unwrap(), [cover]);
+
+        let seek_samples = |bytes: &[u8]| -> Vec<u64> {
+            let metadata = RawMetadata::parse(bytes).unwrap();
+            let block = metadata
+                .blocks
+                .iter()
+                .find(|b| b.block_type == BLOCK_TYPE_SEEKTABLE)
+                .unwrap();
+            block
+                .data
+                .chunks_exact(SEEK_POINT_LENGTH)
+                .map(|point| u64::from_be_bytes(point[..8].try_into().unwrap()))
+                .collect()
+        };
+        // The old points are kept, each moved back to the start of the new frame holding it.
+        let (old, new) = (seek_samples(&bytes), seek_samples(&recompressed));
+        assert_eq!(old.len(), new.len());
+        assert!(new
+            .iter()
+            .zip(&old)
+            .all(|(new, old)| new <= old && old - new < 4096));
+
+        let mut decoder = FlacDecoder::new(&recompressed[..]).unwrap();
+        let mut decoded = vec![0; samples.len() + 1];
+        assert_eq!(decoder.fill(&mut decoded).unwrap(), samples.len());
+        assert_eq!(decoder.total_samples(), samples.len() as u64);
+    }
+
+    #[test]
+    fn failure_leaves_the_original() {
+        let path = std::env::temp_dir().join(format!("recompress-bad-{}.flac", std::process::id()));
+        std::fs::write(&path, b"not flac").unwrap();
+
+        assert!(recompress_in_place(&path, CompressionLevel::L8).is_err());
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(contents, b"not flac");
+        assert!(!atomic::temp_path(&path, None).unwrap().exists());
+    }
+}
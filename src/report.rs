@@ -0,0 +1,384 @@
+//! What an encode found out about its input, for the `*_with_report` output methods.
+
+use std::{collections::HashMap, time::Duration};
+
+use crate::{EncoderError, OutputHash};
+
+/// Returned alongside the output by
+/// [`build_with_report`](crate::FlacBuilder::build_with_report) and
+/// [`write_file_with_report`](crate::FlacBuilder::write_file_with_report).
+#[derive(Debug, Clone, Default)]
+pub struct EncodeReport {
+    /// Silent regions, if [`FlacBuilder::detect_silence`](crate::FlacBuilder::detect_silence)
+    /// was set.
+    pub silent_regions: Vec<SilentRegion>,
+    /// Waveform peaks, if [`FlacBuilder::peaks`](crate::FlacBuilder::peaks) was set.
+    pub peaks: Option<Peaks>,
+    /// One entry per channel, if
+    /// [`FlacBuilder::channel_stats`](crate::FlacBuilder::channel_stats) was set.
+    pub channel_stats: Vec<ChannelStats>,
+    /// How far quantizing float input moved the samples. `None` for integer input.
+    pub conversion_loss: Option<ConversionLoss>,
+    /// Verification failed and the output was produced without it, see
+    /// [`VerifyFailurePolicy::Warn`](crate::VerifyFailurePolicy::Warn).
+    pub verify_failed: bool,
+    /// The input was all silence and wasn't encoded, see
+    /// [`SilentInputPolicy::Skip`](crate::SilentInputPolicy::Skip).
+    pub skipped_silent_input: bool,
+    /// Every channel was identical and only one was encoded, see
+    /// [`DualMonoPolicy::Collapse`](crate::DualMonoPolicy::Collapse).
+    pub collapsed_to_mono: bool,
+    /// Size of the encoded stream.
+    pub encoded_bytes: usize,
+    /// Digest of the encoded stream, if
+    /// [`FlacBuilder::hash_output`](crate::FlacBuilder::hash_output) was set.
+    pub output_hash: Option<OutputHash>,
+    /// Size of the input as raw PCM at the encoded bps.
+    pub pcm_bytes: usize,
+    /// Length of the audio.
+    pub input_duration: Duration,
+    /// Wall-clock time of the whole encode, including any retries.
+    pub encode_time: Duration,
+    pub timings: EncodeTimings,
+}
+
+/// When each stage of an encode happened, measured from its start with a monotonic clock. A
+/// large gap between `prepared` and `first_frame` points at sample conversion, a slow
+/// `finished` relative to `first_frame` at the output.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EncodeTimings {
+    /// libFLAC configured and the metadata built.
+    pub prepared: Duration,
+    /// The first audio frame was handed to the output. `None` if there was no audio.
+    pub first_frame: Option<Duration>,
+    pub finished: Duration,
+    /// When each chunk of input was done, if
+    /// [`FlacBuilder::trace_chunk_times`](crate::FlacBuilder::trace_chunk_times) was set.
+    pub chunks: Vec<Duration>,
+}
+
+impl EncodeReport {
+    /// Encoded size as a fraction of the raw PCM size; lower is better.
+    pub fn compression_ratio(&self) -> f64 {
+        self.encoded_bytes as f64 / self.pcm_bytes.max(1) as f64
+    }
+
+    /// Ways this encode did worse than `baseline` by more than `tolerance`, e.g. from a report
+    /// saved when the settings were last tuned. Both should encode the same input.
+    pub fn regressions(
+        &self,
+        baseline: &EncodeReport,
+        tolerance: RegressionTolerance,
+    ) -> Vec<Regression> {
+        let mut regressions = vec![];
+
+        if self.compression_ratio() > baseline.compression_ratio() * (1.0 + tolerance.ratio) {
+            regressions.push(Regression::CompressionRatio {
+                baseline: baseline.compression_ratio(),
+                current: self.compression_ratio(),
+            });
+        }
+
+        if self.encode_time.as_secs_f64()
+            > baseline.encode_time.as_secs_f64() * (1.0 + tolerance.time)
+        {
+            regressions.push(Regression::EncodeTime {
+                baseline: baseline.encode_time,
+                current: self.encode_time,
+            });
+        }
+
+        regressions
+    }
+
+    /// Panics if this encode regressed against `baseline` under the default
+    /// [`RegressionTolerance`], for use in downstream test suites.
+    pub fn assert_settings_not_worse_than(&self, baseline: &EncodeReport) {
+        let regressions = self.regressions(baseline, RegressionTolerance::default());
+
+        assert!(
+            regressions.is_empty(),
+            "encode regressed against baseline: {regressions:?}"
+        );
+    }
+}
+
+/// How much worse than a baseline is allowed, as fractions, see [`EncodeReport::regressions`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegressionTolerance {
+    pub ratio: f64,
+    pub time: f64,
+}
+
+impl Default for RegressionTolerance {
+    /// No larger output, and up to 50% slower since timings are noisy.
+    fn default() -> Self {
+        RegressionTolerance {
+            ratio: 0.0,
+            time: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Regression {
+    CompressionRatio {
+        baseline: f64,
+        current: f64,
+    },
+    EncodeTime {
+        baseline: Duration,
+        current: Duration,
+    },
+}
+
+/// A run of frames where every channel stayed under the silence threshold. Positions are in
+/// samples per channel; `end` is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SilentRegion {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Waveform overview collected while encoding, see
+/// [`FlacBuilder::peaks`](crate::FlacBuilder::peaks).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peaks {
+    /// How many samples per channel each `(min, max)` pair covers. The last pair of each
+    /// channel may cover fewer.
+    pub samples_per_peak: usize,
+    /// Bits per sample of the values, i.e. the bps that was encoded.
+    pub bits: u32,
+    /// `(min, max)` pairs for each channel.
+    pub channels: Vec<Vec<(i32, i32)>>,
+}
+
+/// What one channel actually contains, at the encoded bps, see
+/// [`FlacBuilder::channel_stats`](crate::FlacBuilder::channel_stats).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelStats {
+    /// Bits per sample minus the low bits that are zero in every sample, e.g. 16 for 16-bit
+    /// audio padded out to 24 bits. `0` for digital silence.
+    pub used_bits: u32,
+    /// Largest absolute sample value.
+    pub peak: u32,
+    pub peak_dbfs: f64,
+    pub rms_dbfs: f64,
+}
+
+impl ChannelStats {
+    /// Peak to RMS ratio in dB (the crest factor). Heavily limited masters sit well under 10.
+    pub fn dynamic_range_db(&self) -> f64 {
+        self.peak_dbfs - self.rms_dbfs
+    }
+}
+
+/// The quantization step from float input to integers, for documenting that it met a
+/// tolerance. Samples are truncated towards zero without dither, so every error is under one
+/// LSB except for clipped samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConversionLoss {
+    /// Bits per sample the floats were quantized to. This is the source bps when one was set
+    /// below the encoded bps.
+    pub bits: u32,
+    /// Largest difference between a float sample and its integer, in LSBs at `bits`, leaving
+    /// out clipped samples.
+    pub max_error_lsb: f64,
+    pub rms_error_lsb: f64,
+    /// Samples beyond `[-1.0, 1.0]`, which were clamped to full scale.
+    pub clipped_samples: usize,
+    pub samples: usize,
+}
+
+impl Peaks {
+    /// A compact little-endian binary form: the ASCII magic `PEAK`, then `u16` channel count,
+    /// `u32` samples per peak and `u32` pairs per channel, then each channel's pairs as `i16`
+    /// min/max scaled to 16 bits.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let pairs = self.channels.first().map_or(0, |c| c.len());
+        let mut out = Vec::with_capacity(14 + self.channels.len() * pairs * 4);
+
+        out.extend(b"PEAK");
+        out.extend((self.channels.len() as u16).to_le_bytes());
+        out.extend((self.samples_per_peak as u32).to_le_bytes());
+        out.extend((pairs as u32).to_le_bytes());
+
+        let shift = self.bits.saturating_sub(16);
+        for channel in &self.channels {
+            for (min, max) in channel {
+                out.extend(((min >> shift) as i16).to_le_bytes());
+                out.extend(((max >> shift) as i16).to_le_bytes());
+            }
+        }
+
+        out
+    }
+
+    /// `{"samples_per_peak":..,"bits":..,"channels":[[min,max,min,max,..],..]}` with values at
+    /// the encoded bit depth.
+    pub fn to_json(&self) -> String {
+        let channels: Vec<String> = self
+            .channels
+            .iter()
+            .map(|channel| {
+                let values: Vec<String> = channel
+                    .iter()
+                    .map(|(min, max)| format!("{min},{max}"))
+                    .collect();
+                format!("[{}]", values.join(","))
+            })
+            .collect();
+
+        format!(
+            "{{\"samples_per_peak\":{},\"bits\":{},\"channels\":[{}]}}",
+            self.samples_per_peak,
+            self.bits,
+            channels.join(",")
+        )
+    }
+}
+
+/// Totals over many encodes, e.g. a batch job or a service's lifetime. Call
+/// [`record`](Self::record) with each result; the totals can be read at any point.
+#[derive(Debug, Clone, Default)]
+pub struct SessionStats {
+    pub encodes: usize,
+    pub total_input: Duration,
+    pub total_output_bytes: u64,
+    pub total_pcm_bytes: u64,
+    pub total_encode_time: Duration,
+    /// Number of failures keyed by [`EncoderError::kind`].
+    pub failures: HashMap<&'static str, usize>,
+}
+
+impl SessionStats {
+    pub fn record(&mut self, result: &Result<EncodeReport, EncoderError>) {
+        match result {
+            Ok(report) => {
+                self.encodes += 1;
+                self.total_input += report.input_duration;
+                self.total_output_bytes += report.encoded_bytes as u64;
+                self.total_pcm_bytes += report.pcm_bytes as u64;
+                self.total_encode_time += report.encode_time;
+            }
+            Err(error) => *self.failures.entry(error.kind()).or_default() += 1,
+        }
+    }
+
+    pub fn failure_count(&self) -> usize {
+        self.failures.values().sum()
+    }
+
+    /// Output size over raw PCM size across every successful encode.
+    pub fn average_ratio(&self) -> f64 {
+        self.total_output_bytes as f64 / self.total_pcm_bytes.max(1) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FlacBuilder;
+
+    fn report(encoded_bytes: usize, encode_time_ms: u64) -> EncodeReport {
+        EncodeReport {
+            encoded_bytes,
+            pcm_bytes: 1000,
+            encode_time: Duration::from_millis(encode_time_ms),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn records_sizes() {
+        let samples: Vec<f32> = (0..10_000).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+        let (bytes, report) = FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .build_with_report()
+            .unwrap();
+
+        assert_eq!(report.encoded_bytes, bytes.len());
+        assert_eq!(report.pcm_bytes, 10_000 * 2);
+        assert!(report.compression_ratio() < 1.0);
+    }
+
+    #[test]
+    fn regressions_past_the_tolerance() {
+        let baseline = report(500, 100);
+
+        assert!(report(500, 140)
+            .regressions(&baseline, Default::default())
+            .is_empty());
+        assert_eq!(
+            report(600, 200).regressions(&baseline, Default::default()),
+            [
+                Regression::CompressionRatio {
+                    baseline: 0.5,
+                    current: 0.6
+                },
+                Regression::EncodeTime {
+                    baseline: Duration::from_millis(100),
+                    current: Duration::from_millis(200)
+                },
+            ]
+        );
+
+        let tolerance = RegressionTolerance {
+            ratio: 0.25,
+            time: 1.0,
+        };
+        assert!(report(600, 200)
+            .regressions(&baseline, tolerance)
+            .is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "encode regressed against baseline")]
+    fn assert_panics_on_a_regression() {
+        report(600, 100).assert_settings_not_worse_than(&report(500, 100));
+    }
+
+    #[test]
+    fn session_stats_add_up_reports_and_failures() {
+        let mut stats = SessionStats::default();
+
+        stats.record(&Ok(report(500, 100)));
+        stats.record(&Ok(EncodeReport {
+            input_duration: Duration::from_secs(3),
+            ..report(300, 50)
+        }));
+        stats.record(&Err(EncoderError::NoData));
+        stats.record(&Err(EncoderError::Io(std::io::Error::other("disk full"))));
+        stats.record(&Err(EncoderError::Io(std::io::Error::other("disk full"))));
+
+        assert_eq!(stats.encodes, 2);
+        assert_eq!(stats.total_input, Duration::from_secs(3));
+        assert_eq!(stats.total_output_bytes, 800);
+        assert_eq!(stats.total_encode_time, Duration::from_millis(150));
+        assert_eq!(stats.average_ratio(), 0.4);
+        assert_eq!(stats.failure_count(), 3);
+        assert_eq!(stats.failures["Io"], 2);
+        assert_eq!(stats.failures["NoData"], 1);
+    }
+
+    #[test]
+    fn timings_follow_the_encode() {
+        let samples: Vec<f32> = (0..10_000).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+
+        let (_, report) = FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .build_with_report()
+            .unwrap();
+        let timings = report.timings;
+        let first_frame = timings.first_frame.unwrap();
+        assert!(timings.prepared <= first_frame && first_frame <= timings.finished);
+        assert!(timings.chunks.is_empty());
+
+        let (_, report) = FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .trace_chunk_times()
+            .build_with_report()
+            .unwrap();
+        let chunks = report.timings.chunks;
+        assert_eq!(chunks.len(), 5);
+        assert!(chunks.is_sorted());
+        assert!(chunks[4] <= report.timings.finished);
+    }
+}
@@ -0,0 +1,207 @@
+//! Splitting a live recording into files of a fixed length.
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{EncodeReport, EncoderError, FlacBuilder, FlacStreamEncoder, IntoSample, Seekable};
+
+/// Where [`RollingEncoder`] writes a segment, and the tags it gets on top of the shared ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentStart {
+    pub path: PathBuf,
+    pub tags: Vec<(String, String)>,
+}
+
+type SegmentFile = Seekable<BufWriter<File>>;
+type Configure<'data, Sample> =
+    Box<dyn Fn(FlacBuilder<'data, Sample>) -> FlacBuilder<'data, Sample> + 'data>;
+type CloseHandler<'data> = Box<dyn FnMut(&Path, EncodeReport) + 'data>;
+
+/// A [`FlacStreamEncoder`] that rolls over to a new file every `segment_length`, e.g. for a
+/// 24/7 recorder rotating hourly. Audio is split on the exact frame, so the segments play back
+/// to back without a gap.
+///
+/// Each segment is opened when the first audio for it arrives: `on_new_segment` is called with
+/// its index, counting from 0, and says where it goes and what extra tags it gets, such as the
+/// time it starts. Once a segment is complete, or the recording is
+/// [`finish`](Self::finish)ed, `on_close` gets its path and report.
+pub struct RollingEncoder<'data, Sample: IntoSample> {
+    channels: usize,
+    sample_rate: u32,
+    /// Frames per channel in each segment.
+    segment_frames: usize,
+    configure: Configure<'data, Sample>,
+    on_new_segment: Box<dyn FnMut(usize) -> SegmentStart + 'data>,
+    on_close: CloseHandler<'data>,
+    current: Option<(PathBuf, FlacStreamEncoder<'data, Sample, SegmentFile>)>,
+    /// Index of the next segment to open.
+    next_segment: usize,
+}
+
+impl<'data, Sample: IntoSample> RollingEncoder<'data, Sample> {
+    /// `configure` sets what every segment shares, as for [`FlacStreamEncoder::new`]. A
+    /// `segment_length` shorter than one frame is one frame long.
+    pub fn new(
+        channels: usize,
+        sample_rate: u32,
+        segment_length: Duration,
+        configure: impl Fn(FlacBuilder<'data, Sample>) -> FlacBuilder<'data, Sample> + 'data,
+        on_new_segment: impl FnMut(usize) -> SegmentStart + 'data,
+        on_close: impl FnMut(&Path, EncodeReport) + 'data,
+    ) -> Result<Self, EncoderError> {
+        if channels == 0 {
+            return Err(EncoderError::InvalidChannelCount);
+        }
+
+        let segment_frames = (segment_length.as_secs_f64() * sample_rate as f64).round() as usize;
+
+        Ok(RollingEncoder {
+            channels,
+            sample_rate,
+            segment_frames: segment_frames.max(1),
+            configure: Box::new(configure),
+            on_new_segment: Box::new(on_new_segment),
+            on_close: Box::new(on_close),
+            current: None,
+            next_segment: 0,
+        })
+    }
+
+    /// Encodes interleaved samples, a whole number of frames, rolling over to new segments as
+    /// they fill up.
+    pub fn push_interleaved(&mut self, samples: &[Sample]) -> Result<(), EncoderError> {
+        if !samples.len().is_multiple_of(self.channels) {
+            return Err(EncoderError::MismatchedSampleCountPerChannels);
+        }
+
+        let mut samples = samples;
+
+        while !samples.is_empty() {
+            if self.current.is_none() {
+                self.current = Some(self.open_segment()?);
+            }
+            let Some((_, encoder)) = &mut self.current else {
+                unreachable!();
+            };
+
+            let frames =
+                (self.segment_frames - encoder.frames()).min(samples.len() / self.channels);
+            let (now, rest) = samples.split_at(frames * self.channels);
+
+            encoder.push_interleaved(now)?;
+            samples = rest;
+
+            if encoder.frames() == self.segment_frames {
+                self.close_segment()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Index of the segment being written, or of the next one if it hasn't been opened yet.
+    pub fn segment(&self) -> usize {
+        match self.current {
+            Some(_) => self.next_segment - 1,
+            None => self.next_segment,
+        }
+    }
+
+    /// Finalizes the segment being written, if any.
+    pub fn finish(mut self) -> Result<(), EncoderError> {
+        self.close_segment()
+    }
+
+    fn open_segment(
+        &mut self,
+    ) -> Result<(PathBuf, FlacStreamEncoder<'data, Sample, SegmentFile>), EncoderError> {
+        let SegmentStart { path, tags } = (self.on_new_segment)(self.next_segment);
+        self.next_segment += 1;
+
+        let file = File::create(&path).map_err(EncoderError::Io)?;
+        let configure = &self.configure;
+
+        let encoder = FlacStreamEncoder::new(
+            self.channels,
+            self.sample_rate,
+            Seekable::new(BufWriter::new(file)).map_err(EncoderError::Io)?,
+            |builder| {
+                tags.iter()
+                    .fold(configure(builder), |builder, (key, value)| {
+                        builder.vorbis_comment(key, value)
+                    })
+            },
+        )?;
+
+        Ok((path, encoder))
+    }
+
+    fn close_segment(&mut self) -> Result<(), EncoderError> {
+        if let Some((path, encoder)) = self.current.take() {
+            let report = encoder.finalize()?;
+            (self.on_close)(&path, report);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, fs};
+
+    use super::*;
+    use crate::{read_comments, AudioSource, FlacDecoder};
+
+    #[test]
+    fn rolls_over_on_the_exact_frame() {
+        let dir = std::env::temp_dir();
+        let samples: Vec<f32> = (0..25_000).map(|i| (i as f32 / 40.0).sin() * 0.5).collect();
+        let closed = RefCell::new(vec![]);
+
+        let mut encoder = RollingEncoder::new(
+            1,
+            10_000,
+            Duration::from_secs(1),
+            |builder| builder.artist("Recorder"),
+            |segment| SegmentStart {
+                path: dir.join(format!("rolling-{segment}-{}.flac", std::process::id())),
+                tags: vec![("SEGMENT".to_string(), segment.to_string())],
+            },
+            |path, report| closed.borrow_mut().push((path.to_owned(), report)),
+        )
+        .unwrap();
+
+        for chunk in samples.chunks(3_000) {
+            encoder.push_interleaved(chunk).unwrap();
+        }
+        assert_eq!(encoder.segment(), 2);
+        encoder.finish().unwrap();
+
+        let mut decoded = vec![];
+        for (segment, (path, report)) in closed.into_inner().into_iter().enumerate() {
+            let bytes = fs::read(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            let mut buffer = vec![0; 20_000];
+            let n = FlacDecoder::new(&bytes[..])
+                .unwrap()
+                .fill(&mut buffer)
+                .unwrap();
+            assert_eq!(n, [10_000, 10_000, 5_000][segment]);
+            assert_eq!(report.encoded_bytes, bytes.len());
+            decoded.extend_from_slice(&buffer[..n]);
+
+            let comments = read_comments(&bytes).unwrap();
+            assert!(comments.contains(&("ARTIST".to_string(), "Recorder".to_string())));
+            assert!(comments.contains(&("SEGMENT".to_string(), segment.to_string())));
+        }
+
+        let expected: Vec<i32> = samples.iter().map(|s| s.to_i16() as i32).collect();
+        assert_eq!(decoded, expected);
+    }
+}
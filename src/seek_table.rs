@@ -0,0 +1,105 @@
+//! `SEEKTABLE` metadata blocks.
+
+use std::time::Duration;
+
+/// Where [`FlacBuilder::seek_table`](crate::FlacBuilder::seek_table) puts seek points, as
+/// sample numbers per channel. Points at or past the end of the input are dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SeekTableSpec {
+    /// A point every this often, e.g. 10 seconds as the `flac` tool does.
+    Every(Duration),
+    /// A point every this many samples.
+    EverySamples(u32),
+    /// Points at exactly these samples, in any order.
+    At(Vec<u64>),
+}
+
+impl SeekTableSpec {
+    /// The sample numbers for an input of `total_samples` per channel, `0` if it isn't known.
+    /// Intervals need the length, so only explicit points are kept then. An interval of zero
+    /// gives no points.
+    pub(crate) fn points(&self, sample_rate: u32, total_samples: u64) -> Vec<u64> {
+        let interval = match self {
+            SeekTableSpec::Every(interval) => {
+                (interval.as_secs_f64() * sample_rate as f64).round() as u64
+            }
+            SeekTableSpec::EverySamples(interval) => *interval as u64,
+            SeekTableSpec::At(points) => {
+                return points
+                    .iter()
+                    .copied()
+                    .filter(|&point| total_samples == 0 || point < total_samples)
+                    .collect();
+            }
+        };
+
+        if interval == 0 {
+            return vec![];
+        }
+
+        (0..total_samples).step_by(interval as usize).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        raw::{RawMetadata, BLOCK_TYPE_SEEKTABLE},
+        FlacBuilder,
+    };
+
+    /// The sample number and byte offset of each point in the stream's seek table.
+    fn seek_points(bytes: &[u8]) -> Vec<(u64, u64)> {
+        let metadata = RawMetadata::parse(bytes).unwrap();
+        let block = metadata
+            .blocks
+            .iter()
+            .find(|b| b.block_type == BLOCK_TYPE_SEEKTABLE)
+            .unwrap();
+
+        block
+            .data
+            .chunks_exact(18)
+            .map(|point| {
+                (
+                    u64::from_be_bytes(point[..8].try_into().unwrap()),
+                    u64::from_be_bytes(point[8..16].try_into().unwrap()),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn points_for_each_spec() {
+        let every = SeekTableSpec::Every(Duration::from_secs(10));
+        assert_eq!(every.points(44100, 1_000_000), [0, 441_000, 882_000]);
+        assert_eq!(every.points(44100, 0), [] as [u64; 0]);
+        assert_eq!(
+            SeekTableSpec::EverySamples(0).points(44100, 1000),
+            [] as [u64; 0]
+        );
+
+        let at = SeekTableSpec::At(vec![500, 20, 5000]);
+        assert_eq!(at.points(44100, 1000), [500, 20]);
+        assert_eq!(at.points(44100, 0), [500, 20, 5000]);
+    }
+
+    #[test]
+    fn libflac_fills_in_the_offsets() {
+        let samples: Vec<f32> = (0..100_000)
+            .map(|i| (i as f32 * 0.01).sin() * 0.5)
+            .collect();
+        let bytes = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .seek_table(SeekTableSpec::EverySamples(40_000))
+            .build()
+            .unwrap();
+
+        let points = seek_points(&bytes);
+        let samples: Vec<u64> = points.iter().map(|&(sample, _)| sample).collect();
+        // Each point moves back to the start of the 4096-sample frame holding it.
+        assert_eq!(samples, [0, 36_864, 77_824]);
+        assert_eq!(points[0].1, 0);
+        assert!(points[1].1 > 0 && points[2].1 > points[1].1);
+    }
+}
@@ -0,0 +1,88 @@
+//! A smoke test of the whole encode path, for checking a deployment at startup.
+
+use std::f64::consts::PI;
+
+use crate::{AudioBlock, EncoderError, FlacBuilder, FlacReader};
+
+const SAMPLE_RATE: u32 = 44100;
+const CHANNELS: usize = 2;
+const BPS: u32 = 16;
+
+/// Encodes one second of a built-in test signal to memory, decodes it again and checks that
+/// the audio and format came back unchanged, failing with [`EncoderError::SelfTestFailed`] if
+/// they didn't. Decoding checks the CRC of every frame and the MD5 in STREAMINFO on the way.
+/// Takes a few milliseconds.
+pub fn self_test() -> Result<(), EncoderError> {
+    let block = test_signal();
+    let encoded = FlacBuilder::from_block(&block).build()?;
+    let decoded = FlacReader::from_bytes(&encoded)?;
+
+    let info = decoded.stream_info;
+    let expected = (SAMPLE_RATE, CHANNELS as u32, BPS, block.frames() as u64);
+    let actual = (
+        info.sample_rate,
+        info.channels,
+        info.bps,
+        info.total_samples,
+    );
+    if actual != expected {
+        return Err(EncoderError::SelfTestFailed(format!(
+            "STREAMINFO has (sample rate, channels, bps, samples) {actual:?}, expected {expected:?}"
+        )));
+    }
+
+    if let Some(i) = (0..block.samples.len().max(decoded.audio.samples.len()))
+        .find(|&i| block.samples.get(i) != decoded.audio.samples.get(i))
+    {
+        return Err(EncoderError::SelfTestFailed(format!(
+            "decoded audio differs from the input from sample {i}"
+        )));
+    }
+
+    Ok(())
+}
+
+/// A different tone in each channel at half of full scale, so swapped or mixed up channels
+/// don't decode to the same audio.
+fn test_signal() -> AudioBlock {
+    let amplitude = ((1 << (BPS - 1)) - 1) as f64 / 2.0;
+    let frequencies = [440.0, 661.0];
+
+    let samples = (0..SAMPLE_RATE as usize)
+        .flat_map(|i| {
+            let t = i as f64 / SAMPLE_RATE as f64;
+            frequencies.map(|f| (amplitude * (2.0 * PI * f * t).sin()).round() as i32)
+        })
+        .collect();
+
+    AudioBlock {
+        channels: CHANNELS,
+        bps: BPS,
+        sample_rate: SAMPLE_RATE,
+        samples,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_with_the_linked_libflac() {
+        self_test().unwrap();
+    }
+
+    #[test]
+    fn the_channels_carry_different_tones() {
+        let block = test_signal();
+        let (left, right): (Vec<i32>, Vec<i32>) = block
+            .samples
+            .chunks_exact(2)
+            .map(|frame| (frame[0], frame[1]))
+            .unzip();
+
+        assert_eq!(block.frames(), SAMPLE_RATE as usize);
+        assert_ne!(left, right);
+        assert!(block.samples.iter().all(|s| s.abs() <= 1 << (BPS - 2)));
+    }
+}
@@ -0,0 +1,116 @@
+//! RAII ownership of the libFLAC objects that make up one encode.
+
+use std::ffi::CStr;
+
+use libflac_sys::*;
+
+use crate::EncoderError;
+
+/// Owns every `FLAC__StreamMetadata` created for an encode. Blocks are owned from the moment
+/// they are allocated so that any early return frees them. They must outlive the encoder they
+/// are set on, as libFLAC only keeps pointers to them.
+pub(crate) struct MetadataSession {
+    blocks: Vec<*mut FLAC__StreamMetadata>,
+}
+
+impl MetadataSession {
+    pub fn new() -> Self {
+        MetadataSession { blocks: vec![] }
+    }
+
+    /// Allocates a new block owned by this session.
+    pub fn new_block(
+        &mut self,
+        block_type: FLAC__MetadataType,
+    ) -> Result<*mut FLAC__StreamMetadata, EncoderError> {
+        let block = unsafe { FLAC__metadata_object_new(block_type) };
+
+        if block.is_null() {
+            return Err(EncoderError::InitializationError);
+        }
+
+        self.blocks.push(block);
+        Ok(block)
+    }
+
+    /// Size of the metadata section these blocks make up, including the `fLaC` marker, the
+    /// STREAMINFO block libFLAC adds and every block header.
+    pub fn encoded_length(&self) -> usize {
+        let blocks: usize = self
+            .blocks
+            .iter()
+            .map(|block| 4 + unsafe { (**block).length } as usize)
+            .sum();
+
+        4 + 4 + 34 + blocks
+    }
+
+    /// Hands the blocks to the encoder. They stay owned by this session.
+    pub unsafe fn set_on(&mut self, encoder: *mut FLAC__StreamEncoder) -> Result<(), EncoderError> {
+        if 0 == FLAC__stream_encoder_set_metadata(
+            encoder,
+            self.blocks.as_mut_ptr(),
+            self.blocks.len() as u32,
+        ) {
+            return Err(EncoderError::FailedToSetMetadata);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for MetadataSession {
+    fn drop(&mut self) {
+        for block in self.blocks.drain(..) {
+            unsafe {
+                FLAC__metadata_object_delete(block);
+            }
+        }
+    }
+}
+
+/// Owns a `FLAC__StreamEncoder`, deleting it when dropped.
+pub(crate) struct EncoderHandle(*mut FLAC__StreamEncoder);
+
+impl EncoderHandle {
+    pub fn new() -> Result<Self, EncoderError> {
+        let encoder = unsafe { FLAC__stream_encoder_new() };
+
+        if encoder.is_null() {
+            return Err(EncoderError::InitializationError);
+        }
+
+        Ok(EncoderHandle(encoder))
+    }
+
+    pub fn as_ptr(&self) -> *mut FLAC__StreamEncoder {
+        self.0
+    }
+
+    pub fn finish(&self) -> Result<(), EncoderError> {
+        unsafe {
+            if 0 == FLAC__stream_encoder_finish(self.0) {
+                if FLAC__stream_encoder_get_state(self.0)
+                    == FLAC__STREAM_ENCODER_VERIFY_MISMATCH_IN_AUDIO_DATA
+                {
+                    return Err(EncoderError::VerifyMismatch);
+                }
+
+                let state = CStr::from_ptr(FLAC__stream_encoder_get_resolved_state_string(self.0));
+                return Err(EncoderError::FinishFailed(
+                    state.to_string_lossy().into_owned(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for EncoderHandle {
+    fn drop(&mut self) {
+        unsafe {
+            FLAC__stream_encoder_delete(self.0);
+        }
+    }
+}
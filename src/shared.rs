@@ -0,0 +1,179 @@
+//! One streaming encoder fed from several threads.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        mpsc::{sync_channel, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{ByteSink, EncodeReport, EncoderError, FlacBuilder, FlacStreamEncoder, IntoSample};
+
+/// Buffers queued for the encoder thread before `push` blocks.
+const QUEUED_BUFFERS: usize = 64;
+
+type EncoderThread = JoinHandle<Result<EncodeReport, EncoderError>>;
+
+enum Message<Sample> {
+    Buffer(u64, Vec<Sample>),
+    Finish,
+}
+
+/// A handle to a [`FlacStreamEncoder`] running on its own thread, which any number of
+/// producer threads can push to, e.g. a capture architecture where successive buffers come
+/// from different threads. It is `Clone`, `Send` and `Sync`.
+///
+/// Each buffer carries its sequence number, counting from 0, and is encoded in that order
+/// whichever thread pushes it first. A buffer that arrives early waits until those before it
+/// have been encoded. Pushes block once 64 buffers are queued, so producers can't outrun the
+/// encoder without bound.
+pub struct SharedEncoder<Sample> {
+    sender: SyncSender<Message<Sample>>,
+    thread: Arc<Mutex<Option<EncoderThread>>>,
+}
+
+impl<Sample> Clone for SharedEncoder<Sample> {
+    fn clone(&self) -> Self {
+        SharedEncoder {
+            sender: self.sender.clone(),
+            thread: self.thread.clone(),
+        }
+    }
+}
+
+impl<Sample: IntoSample + Send + 'static> SharedEncoder<Sample> {
+    /// Starts the encoder thread, with the arguments of [`FlacStreamEncoder::new`]. Fails if
+    /// the encoder can't be set up.
+    pub fn new<S: ByteSink + Send + 'static>(
+        channels: usize,
+        sample_rate: u32,
+        sink: S,
+        configure: impl FnOnce(FlacBuilder<'static, Sample>) -> FlacBuilder<'static, Sample>
+            + Send
+            + 'static,
+    ) -> Result<Self, EncoderError> {
+        let (sender, messages) = sync_channel(QUEUED_BUFFERS);
+        let (started_sender, started) = sync_channel(1);
+
+        // The encoder holds libFLAC pointers, so it is made on the thread that uses it.
+        let thread = thread::spawn(move || {
+            let mut encoder = match FlacStreamEncoder::new(channels, sample_rate, sink, configure) {
+                Ok(encoder) => {
+                    let _ = started_sender.send(Ok(()));
+                    encoder
+                }
+                Err(e) => {
+                    let _ = started_sender.send(Err(e));
+                    return Err(EncoderError::StreamClosed);
+                }
+            };
+
+            let mut early = BTreeMap::new();
+            let mut next = 0;
+
+            // Every handle dropped without `finish` ends the stream the same way.
+            while let Ok(Message::Buffer(sequence, samples)) = messages.recv() {
+                early.insert(sequence, samples);
+
+                while let Some(samples) = early.remove(&next) {
+                    encoder.push_interleaved(&samples)?;
+                    next += 1;
+                }
+            }
+
+            if !early.is_empty() {
+                return Err(EncoderError::MissingBuffer { sequence: next });
+            }
+
+            encoder.finalize()
+        });
+
+        started.recv().unwrap_or(Err(EncoderError::StreamClosed))?;
+
+        Ok(SharedEncoder {
+            sender,
+            thread: Arc::new(Mutex::new(Some(thread))),
+        })
+    }
+
+    /// Queues interleaved samples, a whole number of frames, as buffer `sequence`. Fails with
+    /// [`EncoderError::StreamClosed`] once the encoder has finished or failed; `finish` then
+    /// returns why.
+    pub fn push(&self, sequence: u64, samples: Vec<Sample>) -> Result<(), EncoderError> {
+        self.sender
+            .send(Message::Buffer(sequence, samples))
+            .map_err(|_| EncoderError::StreamClosed)
+    }
+
+    /// Encodes the buffers pushed so far and finalizes the stream, returning its report, or
+    /// the error that stopped the encoder. Later pushes from other handles fail. Fails with
+    /// [`EncoderError::MissingBuffer`] if a buffer was pushed but one before it never was,
+    /// and with [`EncoderError::StreamClosed`] if another handle already finished.
+    pub fn finish(self) -> Result<EncodeReport, EncoderError> {
+        let _ = self.sender.send(Message::Finish);
+
+        let thread = self.thread.lock().unwrap().take();
+        match thread {
+            Some(thread) => thread.join().unwrap_or(Err(EncoderError::StreamClosed)),
+            None => Err(EncoderError::StreamClosed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+    use crate::{AudioSource, FlacDecoder};
+
+    fn buffer(sequence: u64) -> Vec<i16> {
+        (0..1000)
+            .map(|i| (sequence as i16) * 1000 + i % 997)
+            .collect()
+    }
+
+    #[test]
+    fn buffers_are_encoded_in_sequence_order() {
+        let (sink, bytes) = channel();
+        let encoder = SharedEncoder::new(1, 8000, sink, |builder| builder).unwrap();
+
+        thread::scope(|scope| {
+            for first in 0..4 {
+                let encoder = encoder.clone();
+                // Each thread pushes every fourth buffer, last ones first.
+                scope.spawn(move || {
+                    let sequences: Vec<u64> = (first..16).step_by(4).collect();
+                    for &sequence in sequences.iter().rev() {
+                        encoder.push(sequence, buffer(sequence)).unwrap();
+                    }
+                });
+            }
+        });
+        let report = encoder.finish().unwrap();
+
+        let bytes: Vec<u8> = bytes.iter().flatten().collect();
+        assert_eq!(report.encoded_bytes, bytes.len());
+        let mut decoded = vec![0; 16_000];
+        FlacDecoder::new(&bytes[..])
+            .unwrap()
+            .fill(&mut decoded)
+            .unwrap();
+        let expected: Vec<i32> = (0..16).flat_map(buffer).map(i32::from).collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn a_gap_fails_the_finish() {
+        let encoder = SharedEncoder::new(1, 8000, vec![], |builder| builder).unwrap();
+        encoder.push(0, buffer(0)).unwrap();
+        encoder.push(2, buffer(2)).unwrap();
+
+        assert!(matches!(
+            encoder.finish(),
+            Err(EncoderError::MissingBuffer { sequence: 1 })
+        ));
+    }
+}
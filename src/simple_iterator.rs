@@ -0,0 +1,299 @@
+//! Safe wrapper over libFLAC's level 1 metadata interface.
+
+use std::{ffi::CStr, ffi::CString, path::Path, str::FromStr};
+
+use libflac_sys::*;
+
+use crate::EncoderError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataBlockType {
+    StreamInfo,
+    Padding,
+    Application,
+    SeekTable,
+    VorbisComment,
+    CueSheet,
+    Picture,
+    Unknown(u32),
+}
+
+impl MetadataBlockType {
+    pub(crate) fn from_u32(value: u32) -> Self {
+        match value {
+            FLAC__METADATA_TYPE_STREAMINFO => MetadataBlockType::StreamInfo,
+            FLAC__METADATA_TYPE_PADDING => MetadataBlockType::Padding,
+            FLAC__METADATA_TYPE_APPLICATION => MetadataBlockType::Application,
+            FLAC__METADATA_TYPE_SEEKTABLE => MetadataBlockType::SeekTable,
+            FLAC__METADATA_TYPE_VORBIS_COMMENT => MetadataBlockType::VorbisComment,
+            FLAC__METADATA_TYPE_CUESHEET => MetadataBlockType::CueSheet,
+            FLAC__METADATA_TYPE_PICTURE => MetadataBlockType::Picture,
+            other => MetadataBlockType::Unknown(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockInfo {
+    pub block_type: MetadataBlockType,
+    /// Length of the block body in bytes, excluding the 4 byte header.
+    pub length: u32,
+    pub is_last: bool,
+    /// Offset of the block header in the file.
+    pub offset: u64,
+}
+
+/// Walks the metadata blocks of an existing file without loading them all, for quick listing
+/// or small in-place patches like resizing padding. Iterating yields each block in turn; the
+/// patching methods act on the block most recently yielded.
+///
+/// For anything more involved than that, [`MetadataEditor`](crate::MetadataEditor) is a better
+/// fit.
+pub struct SimpleMetadataIterator {
+    iterator: *mut FLAC__Metadata_SimpleIterator,
+    started: bool,
+}
+
+impl SimpleMetadataIterator {
+    /// Open for reading and writing.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, EncoderError> {
+        Self::init(path.as_ref(), false)
+    }
+
+    pub fn open_read_only(path: impl AsRef<Path>) -> Result<Self, EncoderError> {
+        Self::init(path.as_ref(), true)
+    }
+
+    fn init(path: &Path, read_only: bool) -> Result<Self, EncoderError> {
+        let Ok(path) = CString::from_str(&path.to_string_lossy()) else {
+            return Err(EncoderError::NullCharInPath);
+        };
+
+        unsafe {
+            let iterator = FLAC__metadata_simple_iterator_new();
+
+            if iterator.is_null() {
+                return Err(EncoderError::InitializationError);
+            }
+
+            let this = SimpleMetadataIterator {
+                iterator,
+                started: false,
+            };
+
+            if 0 == FLAC__metadata_simple_iterator_init(
+                iterator,
+                path.as_ptr(),
+                read_only as FLAC__bool,
+                1,
+            ) {
+                return Err(this.status_error());
+            }
+
+            Ok(this)
+        }
+    }
+
+    fn current(&self) -> BlockInfo {
+        unsafe {
+            BlockInfo {
+                block_type: MetadataBlockType::from_u32(
+                    FLAC__metadata_simple_iterator_get_block_type(self.iterator),
+                ),
+                length: FLAC__metadata_simple_iterator_get_block_length(self.iterator),
+                is_last: 0 != FLAC__metadata_simple_iterator_is_last(self.iterator),
+                offset: FLAC__metadata_simple_iterator_get_block_offset(self.iterator) as u64,
+            }
+        }
+    }
+
+    /// Change the length of the current block, which must be padding. Growing it may require
+    /// libFLAC to rewrite the whole file.
+    pub fn set_padding_length(&mut self, length: u32) -> Result<(), EncoderError> {
+        if self.current().block_type != MetadataBlockType::Padding {
+            return Err(EncoderError::NotPadding);
+        }
+
+        unsafe {
+            let block = FLAC__metadata_simple_iterator_get_block(self.iterator);
+
+            if block.is_null() {
+                return Err(self.status_error());
+            }
+
+            (*block).length = length;
+
+            let ok = FLAC__metadata_simple_iterator_set_block(self.iterator, block, 0);
+            FLAC__metadata_object_delete(block);
+
+            if 0 == ok {
+                return Err(self.status_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Delete the current block. With `use_padding` it is replaced by padding of the same size
+    /// so the audio doesn't have to move. STREAMINFO can't be deleted.
+    pub fn delete_current(&mut self, use_padding: bool) -> Result<(), EncoderError> {
+        unsafe {
+            if 0 == FLAC__metadata_simple_iterator_delete_block(
+                self.iterator,
+                use_padding as FLAC__bool,
+            ) {
+                return Err(self.status_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Insert a padding block of `length` bytes after the current block.
+    pub fn insert_padding_after(&mut self, length: u32) -> Result<(), EncoderError> {
+        unsafe {
+            let block = FLAC__metadata_object_new(FLAC__METADATA_TYPE_PADDING);
+
+            if block.is_null() {
+                return Err(EncoderError::InitializationError);
+            }
+
+            (*block).length = length;
+
+            let ok = FLAC__metadata_simple_iterator_insert_block_after(self.iterator, block, 0);
+            FLAC__metadata_object_delete(block);
+
+            if 0 == ok {
+                return Err(self.status_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn status_error(&self) -> EncoderError {
+        unsafe {
+            let status = FLAC__metadata_simple_iterator_status(self.iterator);
+            let message = *FLAC__Metadata_SimpleIteratorStatusString
+                .as_ptr()
+                .add(status as usize);
+
+            EncoderError::MetadataIteratorError(CStr::from_ptr(message).to_string_lossy().into())
+        }
+    }
+}
+
+/// Shrinks every padding block of the FLAC file at `path` that is larger than `max_padding`
+/// bytes to that size, returning how many bytes were removed. libFLAC rewrites the file to do
+/// so, through a temporary file next to it.
+pub fn reclaim_padding(path: impl AsRef<Path>, max_padding: u32) -> Result<u64, EncoderError> {
+    let mut iterator = SimpleMetadataIterator::open(path)?;
+    let mut reclaimed = 0;
+
+    while let Some(block) = iterator.next() {
+        if block.block_type == MetadataBlockType::Padding && block.length > max_padding {
+            iterator.set_padding_length(max_padding)?;
+            reclaimed += (block.length - max_padding) as u64;
+        }
+    }
+
+    Ok(reclaimed)
+}
+
+impl Iterator for SimpleMetadataIterator {
+    type Item = BlockInfo;
+
+    fn next(&mut self) -> Option<BlockInfo> {
+        if !self.started {
+            self.started = true;
+            return Some(self.current());
+        }
+
+        unsafe {
+            if 0 == FLAC__metadata_simple_iterator_next(self.iterator) {
+                return None;
+            }
+        }
+
+        Some(self.current())
+    }
+}
+
+impl Drop for SimpleMetadataIterator {
+    fn drop(&mut self) {
+        unsafe {
+            FLAC__metadata_simple_iterator_delete(self.iterator);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FlacBuilder;
+
+    fn block_types(path: &Path) -> Vec<(MetadataBlockType, u32)> {
+        SimpleMetadataIterator::open_read_only(path)
+            .unwrap()
+            .map(|block| (block.block_type, block.length))
+            .collect()
+    }
+
+    #[test]
+    fn walks_and_patches_blocks_in_place() {
+        let path = std::env::temp_dir().join(format!("simple-iter-{}.flac", std::process::id()));
+        let samples = vec![0.0f32; 4096];
+        FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .title("Quiet")
+            .padding(100)
+            .write_file(&path)
+            .unwrap();
+
+        let before = block_types(&path);
+        assert_eq!(before[0], (MetadataBlockType::StreamInfo, 34));
+        assert_eq!(before.last(), Some(&(MetadataBlockType::Padding, 100)));
+
+        let mut iterator = SimpleMetadataIterator::open(&path).unwrap();
+        let first = iterator.next().unwrap();
+        assert!(matches!(
+            iterator.set_padding_length(10),
+            Err(EncoderError::NotPadding)
+        ));
+        assert_eq!(first.offset, 4);
+
+        while iterator.next().is_some_and(|block| !block.is_last) {}
+        iterator.set_padding_length(60).unwrap();
+        drop(iterator);
+
+        let after = block_types(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(after.len(), before.len());
+        assert_eq!(after.last(), Some(&(MetadataBlockType::Padding, 60)));
+    }
+
+    #[test]
+    fn reclaims_padding_over_the_limit() {
+        let path = std::env::temp_dir().join(format!("reclaim-{}.flac", std::process::id()));
+        let samples = vec![0.0f32; 4096];
+        let report = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .padding(100_000)
+            .reclaim_padding(1000)
+            .write_file_with_report(&path)
+            .unwrap();
+
+        let len = std::fs::metadata(&path).unwrap().len();
+        assert_eq!(report.encoded_bytes as u64, len);
+        assert_eq!(
+            block_types(&path).last(),
+            Some(&(MetadataBlockType::Padding, 1000))
+        );
+
+        assert_eq!(reclaim_padding(&path, 10).unwrap(), 990);
+        assert_eq!(reclaim_padding(&path, 10).unwrap(), 0);
+        let shrunk = std::fs::metadata(&path).unwrap().len();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(shrunk, len - 990);
+    }
+}
@@ -0,0 +1,892 @@
+//! Where encoded bytes go. Every output method encodes into a [`ByteSink`], going back to
+//! finalize the header when the sink can overwrite what it already has.
+
+use std::{
+    collections::VecDeque,
+    ffi::{c_void, CStr},
+    fs::File,
+    future::Future,
+    io::{self, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    slice::from_raw_parts,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{Sender, SyncSender},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    time::Instant,
+};
+
+use libflac_sys::*;
+
+use crate::{hash::Hasher, EncoderError};
+
+/// A destination for an encoded stream, see
+/// [`FlacBuilder::write_to_sink`](crate::FlacBuilder::write_to_sink).
+///
+/// libFLAC only knows some STREAMINFO fields (MD5 and exact frame sizes) once the audio is
+/// done. Sinks that can [`overwrite`](Self::overwrite) get the header rewritten with them at the
+/// end. For the rest the MD5 and frame sizes stay zero, which the format reads as unknown, so
+/// decoders can't check the audio against the MD5, and a seek table keeps only placeholder
+/// points. The total sample count is taken from the input up front, or left at zero (unknown)
+/// by [`FlacStreamEncoder`](crate::FlacStreamEncoder).
+///
+/// libFLAC calls the sink synchronously from inside the encode, so a sink can't await. To hand
+/// the output to async code, encode on a blocking thread into an [`async_sink`] and await its
+/// [`AsyncReceiver`].
+pub trait ByteSink {
+    /// Adds `bytes` to the end of the output.
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()>;
+
+    /// Whether [`overwrite`](Self::overwrite) works.
+    fn can_overwrite(&self) -> bool {
+        false
+    }
+
+    /// Replaces output already appended, starting `offset` bytes in. Never goes past the end.
+    fn overwrite(&mut self, _offset: u64, _bytes: &[u8]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Called once the stream is complete.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Passes on anything buffered so far without ending the stream, see
+    /// [`FlacStreamEncoder::flush`](crate::FlacStreamEncoder::flush). Buffering sinks should
+    /// implement it.
+    fn flush_written(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: ByteSink + ?Sized> ByteSink for &mut S {
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        (**self).append(bytes)
+    }
+
+    fn can_overwrite(&self) -> bool {
+        (**self).can_overwrite()
+    }
+
+    fn overwrite(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        (**self).overwrite(offset, bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn flush_written(&mut self) -> io::Result<()> {
+        (**self).flush_written()
+    }
+}
+
+/// The stream is appended, so the `Vec` should start out empty.
+impl ByteSink for Vec<u8> {
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn can_overwrite(&self) -> bool {
+        true
+    }
+
+    fn overwrite(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        let start = offset as usize;
+        self[start..start + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Writes unbuffered; wrap it in [`Seekable`] with a `BufWriter` for many small writes. The
+/// stream must start at offset 0 of the file, as it does for one just created; use
+/// [`Seekable`] for a stream that starts later.
+impl ByteSink for File {
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_all(bytes)
+    }
+
+    fn can_overwrite(&self) -> bool {
+        true
+    }
+
+    fn overwrite(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        overwrite_at(self, offset, bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Write::flush(self)
+    }
+}
+
+/// Each write is sent as its own message; the header write comes first. The channel is
+/// unbounded, so if the receiver falls behind the encoded bytes pile up in it; use a
+/// [`SyncSender`] to hold the encoder back instead.
+impl ByteSink for Sender<Vec<u8>> {
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.send(bytes.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+/// Like a [`Sender`], but once the channel's bound is reached each write blocks until the
+/// receiver takes a message, so a slow consumer (a network upload, a slow disk) slows the
+/// encode down rather than letting memory grow. A
+/// [`FlacStreamEncoder`](crate::FlacStreamEncoder) push then blocks the producer in turn.
+impl ByteSink for SyncSender<Vec<u8>> {
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.send(bytes.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+/// Makes a sink whose writes an async task can await, e.g. to upload the stream from an async
+/// server as it is encoded. The encode blocks, so it runs on its own thread (`spawn_blocking`
+/// under tokio) while the task awaits [`AsyncReceiver::recv`]. It works with any runtime. Like
+/// a [`Sender`], the queue is unbounded.
+pub fn async_sink() -> (AsyncSink, AsyncReceiver) {
+    let queue = Arc::new(Mutex::new(AsyncQueue {
+        writes: VecDeque::new(),
+        waker: None,
+        closed: false,
+        receiver_dropped: false,
+    }));
+
+    (AsyncSink(queue.clone()), AsyncReceiver(queue))
+}
+
+struct AsyncQueue {
+    writes: VecDeque<Vec<u8>>,
+    waker: Option<Waker>,
+    /// Set once the sink is dropped.
+    closed: bool,
+    receiver_dropped: bool,
+}
+
+/// The sending half of [`async_sink`]. Each write is queued as its own message; dropping the
+/// sink, as the encoder does when it is done, ends the stream.
+pub struct AsyncSink(Arc<Mutex<AsyncQueue>>);
+
+impl ByteSink for AsyncSink {
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut queue = self.0.lock().unwrap();
+        if queue.receiver_dropped {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        queue.writes.push_back(bytes.to_vec());
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AsyncSink {
+    fn drop(&mut self) {
+        let mut queue = self.0.lock().unwrap();
+        queue.closed = true;
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The receiving half of [`async_sink`]. Dropping it fails the encode's next write.
+pub struct AsyncReceiver(Arc<Mutex<AsyncQueue>>);
+
+impl AsyncReceiver {
+    /// The next write, or `None` once the sink has been dropped and every write taken.
+    pub fn recv(&mut self) -> impl Future<Output = Option<Vec<u8>>> + '_ {
+        std::future::poll_fn(|cx| self.poll_recv(cx))
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Vec<u8>>> {
+        let mut queue = self.0.lock().unwrap();
+
+        match queue.writes.pop_front() {
+            Some(bytes) => Poll::Ready(Some(bytes)),
+            None if queue.closed => Poll::Ready(None),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for AsyncReceiver {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().receiver_dropped = true;
+    }
+}
+
+/// Any [`Write`] + [`Seek`], e.g. a buffered file or an `io::Cursor`. The stream starts
+/// wherever the writer is when the sink is made, so it can follow other data in the writer.
+#[derive(Debug)]
+pub struct Seekable<W> {
+    writer: W,
+    /// Where the stream starts in `writer`.
+    start: u64,
+}
+
+impl<W: Write + Seek> Seekable<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        let start = writer.stream_position()?;
+        Ok(Seekable { writer, start })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Seek> ByteSink for Seekable<W> {
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)
+    }
+
+    fn can_overwrite(&self) -> bool {
+        true
+    }
+
+    fn overwrite(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        overwrite_at(&mut self.writer, self.start + offset, bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn flush_written(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Any [`Write`] that can only go forward, e.g. a pipe or socket.
+#[derive(Debug)]
+pub struct Streamed<W>(pub W);
+
+impl<W: Write> ByteSink for Streamed<W> {
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.0.write_all(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+
+    fn flush_written(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Splits the stream into its header (`fLaC` and every metadata block) and the audio frames,
+/// e.g. for a packager emitting an init segment followed by media segments. Frames go to the
+/// inner sink as they are encoded; the header is held back and handed to `on_header` once the
+/// stream is complete and libFLAC has finalized STREAMINFO.
+pub struct SplitHeader<S, F> {
+    frames: S,
+    on_header: F,
+    header: Vec<u8>,
+    /// Length of the header once all of its block headers have been seen.
+    header_len: Option<usize>,
+}
+
+impl<S: ByteSink, F: FnMut(&[u8])> SplitHeader<S, F> {
+    pub fn new(frames: S, on_header: F) -> Self {
+        SplitHeader {
+            frames,
+            on_header,
+            header: vec![],
+            header_len: None,
+        }
+    }
+
+    /// The sink the audio frames went to.
+    pub fn into_inner(self) -> S {
+        self.frames
+    }
+}
+
+impl<S: ByteSink, F: FnMut(&[u8])> ByteSink for SplitHeader<S, F> {
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.header_len.is_some() {
+            return self.frames.append(bytes);
+        }
+
+        self.header.extend_from_slice(bytes);
+        self.header_len = metadata_end(&self.header);
+
+        match self.header_len {
+            Some(len) if len < self.header.len() => {
+                let frames = self.header.split_off(len);
+                self.frames.append(&frames)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn can_overwrite(&self) -> bool {
+        true
+    }
+
+    /// Only the header is ever rewritten by libFLAC.
+    fn overwrite(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        let start = offset as usize;
+        match self.header.get_mut(start..start + bytes.len()) {
+            Some(target) => {
+                target.copy_from_slice(bytes);
+                Ok(())
+            }
+            None => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (self.on_header)(&self.header);
+        self.frames.flush()
+    }
+
+    /// The header isn't final until the stream is, so only the frames are flushed.
+    fn flush_written(&mut self) -> io::Result<()> {
+        self.frames.flush_written()
+    }
+}
+
+/// Groups the output into chunks of up to `chunk_bytes`, e.g. for CDN range requests or
+/// resumable upload parts. A chunk is only ever closed between two writes from the encoder,
+/// before the write that would take it over the size; only a single write bigger than
+/// `chunk_bytes` makes a longer chunk. `on_chunk` gets each chunk's byte range in the output
+/// once it is complete, and the last one when the stream is.
+///
+/// This doesn't parse the output, so chunks are aligned to whatever libFLAC hands over in one
+/// write. Current libFLAC versions write each frame in one piece, making the chunks start and
+/// end on frame boundaries, but its API doesn't promise that; check the output with
+/// [`frames`](crate::frames) where it matters. Frames all have the same
+/// [`block_size`](crate::AdvancedSettings::block_size) rather than one varied to hit a byte
+/// count, so chunks come out short of `chunk_bytes` by up to a frame. Put it inside a
+/// [`SplitHeader`] so the chunks hold only audio, as the header in the first one is rewritten
+/// when the stream is finalized.
+pub struct AlignedChunks<S, F> {
+    inner: S,
+    chunk_bytes: u64,
+    on_chunk: F,
+    /// Where the current chunk starts.
+    chunk_start: u64,
+    len: u64,
+}
+
+impl<S: ByteSink, F: FnMut(Range<u64>)> AlignedChunks<S, F> {
+    pub fn new(inner: S, chunk_bytes: u64, on_chunk: F) -> Self {
+        AlignedChunks {
+            inner,
+            chunk_bytes,
+            on_chunk,
+            chunk_start: 0,
+            len: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: ByteSink, F: FnMut(Range<u64>)> ByteSink for AlignedChunks<S, F> {
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let chunk_len = self.len - self.chunk_start;
+        if chunk_len > 0 && chunk_len + bytes.len() as u64 > self.chunk_bytes {
+            (self.on_chunk)(self.chunk_start..self.len);
+            self.chunk_start = self.len;
+        }
+
+        self.inner.append(bytes)?;
+        self.len += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn can_overwrite(&self) -> bool {
+        self.inner.can_overwrite()
+    }
+
+    fn overwrite(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        self.inner.overwrite(offset, bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.len > self.chunk_start {
+            (self.on_chunk)(self.chunk_start..self.len);
+            self.chunk_start = self.len;
+        }
+        self.inner.flush()
+    }
+
+    fn flush_written(&mut self) -> io::Result<()> {
+        self.inner.flush_written()
+    }
+}
+
+/// Where the last metadata block ends, once `header` has reached it.
+fn metadata_end(header: &[u8]) -> Option<usize> {
+    let mut position = 4;
+
+    loop {
+        let block_header = header.get(position..position + 4)?;
+        let is_last = block_header[0] & 0x80 != 0;
+        let length = u32::from_be_bytes([0, block_header[1], block_header[2], block_header[3]]);
+
+        position += 4 + length as usize;
+
+        if is_last {
+            return (header.len() >= position).then_some(position);
+        }
+    }
+}
+
+fn overwrite_at<W: Write + Seek>(writer: &mut W, offset: u64, bytes: &[u8]) -> io::Result<()> {
+    let end = writer.stream_position()?;
+    writer.seek(SeekFrom::Start(offset))?;
+    writer.write_all(bytes)?;
+    writer.seek(SeekFrom::Start(end))?;
+    Ok(())
+}
+
+/// Client data for the callbacks below, tracking libFLAC's position in the output so its
+/// seeks become overwrites. libFLAC can't carry an `io::Error` so the first one is kept here to
+/// be returned instead of the less specific encoder error.
+pub(crate) struct SinkState<S: ByteSink> {
+    pub sink: S,
+    pub error: Option<io::Error>,
+    position: u64,
+    /// Size of the output so far.
+    pub len: u64,
+    /// Frames per channel in the audio frames written so far.
+    pub samples_written: u64,
+    /// When the first audio frame, as opposed to metadata, was written.
+    pub first_frame: Option<Instant>,
+    /// Digest of everything written so far. Overwriting makes it stale, so it is dropped then.
+    pub hasher: Option<Hasher>,
+    /// A copy of the header as first written, kept past the first frame only with a `spool`
+    /// or `keep_header`.
+    pub header_copy: Option<Vec<u8>>,
+    pub keep_header: bool,
+    /// A copy of every frame written, for snapshots of the stream so far.
+    pub spool: Option<Spool>,
+}
+
+/// A temporary file holding a copy of the frames, deleted when dropped. Created in `dir`, or
+/// the system's temporary directory if not set.
+pub(crate) struct Spool {
+    pub file: File,
+    pub path: PathBuf,
+}
+
+impl Spool {
+    pub fn new(dir: Option<&Path>) -> io::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = dir.map_or_else(std::env::temp_dir, Path::to_path_buf);
+        let path = dir.join(format!(
+            ".flac-encoder-{}-{}.spool",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::options().write(true).create_new(true).open(&path)?;
+
+        Ok(Spool { file, path })
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl<S: ByteSink> SinkState<S> {
+    pub fn new(sink: S) -> Self {
+        SinkState {
+            sink,
+            error: None,
+            position: 0,
+            len: 0,
+            samples_written: 0,
+            first_frame: None,
+            hasher: None,
+            header_copy: None,
+            keep_header: false,
+            spool: None,
+        }
+    }
+
+    fn record<T>(&mut self, result: io::Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.error.get_or_insert(e);
+                None
+            }
+        }
+    }
+}
+
+/// Initializes `encoder` to write into `state`, which must outlive it. An I/O error writing the
+/// metadata also fails this, but is kept in `state` as the more specific error.
+pub(crate) unsafe fn init_sink<S: ByteSink>(
+    encoder: *mut FLAC__StreamEncoder,
+    state: &mut SinkState<S>,
+) -> Result<(), EncoderError> {
+    let (seek, tell) = if state.sink.can_overwrite() {
+        (
+            Some(sink_seek_callback::<S> as _),
+            Some(sink_tell_callback::<S> as _),
+        )
+    } else {
+        (None, None)
+    };
+
+    let status = FLAC__stream_encoder_init_stream(
+        encoder,
+        Some(sink_write_callback::<S>),
+        seek,
+        tell,
+        None,
+        state as *mut _ as *mut c_void,
+    );
+
+    if status != FLAC__STREAM_ENCODER_INIT_STATUS_OK {
+        let message = *FLAC__StreamEncoderInitStatusString
+            .as_ptr()
+            .add(status as usize);
+        return Err(EncoderError::FailedToInitializeEncoder(
+            CStr::from_ptr(message).to_string_lossy().into_owned(),
+        ));
+    }
+
+    Ok(())
+}
+
+unsafe extern "C" fn sink_write_callback<S: ByteSink>(
+    _encoder: *const FLAC__StreamEncoder,
+    buffer: *const FLAC__byte,
+    bytes: usize,
+    samples: u32,
+    _current_frame: u32,
+    client_data: *mut c_void,
+) -> FLAC__StreamEncoderWriteStatus {
+    let state = &mut *(client_data as *mut SinkState<S>);
+
+    if samples > 0 && state.first_frame.is_none() {
+        state.first_frame = Some(Instant::now());
+    }
+
+    let buffer = from_raw_parts(buffer, bytes);
+
+    // Anything before the current end replaces what was written there.
+    let overlap = ((state.len - state.position) as usize).min(bytes);
+    let (replaced, appended) = buffer.split_at(overlap);
+
+    let mut result = Ok(());
+
+    if !replaced.is_empty() {
+        state.hasher = None;
+        result = state.sink.overwrite(state.position, replaced);
+    }
+
+    if result.is_ok() && !appended.is_empty() {
+        if let Some(hasher) = &mut state.hasher {
+            hasher.update(appended);
+        }
+        result = state.sink.append(appended);
+    }
+
+    if result.is_ok() && !appended.is_empty() {
+        if samples == 0 && state.samples_written == 0 {
+            if let Some(header) = &mut state.header_copy {
+                header.extend_from_slice(appended);
+            }
+        } else if let Some(spool) = &mut state.spool {
+            result = spool.file.write_all(appended);
+        } else if !state.keep_header {
+            state.header_copy = None;
+        }
+    }
+
+    match state.record(result) {
+        Some(()) => {
+            state.position += bytes as u64;
+            state.samples_written += samples as u64;
+            state.len = state.len.max(state.position);
+            FLAC__STREAM_ENCODER_WRITE_STATUS_OK
+        }
+        None => FLAC__STREAM_ENCODER_WRITE_STATUS_FATAL_ERROR,
+    }
+}
+
+unsafe extern "C" fn sink_seek_callback<S: ByteSink>(
+    _encoder: *const FLAC__StreamEncoder,
+    absolute_byte_offset: u64,
+    client_data: *mut c_void,
+) -> FLAC__StreamEncoderSeekStatus {
+    let state = &mut *(client_data as *mut SinkState<S>);
+
+    if absolute_byte_offset > state.len {
+        return FLAC__STREAM_ENCODER_SEEK_STATUS_ERROR;
+    }
+
+    state.position = absolute_byte_offset;
+    FLAC__STREAM_ENCODER_SEEK_STATUS_OK
+}
+
+unsafe extern "C" fn sink_tell_callback<S: ByteSink>(
+    _encoder: *const FLAC__StreamEncoder,
+    absolute_byte_offset: *mut u64,
+    client_data: *mut c_void,
+) -> FLAC__StreamEncoderTellStatus {
+    let state = &*(client_data as *const SinkState<S>);
+
+    *absolute_byte_offset = state.position;
+    FLAC__STREAM_ENCODER_TELL_STATUS_OK
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{AudioSource, FlacBuilder};
+
+    struct FullDisk;
+
+    impl Write for FullDisk {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for FullDisk {
+        fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
+            Ok(0)
+        }
+    }
+
+    fn samples() -> Vec<f32> {
+        (0..20_000).map(|i| (i as f32 * 0.01).sin() * 0.5).collect()
+    }
+
+    #[test]
+    fn file_output_matches_build() {
+        let samples = samples();
+        let path = std::env::temp_dir().join(format!("sink-{}.flac", std::process::id()));
+
+        FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .write_file(&path)
+            .unwrap();
+        let written = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let built = FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .build()
+            .unwrap();
+        assert_eq!(written, built);
+    }
+
+    #[test]
+    fn keeps_the_first_io_error() {
+        let samples = samples();
+        let mut builder = FlacBuilder::from_interleaved(&samples, 2, 44100);
+        let mut sink = SinkState::new(Seekable::new(FullDisk).unwrap());
+
+        let result = unsafe {
+            let encoder = builder.prepare(true).unwrap();
+            init_sink(encoder.as_ptr(), &mut sink)
+        };
+
+        assert!(matches!(
+            result,
+            Err(EncoderError::FailedToInitializeEncoder(_))
+        ));
+        assert_eq!(sink.error.unwrap().to_string(), "disk full");
+    }
+
+    #[test]
+    fn seekable_streams_can_follow_other_data() {
+        let samples = samples();
+        let mut cursor = io::Cursor::new(b"prefix".to_vec());
+        cursor.seek(SeekFrom::End(0)).unwrap();
+
+        FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .write_to_sink(Seekable::new(&mut cursor).unwrap())
+            .unwrap();
+
+        let built = FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .build()
+            .unwrap();
+        let written = cursor.into_inner();
+        assert_eq!(&written[..6], b"prefix");
+        assert_eq!(&written[6..], &built[..]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_a_fifo_without_seeking() {
+        let samples = samples();
+        let path = std::env::temp_dir().join(format!("sink-{}.fifo", std::process::id()));
+        let status = std::process::Command::new("mkfifo")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let reader = std::thread::spawn({
+            let path = path.clone();
+            move || fs::read(path).unwrap()
+        });
+        FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .write_file(&path)
+            .unwrap();
+        let written = reader.join().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut decoded = vec![0; 1 << 16];
+        let n = crate::FlacDecoder::new(&written[..])
+            .unwrap()
+            .fill(&mut decoded)
+            .unwrap();
+        decoded.truncate(n);
+
+        let built = FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .build()
+            .unwrap();
+        let mut expected = vec![0; 1 << 16];
+        let n = crate::FlacDecoder::new(&built[..])
+            .unwrap()
+            .fill(&mut expected)
+            .unwrap();
+        expected.truncate(n);
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn streamed_output_counts_samples_per_channel() {
+        let samples = samples();
+        let mut out = Vec::new();
+        FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .write_to_sink(Streamed(&mut out))
+            .unwrap();
+
+        let decoder = crate::FlacDecoder::new(&out[..]).unwrap();
+        assert_eq!(decoder.total_samples(), samples.len() as u64 / 2);
+    }
+
+    /// Polls `future` to completion on this thread, parking between wake-ups.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct Unpark(std::thread::Thread);
+
+        impl std::task::Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn async_receiver_awaits_each_write() {
+        let samples = samples();
+        let mut expected = Vec::new();
+        FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .write_to_sink(Streamed(&mut expected))
+            .unwrap();
+
+        let (sink, mut receiver) = async_sink();
+        let encode = std::thread::spawn({
+            let samples = samples.clone();
+            move || FlacBuilder::from_interleaved(&samples, 2, 44100).write_to_sink(sink)
+        });
+        let received = block_on(async {
+            let mut received = Vec::new();
+            while let Some(bytes) = receiver.recv().await {
+                received.extend(bytes);
+            }
+            received
+        });
+        encode.join().unwrap().unwrap();
+        assert_eq!(received, expected);
+
+        let (sink, receiver) = async_sink();
+        drop(receiver);
+        let result = FlacBuilder::from_interleaved(&samples, 2, 44100).write_to_sink(sink);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn split_header_holds_back_the_finalized_header() {
+        let samples = samples();
+        let mut header = vec![];
+        let mut frames = vec![];
+
+        let mut encoder = crate::FlacStreamEncoder::new(
+            2,
+            44100,
+            SplitHeader::new(&mut frames, |h: &[u8]| header = h.to_vec()),
+            |builder| builder,
+        )
+        .unwrap();
+        encoder.push_interleaved(&samples).unwrap();
+        encoder.finalize().unwrap();
+
+        let built = FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .build()
+            .unwrap();
+        assert_eq!([header, frames].concat(), built);
+    }
+
+    #[test]
+    fn aligned_chunks_cover_the_frames_in_order() {
+        // Noise, so each frame is several kilobytes.
+        let mut seed = 1u32;
+        let samples: Vec<f32> = (0..20_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (seed >> 16) as f32 / 65536.0 - 0.5
+            })
+            .collect();
+        let mut frames = vec![];
+        let mut chunks = vec![];
+
+        FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .write_to_sink(SplitHeader::new(
+                AlignedChunks::new(&mut frames, 20_000, |range| chunks.push(range)),
+                |_: &[u8]| {},
+            ))
+            .unwrap();
+
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks[0].start, 0);
+        assert_eq!(chunks.last().unwrap().end, frames.len() as u64);
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        assert!(chunks.iter().all(|chunk| chunk.end - chunk.start <= 20_000));
+    }
+}
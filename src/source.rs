@@ -0,0 +1,399 @@
+//! Pull-based integer audio input that can come from anywhere.
+
+use std::io::{ErrorKind, Read};
+
+use crate::{AudioBlock, EncoderError};
+
+/// Anything that can hand out interleaved integer samples on request, e.g. a file being
+/// decoded or a network stream. Encode one as it is read with
+/// [`FlacBuilder::from_source`](crate::FlacBuilder::from_source) or
+/// [`FlacStreamEncoder::push_source`](crate::FlacStreamEncoder::push_source), or collect it
+/// into an [`AudioBlock`] with [`AudioBlock::from_source`].
+pub trait AudioSource {
+    fn channels(&self) -> usize;
+    fn sample_rate(&self) -> u32;
+    /// Bits per sample; every sample from [`fill`](Self::fill) fits in this many bits, signed.
+    fn bps(&self) -> u32;
+
+    /// Frames left, if known, so buffers can be sized up front.
+    fn len_hint(&self) -> Option<usize> {
+        None
+    }
+
+    /// Fills the start of `buffer` with interleaved samples and returns how many were written,
+    /// always a whole number of frames. `0` means the source is done.
+    fn fill(&mut self, buffer: &mut [i32]) -> Result<usize, EncoderError>;
+}
+
+impl AudioBlock {
+    /// Reads `source` to the end.
+    pub fn from_source(mut source: impl AudioSource) -> Result<Self, EncoderError> {
+        let channels = source.channels();
+        if channels == 0 {
+            return Err(EncoderError::InvalidChannelCount);
+        }
+
+        let mut samples = Vec::with_capacity(source.len_hint().unwrap_or(0) * channels);
+        let mut buffer = vec![0; 4096 * channels];
+
+        loop {
+            match source.fill(&mut buffer)? {
+                0 => break,
+                n => samples.extend_from_slice(&buffer[..n]),
+            }
+        }
+
+        if !samples.len().is_multiple_of(channels) {
+            return Err(EncoderError::MismatchedSampleCountPerChannels);
+        }
+
+        Ok(AudioBlock {
+            channels,
+            bps: source.bps(),
+            sample_rate: source.sample_rate(),
+            samples,
+        })
+    }
+
+    /// This block as a source, e.g. for code that takes any [`AudioSource`].
+    pub fn source(&self) -> SliceSource<'_> {
+        SliceSource::new(&self.samples, self.channels, self.sample_rate, self.bps)
+    }
+}
+
+/// Reads up to `frames` frames from `source` into a block, fewer only once the source is done.
+pub(crate) fn read_block(
+    source: &mut (impl AudioSource + ?Sized),
+    frames: usize,
+) -> Result<AudioBlock, EncoderError> {
+    let channels = source.channels().max(1);
+    let mut samples = vec![0; frames * channels];
+    let mut filled = 0;
+
+    while filled < samples.len() {
+        match source.fill(&mut samples[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+
+    if !filled.is_multiple_of(channels) {
+        return Err(EncoderError::MismatchedSampleCountPerChannels);
+    }
+    samples.truncate(filled);
+
+    Ok(AudioBlock {
+        channels,
+        bps: source.bps(),
+        sample_rate: source.sample_rate(),
+        samples,
+    })
+}
+
+/// Interleaved samples already in memory.
+#[derive(Debug, Clone)]
+pub struct SliceSource<'a> {
+    samples: &'a [i32],
+    channels: usize,
+    sample_rate: u32,
+    bps: u32,
+    cursor: usize,
+}
+
+impl<'a> SliceSource<'a> {
+    pub fn new(samples: &'a [i32], channels: usize, sample_rate: u32, bps: u32) -> Self {
+        SliceSource {
+            samples,
+            channels,
+            sample_rate,
+            bps,
+            cursor: 0,
+        }
+    }
+}
+
+impl AudioSource for SliceSource<'_> {
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn bps(&self) -> u32 {
+        self.bps
+    }
+
+    fn len_hint(&self) -> Option<usize> {
+        Some((self.samples.len() - self.cursor) / self.channels.max(1))
+    }
+
+    fn fill(&mut self, buffer: &mut [i32]) -> Result<usize, EncoderError> {
+        let whole_frames = buffer.len() - buffer.len() % self.channels.max(1);
+        let n = whole_frames.min(self.samples.len() - self.cursor);
+
+        buffer[..n].copy_from_slice(&self.samples[self.cursor..self.cursor + n]);
+        self.cursor += n;
+
+        Ok(n)
+    }
+}
+
+/// Interleaved samples from an iterator, e.g. a generator or a lazily decoded stream.
+#[derive(Debug, Clone)]
+pub struct IterSource<I> {
+    samples: I,
+    channels: usize,
+    sample_rate: u32,
+    bps: u32,
+}
+
+impl<I: Iterator<Item = i32>> IterSource<I> {
+    pub fn new(
+        samples: impl IntoIterator<IntoIter = I>,
+        channels: usize,
+        sample_rate: u32,
+        bps: u32,
+    ) -> Self {
+        IterSource {
+            samples: samples.into_iter(),
+            channels,
+            sample_rate,
+            bps,
+        }
+    }
+}
+
+impl<I: Iterator<Item = i32>> AudioSource for IterSource<I> {
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn bps(&self) -> u32 {
+        self.bps
+    }
+
+    fn len_hint(&self) -> Option<usize> {
+        match self.samples.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(lower / self.channels.max(1)),
+            _ => None,
+        }
+    }
+
+    fn fill(&mut self, buffer: &mut [i32]) -> Result<usize, EncoderError> {
+        let whole_frames = buffer.len() - buffer.len() % self.channels.max(1);
+        let mut n = 0;
+
+        for (slot, sample) in buffer[..whole_frames].iter_mut().zip(&mut self.samples) {
+            *slot = sample;
+            n += 1;
+        }
+
+        Ok(n)
+    }
+}
+
+/// Raw signed little-endian PCM from a reader, e.g. a pipe from another program. Each sample
+/// takes `bps` rounded up to whole bytes.
+#[derive(Debug)]
+pub struct PcmReader<R> {
+    reader: R,
+    channels: usize,
+    sample_rate: u32,
+    bps: u32,
+    big_endian: bool,
+    bytes: Vec<u8>,
+}
+
+impl<R: Read> PcmReader<R> {
+    pub fn new(reader: R, channels: usize, sample_rate: u32, bps: u32) -> Self {
+        PcmReader {
+            reader,
+            channels,
+            sample_rate,
+            bps,
+            big_endian: false,
+            bytes: vec![],
+        }
+    }
+
+    /// Reads big-endian samples instead, e.g. from AIFF data or `sox -B`.
+    pub fn big_endian(mut self) -> Self {
+        self.big_endian = true;
+        self
+    }
+
+    fn bytes_per_sample(&self) -> usize {
+        self.bps.div_ceil(8).clamp(1, 4) as usize
+    }
+}
+
+impl<R: Read> AudioSource for PcmReader<R> {
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn bps(&self) -> u32 {
+        self.bps
+    }
+
+    fn fill(&mut self, buffer: &mut [i32]) -> Result<usize, EncoderError> {
+        let width = self.bytes_per_sample();
+        let frame_bytes = width * self.channels.max(1);
+        let whole_frames = buffer.len() / self.channels.max(1);
+
+        self.bytes.resize(whole_frames * frame_bytes, 0);
+
+        // Read until the buffer is full or the reader ends, then drop any partial frame.
+        let mut read = 0;
+        while read < self.bytes.len() {
+            match self.reader.read(&mut self.bytes[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(EncoderError::Io(e)),
+            }
+        }
+        let read = read - read % frame_bytes;
+
+        for (slot, sample) in buffer
+            .iter_mut()
+            .zip(self.bytes[..read].chunks_exact(width))
+        {
+            let mut le = [0; 4];
+            le[4 - width..].copy_from_slice(sample);
+            if self.big_endian {
+                le[4 - width..].reverse();
+            }
+            // Sign-extend from the top byte down.
+            *slot = i32::from_le_bytes(le) >> (8 * (4 - width));
+        }
+
+        Ok(read / width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{FlacBuilder, FlacDecoder, SilentInputPolicy};
+
+    /// A 16-bit stereo ramp.
+    fn ramp() -> Vec<i32> {
+        (0..20_000).map(|i| (i * 3 % 60_000) - 30_000).collect()
+    }
+
+    fn decode(bytes: &[u8]) -> Vec<i32> {
+        let mut samples = vec![0; 1 << 16];
+        let n = FlacDecoder::new(bytes).unwrap().fill(&mut samples).unwrap();
+        samples.truncate(n);
+        samples
+    }
+
+    #[test]
+    fn pcm_reader_reads_either_byte_order_and_drops_a_partial_frame() {
+        let samples = [1, -2, 300, -32768];
+        let le: Vec<u8> = samples
+            .iter()
+            .flat_map(|&s| (s as i16).to_le_bytes())
+            .chain([0x7f])
+            .collect();
+        let be: Vec<u8> = samples
+            .iter()
+            .flat_map(|&s| (s as i16).to_be_bytes())
+            .collect();
+
+        let read = |reader: PcmReader<&[u8]>| AudioBlock::from_source(reader).unwrap().samples;
+        assert_eq!(read(PcmReader::new(&le[..], 2, 44100, 16)), samples);
+        assert_eq!(
+            read(PcmReader::new(&be[..], 2, 44100, 16).big_endian()),
+            samples
+        );
+
+        let packed = [0x01, 0x00, 0x80, 0xff, 0xff, 0x7f];
+        assert_eq!(
+            read(PcmReader::new(&packed[..], 1, 44100, 24)),
+            [-(1 << 23) + 1, (1 << 23) - 1]
+        );
+    }
+
+    #[test]
+    fn from_source_encodes_like_from_block() {
+        let samples = ramp();
+        let block = AudioBlock {
+            channels: 2,
+            bps: 16,
+            sample_rate: 44100,
+            samples: samples.clone(),
+        };
+        // No length hint, as from a generator.
+        let source = IterSource::new(samples.iter().copied().filter(|_| true), 2, 44100, 16);
+        assert_eq!(source.len_hint(), None);
+
+        let (bytes, report) = FlacBuilder::from_source(source)
+            .build_with_report()
+            .unwrap();
+
+        assert_eq!(decode(&bytes), samples);
+        assert_eq!(
+            decode(&FlacBuilder::from_block(&block).build().unwrap()),
+            samples
+        );
+        assert_eq!(
+            report.input_duration,
+            Duration::from_secs_f64(10_000.0 / 44100.0)
+        );
+        assert_eq!(report.pcm_bytes, 40_000);
+    }
+
+    #[test]
+    fn from_source_refuses_what_needs_the_whole_input() {
+        let samples = ramp();
+        let unhinted = || IterSource::new(samples.iter().copied().filter(|_| true), 2, 44100, 16);
+
+        let results = [
+            FlacBuilder::from_source(unhinted())
+                .on_silent_input(SilentInputPolicy::Skip)
+                .build(),
+            FlacBuilder::from_source(unhinted()).export_looped(0..0, 0..100),
+            FlacBuilder::from_source(unhinted())
+                .fade_out(Duration::from_millis(10))
+                .build(),
+        ];
+        for (result, setting) in
+            results
+                .into_iter()
+                .zip(["on_silent_input", "export_looped", "fade_out"])
+        {
+            assert!(
+                matches!(result, Err(EncoderError::NeedsWholeInput(name)) if name == setting),
+                "{setting}"
+            );
+        }
+
+        // With a length hint the fade out knows where to end.
+        let faded = FlacBuilder::from_source(SliceSource::new(&samples, 2, 44100, 16))
+            .fade_out(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let decoded = decode(&faded);
+        assert_eq!(decoded[..19_000], samples[..19_000]);
+        assert_eq!(decoded[19_998..], [0, 0]);
+
+        assert!(matches!(
+            FlacBuilder::from_source(SliceSource::new(&[], 2, 44100, 16)).build(),
+            Err(EncoderError::NoData)
+        ));
+    }
+}
@@ -0,0 +1,970 @@
+//! Encoding audio as it arrives, for input that is never all in memory at once.
+
+use std::{
+    ffi::CStr,
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use libflac_sys::FLAC__VENDOR_STRING;
+
+use crate::{
+    analysis::LossMeter,
+    atomic, dsp,
+    hash::Hasher,
+    process_chunk,
+    raw::{write_block, RawMetadata, BLOCK_TYPE_PADDING, BLOCK_TYPE_VORBIS_COMMENT},
+    session::EncoderHandle,
+    sink::{init_sink, SinkState, Spool},
+    source::read_block,
+    AudioSource, ByteSink, EmptyInputPolicy, EncodeReport, EncodeTimings, EncoderError,
+    FlacBuilder, InputData, IntoSample, LimitKind, ProcessorContext, CHUNK_SIZE,
+};
+
+/// Encodes audio pushed to it a chunk at a time, e.g. from a live capture device, writing each
+/// frame to the sink as soon as libFLAC has it.
+///
+/// Settings are taken from a [`FlacBuilder`], but those that need the whole input up front
+/// don't apply: the silent input and dual mono policies, fade out, silence detection, peaks
+/// and channel stats. Verification still runs, but a mismatch fails the push it happened in
+/// whatever the [`VerifyFailurePolicy`](crate::VerifyFailurePolicy), as the audio before it
+/// can't be encoded again. The sample limit counts every push.
+///
+/// Nothing is buffered between libFLAC and the sink, so a sink that blocks, e.g. a
+/// [`SyncSender`](std::sync::mpsc::SyncSender), blocks the push in turn.
+pub struct FlacStreamEncoder<'data, Sample: IntoSample, S: ByteSink> {
+    // Declared first so it is dropped first; libFLAC holds pointers into the sink state and the
+    // builder's metadata.
+    encoder: EncoderHandle,
+    builder: FlacBuilder<'data, Sample>,
+    sink: Box<SinkState<S>>,
+    channels: usize,
+    /// Frames per channel pushed so far.
+    frames: usize,
+    /// Frames per channel the producer said it would push, see `expect_frames`.
+    expected_frames: Option<usize>,
+    is_paused: bool,
+    /// Length of the crossfade after a resume, in frames.
+    resume_ramp: usize,
+    /// Frames of the current crossfade done so far, `resume_ramp` when there is none.
+    ramp_done: usize,
+    /// The last frame encoded, which a resume crossfades from.
+    last_frame: Vec<i32>,
+    /// Positions in frames per channel and labels, see `mark`.
+    marks: Vec<(u64, String)>,
+    loss_meter: LossMeter,
+    start: Instant,
+}
+
+impl<'data, Sample: IntoSample, S: ByteSink> FlacStreamEncoder<'data, Sample, S> {
+    /// Writes the metadata to `sink` and gets ready for audio. `configure` receives a builder
+    /// for the stream's format to set compression, tags and so on; any input given to it is
+    /// ignored. As the length isn't known up front, STREAMINFO only gets it (and the MD5) if
+    /// the sink can [`overwrite`](ByteSink::overwrite).
+    pub fn new(
+        channels: usize,
+        sample_rate: u32,
+        sink: S,
+        configure: impl FnOnce(FlacBuilder<'data, Sample>) -> FlacBuilder<'data, Sample>,
+    ) -> Result<Self, EncoderError> {
+        let start = Instant::now();
+        let mut builder = configure(FlacBuilder::from_interleaved(&[], channels, sample_rate));
+        builder.data = InputData::Interleaved {
+            data: &[],
+            channels,
+        };
+        builder.empty_input_policy = EmptyInputPolicy::EncodeEmpty;
+
+        let mut sink = Box::new(SinkState::new(sink));
+        if !sink.sink.can_overwrite() {
+            sink.hasher = builder.output_hash.map(Hasher::new);
+        }
+        // Dropped at the first frame unless snapshots are enabled, or it may get marks.
+        sink.header_copy = Some(vec![]);
+        sink.keep_header = sink.sink.can_overwrite();
+
+        let encoder = unsafe { builder.prepare(true)? };
+        let result = unsafe { init_sink(encoder.as_ptr(), &mut sink) };
+
+        if let Some(e) = sink.error.take() {
+            return Err(EncoderError::Io(e));
+        }
+        result?;
+
+        if let Some(processor) = &mut builder.processor {
+            let tags: Vec<(String, String)> = builder
+                .vorbis_comments
+                .iter()
+                .map(|(k, v)| (k.to_string_lossy().into(), v.to_string_lossy().into()))
+                .collect();
+
+            processor.start(&ProcessorContext {
+                channels,
+                sample_rate,
+                bps: builder.bps.to_u32(),
+                total_frames: 0,
+                tags: &tags,
+            });
+        }
+
+        Ok(FlacStreamEncoder {
+            encoder,
+            builder,
+            sink,
+            channels,
+            frames: 0,
+            expected_frames: None,
+            is_paused: false,
+            resume_ramp: 0,
+            ramp_done: 0,
+            last_frame: vec![],
+            marks: vec![],
+            loss_meter: LossMeter::new(),
+            start,
+        })
+    }
+
+    /// Encodes interleaved samples, a whole number of frames.
+    pub fn push_interleaved(&mut self, samples: &[Sample]) -> Result<(), EncoderError> {
+        if self.is_paused {
+            return Ok(());
+        }
+        if !samples.len().is_multiple_of(self.channels) {
+            return Err(EncoderError::MismatchedSampleCountPerChannels);
+        }
+
+        self.push(InputData::Interleaved {
+            data: samples,
+            channels: self.channels,
+        })
+    }
+
+    /// Encodes one list of samples per channel, all the same length.
+    pub fn push_planar(&mut self, channels: &[Vec<Sample>]) -> Result<(), EncoderError> {
+        if self.is_paused {
+            return Ok(());
+        }
+        if channels.len() != self.channels {
+            return Err(EncoderError::InvalidChannelCount);
+        }
+
+        let data = InputData::Planar(channels);
+        if !data.channel_sizes_match() {
+            return Err(EncoderError::MismatchedSampleCountPerChannels);
+        }
+
+        self.push(data)
+    }
+
+    fn push(&mut self, data: InputData<'_, Sample>) -> Result<(), EncoderError> {
+        let frames = data.samples_per_channel();
+        self.check_push(frames)?;
+
+        let mut input_cursor = 0;
+
+        while input_cursor < frames {
+            let chunk = self.builder.convert_input(
+                &data,
+                input_cursor,
+                CHUNK_SIZE,
+                self.frames,
+                usize::MAX,
+                Some(&mut self.loss_meter),
+            );
+            self.encode_chunk(chunk, self.frames + input_cursor)?;
+
+            input_cursor += CHUNK_SIZE;
+        }
+
+        self.frames += frames;
+        Ok(())
+    }
+
+    /// Encodes everything `source` has left, a chunk at a time, rescaling its samples from its
+    /// own bps like an [`AudioBlock`](crate::AudioBlock)'s. A source at another sample rate
+    /// fails with [`EncoderError::SampleRateMismatch`] rather than playing at the wrong speed.
+    pub fn push_source(&mut self, mut source: impl AudioSource) -> Result<(), EncoderError> {
+        if self.is_paused {
+            return Ok(());
+        }
+        if source.channels() != self.channels {
+            return Err(EncoderError::InvalidChannelCount);
+        }
+        if source.sample_rate() != self.builder.sample_rate {
+            return Err(EncoderError::SampleRateMismatch {
+                expected: self.builder.sample_rate,
+                found: source.sample_rate(),
+            });
+        }
+
+        loop {
+            let block = read_block(&mut source, CHUNK_SIZE)?;
+            if block.samples.is_empty() {
+                return Ok(());
+            }
+
+            self.check_push(block.frames())?;
+
+            let chunk = self.builder.convert_input(
+                &InputData::Block(&block),
+                0,
+                CHUNK_SIZE,
+                self.frames,
+                usize::MAX,
+                Some(&mut self.loss_meter),
+            );
+            self.encode_chunk(chunk, self.frames)?;
+
+            self.frames += block.frames();
+        }
+    }
+
+    /// Runs the processor and any resume crossfade over a converted chunk starting at
+    /// `first_frame`, hands it to libFLAC and reports the progress.
+    fn encode_chunk(
+        &mut self,
+        mut chunk: Vec<i32>,
+        first_frame: usize,
+    ) -> Result<(), EncoderError> {
+        if let Some(processor) = &mut self.builder.processor {
+            processor.process(&mut chunk, self.channels, first_frame);
+        }
+
+        if self.ramp_done < self.resume_ramp {
+            self.ramp_done += dsp::splice_ramp(
+                &mut chunk,
+                &self.last_frame,
+                self.resume_ramp,
+                self.ramp_done,
+            );
+        }
+        if chunk.len() >= self.channels {
+            self.last_frame.clear();
+            self.last_frame
+                .extend_from_slice(&chunk[chunk.len() - self.channels..]);
+        }
+
+        let result = process_chunk(self.encoder.as_ptr(), &chunk, self.channels);
+        if let Some(e) = self.sink.error.take() {
+            return Err(EncoderError::Io(e));
+        }
+        result?;
+
+        self.builder
+            .report_progress((self.sink.samples_written, self.sink.len));
+        Ok(())
+    }
+
+    /// Frames per channel pushed so far.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Declares how many frames per channel will be pushed in all, to catch a producer that
+    /// drops or repeats buffers: a push that would go past it fails, and so does
+    /// [`finalize`](Self::finalize) if fewer arrived, both with
+    /// [`EncoderError::FrameCountMismatch`]. Every push is already checked to give each
+    /// channel the same number of samples.
+    pub fn expect_frames(&mut self, frames: usize) {
+        self.expected_frames = Some(frames);
+    }
+
+    /// Drops pushed audio until [`resume`](Self::resume), e.g. while a recorder is paused.
+    /// Dropped audio isn't counted in [`frames`](Self::frames).
+    pub fn pause(&mut self) {
+        self.is_paused = true;
+    }
+
+    /// Encodes pushed audio again after a [`pause`](Self::pause), starting with the
+    /// crossfade set by [`resume_ramp`](Self::resume_ramp).
+    pub fn resume(&mut self) {
+        if self.is_paused && !self.last_frame.is_empty() {
+            self.ramp_done = 0;
+        }
+        self.is_paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    /// Crossfades from the last frame before a pause into the audio after it over `duration`,
+    /// as butt-splicing the two is audible as a click. Off (zero) by default; 5 to 20 ms is
+    /// usually enough.
+    pub fn resume_ramp(&mut self, duration: Duration) {
+        self.resume_ramp =
+            (duration.as_secs_f64() * self.builder.sample_rate as f64).round() as usize;
+        self.ramp_done = self.resume_ramp;
+    }
+
+    /// Marks the current position, e.g. a new speaker in a live event recording, as a chapter
+    /// for players to navigate by. [`finalize`](Self::finalize) writes the marks into the
+    /// vorbis comments as `CHAPTER001=00:12:34.567` and `CHAPTER001NAME=label` pairs, so the
+    /// sink has to overwrite and the header's [`padding`](FlacBuilder::padding) has to have
+    /// room; a mark takes about 50 bytes plus its label. Fails with
+    /// [`EncoderError::NeedsOverwritableSink`] or [`EncoderError::PaddingExhausted`] straight
+    /// away rather than at the end.
+    pub fn mark(&mut self, label: &str) -> Result<(), EncoderError> {
+        let Some(header) = self
+            .sink
+            .header_copy
+            .as_ref()
+            .filter(|_| self.sink.keep_header)
+        else {
+            return Err(EncoderError::NeedsOverwritableSink("mark"));
+        };
+
+        self.marks.push((self.frames as u64, label.to_string()));
+
+        if let Err(e) = chapter_region(header, &self.marks, self.builder.sample_rate) {
+            self.marks.pop();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Keeps a copy of the encoded frames in a temporary file so [`snapshot`](Self::snapshot)
+    /// can write out the stream so far. Must be called before the first frame is encoded. The
+    /// file goes in the builder's [`temp_dir`](FlacBuilder::temp_dir) if it has one.
+    pub fn enable_snapshots(&mut self) -> Result<(), EncoderError> {
+        if self.sink.header_copy.is_none() || self.sink.samples_written > 0 {
+            return Err(EncoderError::SnapshotUnavailable);
+        }
+
+        let spool = Spool::new(self.builder.temp_dir.as_deref()).map_err(EncoderError::Io)?;
+        self.sink.spool = Some(spool);
+        Ok(())
+    }
+
+    /// Writes every frame encoded so far to `path` as a complete FLAC file, while the live
+    /// encode carries on, e.g. to archive a long broadcast as it goes. Audio libFLAC is still
+    /// holding, see [`buffered_frames`](Self::buffered_frames), isn't included. STREAMINFO
+    /// gets the length but not the MD5. The file is written under a temporary name and
+    /// renamed into place. Fails with [`EncoderError::SnapshotUnavailable`] unless
+    /// [`enable_snapshots`](Self::enable_snapshots) was called.
+    pub fn snapshot(&mut self, path: impl AsRef<Path>) -> Result<(), EncoderError> {
+        let (Some(header), Some(spool)) = (&self.sink.header_copy, &self.sink.spool) else {
+            return Err(EncoderError::SnapshotUnavailable);
+        };
+
+        // The total sample count is the low 36 bits of bytes 10 to 18 of the STREAMINFO body,
+        // which follows `fLaC` and the block header.
+        let mut header = header.clone();
+        let field = &mut header[18..26];
+        let packed = u64::from_be_bytes(field.try_into().unwrap()) & !0xf_ffff_ffff;
+        field.copy_from_slice(&(packed | self.sink.samples_written).to_be_bytes());
+
+        let path = path.as_ref();
+        let write_path = atomic::temp_path(path, self.builder.temp_dir.as_deref())?;
+
+        let write = || -> io::Result<()> {
+            let mut out = File::create(&write_path)?;
+            out.write_all(&header)?;
+            io::copy(&mut File::open(&spool.path)?, &mut out)?;
+            std::fs::rename(&write_path, path)
+        };
+
+        write().map_err(|e| {
+            let _ = std::fs::remove_file(&write_path);
+            EncoderError::Io(e)
+        })
+    }
+
+    /// Frames per channel pushed but not yet written to the sink. libFLAC only writes a FLAC
+    /// frame once it has a whole block, so this stays under the block size.
+    pub fn buffered_frames(&self) -> usize {
+        self.frames - self.frames_emitted() as usize
+    }
+
+    /// Frames per channel in the FLAC frames written to the sink so far.
+    pub fn frames_emitted(&self) -> u64 {
+        self.sink.samples_written
+    }
+
+    /// Bytes written to the sink so far. libFLAC writes whole FLAC frames, so this is always
+    /// on a frame boundary, e.g. where the host application can cut a segment or resume an
+    /// upload.
+    pub fn current_stream_offset(&self) -> u64 {
+        self.sink.len
+    }
+
+    /// Hands every complete FLAC frame encoded so far on to the consumer by flushing the
+    /// sink, e.g. for a live stream that shouldn't sit in a `BufWriter`. The partial block
+    /// libFLAC is still collecting, see [`buffered_frames`](Self::buffered_frames), can't be
+    /// cut short without ending the stream; only [`finalize`](Self::finalize) writes it.
+    pub fn flush(&mut self) -> Result<(), EncoderError> {
+        self.sink.sink.flush_written().map_err(EncoderError::Io)
+    }
+
+    /// Fails a push of `frames` more frames per channel that would go over the expected count
+    /// or the sample limit.
+    fn check_push(&self, frames: usize) -> Result<(), EncoderError> {
+        if let Some(expected) = self.expected_frames {
+            if self.frames + frames > expected {
+                return Err(EncoderError::FrameCountMismatch {
+                    expected,
+                    actual: self.frames + frames,
+                });
+            }
+        }
+
+        self.builder
+            .limits
+            .check(LimitKind::Samples, (self.frames + frames) * self.channels)
+    }
+
+    /// Encodes what libFLAC still has buffered, rewrites STREAMINFO if the sink can overwrite
+    /// and flushes the sink.
+    pub fn finalize(mut self) -> Result<EncodeReport, EncoderError> {
+        if let Some(expected) = self.expected_frames {
+            if self.frames != expected {
+                return Err(EncoderError::FrameCountMismatch {
+                    expected,
+                    actual: self.frames,
+                });
+            }
+        }
+
+        let result = self.encoder.finish();
+        if let Some(e) = self.sink.error.take() {
+            return Err(EncoderError::Io(e));
+        }
+        result?;
+
+        if let (false, Some(header)) = (self.marks.is_empty(), &self.sink.header_copy) {
+            let (offset, region) = chapter_region(header, &self.marks, self.builder.sample_rate)?;
+            self.sink
+                .sink
+                .overwrite(offset as u64, &region)
+                .map_err(EncoderError::Io)?;
+        }
+
+        self.builder
+            .report_progress((self.sink.samples_written, self.sink.len));
+
+        self.sink.sink.flush().map_err(EncoderError::Io)?;
+
+        let bps = self.builder.bps.to_u32() as usize;
+        let sample_rate = self.builder.sample_rate as f64;
+
+        Ok(EncodeReport {
+            encoded_bytes: self.sink.len as usize,
+            output_hash: self.sink.hasher.take().map(Hasher::finish),
+            conversion_loss: self.loss_meter.finish(),
+            pcm_bytes: self.frames * self.channels * bps / 8,
+            input_duration: Duration::from_secs_f64(self.frames as f64 / sample_rate),
+            encode_time: self.start.elapsed(),
+            timings: EncodeTimings {
+                first_frame: self.sink.first_frame.map(|t| t - self.start),
+                finished: self.start.elapsed(),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+}
+
+/// Rewrites the part of `header` from the vorbis comments, or the padding if there are none,
+/// to the end of the padding, with `marks` added as chapter comments and the padding shrunk
+/// to make room. Returns the offset of the part and its new bytes, which are the same length.
+fn chapter_region(
+    header: &[u8],
+    marks: &[(u64, String)],
+    sample_rate: u32,
+) -> Result<(usize, Vec<u8>), EncoderError> {
+    let metadata = RawMetadata::parse(header)?;
+
+    let mut offsets = vec![];
+    let mut cursor = metadata.blocks_offset;
+    for block in &metadata.blocks {
+        offsets.push(cursor);
+        cursor += 4 + block.data.len();
+    }
+
+    let padding = metadata
+        .blocks
+        .iter()
+        .position(|b| b.block_type == BLOCK_TYPE_PADDING);
+    // The builder always adds a padding block, if only an empty one.
+    let Some(padding) = padding else {
+        return Err(EncoderError::MalformedFlacData);
+    };
+    let comments = metadata.blocks[..padding]
+        .iter()
+        .position(|b| b.block_type == BLOCK_TYPE_VORBIS_COMMENT);
+
+    let (first, mut vorbis_comment) = match comments {
+        Some(i) => (i, metadata.blocks[i].data.to_vec()),
+        None => {
+            // Written before the padding, with libFLAC's vendor string as it would have.
+            let vendor = unsafe { CStr::from_ptr(FLAC__VENDOR_STRING) }.to_bytes();
+            let mut data = (vendor.len() as u32).to_le_bytes().to_vec();
+            data.extend(vendor);
+            data.extend(0u32.to_le_bytes());
+            (padding, data)
+        }
+    };
+
+    // The comment count follows the vendor string.
+    let count_at = 4 + u32::from_le_bytes(vorbis_comment[..4].try_into().unwrap()) as usize;
+    let Some(count) = vorbis_comment.get_mut(count_at..count_at + 4) else {
+        return Err(EncoderError::MalformedFlacData);
+    };
+    let total = u32::from_le_bytes((&*count).try_into().unwrap()) + 2 * marks.len() as u32;
+    count.copy_from_slice(&total.to_le_bytes());
+
+    for (i, (position, label)) in marks.iter().enumerate() {
+        let ms = position * 1000 / sample_rate as u64;
+        let time = format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            ms / 3_600_000,
+            ms / 60_000 % 60,
+            ms / 1000 % 60,
+            ms % 1000
+        );
+
+        for entry in [
+            format!("CHAPTER{:03}={time}", i + 1),
+            format!("CHAPTER{:03}NAME={label}", i + 1),
+        ] {
+            vorbis_comment.extend((entry.len() as u32).to_le_bytes());
+            vorbis_comment.extend(entry.as_bytes());
+        }
+    }
+
+    let mut region = vec![];
+    write_block(
+        &mut region,
+        BLOCK_TYPE_VORBIS_COMMENT,
+        &vorbis_comment,
+        false,
+    );
+    let after_comments = first + comments.is_some() as usize;
+    for block in &metadata.blocks[after_comments..padding] {
+        write_block(&mut region, block.block_type, block.data, false);
+    }
+
+    let start = offsets[first];
+    let padding_len = metadata.blocks[padding].data.len();
+    let old_len = offsets[padding] - start;
+    // The padding's own header is kept, so it can only give up its body.
+    let Some(new_padding) = (old_len + padding_len).checked_sub(region.len()) else {
+        return Err(EncoderError::PaddingExhausted {
+            needed: region.len() - old_len,
+            available: padding_len,
+        });
+    };
+
+    let is_last = padding == metadata.blocks.len() - 1;
+    write_block(
+        &mut region,
+        BLOCK_TYPE_PADDING,
+        &vec![0; new_padding],
+        is_last,
+    );
+
+    Ok((start, region))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::BufWriter, time::Duration};
+
+    use super::*;
+    use crate::{FlacDecoder, Streamed, WavReader};
+
+    fn sine(frames: usize) -> Vec<f32> {
+        (0..frames * 2)
+            .map(|i| ((i / 2) as f32 * 0.01).sin() * 0.5)
+            .collect()
+    }
+
+    fn decode(bytes: &[u8]) -> Vec<i32> {
+        let mut samples = vec![0; 1 << 16];
+        let n = FlacDecoder::new(bytes).unwrap().fill(&mut samples).unwrap();
+        samples.truncate(n);
+        samples
+    }
+
+    #[test]
+    fn pushes_in_chunks_decode_like_one_encode() {
+        let samples = sine(10_000);
+        let mut streamed = vec![];
+        let mut encoder =
+            FlacStreamEncoder::new(2, 44100, &mut streamed, |builder| builder).unwrap();
+        for chunk in samples.chunks(2 * 777) {
+            encoder.push_interleaved(chunk).unwrap();
+        }
+        assert_eq!(encoder.frames(), 10_000);
+        let report = encoder.finalize().unwrap();
+        assert_eq!(report.encoded_bytes, streamed.len());
+
+        let whole = FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .build()
+            .unwrap();
+
+        assert_eq!(decode(&streamed), decode(&whole));
+    }
+
+    #[test]
+    fn planar_pushes_decode_like_interleaved_ones() {
+        let samples = sine(5_000);
+        let planar: Vec<Vec<f32>> = (0..2)
+            .map(|channel| samples.iter().skip(channel).step_by(2).copied().collect())
+            .collect();
+
+        let mut interleaved = vec![];
+        let mut encoder =
+            FlacStreamEncoder::new(2, 44100, &mut interleaved, |builder| builder).unwrap();
+        encoder.push_interleaved(&samples).unwrap();
+        encoder.finalize().unwrap();
+
+        let mut streamed = vec![];
+        let mut encoder =
+            FlacStreamEncoder::new(2, 44100, &mut streamed, |builder| builder).unwrap();
+        encoder.push_planar(&planar).unwrap();
+        assert!(matches!(
+            encoder.push_planar(&planar[..1]),
+            Err(EncoderError::InvalidChannelCount)
+        ));
+        assert!(matches!(
+            encoder.push_planar(&[vec![0.0; 3], vec![0.0; 2]]),
+            Err(EncoderError::MismatchedSampleCountPerChannels)
+        ));
+        assert_eq!(encoder.frames(), 5_000);
+        encoder.finalize().unwrap();
+
+        assert_eq!(decode(&streamed), decode(&interleaved));
+    }
+
+    #[test]
+    fn progress_is_reported_for_every_push() {
+        let mut calls = vec![];
+        let mut streamed = vec![];
+        let mut encoder = FlacStreamEncoder::new(2, 44100, &mut streamed, |builder| {
+            builder.on_progress(|samples, total, bytes| calls.push((samples, total, bytes)))
+        })
+        .unwrap();
+        encoder.push_interleaved(&sine(10_000)).unwrap();
+        encoder
+            .push_source(WavReader::new(&wav(44100)[..]).unwrap())
+            .unwrap();
+        encoder.finalize().unwrap();
+
+        assert!(calls.len() > 3);
+        assert!(calls
+            .windows(2)
+            .all(|w| w[0].0 <= w[1].0 && w[0].2 <= w[1].2));
+        assert!(calls.iter().all(|&(_, total, _)| total == 0));
+        let &(samples, _, bytes) = calls.last().unwrap();
+        assert_eq!(samples, 10_000 + 5000);
+        assert_eq!(bytes as usize, streamed.len());
+    }
+
+    #[test]
+    fn flush_hands_over_complete_frames() {
+        let writer = Streamed(BufWriter::with_capacity(1 << 20, vec![]));
+        let mut encoder = FlacStreamEncoder::new(2, 44100, writer, |builder| builder).unwrap();
+
+        encoder.push_interleaved(&sine(10_000)).unwrap();
+        assert_eq!(encoder.buffered_frames(), 10_000 % 4096);
+        assert!(encoder.sink.sink.0.get_ref().is_empty());
+
+        encoder.flush().unwrap();
+        assert!(!encoder.sink.sink.0.get_ref().is_empty());
+    }
+
+    #[test]
+    fn offsets_land_on_frame_boundaries() {
+        let mut streamed = vec![];
+        let mut encoder =
+            FlacStreamEncoder::new(2, 44100, &mut streamed, |builder| builder).unwrap();
+        let header = encoder.current_stream_offset();
+        assert_eq!(encoder.frames_emitted(), 0);
+
+        encoder.push_interleaved(&sine(10_000)).unwrap();
+        assert_eq!(encoder.frames_emitted(), 2 * 4096);
+        let offset = encoder.current_stream_offset() as usize;
+        assert!(offset > header as usize);
+        encoder.finalize().unwrap();
+
+        assert!(crate::frames(&streamed)
+            .unwrap()
+            .any(|frame| frame.unwrap().offset == offset));
+    }
+
+    #[test]
+    fn rejects_partial_frames() {
+        let mut encoder = FlacStreamEncoder::new(2, 44100, vec![], |builder| builder).unwrap();
+
+        assert!(matches!(
+            encoder.push_interleaved(&[0.0f32; 3]),
+            Err(EncoderError::MismatchedSampleCountPerChannels)
+        ));
+    }
+
+    fn wav(sample_rate: u32) -> Vec<u8> {
+        let samples = sine(5000);
+        let flac = FlacBuilder::from_interleaved(&samples, 2, sample_rate)
+            .build()
+            .unwrap();
+
+        let mut wav = vec![];
+        FlacDecoder::new(&flac[..])
+            .unwrap()
+            .write_wav(&mut wav)
+            .unwrap();
+        wav
+    }
+
+    #[test]
+    fn push_source_rescales_to_the_stream_bps() {
+        let wav = wav(44100);
+        let mut streamed = vec![];
+        let mut encoder =
+            FlacStreamEncoder::new(2, 44100, &mut streamed, |builder: FlacBuilder<f32>| {
+                builder.bps(crate::BpsLevel::Bps24)
+            })
+            .unwrap();
+        encoder
+            .push_source(WavReader::new(&wav[..]).unwrap())
+            .unwrap();
+        assert_eq!(encoder.frames(), 5000);
+        encoder.finalize().unwrap();
+
+        let mut expected = vec![0; 1 << 16];
+        let n = WavReader::new(&wav[..])
+            .unwrap()
+            .fill(&mut expected)
+            .unwrap();
+        expected.truncate(n);
+        let expected: Vec<i32> = expected.iter().map(|sample| sample << 8).collect();
+        assert_eq!(decode(&streamed), expected);
+    }
+
+    #[test]
+    fn push_source_refuses_another_sample_rate() {
+        let wav = wav(48000);
+        let mut encoder =
+            FlacStreamEncoder::new(2, 44100, vec![], |builder: FlacBuilder<f32>| builder).unwrap();
+
+        assert!(matches!(
+            encoder.push_source(WavReader::new(&wav[..]).unwrap()),
+            Err(EncoderError::SampleRateMismatch {
+                expected: 44100,
+                found: 48000
+            })
+        ));
+    }
+
+    #[test]
+    fn a_full_channel_holds_the_producer_back() {
+        use std::{
+            sync::{
+                atomic::{AtomicBool, Ordering},
+                mpsc::sync_channel,
+            },
+            thread,
+            time::Duration,
+        };
+
+        let samples = sine(10_000);
+        let (sender, receiver) = sync_channel(1);
+        let done = AtomicBool::new(false);
+
+        let received = thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut encoder =
+                    FlacStreamEncoder::new(2, 44100, sender, |builder| builder).unwrap();
+                encoder.push_interleaved(&samples).unwrap();
+                encoder.finalize().unwrap();
+                done.store(true, Ordering::SeqCst);
+            });
+
+            thread::sleep(Duration::from_millis(50));
+            assert!(!done.load(Ordering::SeqCst));
+
+            receiver.iter().flatten().collect::<Vec<u8>>()
+        });
+
+        assert!(done.load(Ordering::SeqCst));
+        let whole = FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .build()
+            .unwrap();
+        assert_eq!(decode(&received), decode(&whole));
+    }
+
+    #[test]
+    fn fade_in_spans_pushes_and_fade_out_is_ignored() {
+        let mut streamed = vec![];
+        let mut encoder = FlacStreamEncoder::new(1, 1000, &mut streamed, |builder| {
+            builder
+                .fade_in(Duration::from_millis(100))
+                .fade_out(Duration::from_millis(100))
+        })
+        .unwrap();
+        for _ in 0..10 {
+            encoder.push_interleaved(&[10_000i16; 30]).unwrap();
+        }
+        encoder.finalize().unwrap();
+        let decoded = decode(&streamed);
+
+        let expected: Vec<i32> = (0..300).map(|i| 100 * i.min(100)).collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn a_resume_crossfades_from_the_last_frame() {
+        let mut streamed = vec![];
+        let mut encoder =
+            FlacStreamEncoder::new(1, 1000, &mut streamed, |builder| builder).unwrap();
+        encoder.resume_ramp(Duration::from_millis(4));
+
+        encoder.push_interleaved(&[1000i16; 20]).unwrap();
+        encoder.pause();
+        assert!(encoder.is_paused());
+        encoder.push_interleaved(&[5; 20]).unwrap();
+        encoder.resume();
+        encoder.push_interleaved(&[-1000; 20]).unwrap();
+        assert_eq!(encoder.frames(), 40);
+        encoder.finalize().unwrap();
+
+        let mut expected = vec![1000; 20];
+        expected.extend([600, 200, -200, -600]);
+        expected.extend([-1000; 16]);
+        assert_eq!(decode(&streamed), expected);
+    }
+
+    #[test]
+    fn snapshots_hold_the_frames_written_so_far() {
+        let path = std::env::temp_dir().join(format!("snapshot-{}.flac", std::process::id()));
+        let samples = sine(10_000);
+        let mut streamed = vec![];
+        let mut encoder =
+            FlacStreamEncoder::new(2, 44100, &mut streamed, |builder| builder).unwrap();
+        assert!(matches!(
+            encoder.snapshot(&path),
+            Err(EncoderError::SnapshotUnavailable)
+        ));
+        encoder.enable_snapshots().unwrap();
+
+        encoder.push_interleaved(&samples).unwrap();
+        assert!(matches!(
+            encoder.enable_snapshots(),
+            Err(EncoderError::SnapshotUnavailable)
+        ));
+        encoder.snapshot(&path).unwrap();
+        encoder.push_interleaved(&samples).unwrap();
+        encoder.finalize().unwrap();
+
+        let snapshot = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let written = 2 * 4096;
+        let decoder = FlacDecoder::new(&snapshot[..]).unwrap();
+        assert_eq!(decoder.len_hint(), Some(written));
+        assert_eq!(decode(&snapshot), decode(&streamed)[..written * 2]);
+    }
+
+    #[test]
+    fn snapshots_spool_in_the_builders_temp_dir() {
+        let dir = std::env::temp_dir().join(format!("snapshot-dir-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let spooled = |dir: &std::path::Path| {
+            std::fs::read_dir(dir)
+                .unwrap()
+                .filter(|entry| {
+                    let name = entry.as_ref().unwrap().file_name();
+                    name.to_string_lossy().ends_with(".spool")
+                })
+                .count()
+        };
+
+        let mut encoder =
+            FlacStreamEncoder::new(2, 44100, vec![], |builder| builder.temp_dir(&dir)).unwrap();
+        encoder.enable_snapshots().unwrap();
+        encoder.push_interleaved(&sine(10_000)).unwrap();
+        assert_eq!(spooled(&dir), 1);
+
+        let path = dir.join("snapshot.flac");
+        encoder.snapshot(&path).unwrap();
+        encoder.finalize().unwrap();
+        assert_eq!(spooled(&dir), 0);
+        assert!(path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn marks_become_chapter_comments() {
+        let samples = vec![0.25f32; 1000];
+        let mut streamed = vec![];
+        let mut encoder = FlacStreamEncoder::new(1, 1000, &mut streamed, |builder| {
+            builder.title("Live").padding(200)
+        })
+        .unwrap();
+        encoder.mark("Intro").unwrap();
+        encoder.push_interleaved(&samples).unwrap();
+        encoder.push_interleaved(&samples[..500]).unwrap();
+        encoder.mark("Talk").unwrap();
+        encoder.push_interleaved(&samples).unwrap();
+        assert!(matches!(
+            encoder.mark(&"x".repeat(200)),
+            Err(EncoderError::PaddingExhausted { .. })
+        ));
+        encoder.finalize().unwrap();
+
+        let comments = crate::read_comments(&streamed).unwrap();
+        let expected = [
+            ("TITLE", "Live"),
+            ("CHAPTER001", "00:00:00.000"),
+            ("CHAPTER001NAME", "Intro"),
+            ("CHAPTER002", "00:00:01.500"),
+            ("CHAPTER002NAME", "Talk"),
+        ];
+        assert_eq!(
+            comments,
+            expected.map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+        assert_eq!(decode(&streamed).len(), 2500);
+
+        let mut encoder =
+            FlacStreamEncoder::new(1, 1000, Streamed(vec![]), |builder: FlacBuilder<f32>| {
+                builder
+            })
+            .unwrap();
+        assert!(matches!(
+            encoder.mark("Intro"),
+            Err(EncoderError::NeedsOverwritableSink("mark"))
+        ));
+    }
+
+    #[test]
+    fn expect_frames_catches_dropped_and_repeated_buffers() {
+        let buffer = sine(1000);
+
+        let mut encoder = FlacStreamEncoder::new(2, 44100, vec![], |builder| builder).unwrap();
+        encoder.expect_frames(2000);
+        encoder.push_interleaved(&buffer).unwrap();
+        assert!(matches!(
+            encoder.finalize(),
+            Err(EncoderError::FrameCountMismatch {
+                expected: 2000,
+                actual: 1000
+            })
+        ));
+
+        let mut encoder = FlacStreamEncoder::new(2, 44100, vec![], |builder| builder).unwrap();
+        encoder.expect_frames(1500);
+        encoder.push_interleaved(&buffer).unwrap();
+        assert!(matches!(
+            encoder.push_interleaved(&buffer),
+            Err(EncoderError::FrameCountMismatch {
+                expected: 1500,
+                actual: 2000
+            })
+        ));
+        assert_eq!(encoder.frames(), 1000);
+    }
+}
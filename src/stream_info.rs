@@ -0,0 +1,195 @@
+//! The STREAMINFO block every FLAC stream starts with.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{raw::skip_id3v2, EncoderError};
+
+/// Length of the STREAMINFO block body.
+const STREAMINFO_LENGTH: usize = 34;
+
+/// The format and summary of a FLAC stream, as stored in its STREAMINFO block. Fields libFLAC
+/// couldn't fill in, e.g. when writing to a pipe, are zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct StreamInfo {
+    /// Smallest block size in samples per channel, excluding the last block.
+    pub min_block_size: u16,
+    pub max_block_size: u16,
+    /// Smallest frame in bytes, `0` if unknown.
+    pub min_frame_size: u32,
+    /// Largest frame in bytes, `0` if unknown.
+    pub max_frame_size: u32,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub bps: u32,
+    /// Samples per channel, `0` if unknown.
+    pub total_samples: u64,
+    /// MD5 of the decoded audio, all zero if unknown.
+    pub md5: [u8; 16],
+}
+
+impl StreamInfo {
+    /// From an in-memory FLAC stream. Only the start of it is needed.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, EncoderError> {
+        let offset = skip_id3v2(bytes);
+        let end = offset + 8 + STREAMINFO_LENGTH;
+
+        match bytes.get(offset..end) {
+            Some(header) => Self::from_header(header),
+            None => Err(EncoderError::MalformedFlacData),
+        }
+    }
+
+    /// From a FLAC file, reading only as far as the STREAMINFO block.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, EncoderError> {
+        let mut file = File::open(path).map_err(EncoderError::Io)?;
+
+        let mut id3 = [0; 10];
+        let probed = read_up_to(&mut file, &mut id3).map_err(EncoderError::Io)?;
+        let offset = skip_id3v2(&id3[..probed]);
+
+        let mut header = [0; 8 + STREAMINFO_LENGTH];
+        file.seek(SeekFrom::Start(offset as u64))
+            .map_err(EncoderError::Io)?;
+        if read_up_to(&mut file, &mut header).map_err(EncoderError::Io)? < header.len() {
+            return Err(EncoderError::MalformedFlacData);
+        }
+
+        Self::from_header(&header)
+    }
+
+    /// `header` is the `fLaC` marker followed by the STREAMINFO block with its header.
+    fn from_header(header: &[u8]) -> Result<Self, EncoderError> {
+        let block_type = header[4] & 0x7f;
+        let length = u32::from_be_bytes([0, header[5], header[6], header[7]]) as usize;
+
+        if &header[..4] != b"fLaC" || block_type != 0 || length < STREAMINFO_LENGTH {
+            return Err(EncoderError::MalformedFlacData);
+        }
+
+        Ok(Self::parse(&header[8..]))
+    }
+
+    /// From the body of a STREAMINFO block, which must be at least 34 bytes.
+    pub(crate) fn parse(data: &[u8]) -> Self {
+        let u24 = |i: usize| u32::from_be_bytes([0, data[i], data[i + 1], data[i + 2]]);
+        // Sample rate (20 bits), channels - 1 (3), bps - 1 (5) and total samples (36).
+        let packed = u64::from_be_bytes(data[10..18].try_into().unwrap());
+
+        StreamInfo {
+            min_block_size: u16::from_be_bytes([data[0], data[1]]),
+            max_block_size: u16::from_be_bytes([data[2], data[3]]),
+            min_frame_size: u24(4),
+            max_frame_size: u24(7),
+            sample_rate: (packed >> 44) as u32,
+            channels: ((packed >> 41) & 0x07) as u32 + 1,
+            bps: ((packed >> 36) & 0x1f) as u32 + 1,
+            total_samples: packed & 0xf_ffff_ffff,
+            md5: data[18..34].try_into().unwrap(),
+        }
+    }
+
+    /// Length of the audio, `None` if the total sample count is unknown.
+    pub fn duration(&self) -> Option<Duration> {
+        if self.total_samples == 0 || self.sample_rate == 0 {
+            return None;
+        }
+
+        Some(Duration::from_secs_f64(
+            self.total_samples as f64 / self.sample_rate as f64,
+        ))
+    }
+
+    /// Whether the MD5 of the audio was filled in.
+    pub fn has_md5(&self) -> bool {
+        self.md5 != [0; 16]
+    }
+}
+
+/// Like `read_exact` but stops early at the end of the file, returning how much was read.
+fn read_up_to(file: &mut File, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BpsLevel, FlacBuilder, FlacStreamEncoder, Streamed};
+
+    fn samples() -> Vec<f32> {
+        (0..88_200).map(|i| (i as f32 * 0.01).sin() * 0.5).collect()
+    }
+
+    #[test]
+    fn reads_what_the_encoder_wrote() {
+        let bytes = FlacBuilder::from_interleaved(&samples(), 2, 44100)
+            .bps(BpsLevel::Bps24)
+            .build()
+            .unwrap();
+        let info = StreamInfo::from_bytes(&bytes).unwrap();
+
+        assert_eq!(
+            (
+                info.sample_rate,
+                info.channels,
+                info.bps,
+                info.total_samples
+            ),
+            (44100, 2, 24, 44100)
+        );
+        assert_eq!(info.duration(), Some(Duration::from_secs(1)));
+        assert_eq!(info.max_block_size, 4096);
+        assert!(info.min_frame_size > 0 && info.min_frame_size <= info.max_frame_size);
+        assert!(info.has_md5());
+
+        let path = std::env::temp_dir().join(format!("stream-info-{}.flac", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let from_file = StreamInfo::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(from_file.unwrap(), info);
+    }
+
+    #[test]
+    fn unknown_fields_of_a_streamed_encode_are_zero() {
+        let mut streamed = vec![];
+        let mut encoder = FlacStreamEncoder::new(
+            2,
+            44100,
+            Streamed(&mut streamed),
+            |builder: FlacBuilder<f32>| builder,
+        )
+        .unwrap();
+        encoder.push_interleaved(&samples()).unwrap();
+        encoder.finalize().unwrap();
+        let info = StreamInfo::from_bytes(&streamed).unwrap();
+
+        assert_eq!(info.total_samples, 0);
+        assert_eq!(info.duration(), None);
+        assert!(!info.has_md5());
+    }
+
+    #[test]
+    fn rejects_what_isnt_flac() {
+        assert!(matches!(
+            StreamInfo::from_bytes(b"RIFF"),
+            Err(EncoderError::MalformedFlacData)
+        ));
+        assert!(matches!(
+            StreamInfo::from_bytes(&[b'f', b'L', b'a', b'C', 4, 0, 0, 34].repeat(6)),
+            Err(EncoderError::MalformedFlacData)
+        ));
+    }
+}
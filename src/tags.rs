@@ -0,0 +1,565 @@
+//! Helpers for working with vorbis comments outside of the builder.
+
+use std::{collections::HashMap, fmt, sync::Arc};
+
+/// Tags keyed by upper-cased field name, with every value for that field in order. This is a
+/// plain `HashMap` so it can be shared with other tagging code and (de)serialized with serde
+/// as-is.
+pub type TagMap = HashMap<String, Vec<String>>;
+
+/// Groups `(key, value)` comments, e.g. from [`read_comments`](crate::read_comments), into a
+/// [`TagMap`]. Field names are case-insensitive in vorbis comments so keys are upper-cased.
+pub fn comments_to_map<K: AsRef<str>, V: AsRef<str>>(comments: &[(K, V)]) -> TagMap {
+    let mut map = TagMap::new();
+
+    for (key, value) in comments {
+        map.entry(key.as_ref().to_ascii_uppercase())
+            .or_default()
+            .push(value.as_ref().to_string());
+    }
+
+    map
+}
+
+/// Flattens a [`TagMap`] back into `(key, value)` comments. Keys are sorted so the output is
+/// deterministic; values keep their order within a key.
+pub fn map_to_comments(map: &TagMap) -> Vec<(String, String)> {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+
+    keys.into_iter()
+        .flat_map(|key| {
+            map[key]
+                .iter()
+                .map(move |value| (key.clone(), value.clone()))
+        })
+        .collect()
+}
+
+/// Rules that the comments set on a builder are checked against before encoding, see
+/// [`FlacBuilder::tag_profile`](crate::FlacBuilder::tag_profile). Every profile also requires
+/// field names that are valid per the vorbis comment spec.
+#[derive(Debug, Clone)]
+pub enum TagProfile {
+    /// Only requires `TITLE` and `ARTIST`.
+    Minimal,
+    /// What MusicBrainz Picard writes and expects: `TITLE`, `ARTIST` and `ALBUM` present,
+    /// `DATE` as `YYYY`, `YYYY-MM`, `YYYY-MM-DD` or an ISO 8601 date and time like
+    /// [`recorded_at`](crate::FlacBuilder::recorded_at) writes, `TRACKNUMBER`/`DISCNUMBER` as
+    /// `n` or `n/m` and MusicBrainz IDs as UUIDs.
+    Picard,
+    /// Only checks field names, which must be non-empty printable ASCII without `=`.
+    StrictVorbis,
+    /// Rejects any field not in the list, compared case-insensitively, e.g.
+    /// [`STANDARD_VORBIS_FIELDS`] or a team's own tagging policy read from a config file. See
+    /// [`allowed`](Self::allowed).
+    Allowed(Arc<[String]>),
+    /// Your own checks, run after the field name check.
+    Custom(fn(&[(String, String)]) -> Vec<TagIssue>),
+}
+
+/// The field names proposed by the
+/// [vorbis comment spec](https://xiph.org/vorbis/doc/v-comment.html), for
+/// [`TagProfile::Allowed`].
+pub const STANDARD_VORBIS_FIELDS: &[&str] = &[
+    "TITLE",
+    "VERSION",
+    "ALBUM",
+    "TRACKNUMBER",
+    "ARTIST",
+    "PERFORMER",
+    "COPYRIGHT",
+    "LICENSE",
+    "ORGANIZATION",
+    "DESCRIPTION",
+    "GENRE",
+    "DATE",
+    "LOCATION",
+    "CONTACT",
+    "ISRC",
+];
+
+/// A problem found by a [`TagProfile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagIssue {
+    pub field: String,
+    pub problem: TagProblem,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagProblem {
+    Missing,
+    InvalidFieldName,
+    InvalidValue,
+    /// The field isn't in the profile's list of allowed fields.
+    NotAllowed,
+}
+
+impl fmt::Display for TagIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let problem = match self.problem {
+            TagProblem::Missing => "is missing",
+            TagProblem::InvalidFieldName => "isn't a valid field name",
+            TagProblem::InvalidValue => "has an invalid value",
+            TagProblem::NotAllowed => "isn't allowed",
+        };
+
+        write!(f, "{} {problem}", self.field)
+    }
+}
+
+impl TagProfile {
+    /// [`TagProfile::Allowed`] with the given field names.
+    pub fn allowed<T: Into<String>>(fields: impl IntoIterator<Item = T>) -> Self {
+        TagProfile::Allowed(fields.into_iter().map(Into::into).collect())
+    }
+
+    /// Every issue with `comments` under this profile; empty if they pass.
+    pub fn check<K: AsRef<str>, V: AsRef<str>>(&self, comments: &[(K, V)]) -> Vec<TagIssue> {
+        let comments: Vec<(String, String)> = comments
+            .iter()
+            .map(|(k, v)| (k.as_ref().to_string(), v.as_ref().to_string()))
+            .collect();
+
+        let mut issues: Vec<TagIssue> = comments
+            .iter()
+            .filter(|(key, _)| !is_valid_field_name(key))
+            .map(|(key, _)| issue(key, TagProblem::InvalidFieldName))
+            .collect();
+
+        match self {
+            TagProfile::Minimal => {
+                issues.extend(missing(&comments, &["TITLE", "ARTIST"]));
+            }
+            TagProfile::Picard => {
+                issues.extend(missing(&comments, &["TITLE", "ARTIST", "ALBUM"]));
+
+                for (key, value) in &comments {
+                    let field = key.to_ascii_uppercase();
+
+                    let valid = match field.as_str() {
+                        "DATE" => is_date(value),
+                        "TRACKNUMBER" | "DISCNUMBER" => is_position(value),
+                        // The disc ID is a hash, not a UUID.
+                        "MUSICBRAINZ_DISCID" => true,
+                        _ if field.starts_with("MUSICBRAINZ_") && field.ends_with("ID") => {
+                            is_uuid(value)
+                        }
+                        _ => true,
+                    };
+
+                    if !valid {
+                        issues.push(issue(key, TagProblem::InvalidValue));
+                    }
+                }
+            }
+            TagProfile::StrictVorbis => {}
+            TagProfile::Allowed(fields) => {
+                issues.extend(
+                    comments
+                        .iter()
+                        .filter(|(key, _)| !fields.iter().any(|f| key.eq_ignore_ascii_case(f)))
+                        .map(|(key, _)| issue(key, TagProblem::NotAllowed)),
+                );
+            }
+            TagProfile::Custom(check) => issues.extend(check(&comments)),
+        }
+
+        issues
+    }
+}
+
+/// Rewrites the value of a numeric field (`TRACKNUMBER`, `TRACKTOTAL`, `DISCNUMBER`,
+/// `DISCTOTAL`, `DATE` and `BPM`) into the plain ASCII form other software parses, whatever
+/// locale produced it: digits from other scripts become `0`-`9`, decimal separators in `BPM`
+/// become `.`, and fullwidth or Unicode slashes and dashes become `/` and `-`. Other fields are
+/// returned as they are. Fails with [`TagProblem::InvalidValue`] if the result still isn't a
+/// valid value for the field, e.g. a `DATE` that isn't `YYYY[-MM[-DD]]` or a date and time
+/// such as `2024-05-01T09:30:00+02:00`. Spaces are only dropped from the other fields, so a
+/// `DATE` keeps the one between date and time.
+pub fn normalize_numeric_tag(field: &str, value: &str) -> Result<String, TagProblem> {
+    let field = field.to_ascii_uppercase();
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+    let normalized: String = match field.as_str() {
+        "TRACKNUMBER" | "TRACKTOTAL" | "DISCNUMBER" | "DISCTOTAL" | "DATE" | "BPM" => value
+            .trim()
+            .chars()
+            .filter_map(|c| match c {
+                // Grouping separators and spaces, e.g. from "1 000".
+                '\u{066c}' | '\u{00a0}' | '\u{202f}' | ' ' if field != "DATE" => None,
+                '\u{066b}' | ',' | '\u{ff0e}' if field == "BPM" => Some('.'),
+                '\u{ff0f}' | '\u{2215}' | '\u{2044}' => Some('/'),
+                '\u{2010}'..='\u{2015}' | '\u{2212}' | '\u{ff0d}' => Some('-'),
+                c => Some(ascii_digit(c).map_or(c, char::from)),
+            })
+            .collect(),
+        _ => return Ok(value.to_string()),
+    };
+
+    let valid = match field.as_str() {
+        "DATE" => is_date(&normalized),
+        "TRACKNUMBER" | "DISCNUMBER" => is_position(&normalized),
+        "BPM" => match normalized.split_once('.') {
+            Some((whole, fraction)) => is_number(whole) && is_number(fraction),
+            None => is_number(&normalized),
+        },
+        _ => is_number(&normalized),
+    };
+
+    if valid {
+        Ok(normalized)
+    } else {
+        Err(TagProblem::InvalidValue)
+    }
+}
+
+/// The ASCII digit for a decimal digit from any of the common scripts, e.g. Arabic-Indic `٣`.
+fn ascii_digit(c: char) -> Option<u8> {
+    /// Code point of the zero of each script's run of ten digits.
+    const ZEROS: [u32; 20] = [
+        0x0030, 0x0660, 0x06f0, 0x07c0, 0x0966, 0x09e6, 0x0a66, 0x0ae6, 0x0b66, 0x0be6, 0x0c66,
+        0x0ce6, 0x0d66, 0x0e50, 0x0ed0, 0x0f20, 0x1040, 0x17e0, 0x1810, 0xff10,
+    ];
+
+    let c = c as u32;
+    ZEROS
+        .iter()
+        .find(|&&zero| (zero..zero + 10).contains(&c))
+        .map(|zero| b'0' + (c - zero) as u8)
+}
+
+fn issue(field: &str, problem: TagProblem) -> TagIssue {
+    TagIssue {
+        field: field.to_string(),
+        problem,
+    }
+}
+
+fn missing<'a>(
+    comments: &'a [(String, String)],
+    required: &'a [&str],
+) -> impl Iterator<Item = TagIssue> + 'a {
+    required
+        .iter()
+        .filter(|field| !comments.iter().any(|(k, _)| k.eq_ignore_ascii_case(field)))
+        .map(|field| issue(field, TagProblem::Missing))
+}
+
+/// Vorbis comment field names are ASCII 0x20 to 0x7D excluding `=`.
+fn is_valid_field_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| (0x20..=0x7d).contains(&b) && b != b'=')
+}
+
+fn is_digits(s: &str, len: usize) -> bool {
+    s.len() == len && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// `YYYY`, `YYYY-MM`, `YYYY-MM-DD`, or an ISO 8601 date and time such as the RFC 3339 written
+/// by `recorded_at`.
+fn is_date(value: &str) -> bool {
+    let (date, time) = match value.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    let parts: Vec<&str> = date.split('-').collect();
+
+    match (parts.as_slice(), time) {
+        ([year], None) => is_digits(year, 4),
+        ([year, month], None) => is_digits(year, 4) && is_digits(month, 2),
+        ([year, month, day], time) => {
+            is_digits(year, 4)
+                && is_digits(month, 2)
+                && is_digits(day, 2)
+                && time.is_none_or(is_time)
+        }
+        _ => false,
+    }
+}
+
+/// `hh:mm`, `hh:mm:ss` or `hh:mm:ss.fff`, optionally followed by `Z` or a `+hh:mm`/`-hh:mm`
+/// offset.
+fn is_time(value: &str) -> bool {
+    let (time, offset) = match value.strip_suffix('Z') {
+        Some(time) => (time, None),
+        None => match value.rfind(['+', '-']) {
+            Some(i) => (&value[..i], Some(&value[i + 1..])),
+            None => (value, None),
+        },
+    };
+
+    let is_offset = |offset: &str| match offset.split_once(':') {
+        Some((hours, minutes)) => is_digits(hours, 2) && is_digits(minutes, 2),
+        None => false,
+    };
+    let is_seconds = |seconds: &str| match seconds.split_once('.') {
+        Some((whole, fraction)) => {
+            is_digits(whole, 2) && !fraction.is_empty() && is_digits(fraction, fraction.len())
+        }
+        None => is_digits(seconds, 2),
+    };
+
+    let parts: Vec<&str> = time.split(':').collect();
+    let valid_time = match parts.as_slice() {
+        [hours, minutes] => is_digits(hours, 2) && is_digits(minutes, 2),
+        [hours, minutes, seconds] => {
+            is_digits(hours, 2) && is_digits(minutes, 2) && is_seconds(seconds)
+        }
+        _ => false,
+    };
+
+    valid_time && offset.is_none_or(is_offset)
+}
+
+fn is_position(value: &str) -> bool {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+    match value.split_once('/') {
+        Some((n, total)) => is_number(n) && is_number(total),
+        None => is_number(value),
+    }
+}
+
+fn is_uuid(value: &str) -> bool {
+    value.len() == 36
+        && value.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{read_comments, EncoderError, FlacBuilder};
+
+    fn comments(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn map_groups_fields_case_insensitively() {
+        let map = comments_to_map(&[("Artist", "A"), ("TITLE", "Song"), ("artist", "B")]);
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["ARTIST"], ["A", "B"]);
+        assert_eq!(map["TITLE"], ["Song"]);
+    }
+
+    #[test]
+    fn comments_come_back_sorted_by_key() {
+        let map = comments_to_map(&[("TITLE", "Song"), ("ARTIST", "A"), ("ARTIST", "B")]);
+
+        assert_eq!(
+            map_to_comments(&map),
+            comments(&[("ARTIST", "A"), ("ARTIST", "B"), ("TITLE", "Song")])
+        );
+    }
+
+    #[test]
+    fn builder_writes_every_value() {
+        let map = comments_to_map(&[("GENRE", "Jazz"), ("GENRE", "Funk")]);
+        let bytes = FlacBuilder::from_interleaved(&[0.0f32; 1024], 1, 44100)
+            .tags(&map)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            read_comments(&bytes).unwrap(),
+            comments(&[("GENRE", "Jazz"), ("GENRE", "Funk")])
+        );
+    }
+
+    fn problems(profile: TagProfile, pairs: &[(&str, &str)]) -> Vec<(String, TagProblem)> {
+        profile
+            .check(pairs)
+            .into_iter()
+            .map(|issue| (issue.field, issue.problem))
+            .collect()
+    }
+
+    #[test]
+    fn minimal_requires_title_and_artist() {
+        assert_eq!(
+            problems(TagProfile::Minimal, &[("title", "Song")]),
+            [("ARTIST".to_string(), TagProblem::Missing)]
+        );
+        assert!(problems(TagProfile::Minimal, &[("TITLE", "Song"), ("ARTIST", "A")]).is_empty());
+    }
+
+    #[test]
+    fn picard_checks_value_formats() {
+        let tags = [
+            ("TITLE", "Song"),
+            ("ARTIST", "A"),
+            ("ALBUM", "B"),
+            ("DATE", "2024-1"),
+            ("TRACKNUMBER", "3/12"),
+            ("DISCNUMBER", "one"),
+            (
+                "MUSICBRAINZ_TRACKID",
+                "b1a9c0e9-d987-4042-ae91-78d6a3267d69",
+            ),
+            ("MUSICBRAINZ_ALBUMID", "not-a-uuid"),
+            ("MUSICBRAINZ_DISCID", "lwHl8fGzJyLXQR33ug60E8jhf4k-"),
+        ];
+
+        assert_eq!(
+            problems(TagProfile::Picard, &tags),
+            [
+                ("DATE".to_string(), TagProblem::InvalidValue),
+                ("DISCNUMBER".to_string(), TagProblem::InvalidValue),
+                ("MUSICBRAINZ_ALBUMID".to_string(), TagProblem::InvalidValue),
+            ]
+        );
+    }
+
+    #[test]
+    fn dates_may_carry_an_iso_8601_time() {
+        let date = |value| {
+            problems(
+                TagProfile::Picard,
+                &[
+                    ("TITLE", "Song"),
+                    ("ARTIST", "A"),
+                    ("ALBUM", "B"),
+                    ("DATE", value),
+                ],
+            )
+        };
+
+        assert!(date("2024-05-01T09:30:00+02:00").is_empty());
+        assert!(date("2024-05-01 09:30").is_empty());
+        assert!(date("2024-05-01T09:30:00.250Z").is_empty());
+        assert!(!date("2024-05T09:30").is_empty());
+        assert!(!date("2024-05-01T9:30").is_empty());
+        assert_eq!(
+            normalize_numeric_tag("DATE", "\u{ff12}024-05-01 09:30"),
+            Ok("2024-05-01 09:30".to_string())
+        );
+    }
+
+    #[test]
+    fn every_profile_checks_field_names() {
+        assert_eq!(
+            problems(
+                TagProfile::StrictVorbis,
+                &[("A=B", "x"), ("", "y"), ("OK", "z")]
+            ),
+            [
+                ("A=B".to_string(), TagProblem::InvalidFieldName),
+                (String::new(), TagProblem::InvalidFieldName),
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_checks_run_too() {
+        fn no_comments(comments: &[(String, String)]) -> Vec<TagIssue> {
+            comments
+                .iter()
+                .filter(|(key, _)| key == "COMMENT")
+                .map(|(key, _)| issue(key, TagProblem::InvalidValue))
+                .collect()
+        }
+
+        assert_eq!(
+            problems(TagProfile::Custom(no_comments), &[("COMMENT", "x")]),
+            [("COMMENT".to_string(), TagProblem::InvalidValue)]
+        );
+    }
+
+    #[test]
+    fn builder_rejects_tags_that_fail_the_profile() {
+        let result = FlacBuilder::from_interleaved(&[0.0f32; 1024], 1, 44100)
+            .title("Song")
+            .tag_profile(TagProfile::Minimal)
+            .build();
+
+        match result {
+            Err(EncoderError::InvalidTags(issues)) => {
+                assert_eq!(issues, [issue("ARTIST", TagProblem::Missing)])
+            }
+            other => panic!("expected InvalidTags, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn numeric_tags_are_normalized_to_ascii() {
+        assert_eq!(
+            normalize_numeric_tag("tracknumber", "\u{663}\u{ff0f}\u{661}\u{662}"),
+            Ok("3/12".to_string())
+        );
+        assert_eq!(
+            normalize_numeric_tag("DATE", "\u{967}\u{96f}\u{96f}\u{969}\u{2212}\u{966}\u{968}"),
+            Ok("1993-02".to_string())
+        );
+        assert_eq!(
+            normalize_numeric_tag("BPM", "120,5"),
+            Ok("120.5".to_string())
+        );
+        assert_eq!(
+            normalize_numeric_tag("TRACKTOTAL", "1\u{a0}000"),
+            Ok("1000".to_string())
+        );
+        assert_eq!(
+            normalize_numeric_tag("TITLE", "\u{663},5"),
+            Ok("\u{663},5".to_string())
+        );
+        assert_eq!(
+            normalize_numeric_tag("DATE", "March 1993"),
+            Err(TagProblem::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn builder_normalizes_numeric_tags() {
+        let bytes = FlacBuilder::from_interleaved(&[0.0f32; 1024], 1, 44100)
+            .vorbis_comment("TRACKNUMBER", "\u{664}")
+            .normalize_numeric_tags()
+            .build()
+            .unwrap();
+        assert_eq!(
+            read_comments(&bytes).unwrap(),
+            comments(&[("TRACKNUMBER", "4")])
+        );
+
+        let result = FlacBuilder::from_interleaved(&[0.0f32; 1024], 1, 44100)
+            .vorbis_comment("BPM", "fast")
+            .normalize_numeric_tags()
+            .build();
+
+        match result {
+            Err(EncoderError::InvalidTags(issues)) => {
+                assert_eq!(issues, [issue("BPM", TagProblem::InvalidValue)])
+            }
+            other => panic!("expected InvalidTags, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn allowed_rejects_fields_off_the_list() {
+        assert_eq!(
+            problems(
+                TagProfile::allowed(STANDARD_VORBIS_FIELDS.iter().copied()),
+                &[("Title", "Song"), ("ENCODER", "x"), ("isrc", "y")]
+            ),
+            [("ENCODER".to_string(), TagProblem::NotAllowed)]
+        );
+
+        // A list read at runtime, e.g. from a config file.
+        let policy = String::from("TITLE\nARTIST\nLABEL\n");
+        assert_eq!(
+            problems(
+                TagProfile::allowed(policy.lines()),
+                &[("title", "Song"), ("label", "x"), ("GENRE", "y")]
+            ),
+            [("GENRE".to_string(), TagProblem::NotAllowed)]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! An experimental file sink that writes through io_uring, for servers writing many encodes at
+//! once. Writes are batched into large buffers and several are kept in flight while encoding
+//! continues.
+
+use std::{
+    fs::File,
+    io::{self, Seek, SeekFrom, Write},
+    os::{fd::AsRawFd, unix::fs::FileExt},
+    path::Path,
+};
+
+use io_uring::{opcode, types, IoUring};
+
+const BUFFER_SIZE: usize = 1 << 20;
+const QUEUE_DEPTH: usize = 8;
+
+pub(crate) struct UringFile {
+    file: File,
+    ring: IoUring,
+    buffer: Vec<u8>,
+    /// File offset that `buffer` starts at.
+    buffer_offset: u64,
+    /// Buffers the kernel is writing from, indexed by the `user_data` of their submission, with
+    /// the offset they are written at.
+    in_flight: Vec<Option<(Vec<u8>, u64)>>,
+}
+
+impl UringFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(UringFile {
+            file: File::create(path)?,
+            ring: IoUring::new(QUEUE_DEPTH as u32)?,
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+            buffer_offset: 0,
+            in_flight: (0..QUEUE_DEPTH).map(|_| None).collect(),
+        })
+    }
+
+    fn submit_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let slot = loop {
+            match self.in_flight.iter().position(Option::is_none) {
+                Some(slot) => break slot,
+                None => self.reap(1)?,
+            }
+        };
+
+        let buffer = std::mem::replace(&mut self.buffer, Vec::with_capacity(BUFFER_SIZE));
+        let offset = self.buffer_offset;
+        self.buffer_offset += buffer.len() as u64;
+
+        let entry = opcode::Write::new(
+            types::Fd(self.file.as_raw_fd()),
+            buffer.as_ptr(),
+            buffer.len() as u32,
+        )
+        .offset(offset)
+        .build()
+        .user_data(slot as u64);
+
+        // The buffer's heap allocation doesn't move when the `Vec` does, and it is kept alive
+        // in `in_flight` until its completion is reaped.
+        self.in_flight[slot] = Some((buffer, offset));
+
+        unsafe {
+            if self.ring.submission().push(&entry).is_err() {
+                self.in_flight[slot] = None;
+                return Err(io::Error::other("io_uring submission queue is full"));
+            }
+        }
+
+        self.ring.submit()?;
+
+        Ok(())
+    }
+
+    /// Waits for at least `want` writes to complete and handles every completion available.
+    fn reap(&mut self, want: usize) -> io::Result<()> {
+        match self.ring.submit_and_wait(want) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+            result => result?,
+        };
+
+        let completed: Vec<(u64, i32)> = self
+            .ring
+            .completion()
+            .map(|entry| (entry.user_data(), entry.result()))
+            .collect();
+
+        let mut result = Ok(());
+
+        for (slot, written) in completed {
+            let Some((buffer, offset)) = self.in_flight[slot as usize].take() else {
+                continue;
+            };
+
+            let outcome = if written < 0 {
+                Err(io::Error::from_raw_os_error(-written))
+            } else if (written as usize) < buffer.len() {
+                // Short write; finish it synchronously.
+                let written = written as usize;
+                self.file
+                    .write_all_at(&buffer[written..], offset + written as u64)
+            } else {
+                Ok(())
+            };
+
+            if result.is_ok() {
+                result = outcome;
+            }
+        }
+
+        result
+    }
+
+    fn drain(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+
+        while self.in_flight.iter().any(Option::is_some) {
+            if let Err(e) = self.reap(1) {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+
+        result
+    }
+}
+
+impl Write for UringFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+
+        if self.buffer.len() >= BUFFER_SIZE {
+            self.submit_buffer()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.submit_buffer()?;
+        self.drain()
+    }
+}
+
+impl Seek for UringFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        // Writes in flight can land in any order, so they must all be done before anything is
+        // written over them. libFLAC only seeks when finishing so this costs little.
+        self.flush()?;
+
+        let position = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.buffer_offset.checked_add_signed(n),
+            SeekFrom::End(n) => self.file.metadata()?.len().checked_add_signed(n),
+        };
+
+        self.buffer_offset =
+            position.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek"))?;
+
+        Ok(self.buffer_offset)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.buffer_offset + self.buffer.len() as u64)
+    }
+}
+
+impl Drop for UringFile {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::FlacBuilder;
+
+    #[test]
+    fn writes_the_same_file_as_build() {
+        let samples: Vec<f32> = (0..200_000)
+            .map(|i| (i as f32 * 0.01).sin() * 0.5)
+            .collect();
+        let path = std::env::temp_dir().join(format!("uring-{}.flac", std::process::id()));
+
+        FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .write_file_with_io_uring(&path)
+            .unwrap();
+        let written = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let built = FlacBuilder::from_interleaved(&samples, 2, 44100)
+            .build()
+            .unwrap();
+        assert_eq!(written, built);
+    }
+}
@@ -0,0 +1,163 @@
+//! Checking many files at once.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::{scan_frames, EncoderError, FlacReader, FrameScanReport};
+
+/// Result of [`verify_batch`], with one entry per input path in the same order.
+#[derive(Debug, Default)]
+pub struct VerifyBatchReport {
+    pub files: Vec<FileVerification>,
+}
+
+impl VerifyBatchReport {
+    /// Whether every file could be read, scanned and decoded without errors.
+    pub fn is_ok(&self) -> bool {
+        self.files.iter().all(FileVerification::is_ok)
+    }
+
+    /// Files that couldn't be read, have damaged frames or don't match their MD5.
+    pub fn failed(&self) -> impl Iterator<Item = &FileVerification> {
+        self.files.iter().filter(|f| !f.is_ok())
+    }
+}
+
+#[derive(Debug)]
+pub struct FileVerification {
+    pub path: PathBuf,
+    /// The frame scan, or why the file couldn't be read or decoded, e.g. an MD5 mismatch.
+    pub result: Result<FrameScanReport, EncoderError>,
+}
+
+impl FileVerification {
+    pub fn is_ok(&self) -> bool {
+        matches!(&self.result, Ok(report) if report.is_ok())
+    }
+}
+
+/// Runs [`scan_frames`] over every file in `paths` using up to `parallelism` threads (`0` for
+/// one per CPU), for scrubbing large collections. Files whose frames are all intact are then
+/// decoded with [`FlacReader`], failing with [`EncoderError::DecodeFailed`] when the audio
+/// doesn't match the MD5 in STREAMINFO. Files without an MD5 only get the frame checks.
+/// `progress` is called from the worker threads after each file with the file's path, the
+/// number of files done so far and the total.
+pub fn verify_batch<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    parallelism: usize,
+    progress: impl Fn(&Path, usize, usize) + Sync,
+) -> VerifyBatchReport {
+    let parallelism = match parallelism {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+    .min(paths.len());
+
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<FileVerification>>> =
+        Mutex::new(paths.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..parallelism {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = paths.get(i) else {
+                    break;
+                };
+                let path = path.as_ref();
+
+                let result = std::fs::read(path)
+                    .map_err(EncoderError::Io)
+                    .and_then(|bytes| verify_bytes(&bytes));
+
+                progress(path, done.fetch_add(1, Ordering::Relaxed) + 1, paths.len());
+
+                results.lock().unwrap()[i] = Some(FileVerification {
+                    path: path.to_path_buf(),
+                    result,
+                });
+            });
+        }
+    });
+
+    VerifyBatchReport {
+        files: results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect(),
+    }
+}
+
+/// Scans the frames and, if they are intact, decodes the audio to check its MD5.
+fn verify_bytes(bytes: &[u8]) -> Result<FrameScanReport, EncoderError> {
+    let report = scan_frames(bytes)?;
+
+    if report.is_ok() {
+        FlacReader::from_bytes(bytes)?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FlacBuilder;
+
+    #[test]
+    fn reports_each_file_in_order() {
+        let dir = std::env::temp_dir();
+        let good = dir.join(format!("verify-good-{}.flac", std::process::id()));
+        let missing = dir.join(format!("verify-missing-{}.flac", std::process::id()));
+
+        let samples = vec![0.25f32; 8192];
+        FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .write_file(&good)
+            .unwrap();
+
+        let calls = AtomicUsize::new(0);
+        let report = verify_batch(&[&good, &missing, &good], 2, |_, done, total| {
+            assert!(done <= total);
+            calls.fetch_add(1, Ordering::Relaxed);
+        });
+        std::fs::remove_file(&good).unwrap();
+
+        assert_eq!(calls.into_inner(), 3);
+        assert!(!report.is_ok());
+        assert_eq!(report.files.len(), 3);
+        assert_eq!(report.files[1].path, missing);
+
+        let failed: Vec<_> = report.failed().map(|f| &f.path).collect();
+        assert_eq!(failed, [&missing]);
+        assert!(matches!(report.files[1].result, Err(EncoderError::Io(_))));
+    }
+
+    #[test]
+    fn decodes_intact_files_to_check_the_md5() {
+        let path = std::env::temp_dir().join(format!("verify-md5-{}.flac", std::process::id()));
+        let samples: Vec<f32> = (0..8192).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+        let mut bytes = FlacBuilder::from_interleaved(&samples, 1, 44100)
+            .build()
+            .unwrap();
+        // The MD5 is the last 16 bytes of STREAMINFO, after "fLaC" and the block header.
+        bytes[4 + 4 + 18] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let report = verify_batch(&[&path], 1, |_, _, _| {});
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            report.files[0].result,
+            Err(EncoderError::DecodeFailed(_))
+        ));
+    }
+}
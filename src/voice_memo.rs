@@ -0,0 +1,229 @@
+//! A voice memo recorder built from the rest of the crate, capturing with cpal.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, SampleFormat, SizedSample,
+};
+
+use crate::{analyze, EncodeReport, EncoderError, FlacBuilder};
+
+/// Level below which audio at the start and end counts as silence, in dBFS.
+const DEFAULT_SILENCE_DB: f64 = -50.0;
+
+/// Loudness memos are brought to, in LUFS, as is usual for speech.
+const DEFAULT_TARGET_LUFS: f64 = -16.0;
+
+/// Highest sample peak normalization may raise the audio to, about -0.2 dBFS.
+const PEAK_CEILING: f64 = 0.98;
+
+/// Records from the default input device into memory, then trims the silence at either end,
+/// normalizes the loudness and writes a tagged FLAC file atomically, e.g. for a dictation app
+/// or a demo of the crate. For long recordings use a [`FlacStreamEncoder`] or
+/// [`RollingEncoder`] instead, as this keeps everything in memory until it is saved.
+///
+/// [`FlacStreamEncoder`]: crate::FlacStreamEncoder
+/// [`RollingEncoder`]: crate::RollingEncoder
+pub struct VoiceMemoRecorder {
+    stream: cpal::Stream,
+    samples: Arc<Mutex<Vec<f32>>>,
+    channels: usize,
+    sample_rate: u32,
+    silence_db: f64,
+    target_lufs: Option<f64>,
+    tags: Vec<(String, String)>,
+}
+
+impl VoiceMemoRecorder {
+    /// Starts recording in the input device's default format. Fails with
+    /// [`EncoderError::Capture`] if there is no input device or it can't be opened.
+    pub fn start() -> Result<Self, EncoderError> {
+        let device = cpal::default_host()
+            .default_input_device()
+            .ok_or_else(|| EncoderError::Capture("no input device".to_string()))?;
+        let supported = device
+            .default_input_config()
+            .map_err(|e| EncoderError::Capture(e.to_string()))?;
+        let config = supported.config();
+
+        let samples = Arc::new(Mutex::new(vec![]));
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => capture::<f32>(&device, &config, samples.clone()),
+            SampleFormat::I16 => capture::<i16>(&device, &config, samples.clone()),
+            SampleFormat::U16 => capture::<u16>(&device, &config, samples.clone()),
+            SampleFormat::I32 => capture::<i32>(&device, &config, samples.clone()),
+            format => {
+                return Err(EncoderError::Capture(format!(
+                    "unsupported sample format {format}"
+                )))
+            }
+        }
+        .map_err(|e| EncoderError::Capture(e.to_string()))?;
+        stream
+            .play()
+            .map_err(|e| EncoderError::Capture(e.to_string()))?;
+
+        Ok(VoiceMemoRecorder {
+            stream,
+            samples,
+            channels: config.channels as usize,
+            sample_rate: config.sample_rate.0,
+            silence_db: DEFAULT_SILENCE_DB,
+            target_lufs: Some(DEFAULT_TARGET_LUFS),
+            tags: vec![],
+        })
+    }
+
+    /// Trims audio quieter than `threshold_db` (dBFS) at the start and end. `-50.0` by default.
+    pub fn trim_below(mut self, threshold_db: f64) -> Self {
+        self.silence_db = threshold_db;
+        self
+    }
+
+    /// Brings the memo to `lufs` integrated loudness, as far as the peaks allow. `-16.0` by
+    /// default.
+    pub fn normalize_to(mut self, lufs: f64) -> Self {
+        self.target_lufs = Some(lufs);
+        self
+    }
+
+    /// Keeps the level as it was recorded.
+    pub fn without_normalizing(mut self) -> Self {
+        self.target_lufs = None;
+        self
+    }
+
+    /// Adds a vorbis comment, e.g. `TITLE`.
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// How much has been recorded so far.
+    pub fn duration(&self) -> Duration {
+        let frames = self.samples.lock().unwrap().len() / self.channels.max(1);
+        Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64)
+    }
+
+    /// Stops recording and writes the memo to `path` as 16-bit FLAC. Fails with
+    /// [`EncoderError::NoData`] if nothing louder than the silence threshold was recorded.
+    pub fn stop_and_save(self, path: impl AsRef<Path>) -> Result<EncodeReport, EncoderError> {
+        drop(self.stream);
+        let mut samples = std::mem::take(&mut *self.samples.lock().unwrap());
+        let channels = self.channels.max(1);
+
+        trim_silence(&mut samples, channels, self.silence_db)?;
+        if let Some(target) = self.target_lufs {
+            normalize(&mut samples, channels, self.sample_rate, target)?;
+        }
+
+        let mut builder =
+            FlacBuilder::from_interleaved(&samples, channels, self.sample_rate).atomic_write();
+        for (key, value) in &self.tags {
+            builder = builder.vorbis_comment(key, value);
+        }
+
+        builder.write_file_with_report(path)
+    }
+}
+
+/// Cuts `samples` down to whole frames from the first to the last one with any channel above
+/// `silence_db` (dBFS).
+fn trim_silence(
+    samples: &mut Vec<f32>,
+    channels: usize,
+    silence_db: f64,
+) -> Result<(), EncoderError> {
+    let threshold = 10f64.powf(silence_db / 20.0) as f32;
+    let is_loud = |frame: &[f32]| frame.iter().any(|sample| sample.abs() > threshold);
+    let frames: Vec<&[f32]> = samples.chunks_exact(channels).collect();
+    let start = frames.iter().position(|frame| is_loud(frame));
+    let end = frames.iter().rposition(|frame| is_loud(frame));
+
+    let Some((start, end)) = start.zip(end) else {
+        return Err(EncoderError::NoData);
+    };
+    samples.truncate((end + 1) * channels);
+    samples.drain(..start * channels);
+    Ok(())
+}
+
+/// Brings `samples` to `target` LUFS integrated loudness, or as close as the peak ceiling
+/// allows.
+fn normalize(
+    samples: &mut [f32],
+    channels: usize,
+    sample_rate: u32,
+    target: f64,
+) -> Result<(), EncoderError> {
+    let loudness = analyze(samples, channels, sample_rate)?;
+
+    if loudness.integrated_lufs.is_finite() && loudness.sample_peak > 0.0 {
+        let gain = 10f64
+            .powf((target - loudness.integrated_lufs) / 20.0)
+            .min(PEAK_CEILING / loudness.sample_peak) as f32;
+
+        for sample in samples {
+            *sample *= gain;
+        }
+    }
+    Ok(())
+}
+
+/// Builds an input stream appending everything captured to `samples` as floats.
+fn capture<T: SizedSample + Send + 'static>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    samples: Arc<Mutex<Vec<f32>>>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    f32: FromSample<T>,
+{
+    device.build_input_stream(
+        config,
+        move |data: &[T], _| {
+            samples
+                .lock()
+                .unwrap()
+                .extend(data.iter().map(|&sample| f32::from_sample_(sample)));
+        },
+        |_| {},
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trims_whole_frames_of_silence() {
+        let mut samples = vec![0.0, 0.001, 0.0, 0.5, -0.5, 0.0, 0.0, 0.0];
+        trim_silence(&mut samples, 2, DEFAULT_SILENCE_DB).unwrap();
+        assert_eq!(samples, [0.0, 0.5, -0.5, 0.0]);
+
+        let mut silence = vec![0.001; 8];
+        assert!(matches!(
+            trim_silence(&mut silence, 2, DEFAULT_SILENCE_DB),
+            Err(EncoderError::NoData)
+        ));
+    }
+
+    #[test]
+    fn normalizing_stops_at_the_peak_ceiling() {
+        let mut samples: Vec<f32> = (0..48000)
+            .map(|i| (i as f32 * 0.05).sin() * if i % 4800 == 0 { 0.5 } else { 0.01 })
+            .collect();
+        normalize(&mut samples, 1, 48000, DEFAULT_TARGET_LUFS).unwrap();
+
+        let peak = samples
+            .iter()
+            .fold(0f32, |peak, sample| peak.max(sample.abs()));
+        assert!((peak as f64 - PEAK_CEILING).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,555 @@
+//! Reading WAV input and writing decoded audio out as WAV.
+
+use std::io::{self, ErrorKind, Read, Take, Write};
+
+use crate::{AudioBlock, AudioSource, EncoderError, FlacDecoder, FlacReader};
+
+/// The comment `flac` stores a WAV file's speaker layout in.
+pub const CHANNEL_MASK_TAG: &str = "WAVEFORMATEXTENSIBLE_CHANNEL_MASK";
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// `KSDATAFORMAT_SUBTYPE_PCM` as laid out in the file.
+const SUBTYPE_PCM: [u8; 16] = [
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+/// The speakers FLAC assigns to each channel count when nothing else is said, as a WAV
+/// channel mask.
+pub fn default_channel_mask(channels: usize) -> u32 {
+    match channels {
+        1 => 0x4,
+        2 => 0x3,
+        3 => 0x7,
+        4 => 0x33,
+        5 => 0x37,
+        6 => 0x3f,
+        7 => 0x70f,
+        8 => 0x63f,
+        _ => 0,
+    }
+}
+
+impl<R: Read> FlacDecoder<R> {
+    /// Decodes what is left of the stream and writes it as a WAV file, keeping the speaker
+    /// layout in the [`WAVEFORMATEXTENSIBLE_CHANNEL_MASK`](CHANNEL_MASK_TAG) comment if there
+    /// is one, as `flac -d` does. The audio is decoded into memory first, as the WAV header
+    /// needs its length. See [`AudioBlock::write_wav`].
+    pub fn write_wav(&mut self, writer: impl Write) -> Result<(), EncoderError> {
+        let mut samples = vec![];
+        let mut buffer = vec![0; 4096 * self.channels()];
+
+        loop {
+            match self.fill(&mut buffer)? {
+                0 => break,
+                n => samples.extend_from_slice(&buffer[..n]),
+            }
+        }
+
+        let block = AudioBlock {
+            channels: self.channels(),
+            bps: self.bps(),
+            sample_rate: self.sample_rate(),
+            samples,
+        };
+        block.write_wav(writer, self.channel_mask())
+    }
+
+    /// The speaker layout from the [`WAVEFORMATEXTENSIBLE_CHANNEL_MASK`](CHANNEL_MASK_TAG)
+    /// comment, written as hex like `0x0033`.
+    pub fn channel_mask(&self) -> Option<u32> {
+        channel_mask(self.comments())
+    }
+}
+
+impl FlacReader {
+    /// Writes the audio as a WAV file, keeping the speaker layout in the
+    /// [`WAVEFORMATEXTENSIBLE_CHANNEL_MASK`](CHANNEL_MASK_TAG) comment if there is one, as
+    /// `flac -d` does. See [`AudioBlock::write_wav`].
+    pub fn write_wav(&self, writer: impl Write) -> Result<(), EncoderError> {
+        self.audio.write_wav(writer, self.channel_mask())
+    }
+
+    /// The speaker layout from the [`WAVEFORMATEXTENSIBLE_CHANNEL_MASK`](CHANNEL_MASK_TAG)
+    /// comment, written as hex like `0x0033`.
+    pub fn channel_mask(&self) -> Option<u32> {
+        channel_mask(&self.comments)
+    }
+}
+
+fn channel_mask(comments: &[(String, String)]) -> Option<u32> {
+    comments
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(CHANNEL_MASK_TAG))
+        .and_then(|(_, value)| {
+            let value = value.trim();
+            let hex = value
+                .strip_prefix("0x")
+                .or_else(|| value.strip_prefix("0X"))?;
+
+            u32::from_str_radix(hex, 16).ok()
+        })
+}
+
+impl AudioBlock {
+    /// Writes the block as a WAV file. `WAVE_FORMAT_EXTENSIBLE` is used, as the format asks
+    /// for, with more than two channels, more than 16 bps, a bps that isn't a whole number of
+    /// bytes, or a `channel_mask`; without one FLAC's default layout is assumed. Fails if the
+    /// audio is too long for the 4 GiB a WAV file can hold.
+    pub fn write_wav(
+        &self,
+        mut writer: impl Write,
+        channel_mask: Option<u32>,
+    ) -> Result<(), EncoderError> {
+        let container_bytes = self.bps.div_ceil(8) as usize;
+        let shift = container_bytes as u32 * 8 - self.bps;
+
+        let is_extensible =
+            self.channels > 2 || self.bps > 16 || shift != 0 || channel_mask.is_some();
+
+        let Ok(data_len) = u32::try_from(self.samples.len() * container_bytes) else {
+            return Err(EncoderError::TooManyOrTooFewSamples);
+        };
+        let fmt_len: u32 = if is_extensible { 40 } else { 16 };
+        let padding = data_len % 2;
+        let Some(riff_len) = (4 + 8 + fmt_len + 8 + padding).checked_add(data_len) else {
+            return Err(EncoderError::TooManyOrTooFewSamples);
+        };
+
+        let block_align = (self.channels * container_bytes) as u16;
+        let mut header = Vec::with_capacity(68);
+
+        header.extend(b"RIFF");
+        header.extend(riff_len.to_le_bytes());
+        header.extend(b"WAVE");
+
+        header.extend(b"fmt ");
+        header.extend(fmt_len.to_le_bytes());
+        let format = if is_extensible {
+            WAVE_FORMAT_EXTENSIBLE
+        } else {
+            WAVE_FORMAT_PCM
+        };
+        header.extend(format.to_le_bytes());
+        header.extend((self.channels as u16).to_le_bytes());
+        header.extend(self.sample_rate.to_le_bytes());
+        header.extend((self.sample_rate * block_align as u32).to_le_bytes());
+        header.extend(block_align.to_le_bytes());
+        header.extend((container_bytes as u16 * 8).to_le_bytes());
+
+        if is_extensible {
+            let mask = channel_mask.unwrap_or_else(|| default_channel_mask(self.channels));
+
+            header.extend(22u16.to_le_bytes());
+            header.extend((self.bps as u16).to_le_bytes());
+            header.extend(mask.to_le_bytes());
+            header.extend(SUBTYPE_PCM);
+        }
+
+        header.extend(b"data");
+        header.extend(data_len.to_le_bytes());
+
+        let write = |writer: &mut dyn Write| -> io::Result<()> {
+            writer.write_all(&header)?;
+
+            let mut bytes = Vec::with_capacity(4096 * container_bytes);
+            for chunk in self.samples.chunks(4096) {
+                bytes.clear();
+
+                for &sample in chunk {
+                    // Samples are left-justified in their container; 8-bit WAV is unsigned.
+                    let sample = sample << shift;
+                    if container_bytes == 1 {
+                        bytes.push((sample + 128) as u8);
+                    } else {
+                        bytes.extend(&sample.to_le_bytes()[..container_bytes]);
+                    }
+                }
+
+                writer.write_all(&bytes)?;
+            }
+
+            if padding != 0 {
+                writer.write_all(&[0])?;
+            }
+
+            writer.flush()
+        };
+
+        write(&mut writer).map_err(EncoderError::Io)
+    }
+}
+
+/// The format of a WAV file, from its `fmt ` chunk.
+#[derive(Debug)]
+struct WavFormat {
+    channels: usize,
+    sample_rate: u32,
+    /// Bits each sample takes in the file, a whole number of bytes.
+    container_bits: u32,
+    /// Bits of each sample that are audio, from the top of the container down.
+    bps: u32,
+    channel_mask: Option<u32>,
+}
+
+/// PCM audio from a WAV file, plain or `WAVE_FORMAT_EXTENSIBLE`. Its sample rate and channel
+/// count come from the file, so
+/// [`FlacStreamEncoder::push_source`](crate::FlacStreamEncoder::push_source) can refuse it if
+/// it doesn't match the stream. A `data` length of `0xFFFFFFFF`, as written by tools streaming
+/// into a pipe, reads until the end of the input.
+#[derive(Debug)]
+pub struct WavReader<R> {
+    reader: Take<R>,
+    format: WavFormat,
+    frames_left: Option<usize>,
+    bytes: Vec<u8>,
+}
+
+impl<R: Read> WavReader<R> {
+    /// Reads the header up to the start of the audio.
+    pub fn new(mut reader: R) -> Result<Self, EncoderError> {
+        let (format, data_len) = read_header(&mut reader)?;
+
+        let frame_bytes = format.channels as u64 * format.container_bits as u64 / 8;
+        let (limit, frames_left) = match data_len {
+            u32::MAX => (u64::MAX, None),
+            len => (len as u64, Some((len as u64 / frame_bytes) as usize)),
+        };
+
+        Ok(WavReader {
+            reader: reader.take(limit),
+            format,
+            frames_left,
+            bytes: vec![],
+        })
+    }
+
+    /// The speaker layout of a `WAVE_FORMAT_EXTENSIBLE` file. Can be kept in the FLAC file by
+    /// tagging it as [`CHANNEL_MASK_TAG`] in hex, like `0x0033`.
+    pub fn channel_mask(&self) -> Option<u32> {
+        self.format.channel_mask
+    }
+}
+
+impl<R: Read> AudioSource for WavReader<R> {
+    fn channels(&self) -> usize {
+        self.format.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.format.sample_rate
+    }
+
+    /// May be fewer bits than the file stores each sample in.
+    fn bps(&self) -> u32 {
+        self.format.bps
+    }
+
+    fn len_hint(&self) -> Option<usize> {
+        self.frames_left
+    }
+
+    /// A partial frame at the end of the audio is dropped.
+    fn fill(&mut self, buffer: &mut [i32]) -> Result<usize, EncoderError> {
+        let container_bytes = self.format.container_bits as usize / 8;
+        let channels = self.format.channels;
+        let frames = buffer.len() / channels;
+
+        self.bytes.resize(frames * channels * container_bytes, 0);
+        let mut read = 0;
+        while read < self.bytes.len() {
+            match self.reader.read(&mut self.bytes[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(EncoderError::Io(e)),
+            }
+        }
+
+        let n = read / (channels * container_bytes) * channels;
+        let shift = 32 - self.format.container_bits;
+        let bps_shift = self.format.container_bits - self.format.bps;
+
+        for (sample, bytes) in buffer[..n]
+            .iter_mut()
+            .zip(self.bytes.chunks_exact(container_bytes))
+        {
+            let container = if container_bytes == 1 {
+                // 8-bit WAV is unsigned.
+                bytes[0] as i32 - 128
+            } else {
+                let mut word = [0; 4];
+                word[4 - container_bytes..].copy_from_slice(bytes);
+                i32::from_le_bytes(word) >> shift
+            };
+            // Samples are left-justified in their container.
+            *sample = container >> bps_shift;
+        }
+
+        if let Some(frames_left) = &mut self.frames_left {
+            *frames_left = frames_left.saturating_sub(n / channels);
+        }
+
+        Ok(n)
+    }
+}
+
+/// Reads up to the start of the `data` chunk, returning the format and the chunk's length.
+fn read_header(reader: &mut impl Read) -> Result<(WavFormat, u32), EncoderError> {
+    let mut riff = [0; 12];
+    read_exact(reader, &mut riff)?;
+    if &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+        return Err(EncoderError::InvalidWav("not a RIFF WAVE file".to_string()));
+    }
+
+    let mut format = None;
+
+    loop {
+        let mut chunk_header = [0; 8];
+        read_exact(reader, &mut chunk_header)?;
+        let len = u32::from_le_bytes(chunk_header[4..].try_into().unwrap());
+
+        match &chunk_header[..4] {
+            b"fmt " => {
+                let mut fmt = vec![0; len as usize + len as usize % 2];
+                read_exact(reader, &mut fmt)?;
+                format = Some(parse_fmt(&fmt[..len as usize])?);
+            }
+            b"data" => {
+                return match format {
+                    Some(format) => Ok((format, len)),
+                    None => Err(EncoderError::InvalidWav(
+                        "no fmt chunk before the data".to_string(),
+                    )),
+                };
+            }
+            _ => {
+                let skip = len as u64 + len as u64 % 2;
+                let skipped =
+                    io::copy(&mut reader.take(skip), &mut io::sink()).map_err(EncoderError::Io)?;
+                if skipped < skip {
+                    return Err(EncoderError::InvalidWav("truncated header".to_string()));
+                }
+            }
+        }
+    }
+}
+
+fn read_exact(reader: &mut impl Read, buffer: &mut [u8]) -> Result<(), EncoderError> {
+    reader.read_exact(buffer).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => EncoderError::InvalidWav("truncated header".to_string()),
+        _ => EncoderError::Io(e),
+    })
+}
+
+fn parse_fmt(fmt: &[u8]) -> Result<WavFormat, EncoderError> {
+    let invalid = |problem: &str| Err(EncoderError::InvalidWav(problem.to_string()));
+
+    if fmt.len() < 16 {
+        return invalid("fmt chunk is too short");
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([fmt[i], fmt[i + 1]]);
+
+    let format_tag = u16_at(0);
+    let channels = u16_at(2) as usize;
+    let sample_rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap());
+    let block_align = u16_at(12) as usize;
+    let container_bits = u16_at(14) as u32;
+
+    let (bps, channel_mask) = match format_tag {
+        WAVE_FORMAT_PCM => (container_bits, None),
+        WAVE_FORMAT_EXTENSIBLE if fmt.len() >= 40 => {
+            if fmt[24..40] != SUBTYPE_PCM {
+                return invalid("only integer PCM is supported");
+            }
+            let valid_bits = u16_at(18) as u32;
+            let mask = u32::from_le_bytes(fmt[20..24].try_into().unwrap());
+
+            // Zero valid bits means the whole container is used.
+            let bps = if valid_bits == 0 {
+                container_bits
+            } else {
+                valid_bits
+            };
+            (bps, Some(mask))
+        }
+        _ => return invalid("only integer PCM is supported"),
+    };
+
+    if channels == 0 {
+        return invalid("no channels");
+    }
+    if !container_bits.is_multiple_of(8) || !(8..=32).contains(&container_bits) {
+        return invalid("bits per sample isn't 8, 16, 24 or 32");
+    }
+    if bps == 0 || bps > container_bits {
+        return invalid("valid bits don't fit in the container");
+    }
+    if block_align != channels * container_bits as usize / 8 {
+        return invalid("block align doesn't match the channels and bits per sample");
+    }
+
+    Ok(WavFormat {
+        channels,
+        sample_rate,
+        container_bits,
+        bps,
+        channel_mask,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FlacBuilder;
+
+    fn encode(channels: usize, mask: Option<&str>) -> Vec<u8> {
+        let samples: Vec<f32> = (0..1000 * channels)
+            .map(|i| (i % 7) as f32 / 10.0)
+            .collect();
+        let mut builder = FlacBuilder::from_interleaved(&samples, channels, 8000);
+        if let Some(mask) = mask {
+            builder = builder.vorbis_comment(CHANNEL_MASK_TAG, mask);
+        }
+
+        builder.build().unwrap()
+    }
+
+    fn decoder(channels: usize, mask: Option<&str>) -> FlacDecoder<io::Cursor<Vec<u8>>> {
+        FlacDecoder::new(io::Cursor::new(encode(channels, mask))).unwrap()
+    }
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn stereo_16_bit_is_plain_pcm() {
+        let mut wav = vec![];
+        decoder(2, None).write_wav(&mut wav).unwrap();
+
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(u32_at(&wav, 4) as usize, wav.len() - 8);
+        assert_eq!(u16_at(&wav, 20), WAVE_FORMAT_PCM);
+        assert_eq!(u16_at(&wav, 22), 2);
+        assert_eq!(u32_at(&wav, 24), 8000);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32_at(&wav, 40), 1000 * 2 * 2);
+
+        let mut samples = vec![0; 2000];
+        decoder(2, None).fill(&mut samples).unwrap();
+        let written: Vec<i32> = wav[44..]
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as i32)
+            .collect();
+        assert_eq!(written, samples);
+    }
+
+    #[test]
+    fn channel_mask_tag_makes_it_extensible() {
+        let mut decoder = decoder(4, Some("0x0033"));
+        assert_eq!(decoder.channel_mask(), Some(0x33));
+
+        let mut wav = vec![];
+        decoder.write_wav(&mut wav).unwrap();
+
+        assert_eq!(u16_at(&wav, 20), WAVE_FORMAT_EXTENSIBLE);
+        assert_eq!(u32_at(&wav, 40), 0x33);
+        assert_eq!(wav[44..60], SUBTYPE_PCM);
+    }
+
+    #[test]
+    fn the_reader_writes_what_the_decoder_writes() {
+        let reader = FlacReader::from_bytes(&encode(4, Some("0x0033"))).unwrap();
+        assert_eq!(reader.channel_mask(), Some(0x33));
+
+        let (mut from_reader, mut from_decoder) = (vec![], vec![]);
+        reader.write_wav(&mut from_reader).unwrap();
+        decoder(4, Some("0x0033"))
+            .write_wav(&mut from_decoder)
+            .unwrap();
+        assert_eq!(from_reader, from_decoder);
+    }
+
+    #[test]
+    fn default_masks_follow_flac() {
+        assert_eq!(default_channel_mask(2), 0x3);
+        assert_eq!(default_channel_mask(6), 0x3f);
+        assert_eq!(default_channel_mask(9), 0);
+    }
+
+    fn read_all(wav: &[u8]) -> (WavReader<&[u8]>, Vec<i32>) {
+        let mut reader = WavReader::new(wav).unwrap();
+        let mut samples = vec![0; 1 << 16];
+        let n = reader.fill(&mut samples).unwrap();
+        samples.truncate(n);
+        (reader, samples)
+    }
+
+    #[test]
+    fn reads_back_what_write_wav_wrote() {
+        for (channels, mask) in [(2, None), (4, Some("0x0033"))] {
+            let mut decoded = vec![0; 1 << 16];
+            let n = decoder(channels, mask).fill(&mut decoded).unwrap();
+            decoded.truncate(n);
+
+            let mut wav = vec![];
+            decoder(channels, mask).write_wav(&mut wav).unwrap();
+            let (reader, samples) = read_all(&wav);
+
+            assert_eq!(reader.channels(), channels);
+            assert_eq!(reader.sample_rate(), 8000);
+            assert_eq!(reader.bps(), 16);
+            assert_eq!(reader.channel_mask(), mask.map(|_| 0x33));
+            assert_eq!(reader.len_hint(), Some(0));
+            assert_eq!(samples, decoded);
+        }
+    }
+
+    #[test]
+    fn valid_bits_and_8_bit_samples() {
+        let samples = [-8, 7, 0, -1];
+        let write = |bps| {
+            let mut wav = vec![];
+            let block = AudioBlock {
+                channels: 1,
+                bps,
+                sample_rate: 8000,
+                samples: samples.to_vec(),
+            };
+            block.write_wav(&mut wav, None).unwrap();
+            wav
+        };
+
+        // 4 valid bits in an 8-bit container, and 12 in a 16-bit one.
+        for bps in [4, 12] {
+            let wav = write(bps);
+            let (reader, read) = read_all(&wav);
+            assert_eq!(reader.bps(), bps);
+            assert_eq!(read, samples);
+        }
+    }
+
+    #[test]
+    fn rejects_what_it_cant_read() {
+        let mut wav = vec![];
+        decoder(2, None).write_wav(&mut wav).unwrap();
+
+        let problem = |bytes: &[u8]| match WavReader::new(bytes) {
+            Err(EncoderError::InvalidWav(problem)) => problem,
+            other => panic!("expected InvalidWav, got {other:?}"),
+        };
+
+        assert_eq!(problem(b"RIFX\0\0\0\0WAVE"), "not a RIFF WAVE file");
+        assert_eq!(problem(&wav[..30]), "truncated header");
+
+        let mut float = wav.clone();
+        float[20] = 3;
+        assert_eq!(problem(&float), "only integer PCM is supported");
+    }
+}